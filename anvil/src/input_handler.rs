@@ -535,6 +535,19 @@ impl AnvilState<UdevData> {
                         self.backend_data.reset_buffers(&output);
                     }
                 }
+                KeyAction::ToggleVrr => {
+                    let pos = self.pointer_location.to_i32_round();
+                    let output = self
+                        .space
+                        .outputs()
+                        .find(|o| self.space.output_geometry(o).unwrap().contains(pos))
+                        .cloned();
+
+                    if let Some(output) = output {
+                        let vrr = !self.backend_data.vrr(&output);
+                        self.backend_data.set_vrr(&output, vrr);
+                    }
+                }
 
                 action => match action {
                     KeyAction::None | KeyAction::Quit | KeyAction::Run(_) => {
@@ -802,6 +815,8 @@ enum KeyAction {
     Screen(usize),
     ScaleUp,
     ScaleDown,
+    /// Toggle variable refresh rate of the current output
+    ToggleVrr,
     /// Do nothing more
     None,
 }
@@ -827,6 +842,8 @@ fn process_keyboard_shortcut(modifiers: ModifiersState, keysym: Keysym) -> Optio
         Some(KeyAction::ScaleDown)
     } else if modifiers.logo && modifiers.shift && keysym == xkb::KEY_P {
         Some(KeyAction::ScaleUp)
+    } else if modifiers.logo && modifiers.shift && keysym == xkb::KEY_V {
+        Some(KeyAction::ToggleVrr)
     } else {
         None
     }
@@ -6,8 +6,9 @@ use std::{
 use smithay::{
     delegate_compositor, delegate_data_device, delegate_input_method_manager,
     delegate_keyboard_shortcuts_inhibit, delegate_layer_shell, delegate_output, delegate_primary_selection,
-    delegate_seat, delegate_shm, delegate_tablet_manager, delegate_text_input_manager, delegate_viewporter,
-    delegate_xdg_activation, delegate_xdg_decoration, delegate_xdg_shell,
+    delegate_seat, delegate_shm, delegate_tablet_manager, delegate_tearing_control,
+    delegate_text_input_manager, delegate_viewporter, delegate_xdg_activation, delegate_xdg_decoration,
+    delegate_xdg_shell,
     desktop::{PopupManager, Space, Window},
     input::{keyboard::XkbConfig, pointer::CursorImageStatus, Seat, SeatHandler, SeatState},
     output::Output,
@@ -47,6 +48,7 @@ use smithay::{
         shm::{ShmHandler, ShmState},
         socket::ListeningSocketSource,
         tablet_manager::TabletSeatTrait,
+        tearing_control::TearingControlState,
         text_input::TextInputManagerState,
         viewporter::ViewporterState,
         xdg_activation::{
@@ -96,6 +98,7 @@ pub struct AnvilState<BackendData: 'static> {
     pub seat_state: SeatState<AnvilState<BackendData>>,
     pub keyboard_shortcuts_inhibit_state: KeyboardShortcutsInhibitState,
     pub shm_state: ShmState,
+    pub tearing_control_state: TearingControlState,
     pub viewporter_state: ViewporterState,
     pub xdg_activation_state: XdgActivationState,
     pub xdg_decoration_state: XdgDecorationState,
@@ -201,6 +204,8 @@ impl<BackendData> KeyboardShortcutsInhibitHandler for AnvilState<BackendData> {
 
 delegate_keyboard_shortcuts_inhibit!(@<BackendData: 'static> AnvilState<BackendData>);
 
+delegate_tearing_control!(@<BackendData: 'static> AnvilState<BackendData>);
+
 delegate_viewporter!(@<BackendData: 'static> AnvilState<BackendData>);
 
 impl<BackendData> XdgActivationHandler for AnvilState<BackendData> {
@@ -309,6 +314,7 @@ impl<BackendData: Backend + 'static> AnvilState<BackendData> {
         let primary_selection_state = PrimarySelectionState::new::<Self, _>(&dh, log.clone());
         let mut seat_state = SeatState::new();
         let shm_state = ShmState::new::<Self, _>(&dh, vec![], log.clone());
+        let tearing_control_state = TearingControlState::new::<Self, _>(&dh, log.clone());
         let viewporter_state = ViewporterState::new::<Self, _>(&dh, log.clone());
         let xdg_activation_state = XdgActivationState::new::<Self, _>(&dh, log.clone());
         let xdg_decoration_state = XdgDecorationState::new::<Self, _>(&dh, log.clone());
@@ -369,6 +375,7 @@ impl<BackendData: Backend + 'static> AnvilState<BackendData> {
             seat_state,
             keyboard_shortcuts_inhibit_state,
             shm_state,
+            tearing_control_state,
             viewporter_state,
             xdg_activation_state,
             xdg_decoration_state,
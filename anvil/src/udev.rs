@@ -1,7 +1,7 @@
 use std::{
     borrow::Cow,
     cell::RefCell,
-    collections::hash_map::{Entry, HashMap},
+    collections::hash_map::HashMap,
     os::unix::io::{AsRawFd, RawFd},
     path::PathBuf,
     rc::Rc,
@@ -14,6 +14,7 @@ use slog::Logger;
 use crate::{
     drawing::*,
     render::*,
+    shell::FullscreenSurface,
     state::{AnvilState, Backend, CalloopData},
 };
 #[cfg(feature = "debug")]
@@ -29,7 +30,12 @@ use smithay::{
 };
 use smithay::{
     backend::{
-        drm::{DrmDevice, DrmError, DrmEvent, DrmNode, GbmBufferedSurface, NodeType},
+        drm::{
+            edid::Edid,
+            frame_clock::FrameClock,
+            scanner::{ConnectorEvent, ConnectorScanner},
+            DrmDevice, DrmError, DrmEvent, DrmEventMetadata, DrmNode, GbmBufferedSurface, NodeType,
+        },
         egl::{EGLContext, EGLDevice, EGLDisplay},
         libinput::{LibinputInputBackend, LibinputSessionInterface},
         renderer::{
@@ -54,15 +60,7 @@ use smithay::{
             timer::{TimeoutAction, Timer},
             Dispatcher, EventLoop, LoopHandle, RegistrationToken,
         },
-        drm::{
-            self,
-            control::{
-                connector::{Info as ConnectorInfo, State as ConnectorState},
-                crtc,
-                encoder::Info as EncoderInfo,
-                Device as ControlDevice,
-            },
-        },
+        drm::{self, control::crtc},
         gbm::Device as GbmDevice,
         input::Libinput,
         nix::{fcntl::OFlag, sys::stat::dev_t},
@@ -75,9 +73,13 @@ use smithay::{
     wayland::{
         compositor,
         input_method::{InputMethodHandle, InputMethodSeat},
+        tearing_control::TearingControlSurfaceCachedState,
     },
 };
 
+// time reserved for rendering and queueing a frame before the next vblank
+const RENDER_MARGIN: Duration = Duration::from_millis(6);
+
 type UdevRenderer<'a> =
     MultiRenderer<'a, 'a, EglGlesBackend<Gles2Renderer>, EglGlesBackend<Gles2Renderer>, Gles2Renderbuffer>;
 
@@ -130,6 +132,39 @@ impl DmabufHandler for AnvilState<UdevData> {
 #[cfg(feature = "egl")]
 delegate_dmabuf!(AnvilState<UdevData>);
 
+impl UdevData {
+    /// Enables or disables variable refresh rate on the given output, starting with the next frame
+    pub fn set_vrr(&self, output: &Output, vrr: bool) {
+        if let Some(id) = output.user_data().get::<UdevOutputId>() {
+            if let Some(gpu) = self.backends.get(&id.device_id) {
+                let surfaces = gpu.surfaces.borrow();
+                if let Some(surface) = surfaces.get(&id.crtc) {
+                    if let Err(err) = surface.borrow().surface.use_vrr(vrr) {
+                        warn!(self.logger, "Failed to set vrr on {}: {}", output.name(), err);
+                    } else {
+                        info!(self.logger, "Set vrr on {} to {}", output.name(), vrr);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns true, if variable refresh rate is enabled or pending for the given output
+    pub fn vrr(&self, output: &Output) -> bool {
+        output
+            .user_data()
+            .get::<UdevOutputId>()
+            .and_then(|id| self.backends.get(&id.device_id).map(|gpu| (gpu, id.crtc)))
+            .and_then(|(gpu, crtc)| {
+                gpu.surfaces
+                    .borrow()
+                    .get(&crtc)
+                    .map(|surface| surface.borrow().surface.pending_vrr())
+            })
+            .unwrap_or(false)
+    }
+}
+
 impl Backend for UdevData {
     fn seat_name(&self) -> String {
         self.session.seat()
@@ -343,6 +378,7 @@ struct SurfaceData {
     surface: RenderSurface,
     global: Option<GlobalId>,
     damage_tracked_renderer: DamageTrackedRenderer,
+    frame_clock: FrameClock,
     #[cfg(feature = "debug")]
     fps: fps_ticker::Fps,
     #[cfg(feature = "debug")]
@@ -360,6 +396,7 @@ impl Drop for SurfaceData {
 struct BackendData {
     _restart_token: SignalToken,
     surfaces: Rc<RefCell<HashMap<crtc::Handle, Rc<RefCell<SurfaceData>>>>>,
+    scanner: ConnectorScanner,
     gbm: Rc<RefCell<GbmDevice<SessionFd>>>,
     registration_token: RegistrationToken,
     event_dispatcher: Dispatcher<'static, DrmDevice<SessionFd>, CalloopData<UdevData>>,
@@ -370,25 +407,24 @@ fn scan_connectors(
     device_id: DrmNode,
     device: &DrmDevice<SessionFd>,
     gbm: &Rc<RefCell<GbmDevice<SessionFd>>>,
+    scanner: &mut ConnectorScanner,
+    backends: &mut HashMap<crtc::Handle, Rc<RefCell<SurfaceData>>>,
     display: &mut Display<AnvilState<UdevData>>,
     space: &mut Space<Window>,
     #[cfg(feature = "debug")] fps_texture: &MultiTexture,
     signaler: &Signaler<SessionSignal>,
     logger: &::slog::Logger,
-) -> HashMap<crtc::Handle, Rc<RefCell<SurfaceData>>> {
-    // Get a set of all modesetting resource handles (excluding planes):
-    let res_handles = device.resource_handles().unwrap();
-
-    // Find all connected output ports.
-    let connector_infos: Vec<ConnectorInfo> = res_handles
-        .connectors()
-        .iter()
-        .map(|conn| device.get_connector(*conn, true).unwrap())
-        .filter(|conn| conn.state() == ConnectorState::Connected)
-        .inspect(|conn| info!(logger, "Connected: {:?}", conn.interface()))
-        .collect();
-
-    let mut backends = HashMap::new();
+) -> Vec<crtc::Handle> {
+    let events = match scanner.scan(device) {
+        Ok(events) => events,
+        Err(err) => {
+            warn!(logger, "Failed to scan connectors: {}", err);
+            return Vec::new();
+        }
+    };
+    if events.is_empty() {
+        return Vec::new();
+    }
 
     let (render_node, formats) = {
         let display = unsafe { EGLDisplay::new(&*gbm.borrow(), logger.clone()).unwrap() };
@@ -397,127 +433,167 @@ fn scan_connectors(
             .and_then(|x| x.try_get_render_node().ok().flatten())
         {
             Some(node) => node,
-            None => return HashMap::new(),
+            None => return Vec::new(),
         };
         let context = EGLContext::new(&display, logger.clone()).unwrap();
         (node, context.dmabuf_render_formats().clone())
     };
 
-    // very naive way of finding good crtc/encoder/connector combinations. This problem is np-complete
-    for connector_info in connector_infos {
-        let encoder_infos = connector_info
-            .encoders()
-            .iter()
-            .flat_map(|encoder_handle| device.get_encoder(*encoder_handle))
-            .collect::<Vec<EncoderInfo>>();
-
-        let crtcs = encoder_infos
-            .iter()
-            .flat_map(|encoder_info| res_handles.filter_crtcs(encoder_info.possible_crtcs()));
+    let mut new_surfaces = Vec::new();
+    for event in events {
+        let (connector_info, crtc) = match event {
+            ConnectorEvent::Disconnected { connector, crtc } => {
+                info!(logger, "Disconnected: {:?}", connector.interface());
+                if let Some(crtc) = crtc {
+                    remove_output(device_id, crtc, backends, space);
+                }
+                continue;
+            }
+            ConnectorEvent::Changed { connector, crtc } => {
+                info!(logger, "Changed: {:?}", connector.interface());
+                // the crtc is kept, unless the connector did not have one before
+                if let Some(crtc) = crtc {
+                    remove_output(device_id, crtc, backends, space);
+                }
+                (connector, crtc)
+            }
+            ConnectorEvent::Connected { connector, crtc } => {
+                info!(logger, "Connected: {:?}", connector.interface());
+                (connector, crtc)
+            }
+        };
+        let crtc = match crtc {
+            Some(crtc) => crtc,
+            None => {
+                warn!(
+                    logger,
+                    "No free crtc for connector {:?}-{}",
+                    connector_info.interface(),
+                    connector_info.interface_id()
+                );
+                continue;
+            }
+        };
 
-        for crtc in crtcs {
-            // Skip CRTCs used by previous connectors.
-            let entry = match backends.entry(crtc) {
-                Entry::Vacant(entry) => entry,
-                Entry::Occupied(_) => continue,
-            };
+        info!(
+            logger,
+            "Trying to setup connector {:?}-{} with crtc {:?}",
+            connector_info.interface(),
+            connector_info.interface_id(),
+            crtc,
+        );
 
-            info!(
-                logger,
-                "Trying to setup connector {:?}-{} with crtc {:?}",
-                connector_info.interface(),
-                connector_info.interface_id(),
-                crtc,
-            );
+        let mode = connector_info.modes()[0];
+        let frame_clock = FrameClock::from_mode(&mode);
+        let mut surface = match device.create_surface(crtc, mode, &[connector_info.handle()]) {
+            Ok(surface) => surface,
+            Err(err) => {
+                warn!(logger, "Failed to create drm surface: {}", err);
+                continue;
+            }
+        };
+        surface.link(signaler.clone());
 
-            let mode = connector_info.modes()[0];
-            let mut surface = match device.create_surface(crtc, mode, &[connector_info.handle()]) {
-                Ok(surface) => surface,
-                Err(err) => {
-                    warn!(logger, "Failed to create drm surface: {}", err);
-                    continue;
-                }
-            };
-            surface.link(signaler.clone());
-
-            let gbm_surface =
-                match GbmBufferedSurface::new(surface, gbm.clone(), formats.clone(), logger.clone()) {
-                    Ok(renderer) => renderer,
-                    Err(err) => {
-                        warn!(logger, "Failed to create rendering surface: {}", err);
-                        continue;
-                    }
-                };
+        let gbm_surface = match GbmBufferedSurface::new(surface, gbm.clone(), formats.clone(), logger.clone())
+        {
+            Ok(renderer) => renderer,
+            Err(err) => {
+                warn!(logger, "Failed to create rendering surface: {}", err);
+                continue;
+            }
+        };
 
-            let size = mode.size();
-            let mode = Mode {
-                size: (size.0 as i32, size.1 as i32).into(),
-                refresh: mode.vrefresh() as i32 * 1000,
-            };
+        let size = mode.size();
+        let mode = Mode {
+            size: (size.0 as i32, size.1 as i32).into(),
+            refresh: mode.vrefresh() as i32 * 1000,
+        };
 
-            let interface_short_name = match connector_info.interface() {
-                drm::control::connector::Interface::DVII => Cow::Borrowed("DVI-I"),
-                drm::control::connector::Interface::DVID => Cow::Borrowed("DVI-D"),
-                drm::control::connector::Interface::DVIA => Cow::Borrowed("DVI-A"),
-                drm::control::connector::Interface::SVideo => Cow::Borrowed("S-VIDEO"),
-                drm::control::connector::Interface::DisplayPort => Cow::Borrowed("DP"),
-                drm::control::connector::Interface::HDMIA => Cow::Borrowed("HDMI-A"),
-                drm::control::connector::Interface::HDMIB => Cow::Borrowed("HDMI-B"),
-                drm::control::connector::Interface::EmbeddedDisplayPort => Cow::Borrowed("eDP"),
-                other => Cow::Owned(format!("{:?}", other)),
-            };
+        let interface_short_name = match connector_info.interface() {
+            drm::control::connector::Interface::DVII => Cow::Borrowed("DVI-I"),
+            drm::control::connector::Interface::DVID => Cow::Borrowed("DVI-D"),
+            drm::control::connector::Interface::DVIA => Cow::Borrowed("DVI-A"),
+            drm::control::connector::Interface::SVideo => Cow::Borrowed("S-VIDEO"),
+            drm::control::connector::Interface::DisplayPort => Cow::Borrowed("DP"),
+            drm::control::connector::Interface::HDMIA => Cow::Borrowed("HDMI-A"),
+            drm::control::connector::Interface::HDMIB => Cow::Borrowed("HDMI-B"),
+            drm::control::connector::Interface::EmbeddedDisplayPort => Cow::Borrowed("eDP"),
+            other => Cow::Owned(format!("{:?}", other)),
+        };
 
-            let output_name = format!("{}-{}", interface_short_name, connector_info.interface_id());
+        let output_name = format!("{}-{}", interface_short_name, connector_info.interface_id());
 
-            let (phys_w, phys_h) = connector_info.size().unwrap_or((0, 0));
-            let output = Output::new(
-                output_name,
+        let physical_properties = match Edid::from_connector(device, connector_info.handle()) {
+            Ok(edid) => edid.physical_properties(Subpixel::Unknown),
+            Err(err) => {
+                warn!(logger, "Failed to read EDID of {}: {}", output_name, err);
+                let (phys_w, phys_h) = connector_info.size().unwrap_or((0, 0));
                 PhysicalProperties {
                     size: (phys_w as i32, phys_h as i32).into(),
                     subpixel: Subpixel::Unknown,
                     make: "Smithay".into(),
                     model: "Generic DRM".into(),
-                },
-                None,
-            );
-            let global = output.create_global::<AnvilState<UdevData>>(&display.handle());
-            let position = (
-                space
-                    .outputs()
-                    .fold(0, |acc, o| acc + space.output_geometry(o).unwrap().size.w),
-                0,
-            )
-                .into();
-            output.change_current_state(Some(mode), None, None, Some(position));
-            output.set_preferred(mode);
-            space.map_output(&output, position);
-
-            output
-                .user_data()
-                .insert_if_missing(|| UdevOutputId { crtc, device_id });
+                }
+            }
+        };
+        let output = Output::new(output_name, physical_properties, None);
+        let global = output.create_global::<AnvilState<UdevData>>(&display.handle());
+        let position = (
+            space
+                .outputs()
+                .fold(0, |acc, o| acc + space.output_geometry(o).unwrap().size.w),
+            0,
+        )
+            .into();
+        output.change_current_state(Some(mode), None, None, Some(position));
+        output.set_preferred(mode);
+        space.map_output(&output, position);
 
-            let damage_tracked_renderer = DamageTrackedRenderer::from_output(&output);
-            #[cfg(feature = "debug")]
-            let fps_element = FpsElement::new(fps_texture.clone());
+        output
+            .user_data()
+            .insert_if_missing(|| UdevOutputId { crtc, device_id });
+
+        let damage_tracked_renderer = DamageTrackedRenderer::from_output(&output);
+        #[cfg(feature = "debug")]
+        let fps_element = FpsElement::new(fps_texture.clone());
 
-            entry.insert(Rc::new(RefCell::new(SurfaceData {
+        backends.insert(
+            crtc,
+            Rc::new(RefCell::new(SurfaceData {
                 dh: display.handle(),
                 device_id,
                 render_node,
                 surface: gbm_surface,
                 global: Some(global),
                 damage_tracked_renderer,
+                frame_clock,
                 #[cfg(feature = "debug")]
                 fps: fps_ticker::Fps::default(),
                 #[cfg(feature = "debug")]
                 fps_element,
-            })));
+            })),
+        );
 
-            break;
-        }
+        new_surfaces.push(crtc);
     }
 
-    backends
+    new_surfaces
+}
+
+fn remove_output(
+    device_id: DrmNode,
+    crtc: crtc::Handle,
+    backends: &mut HashMap<crtc::Handle, Rc<RefCell<SurfaceData>>>,
+    space: &mut Space<Window>,
+) {
+    backends.remove(&crtc);
+    let output = space
+        .outputs()
+        .find(|o| o.user_data().get::<UdevOutputId>() == Some(&UdevOutputId { device_id, crtc }))
+        .cloned();
+    if let Some(output) = output {
+        space.unmap_output(&output);
+    }
 }
 
 impl AnvilState<UdevData> {
@@ -558,17 +634,22 @@ impl AnvilState<UdevData> {
                 return;
             }
         };
-        let backends = Rc::new(RefCell::new(scan_connectors(
+        let mut scanner = ConnectorScanner::new();
+        let mut backends = HashMap::new();
+        scan_connectors(
             node,
             &device,
             &gbm,
+            &mut scanner,
+            &mut backends,
             display,
             &mut self.space,
             #[cfg(feature = "debug")]
             &self.backend_data.fps_texture,
             &self.backend_data.signaler,
             &self.log,
-        )));
+        );
+        let backends = Rc::new(RefCell::new(backends));
 
         let handle = self.handle.clone();
         let restart_token = self.backend_data.signaler.register(move |signal| match signal {
@@ -580,12 +661,15 @@ impl AnvilState<UdevData> {
 
         device.link(self.backend_data.signaler.clone());
         let event_dispatcher =
-            Dispatcher::new(device, move |event, _, data: &mut CalloopData<_>| match event {
-                DrmEvent::VBlank(crtc) => data.state.render(node, Some(crtc)),
-                DrmEvent::Error(error) => {
-                    error!(data.state.log, "{:?}", error);
-                }
-            });
+            Dispatcher::new(
+                device,
+                move |event, metadata, data: &mut CalloopData<_>| match event {
+                    DrmEvent::VBlank(crtc) => data.state.frame_finish(node, crtc, metadata),
+                    DrmEvent::Error(error) => {
+                        error!(data.state.log, "{:?}", error);
+                    }
+                },
+            );
         let registration_token = self.handle.register_dispatcher(event_dispatcher.clone()).unwrap();
 
         for backend in backends.borrow_mut().values() {
@@ -606,6 +690,7 @@ impl AnvilState<UdevData> {
                 registration_token,
                 event_dispatcher,
                 surfaces: backends,
+                scanner,
                 gbm,
             },
         );
@@ -617,35 +702,19 @@ impl AnvilState<UdevData> {
             None => return, // we already logged a warning on device_added
         };
 
-        //quick and dirty, just re-init all backends
         if let Some(ref mut backend_data) = self.backend_data.backends.get_mut(&node) {
             let logger = self.log.clone();
             let loop_handle = self.handle.clone();
             let signaler = self.backend_data.signaler.clone();
 
-            // scan_connectors will recreate the outputs (and sadly also reset the scales)
-            for output in self
-                .space
-                .outputs()
-                .filter(|o| {
-                    o.user_data()
-                        .get::<UdevOutputId>()
-                        .map(|id| id.device_id == node)
-                        .unwrap_or(false)
-                })
-                .cloned()
-                .collect::<Vec<_>>()
-                .into_iter()
-            {
-                self.space.unmap_output(&output);
-            }
-
             let source = backend_data.event_dispatcher.as_source_mut();
             let mut backends = backend_data.surfaces.borrow_mut();
-            *backends = scan_connectors(
+            let new_surfaces = scan_connectors(
                 node,
                 &source,
                 &backend_data.gbm,
+                &mut backend_data.scanner,
+                &mut backends,
                 display,
                 &mut self.space,
                 #[cfg(feature = "debug")]
@@ -657,10 +726,15 @@ impl AnvilState<UdevData> {
             // fixup window coordinates
             crate::shell::fixup_positions(&mut self.space);
 
-            for surface in backends.values() {
+            for crtc in new_surfaces {
                 let logger = logger.clone();
                 // render first frame
-                schedule_initial_render(&mut self.backend_data.gpus, surface.clone(), &loop_handle, logger);
+                schedule_initial_render(
+                    &mut self.backend_data.gpus,
+                    backends[&crtc].clone(),
+                    &loop_handle,
+                    logger,
+                );
             }
         }
     }
@@ -700,6 +774,38 @@ impl AnvilState<UdevData> {
         }
     }
 
+    fn frame_finish(&mut self, dev_id: DrmNode, crtc: crtc::Handle, metadata: &mut Option<DrmEventMetadata>) {
+        let device_backend = match self.backend_data.backends.get(&dev_id) {
+            Some(backend) => backend,
+            None => {
+                error!(
+                    self.log,
+                    "Trying to finish frame on non-existent backend {}", dev_id
+                );
+                return;
+            }
+        };
+        let surface = match device_backend.surfaces.borrow().get(&crtc) {
+            Some(surface) => surface.clone(),
+            None => return,
+        };
+
+        // delay rendering until shortly before the next vblank to minimize latency
+        let timer = {
+            let mut surface = surface.borrow_mut();
+            if let Some(metadata) = metadata {
+                surface.frame_clock.presented(metadata);
+            }
+            surface.frame_clock.render_timer(RENDER_MARGIN)
+        };
+        self.handle
+            .insert_source(timer, move |_, _, data| {
+                data.state.render(dev_id, Some(crtc));
+                TimeoutAction::Drop
+            })
+            .expect("failed to schedule frame timer");
+    }
+
     // If crtc is `Some()`, render it, else render all crtcs
     fn render(&mut self, dev_id: DrmNode, crtc: Option<crtc::Handle>) {
         let device_backend = match self.backend_data.backends.get_mut(&dev_id) {
@@ -809,9 +915,10 @@ impl AnvilState<UdevData> {
             };
 
             if reschedule {
-                let timer = Timer::from_duration(Duration::from_millis(
+                let refresh_interval = surface.borrow().frame_clock.refresh_interval();
+                let timer = Timer::from_duration(refresh_interval.unwrap_or(Duration::from_millis(
                     1000 /*a seconds*/ / 60, /*refresh rate*/
-                ));
+                )));
                 self.handle
                     .insert_source(timer, move |_, _, data| {
                         data.state.render(dev_id, Some(crtc));
@@ -942,9 +1049,23 @@ fn render_surface<'a>(
         _ => unreachable!(),
     }) {
         Ok(true) => {
+            // honor the presentation hint of a fullscreen client
+            let allow_tearing = output
+                .user_data()
+                .get::<FullscreenSurface>()
+                .and_then(|f| f.get())
+                .map(|window| {
+                    compositor::with_states(window.toplevel().wl_surface(), |states| {
+                        states
+                            .cached_state
+                            .current::<TearingControlSurfaceCachedState>()
+                            .is_async()
+                    })
+                })
+                .unwrap_or(false);
             surface
                 .surface
-                .queue_buffer()
+                .queue_buffer(allow_tearing)
                 .map_err(Into::<SwapBuffersError>::into)?;
             Ok(true)
         }
@@ -995,7 +1116,7 @@ fn initial_render(
         })
         .map_err(Into::<SwapBuffersError>::into)
         .and_then(|x| x.map_err(Into::<SwapBuffersError>::into))?;
-    surface.queue_buffer()?;
+    surface.queue_buffer(false)?;
     surface.reset_buffers();
     Ok(())
 }
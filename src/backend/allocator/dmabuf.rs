@@ -9,12 +9,17 @@
 //!
 //! This can be especially useful in resources where other parts of the stack should decide upon
 //! the lifetime of the buffer. E.g. when you are only caching associated resources for a dmabuf.
+//!
+//! The planes of linear dmabufs can be accessed from the cpu via [`Dmabuf::map_plane`].
 
 use super::{Buffer, Format, Fourcc, Modifier};
 use crate::utils::{Buffer as BufferCoords, Size};
 use io_lifetimes::{AsFd, BorrowedFd, OwnedFd};
+use nix::sys::mman;
 use std::hash::{Hash, Hasher};
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::sync::{Arc, Weak};
+use std::{ptr, slice};
 
 /// Maximum amount of planes this implementation supports
 pub const MAX_PLANES: usize = 4;
@@ -216,6 +221,187 @@ impl Dmabuf {
     pub fn weak(&self) -> WeakDmabuf {
         WeakDmabuf(Arc::downgrade(&self.0))
     }
+
+    /// Maps a plane of this buffer into memory to access it from the cpu
+    ///
+    /// The mapping starts at the offset of the plane and spans until the end of the underlying buffer,
+    /// rows of the plane are [`stride`](DmabufMapping::stride) bytes apart.
+    /// Access is synchronized with other users of the buffer (e.g. the gpu) for the lifetime of the returned mapping,
+    /// so it should be dropped as soon as possible.
+    ///
+    /// Only buffers without a vendor-specific modifier can be mapped, as the memory layout
+    /// of the others is unknown.
+    pub fn map_plane(
+        &self,
+        plane: usize,
+        mode: DmabufMappingMode,
+    ) -> Result<DmabufMapping<'_>, DmabufMappingError> {
+        if self.has_modifier() {
+            return Err(DmabufMappingError::UnsupportedModifier(self.0.planes[0].modifier));
+        }
+        let plane_info = self
+            .0
+            .planes
+            .get(plane)
+            .ok_or(DmabufMappingError::InvalidPlane(plane))?;
+        let fd = plane_info.fd.as_raw_fd();
+
+        let len = nix::unistd::lseek(fd, 0, nix::unistd::Whence::SeekEnd).map_err(DmabufMappingError::Map)?
+            as usize;
+        let offset = plane_info.offset as usize;
+        if offset >= len {
+            return Err(DmabufMappingError::InvalidPlane(plane));
+        }
+
+        let prot = match mode {
+            DmabufMappingMode::Read => mman::ProtFlags::PROT_READ,
+            DmabufMappingMode::Write | DmabufMappingMode::ReadWrite => {
+                mman::ProtFlags::PROT_READ | mman::ProtFlags::PROT_WRITE
+            }
+        };
+        // mmap requires a page-aligned offset, so we map the whole buffer.
+        let ptr = unsafe { mman::mmap(ptr::null_mut(), len, prot, mman::MapFlags::MAP_SHARED, fd, 0) }
+            .map_err(DmabufMappingError::Map)? as *mut u8;
+
+        if let Err(err) = dma_buf_sync(fd, mode.sync_flags() | DMA_BUF_SYNC_START) {
+            let _ = unsafe { mman::munmap(ptr as *mut _, len) };
+            return Err(DmabufMappingError::Sync(err));
+        }
+
+        Ok(DmabufMapping {
+            _dmabuf: self,
+            fd,
+            ptr,
+            len,
+            offset,
+            stride: plane_info.stride,
+            mode,
+        })
+    }
+}
+
+/// Access mode of a [`DmabufMapping`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DmabufMappingMode {
+    /// The mapping is only read from
+    Read,
+    /// The mapping is only written to
+    ///
+    /// Reading the mapping might return stale content.
+    Write,
+    /// The mapping is read from and written to
+    ReadWrite,
+}
+
+impl DmabufMappingMode {
+    fn sync_flags(&self) -> u64 {
+        match self {
+            DmabufMappingMode::Read => DMA_BUF_SYNC_READ,
+            DmabufMappingMode::Write => DMA_BUF_SYNC_WRITE,
+            DmabufMappingMode::ReadWrite => DMA_BUF_SYNC_READ | DMA_BUF_SYNC_WRITE,
+        }
+    }
+}
+
+/// Errors that may happen when mapping a [`Dmabuf`]
+#[derive(Debug, thiserror::Error)]
+pub enum DmabufMappingError {
+    /// The buffer has no plane with the given index
+    #[error("The buffer has no plane with index {0}")]
+    InvalidPlane(usize),
+    /// The buffer uses a modifier with an unknown memory layout
+    #[error("Buffers with modifier {0:?} cannot be mapped")]
+    UnsupportedModifier(Modifier),
+    /// Mapping the buffer failed
+    #[error("Failed to map the buffer: {0}")]
+    Map(#[source] nix::Error),
+    /// Synchronizing access to the buffer failed
+    #[error("Failed to synchronize access to the buffer: {0}")]
+    Sync(#[source] nix::Error),
+}
+
+/// Cpu mapping of a plane of a [`Dmabuf`]
+///
+/// Access to the buffer is synchronized until the mapping is dropped.
+#[derive(Debug)]
+pub struct DmabufMapping<'a> {
+    _dmabuf: &'a Dmabuf,
+    fd: RawFd,
+    ptr: *mut u8,
+    len: usize,
+    offset: usize,
+    stride: u32,
+    mode: DmabufMappingMode,
+}
+
+impl<'a> DmabufMapping<'a> {
+    /// Access mode of this mapping
+    pub fn mode(&self) -> DmabufMappingMode {
+        self.mode
+    }
+
+    /// Distance in bytes between the rows of the mapped plane
+    pub fn stride(&self) -> u32 {
+        self.stride
+    }
+
+    /// Content of the mapped plane
+    pub fn as_slice(&self) -> &[u8] {
+        // SAFETY: the mapping stays valid for the lifetime of self and offset < len
+        unsafe { slice::from_raw_parts(self.ptr.add(self.offset), self.len - self.offset) }
+    }
+
+    /// Mutable content of the mapped plane
+    ///
+    /// Returns `None` for mappings created with [`DmabufMappingMode::Read`].
+    pub fn as_mut_slice(&mut self) -> Option<&mut [u8]> {
+        if self.mode == DmabufMappingMode::Read {
+            return None;
+        }
+        // SAFETY: the mapping stays valid for the lifetime of self, is writable and offset < len
+        Some(unsafe { slice::from_raw_parts_mut(self.ptr.add(self.offset), self.len - self.offset) })
+    }
+}
+
+impl<'a> Drop for DmabufMapping<'a> {
+    fn drop(&mut self) {
+        let _ = dma_buf_sync(self.fd, self.mode.sync_flags() | DMA_BUF_SYNC_END);
+        let _ = unsafe { mman::munmap(self.ptr as *mut _, self.len) };
+    }
+}
+
+const DMA_BUF_SYNC_READ: u64 = 1 << 0;
+const DMA_BUF_SYNC_WRITE: u64 = 1 << 1;
+const DMA_BUF_SYNC_START: u64 = 0 << 2;
+const DMA_BUF_SYNC_END: u64 = 1 << 2;
+
+mod ioctl {
+    #[repr(C)]
+    pub struct DmaBufSync {
+        pub flags: u64,
+    }
+
+    nix::ioctl_write_ptr!(dma_buf_ioctl_sync, b'b', 0, DmaBufSync);
+}
+
+#[cfg(test)]
+thread_local! {
+    // flags of all sync ioctls issued by the current thread, to check that they are paired
+    static SYNC_LOG: std::cell::RefCell<Vec<u64>> = std::cell::RefCell::new(Vec::new());
+}
+
+fn dma_buf_sync(fd: RawFd, flags: u64) -> Result<(), nix::Error> {
+    #[cfg(test)]
+    SYNC_LOG.with(|log| log.borrow_mut().push(flags));
+
+    let sync = ioctl::DmaBufSync { flags };
+    loop {
+        // the ioctl has to be restarted, if it was interrupted
+        match unsafe { ioctl::dma_buf_ioctl_sync(fd, &sync) } {
+            Err(nix::Error::EINTR) | Err(nix::Error::EAGAIN) => continue,
+            result => return result.map(|_| ()),
+        }
+    }
 }
 
 impl WeakDmabuf {
@@ -248,3 +434,104 @@ impl AsDmabuf for Dmabuf {
         Ok(self.clone())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Dmabuf, DmabufFlags, DmabufMappingError, DmabufMappingMode, DMA_BUF_SYNC_END, DMA_BUF_SYNC_READ,
+        DMA_BUF_SYNC_START, DMA_BUF_SYNC_WRITE, SYNC_LOG,
+    };
+    use crate::backend::allocator::{udmabuf::UdmabufAllocator, Allocator, Fourcc, Modifier};
+    use io_lifetimes::OwnedFd;
+    use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+    use std::ffi::CStr;
+    use std::os::unix::io::FromRawFd;
+
+    fn take_sync_log() -> Vec<u64> {
+        SYNC_LOG.with(|log| std::mem::take(&mut *log.borrow_mut()))
+    }
+
+    // a memfd is no dmabuf, but good enough to test everything up to the sync ioctl
+    fn memfd_dmabuf(size: usize, offset: u32, modifier: Modifier) -> Dmabuf {
+        let fd = memfd_create(
+            CStr::from_bytes_with_nul(b"smithay-test\0").unwrap(),
+            MemFdCreateFlag::MFD_CLOEXEC,
+        )
+        .unwrap();
+        nix::unistd::ftruncate(fd, size as nix::libc::off_t).unwrap();
+        let mut builder = Dmabuf::builder((16, 16), Fourcc::Argb8888, DmabufFlags::empty());
+        builder.add_plane(unsafe { OwnedFd::from_raw_fd(fd) }, 0, offset, 64, modifier);
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn map_invalid_planes() {
+        let dmabuf = memfd_dmabuf(16 * 64, 0, Modifier::Linear);
+        assert!(matches!(
+            dmabuf.map_plane(1, DmabufMappingMode::Read),
+            Err(DmabufMappingError::InvalidPlane(1))
+        ));
+        let dmabuf = memfd_dmabuf(16 * 64, 16 * 64, Modifier::Linear);
+        assert!(matches!(
+            dmabuf.map_plane(0, DmabufMappingMode::Read),
+            Err(DmabufMappingError::InvalidPlane(0))
+        ));
+        let dmabuf = memfd_dmabuf(16 * 64, 0, Modifier::I915_x_tiled);
+        assert!(matches!(
+            dmabuf.map_plane(0, DmabufMappingMode::Read),
+            Err(DmabufMappingError::UnsupportedModifier(Modifier::I915_x_tiled))
+        ));
+        assert!(take_sync_log().is_empty());
+    }
+
+    #[test]
+    fn failed_sync_is_not_ended() {
+        let dmabuf = memfd_dmabuf(16 * 64, 0, Modifier::Linear);
+        take_sync_log();
+        assert!(matches!(
+            dmabuf.map_plane(0, DmabufMappingMode::Read),
+            Err(DmabufMappingError::Sync(_))
+        ));
+        assert_eq!(take_sync_log(), vec![DMA_BUF_SYNC_READ | DMA_BUF_SYNC_START]);
+    }
+
+    #[test]
+    fn mapping_is_synced_until_dropped() {
+        let mut allocator = match UdmabufAllocator::new() {
+            Ok(allocator) => allocator,
+            Err(err) => {
+                eprintln!("skipping test, /dev/udmabuf is not available: {}", err);
+                return;
+            }
+        };
+        let dmabuf = allocator
+            .create_buffer(16, 16, Fourcc::Argb8888, &[Modifier::Linear])
+            .unwrap();
+        take_sync_log();
+
+        {
+            let mut mapping = dmabuf.map_plane(0, DmabufMappingMode::ReadWrite).unwrap();
+            assert_eq!(
+                take_sync_log(),
+                vec![DMA_BUF_SYNC_READ | DMA_BUF_SYNC_WRITE | DMA_BUF_SYNC_START]
+            );
+            mapping.as_mut_slice().unwrap()[..4].copy_from_slice(&[1, 2, 3, 4]);
+        }
+        assert_eq!(
+            take_sync_log(),
+            vec![DMA_BUF_SYNC_READ | DMA_BUF_SYNC_WRITE | DMA_BUF_SYNC_END]
+        );
+
+        let mut mapping = dmabuf.map_plane(0, DmabufMappingMode::Read).unwrap();
+        assert_eq!(&mapping.as_slice()[..4], &[1, 2, 3, 4]);
+        assert!(mapping.as_mut_slice().is_none());
+        std::mem::drop(mapping);
+        assert_eq!(
+            take_sync_log(),
+            vec![
+                DMA_BUF_SYNC_READ | DMA_BUF_SYNC_START,
+                DMA_BUF_SYNC_READ | DMA_BUF_SYNC_END
+            ]
+        );
+    }
+}
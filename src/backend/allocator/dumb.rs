@@ -37,34 +37,88 @@ impl<A: AsRawFd + 'static> Allocator<DumbBuffer<A>> for DrmDevice<A> {
         fourcc: Fourcc,
         modifiers: &[Modifier],
     ) -> Result<DumbBuffer<A>, Self::Error> {
-        // dumb buffers are always linear
-        if modifiers
-            .iter()
-            .all(|&x| x != Modifier::Invalid && x != Modifier::Linear)
-        {
-            return Err(drm::SystemError::InvalidArgument);
+        create_dumb_buffer(&device_fd(&*self.internal), width, height, fourcc, modifiers)
+    }
+}
+
+/// Allocator for [`DumbBuffer`]s independent of the lifetime of the [`DrmDevice`] it was created from
+///
+/// Unlike the [`DrmDevice`] itself, this allocator can be moved into a [`Swapchain`](super::Swapchain).
+pub struct DumbAllocator<A: AsRawFd + 'static> {
+    fd: Arc<FdWrapper<A>>,
+}
+
+impl<A: AsRawFd + 'static> fmt::Debug for DumbAllocator<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DumbAllocator").finish_non_exhaustive()
+    }
+}
+
+impl<A: AsRawFd + 'static> DumbAllocator<A> {
+    /// Creates a new allocator for the given device
+    pub fn new(device: &DrmDevice<A>) -> DumbAllocator<A> {
+        DumbAllocator::from_internal(&*device.internal)
+    }
+
+    pub(crate) fn from_internal(device: &DrmDeviceInternal<A>) -> DumbAllocator<A> {
+        DumbAllocator {
+            fd: device_fd(device),
         }
+    }
+}
+
+impl<A: AsRawFd + 'static> Allocator<DumbBuffer<A>> for DumbAllocator<A> {
+    type Error = drm::SystemError;
 
-        let handle = self.create_dumb_buffer(
-            (width, height),
-            fourcc,
-            get_bpp(fourcc).ok_or(drm::SystemError::InvalidArgument)? as u32,
-        )?;
-
-        Ok(DumbBuffer {
-            fd: match &*self.internal {
-                DrmDeviceInternal::Atomic(dev) => dev.fd.clone(),
-                DrmDeviceInternal::Legacy(dev) => dev.fd.clone(),
-            },
-            handle,
-            format: Format {
-                code: fourcc,
-                modifier: Modifier::Linear,
-            },
-        })
+    fn create_buffer(
+        &mut self,
+        width: u32,
+        height: u32,
+        fourcc: Fourcc,
+        modifiers: &[Modifier],
+    ) -> Result<DumbBuffer<A>, Self::Error> {
+        create_dumb_buffer(&self.fd, width, height, fourcc, modifiers)
     }
 }
 
+fn device_fd<A: AsRawFd + 'static>(device: &DrmDeviceInternal<A>) -> Arc<FdWrapper<A>> {
+    match device {
+        DrmDeviceInternal::Atomic(dev) => dev.fd.clone(),
+        DrmDeviceInternal::Legacy(dev) => dev.fd.clone(),
+    }
+}
+
+fn create_dumb_buffer<A: AsRawFd + 'static>(
+    fd: &Arc<FdWrapper<A>>,
+    width: u32,
+    height: u32,
+    fourcc: Fourcc,
+    modifiers: &[Modifier],
+) -> Result<DumbBuffer<A>, drm::SystemError> {
+    // dumb buffers are always linear
+    if modifiers
+        .iter()
+        .all(|&x| x != Modifier::Invalid && x != Modifier::Linear)
+    {
+        return Err(drm::SystemError::InvalidArgument);
+    }
+
+    let handle = fd.create_dumb_buffer(
+        (width, height),
+        fourcc,
+        get_bpp(fourcc).ok_or(drm::SystemError::InvalidArgument)? as u32,
+    )?;
+
+    Ok(DumbBuffer {
+        fd: fd.clone(),
+        handle,
+        format: Format {
+            code: fourcc,
+            modifier: Modifier::Linear,
+        },
+    })
+}
+
 impl<A: AsRawFd + 'static> Buffer for DumbBuffer<A> {
     fn size(&self) -> Size<i32, BufferCoords> {
         let (w, h) = self.handle.size();
@@ -1,6 +1,7 @@
 //! Format info tables for DRM formats.
 //!
-//! This module provides three functions, [`get_opaque`], [`has_alpha`] and [`get_bpp`].
+//! This module provides four functions, [`get_opaque`], [`has_alpha`], [`get_bpp`] and [`get_depth`],
+//! as well as the [`FormatNegotiator`] to select formats supported by multiple parties.
 //!
 //! [`get_opaque`] returns the opaque alternative of a DRM format with an alpha channel.
 //!
@@ -36,6 +37,34 @@
 //! assert_eq!(get_depth(Fourcc::Argb8888), Some(32));
 //! assert_eq!(get_depth(Fourcc::Xrgb8888), Some(24));
 //! ```
+//!
+//! [`FormatNegotiator`] intersects the formats supported by e.g. a drm plane, a renderer and an allocator
+//! and returns the remaining formats ranked by preference.
+//!
+//! ```
+//! # use std::collections::HashSet;
+//! # use smithay::backend::allocator::{Format, Fourcc, Modifier};
+//! # use smithay::backend::allocator::format::FormatNegotiator;
+//! # let plane_formats: HashSet<Format> = HashSet::new();
+//! # let renderer_formats: HashSet<Format> = HashSet::new();
+//! let negotiation = FormatNegotiator::new([Fourcc::Argb8888, Fourcc::Xrgb8888])
+//!     .constraint("plane", plane_formats)
+//!     .constraint("renderer", renderer_formats)
+//!     .negotiate();
+//! match negotiation.best() {
+//!     Some(candidate) => println!("Using {} with modifiers {:?}", candidate.code, candidate.modifiers),
+//!     None => {
+//!         for rejected in &negotiation.rejected {
+//!             println!("{}", rejected);
+//!         }
+//!     }
+//! }
+//! ```
+
+use std::collections::HashSet;
+use std::fmt;
+
+use super::{Format, Fourcc, Modifier};
 
 /// Macro to generate table lookup functions for formats.
 ///
@@ -346,9 +375,211 @@ format_tables! {
     // TODO: YUV and other formats
 }
 
+/// Helper to select formats supported by multiple parties
+///
+/// Each constraint is a set of formats supported by one party (e.g. a drm plane, a renderer or a client).
+/// Negotiating returns the preferred formats supported by all constraints together with their common modifiers,
+/// see [`FormatNegotiator::negotiate`].
+#[derive(Debug, Clone)]
+pub struct FormatNegotiator {
+    preferences: Vec<Fourcc>,
+    constraints: Vec<(String, HashSet<Format>)>,
+}
+
+impl FormatNegotiator {
+    /// Creates a new negotiator for the given formats ordered by preference, starting with the most preferred format
+    pub fn new(preferences: impl IntoIterator<Item = Fourcc>) -> FormatNegotiator {
+        let mut ordered = Vec::new();
+        for code in preferences {
+            if !ordered.contains(&code) {
+                ordered.push(code);
+            }
+        }
+        FormatNegotiator {
+            preferences: ordered,
+            constraints: Vec::new(),
+        }
+    }
+
+    /// Adds a set of supported formats, all negotiated formats have to be part of
+    ///
+    /// The name is used to explain why formats were rejected.
+    pub fn constraint(mut self, name: impl Into<String>, formats: impl IntoIterator<Item = Format>) -> Self {
+        self.constraints
+            .push((name.into(), formats.into_iter().collect()));
+        self
+    }
+
+    /// Negotiates the formats supported by all constraints
+    ///
+    /// A format is a candidate, if every constraint supports at least one common modifier for it.
+    /// If there is none, but every constraint supports either implicit ([`Modifier::Invalid`])
+    /// or [`Modifier::Linear`], the format is still a candidate using implicit modifiers,
+    /// which should result in a working, likely linear, buffer.
+    ///
+    /// Without any constraints all preferred formats are candidates with an empty modifier list,
+    /// meaning the modifier is not restricted.
+    pub fn negotiate(&self) -> Negotiation {
+        let mut candidates = Vec::new();
+        let mut rejected = Vec::new();
+
+        for &code in &self.preferences {
+            let unsupported = self
+                .constraints
+                .iter()
+                .filter(|(_, formats)| !formats.iter().any(|format| format.code == code))
+                .map(|(name, _)| name.clone())
+                .collect::<Vec<_>>();
+            if !unsupported.is_empty() {
+                rejected.push(RejectedFormat {
+                    code,
+                    reason: RejectionReason::Unsupported {
+                        constraints: unsupported,
+                    },
+                });
+                continue;
+            }
+
+            let mut constraints = self.constraints.iter().map(|(_, formats)| {
+                formats
+                    .iter()
+                    .filter(|format| format.code == code)
+                    .map(|format| format.modifier)
+                    .collect::<HashSet<_>>()
+            });
+            let mut modifiers = match constraints.next() {
+                Some(first) => constraints.fold(first, |common, modifiers| {
+                    common.intersection(&modifiers).copied().collect()
+                }),
+                None => {
+                    candidates.push(FormatCandidate {
+                        code,
+                        modifiers: Vec::new(),
+                        implicit_fallback: false,
+                    });
+                    continue;
+                }
+            };
+
+            if !modifiers.is_empty() {
+                let mut modifiers = modifiers.drain().collect::<Vec<_>>();
+                // explicit modifiers first, then linear and implicit as the least efficient options
+                modifiers.sort_by_key(|modifier| match modifier {
+                    Modifier::Invalid => (2, 0),
+                    Modifier::Linear => (1, 0),
+                    x => (0, u64::from(*x)),
+                });
+                candidates.push(FormatCandidate {
+                    code,
+                    modifiers,
+                    implicit_fallback: false,
+                });
+            } else if self.constraints.iter().all(|(_, formats)| {
+                formats.iter().any(|format| {
+                    format.code == code && matches!(format.modifier, Modifier::Invalid | Modifier::Linear)
+                })
+            }) {
+                candidates.push(FormatCandidate {
+                    code,
+                    modifiers: vec![Modifier::Invalid],
+                    implicit_fallback: true,
+                });
+            } else {
+                rejected.push(RejectedFormat {
+                    code,
+                    reason: RejectionReason::NoCommonModifier,
+                });
+            }
+        }
+
+        Negotiation { candidates, rejected }
+    }
+}
+
+/// Result of a [`FormatNegotiator`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Negotiation {
+    /// Formats supported by all constraints, ordered by preference
+    pub candidates: Vec<FormatCandidate>,
+    /// Preferred formats, which are not supported by all constraints
+    pub rejected: Vec<RejectedFormat>,
+}
+
+impl Negotiation {
+    /// Returns the most preferred candidate, if any
+    pub fn best(&self) -> Option<&FormatCandidate> {
+        self.candidates.first()
+    }
+}
+
+/// Format supported by all constraints of a [`FormatNegotiator`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatCandidate {
+    /// Fourcc code of the format
+    pub code: Fourcc,
+    /// Modifiers supported by all constraints
+    ///
+    /// Explicit modifiers are sorted before [`Modifier::Linear`] and [`Modifier::Invalid`].
+    pub modifiers: Vec<Modifier>,
+    /// Whether the constraints did not share any modifier and implicit modifiers
+    /// were chosen as a fallback, see [`FormatNegotiator::negotiate`]
+    pub implicit_fallback: bool,
+}
+
+impl FormatCandidate {
+    /// Returns the formats of this candidate
+    pub fn formats(&self) -> impl Iterator<Item = Format> + '_ {
+        self.modifiers.iter().map(move |&modifier| Format {
+            code: self.code,
+            modifier,
+        })
+    }
+}
+
+/// Preferred format rejected by a [`FormatNegotiator`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectedFormat {
+    /// Fourcc code of the format
+    pub code: Fourcc,
+    /// Reason for the rejection
+    pub reason: RejectionReason,
+}
+
+impl fmt::Display for RejectedFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.reason)
+    }
+}
+
+/// Reason for rejecting a format during negotiation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RejectionReason {
+    /// The format is not supported by the named constraints
+    Unsupported {
+        /// Names of the constraints, which do not support the format
+        constraints: Vec<String>,
+    },
+    /// All constraints support the format, but without any common modifier
+    NoCommonModifier,
+}
+
+impl fmt::Display for RejectionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectionReason::Unsupported { constraints } => {
+                write!(f, "not supported by {}", constraints.join(", "))
+            }
+            RejectionReason::NoCommonModifier => write!(f, "no common modifier"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{_impl_formats, get_bpp, get_depth, get_opaque, has_alpha};
+    use super::{
+        _impl_formats, get_bpp, get_depth, get_opaque, has_alpha, FormatNegotiator, RejectionReason,
+    };
+    use crate::backend::allocator::{Format, Fourcc, Modifier};
 
     /// Tests that opaque alternatives are not the same as the variant with alpha.
     #[test]
@@ -443,4 +674,78 @@ mod tests {
             );
         }
     }
+
+    fn formats(formats: &[(Fourcc, Modifier)]) -> Vec<Format> {
+        formats
+            .iter()
+            .map(|&(code, modifier)| Format { code, modifier })
+            .collect()
+    }
+
+    #[test]
+    fn negotiate_ranks_by_preference() {
+        let negotiation = FormatNegotiator::new([Fourcc::Argb8888, Fourcc::Xrgb8888, Fourcc::Rgb565])
+            .constraint(
+                "plane",
+                formats(&[
+                    (Fourcc::Xrgb8888, Modifier::Linear),
+                    (Fourcc::Xrgb8888, Modifier::I915_x_tiled),
+                    (Fourcc::Argb8888, Modifier::Linear),
+                    (Fourcc::Rgb565, Modifier::Linear),
+                ]),
+            )
+            .constraint(
+                "renderer",
+                formats(&[
+                    (Fourcc::Xrgb8888, Modifier::Linear),
+                    (Fourcc::Xrgb8888, Modifier::I915_x_tiled),
+                    (Fourcc::Argb8888, Modifier::Linear),
+                ]),
+            )
+            .negotiate();
+
+        let codes = negotiation.candidates.iter().map(|c| c.code).collect::<Vec<_>>();
+        assert_eq!(codes, vec![Fourcc::Argb8888, Fourcc::Xrgb8888]);
+        assert_eq!(
+            negotiation.candidates[1].modifiers,
+            vec![Modifier::I915_x_tiled, Modifier::Linear]
+        );
+        assert_eq!(negotiation.rejected.len(), 1);
+        assert_eq!(negotiation.rejected[0].code, Fourcc::Rgb565);
+        assert_eq!(
+            negotiation.rejected[0].reason,
+            RejectionReason::Unsupported {
+                constraints: vec![String::from("renderer")]
+            }
+        );
+    }
+
+    #[test]
+    fn negotiate_implicit_fallback() {
+        let negotiation = FormatNegotiator::new([Fourcc::Argb8888])
+            .constraint("plane", formats(&[(Fourcc::Argb8888, Modifier::Invalid)]))
+            .constraint(
+                "renderer",
+                formats(&[
+                    (Fourcc::Argb8888, Modifier::Linear),
+                    (Fourcc::Argb8888, Modifier::I915_y_tiled),
+                ]),
+            )
+            .negotiate();
+
+        let best = negotiation.best().unwrap();
+        assert!(best.implicit_fallback);
+        assert_eq!(best.modifiers, vec![Modifier::Invalid]);
+    }
+
+    #[test]
+    fn negotiate_no_common_modifier() {
+        let negotiation = FormatNegotiator::new([Fourcc::Argb8888])
+            .constraint("plane", formats(&[(Fourcc::Argb8888, Modifier::I915_x_tiled)]))
+            .constraint("renderer", formats(&[(Fourcc::Argb8888, Modifier::I915_y_tiled)]))
+            .negotiate();
+
+        assert!(negotiation.best().is_none());
+        assert_eq!(negotiation.rejected[0].reason, RejectionReason::NoCommonModifier);
+    }
 }
@@ -6,6 +6,7 @@
 //! Allocators provided:
 //! - Dumb Buffers through [`crate::backend::drm::DrmDevice`]
 //! - Gbm Buffers through [`::gbm::Device`]
+//! - Dmabufs in system memory through [`udmabuf::UdmabufAllocator`]
 //!
 //! Buffer types supported:
 //! - [DumbBuffers](dumb::DumbBuffer)
@@ -21,6 +22,7 @@ pub mod dumb;
 pub mod format;
 #[cfg(feature = "backend_gbm")]
 pub mod gbm;
+pub mod udmabuf;
 #[cfg(feature = "backend_vulkan")]
 pub mod vulkan;
 
@@ -1,12 +1,16 @@
 use std::{
     fmt,
     ops::Deref,
+    os::unix::io::{AsRawFd, RawFd},
     sync::{
         atomic::{AtomicBool, AtomicU8, Ordering},
-        Arc,
+        Arc, Mutex,
     },
 };
 
+use io_lifetimes::OwnedFd;
+use nix::poll::{poll, PollFd, PollFlags};
+
 use crate::backend::allocator::{Allocator, Buffer, Fourcc, Modifier};
 use crate::utils::user_data::UserDataMap;
 
@@ -39,6 +43,10 @@ pub const SLOT_CAP: usize = 4;
 /// If you have associated resources for each buffer that can be reused (e.g. framebuffer `Handle`s for a `DrmDevice`),
 /// you can store then in the `Slot`s userdata field. If a buffer is re-used, its userdata is preserved for the next time
 /// it is returned by `acquire()`.
+///
+/// If a buffer is still read after dropping its `Slot`, e.g. by the display or another gpu,
+/// you can attach a sync file to the slot using [`Slot::add_release_fence`].
+/// The swapchain will not hand out the buffer again until the fence signals.
 pub struct Swapchain<A: Allocator<B>, B: Buffer> {
     /// Allocator used by the swapchain
     pub allocator: A,
@@ -74,9 +82,47 @@ struct InternalSlot<B: Buffer> {
     buffer: Option<B>,
     acquired: AtomicBool,
     age: AtomicU8,
+    release_fences: Mutex<Vec<OwnedFd>>,
     userdata: UserDataMap,
 }
 
+impl<B: Buffer> InternalSlot<B> {
+    // Returns whether the buffer is still in use by any release fence
+    fn is_busy(&self) -> bool {
+        let mut fences = self.release_fences.lock().unwrap();
+        fences.retain(|fence| !fence_signaled(fence.as_raw_fd()));
+        !fences.is_empty()
+    }
+}
+
+// Resets a slot. Release fences of slots, that are not acquired, are kept,
+// as their buffers may still be read. The slot is not handed out again until they signaled.
+fn reset_slot<B: Buffer>(slot: &mut Arc<InternalSlot<B>>, keep_buffer: bool) {
+    if let Some(internal_slot) = Arc::get_mut(slot) {
+        let release_fences = std::mem::take(internal_slot.release_fences.get_mut().unwrap());
+        *internal_slot = InternalSlot {
+            buffer: internal_slot.buffer.take().filter(|_| keep_buffer),
+            release_fences: Mutex::new(release_fences),
+            ..Default::default()
+        };
+    } else {
+        *slot = Default::default();
+    }
+}
+
+// Polls a sync file without blocking, returns whether it signaled
+fn fence_signaled(fence: RawFd) -> bool {
+    let mut fds = [PollFd::new(fence, PollFlags::POLLIN)];
+    loop {
+        match poll(&mut fds, 0) {
+            Ok(ready) => return ready > 0,
+            Err(nix::errno::Errno::EINTR) => continue,
+            // an invalid fence will never block the buffer
+            Err(_) => return true,
+        }
+    }
+}
+
 impl<B: Buffer> Slot<B> {
     /// Retrieve userdata for this slot.
     pub fn userdata(&self) -> &UserDataMap {
@@ -87,6 +133,20 @@ impl<B: Buffer> Slot<B> {
     pub fn age(&self) -> u8 {
         self.0.age.load(Ordering::SeqCst)
     }
+
+    /// Attach a release fence to this slot
+    ///
+    /// The `fence` is a sync file, that signals once the buffer is not read anymore,
+    /// e.g. by the display or another gpu. The swapchain will not return the buffer from
+    /// [`Swapchain::acquire`] again, before all release fences of the slot have signaled.
+    pub fn add_release_fence(&self, fence: OwnedFd) {
+        self.0.release_fences.lock().unwrap().push(fence);
+    }
+
+    /// Returns whether the buffer is still in use according to its release fences
+    pub fn is_busy(&self) -> bool {
+        self.0.is_busy()
+    }
 }
 
 impl<B: Buffer> Default for InternalSlot<B> {
@@ -95,6 +155,7 @@ impl<B: Buffer> Default for InternalSlot<B> {
             buffer: None,
             acquired: AtomicBool::new(false),
             age: AtomicU8::new(0),
+            release_fences: Mutex::new(Vec::new()),
             userdata: UserDataMap::new(),
         }
     }
@@ -139,13 +200,19 @@ where
     /// Acquire a new slot from the swapchain, if one is still free.
     ///
     /// The swapchain has an internal maximum of four re-usable buffers.
-    /// This function returns the first free one.
+    /// This function returns the first free one, skipping buffers that are still
+    /// in use according to their [release fences](Slot::add_release_fence).
+    /// If all free buffers are still in use, `None` is returned just like without
+    /// any free buffers, so you may try again once one of the release fences signaled.
     pub fn acquire(&mut self) -> Result<Option<Slot<B>>, A::Error> {
-        if let Some(free_slot) = self
+        // Slots are only acquired through `&mut self`, so a free slot stays free until we acquire it
+        let free_slot = self
             .slots
             .iter_mut()
-            .find(|s| !s.acquired.swap(true, Ordering::SeqCst))
-        {
+            .find(|s| !s.acquired.load(Ordering::SeqCst) && !s.is_busy());
+
+        if let Some(free_slot) = free_slot {
+            free_slot.acquired.store(true, Ordering::SeqCst);
             if free_slot.buffer.is_none() {
                 let mut free_slot =
                     Arc::get_mut(free_slot).expect("Acquired was false, but Arc is not unique?");
@@ -193,7 +260,7 @@ where
                     });
                 // If the age overflows the slot was not used for a long time. Lets clear it
                 if res.is_err() {
-                    *other_slot = Default::default();
+                    reset_slot(other_slot, false);
                 }
             }
         }
@@ -202,6 +269,8 @@ where
     /// Change the dimensions of newly returned buffers.
     ///
     /// Already obtained buffers are unaffected and will be cleaned up on drop.
+    /// Slots of discarded buffers, which are still in use according to their release fences,
+    /// are not re-used until the fences signaled.
     pub fn resize(&mut self, width: u32, height: u32) {
         if self.width == width && self.height == height {
             return;
@@ -209,20 +278,187 @@ where
 
         self.width = width;
         self.height = height;
-        self.slots = Default::default();
+        for slot in &mut self.slots {
+            reset_slot(slot, false);
+        }
     }
 
     /// Remove all internally cached buffers to e.g. reset age values
     pub fn reset_buffers(&mut self) {
         for slot in &mut self.slots {
-            if let Some(internal_slot) = Arc::get_mut(slot) {
-                *internal_slot = InternalSlot {
-                    buffer: internal_slot.buffer.take(),
-                    ..Default::default()
-                };
-            } else {
-                *slot = Default::default();
+            reset_slot(slot, true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        os::unix::io::{AsRawFd, FromRawFd},
+        sync::{atomic::AtomicU8, Arc},
+    };
+
+    use io_lifetimes::OwnedFd;
+
+    use super::{InternalSlot, Swapchain, SLOT_CAP};
+    use crate::{
+        backend::allocator::{Allocator, Buffer, Format, Fourcc, Modifier},
+        utils::{Buffer as BufferCoords, Size},
+    };
+
+    #[derive(Debug)]
+    struct TestBuffer(Size<i32, BufferCoords>);
+
+    impl Buffer for TestBuffer {
+        fn size(&self) -> Size<i32, BufferCoords> {
+            self.0
+        }
+
+        fn format(&self) -> Format {
+            Format {
+                code: Fourcc::Argb8888,
+                modifier: Modifier::Linear,
             }
         }
     }
+
+    struct TestAllocator;
+
+    impl Allocator<TestBuffer> for TestAllocator {
+        type Error = std::io::Error;
+
+        fn create_buffer(
+            &mut self,
+            width: u32,
+            height: u32,
+            _fourcc: Fourcc,
+            _modifiers: &[Modifier],
+        ) -> Result<TestBuffer, Self::Error> {
+            Ok(TestBuffer((width as i32, height as i32).into()))
+        }
+    }
+
+    fn swapchain() -> Swapchain<TestAllocator, TestBuffer> {
+        Swapchain::new(TestAllocator, 64, 64, Fourcc::Argb8888, vec![Modifier::Linear])
+    }
+
+    // duplicates a fence to attach it to multiple slots
+    fn dup(fd: &OwnedFd) -> OwnedFd {
+        unsafe { OwnedFd::from_raw_fd(nix::unistd::dup(fd.as_raw_fd()).unwrap()) }
+    }
+
+    #[test]
+    fn ages() {
+        let mut swapchain = swapchain();
+
+        let first = swapchain.acquire().unwrap().unwrap();
+        assert_eq!(first.age(), 0);
+        swapchain.submitted(&first);
+        std::mem::drop(first);
+
+        let second = swapchain.acquire().unwrap().unwrap();
+        assert_eq!(second.age(), 1);
+        swapchain.submitted(&second);
+        std::mem::drop(second);
+
+        // the buffer submitted before the resize must not affect the new buffers
+        let old = swapchain.acquire().unwrap().unwrap();
+        swapchain.resize(32, 32);
+        swapchain.submitted(&old);
+        std::mem::drop(old);
+        let resized = swapchain.acquire().unwrap().unwrap();
+        assert_eq!(resized.age(), 0);
+        assert_eq!(resized.size(), (32, 32).into());
+        swapchain.submitted(&resized);
+        std::mem::drop(resized);
+
+        swapchain.reset_buffers();
+        let reset = swapchain.acquire().unwrap().unwrap();
+        assert_eq!(reset.age(), 0);
+        assert_eq!(reset.size(), (32, 32).into());
+    }
+
+    #[test]
+    fn release_fences() {
+        let mut swapchain = swapchain();
+
+        // a pipe becomes readable just like a signaled sync file
+        let (read, write) = nix::unistd::pipe().unwrap();
+        let write = unsafe { OwnedFd::from_raw_fd(write) };
+
+        let busy = swapchain.acquire().unwrap().unwrap();
+        swapchain.submitted(&busy);
+        busy.add_release_fence(unsafe { OwnedFd::from_raw_fd(read) });
+        assert!(busy.is_busy());
+        let busy_ptr = &*busy as *const TestBuffer;
+        std::mem::drop(busy);
+
+        // busy slots are skipped
+        let free = swapchain.acquire().unwrap().unwrap();
+        assert_ne!(&*free as *const TestBuffer, busy_ptr);
+        std::mem::drop(free);
+
+        // reset_buffers keeps the fences of retained buffers
+        swapchain.reset_buffers();
+        let free = swapchain.acquire().unwrap().unwrap();
+        assert_ne!(&*free as *const TestBuffer, busy_ptr);
+        std::mem::drop(free);
+
+        nix::unistd::write(write.as_raw_fd(), &[1]).unwrap();
+        let released = swapchain.acquire().unwrap().unwrap();
+        assert_eq!(&*released as *const TestBuffer, busy_ptr);
+        assert!(!released.is_busy());
+    }
+
+    #[test]
+    fn busy_slots_do_not_block() {
+        let mut swapchain = swapchain();
+        let (read, write) = nix::unistd::pipe().unwrap();
+        let read = unsafe { OwnedFd::from_raw_fd(read) };
+        let write = unsafe { OwnedFd::from_raw_fd(write) };
+
+        let slots = (0..SLOT_CAP)
+            .map(|_| swapchain.acquire().unwrap().unwrap())
+            .collect::<Vec<_>>();
+        for slot in slots {
+            slot.add_release_fence(dup(&read));
+        }
+        assert!(swapchain.acquire().unwrap().is_none());
+
+        nix::unistd::write(write.as_raw_fd(), &[1]).unwrap();
+        assert!(swapchain.acquire().unwrap().is_some());
+    }
+
+    #[test]
+    fn discarded_slots_keep_release_fences() {
+        let mut swapchain = swapchain();
+        let (read, write) = nix::unistd::pipe().unwrap();
+        let read = unsafe { OwnedFd::from_raw_fd(read) };
+        let write = unsafe { OwnedFd::from_raw_fd(write) };
+
+        let busy = swapchain.acquire().unwrap().unwrap();
+        swapchain.submitted(&busy);
+        busy.add_release_fence(dup(&read));
+        std::mem::drop(busy);
+
+        swapchain.resize(32, 32);
+        assert!(swapchain.slots[0].buffer.is_none());
+        assert!(swapchain.slots[0].is_busy());
+
+        // let the age of the busy slot overflow
+        swapchain.slots[0] = Arc::new(InternalSlot {
+            buffer: Some(TestBuffer((32, 32).into())),
+            age: AtomicU8::new(u8::MAX),
+            ..Default::default()
+        });
+        swapchain.slots[0].release_fences.lock().unwrap().push(dup(&read));
+        let other = swapchain.acquire().unwrap().unwrap();
+        swapchain.submitted(&other);
+        assert!(swapchain.slots[0].buffer.is_none());
+        assert!(swapchain.slots[0].is_busy());
+        std::mem::drop(other);
+
+        nix::unistd::write(write.as_raw_fd(), &[1]).unwrap();
+        assert!(!swapchain.slots[0].is_busy());
+    }
 }
@@ -0,0 +1,220 @@
+//! Module for dmabufs backed by system memory through [udmabuf](https://docs.kernel.org/driver-api/dma-buf.html)
+//!
+//! The [`UdmabufAllocator`] creates sealed memfds and converts them into linear dmabufs
+//! through `/dev/udmabuf`. As it does not require a gpu or a drm device,
+//! it can be used to create dmabufs for tests or on machines without any graphics hardware.
+//!
+//! The buffers can be accessed from the cpu via [`Dmabuf::map_plane`].
+//!
+//! ```no_run
+//! # use smithay::backend::allocator::{Allocator, Fourcc, Modifier, udmabuf::UdmabufAllocator};
+//! let mut allocator = UdmabufAllocator::new().expect("udmabuf is not available");
+//! let dmabuf = allocator
+//!     .create_buffer(256, 256, Fourcc::Argb8888, &[Modifier::Linear])
+//!     .unwrap();
+//! ```
+
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::os::unix::{
+    fs::OpenOptionsExt,
+    io::{AsRawFd, FromRawFd, RawFd},
+};
+use std::path::Path;
+
+use io_lifetimes::OwnedFd;
+use nix::{
+    fcntl::{self, FcntlArg, SealFlag},
+    sys::memfd::{self, MemFdCreateFlag},
+    unistd,
+};
+
+use super::{
+    dmabuf::{Dmabuf, DmabufFlags},
+    format::get_bpp,
+    Allocator, Fourcc, Modifier,
+};
+
+const UDMABUF_PATH: &str = "/dev/udmabuf";
+// Alignment of the stride of created buffers, satisfying the requirements of common gpus for imports
+const STRIDE_ALIGNMENT: usize = 256;
+
+/// Allocator for linear [`Dmabuf`]s in system memory
+#[derive(Debug)]
+pub struct UdmabufAllocator {
+    device: File,
+}
+
+/// Errors that may happen when allocating a buffer with [`UdmabufAllocator`]
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The format is no packed format known to smithay
+    #[error("Unsupported format {0:?}, only packed formats are supported")]
+    UnsupportedFormat(Fourcc),
+    /// None of the requested modifiers is supported, only linear buffers can be created
+    #[error("None of the requested modifiers {0:?} are supported")]
+    UnsupportedModifiers(Vec<Modifier>),
+    /// The requested size is invalid
+    #[error("Invalid buffer size {0}x{1}")]
+    InvalidSize(u32, u32),
+    /// Creating the memfd backing the buffer failed
+    #[error("Failed to create the memfd backing the buffer: {0}")]
+    Memfd(#[source] nix::Error),
+    /// Converting the memfd into a dmabuf failed
+    #[error("Failed to create the udmabuf: {0}")]
+    Create(#[source] nix::Error),
+}
+
+impl UdmabufAllocator {
+    /// Creates a new allocator by opening `/dev/udmabuf`
+    ///
+    /// Fails if the kernel was built without udmabuf support or the device is not accessible.
+    pub fn new() -> std::io::Result<UdmabufAllocator> {
+        UdmabufAllocator::with_path(UDMABUF_PATH)
+    }
+
+    /// Creates a new allocator using the udmabuf device at the given path
+    pub fn with_path(path: impl AsRef<Path>) -> std::io::Result<UdmabufAllocator> {
+        let device = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_CLOEXEC)
+            .open(path)?;
+        Ok(UdmabufAllocator { device })
+    }
+}
+
+impl Allocator<Dmabuf> for UdmabufAllocator {
+    type Error = Error;
+
+    fn create_buffer(
+        &mut self,
+        width: u32,
+        height: u32,
+        fourcc: Fourcc,
+        modifiers: &[Modifier],
+    ) -> Result<Dmabuf, Error> {
+        if !modifiers.is_empty()
+            && !modifiers
+                .iter()
+                .any(|modifier| matches!(modifier, Modifier::Linear | Modifier::Invalid))
+        {
+            return Err(Error::UnsupportedModifiers(modifiers.to_vec()));
+        }
+        let bpp = get_bpp(fourcc).ok_or(Error::UnsupportedFormat(fourcc))?;
+        if width == 0 || height == 0 || width > i32::MAX as u32 || height > i32::MAX as u32 {
+            return Err(Error::InvalidSize(width, height));
+        }
+
+        let stride = (width as usize * bpp / 8 + STRIDE_ALIGNMENT - 1) & !(STRIDE_ALIGNMENT - 1);
+        let page_size = unistd::sysconf(unistd::SysconfVar::PAGE_SIZE)
+            .ok()
+            .flatten()
+            .unwrap_or(4096) as usize;
+        // udmabuf only accepts whole pages
+        let size = (stride * height as usize + page_size - 1) & !(page_size - 1);
+        if stride > u32::MAX as usize {
+            return Err(Error::InvalidSize(width, height));
+        }
+
+        let memfd = create_memfd(size).map_err(Error::Memfd)?;
+        let fd = udmabuf_create(self.device.as_raw_fd(), memfd.as_raw_fd(), size).map_err(Error::Create)?;
+
+        let mut builder = Dmabuf::builder((width as i32, height as i32), fourcc, DmabufFlags::empty());
+        builder.add_plane(fd, 0, 0, stride as u32, Modifier::Linear);
+        Ok(builder.build().unwrap())
+    }
+}
+
+// Creates a memfd of the given size, sealed against shrinking as required by udmabuf.
+fn create_memfd(size: usize) -> Result<OwnedFd, nix::Error> {
+    let fd = memfd::memfd_create(
+        CStr::from_bytes_with_nul(b"smithay-udmabuf\0").unwrap(),
+        MemFdCreateFlag::MFD_CLOEXEC | MemFdCreateFlag::MFD_ALLOW_SEALING,
+    )?;
+    // SAFETY: memfd_create returned a new file descriptor, which is owned by nobody else
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    unistd::ftruncate(fd.as_raw_fd(), size as libc::off_t)?;
+    fcntl::fcntl(fd.as_raw_fd(), FcntlArg::F_ADD_SEALS(SealFlag::F_SEAL_SHRINK))?;
+    Ok(fd)
+}
+
+mod ioctl {
+    pub const UDMABUF_FLAGS_CLOEXEC: u32 = 0x01;
+
+    #[repr(C)]
+    pub struct UdmabufCreate {
+        pub memfd: u32,
+        pub flags: u32,
+        pub offset: u64,
+        pub size: u64,
+    }
+
+    nix::ioctl_write_ptr!(udmabuf_create, b'u', 0x42, UdmabufCreate);
+}
+
+fn udmabuf_create(device: RawFd, memfd: RawFd, size: usize) -> Result<OwnedFd, nix::Error> {
+    let create = ioctl::UdmabufCreate {
+        memfd: memfd as u32,
+        flags: ioctl::UDMABUF_FLAGS_CLOEXEC,
+        offset: 0,
+        size: size as u64,
+    };
+    // SAFETY: the ioctl returns a new dmabuf file descriptor
+    let fd = unsafe { ioctl::udmabuf_create(device, &create)? };
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UdmabufAllocator;
+    use crate::backend::allocator::{dmabuf::DmabufMappingMode, Allocator, Buffer, Fourcc, Modifier};
+
+    // udmabuf is not available everywhere, the tests are skipped without it
+    fn test_allocator() -> Option<UdmabufAllocator> {
+        match UdmabufAllocator::new() {
+            Ok(allocator) => Some(allocator),
+            Err(err) => {
+                eprintln!("skipping test, /dev/udmabuf is not available: {}", err);
+                None
+            }
+        }
+    }
+
+    #[test]
+    fn allocate_and_map() {
+        let mut allocator = match test_allocator() {
+            Some(allocator) => allocator,
+            None => return,
+        };
+
+        let dmabuf = allocator
+            .create_buffer(64, 32, Fourcc::Argb8888, &[Modifier::Linear])
+            .unwrap();
+        assert_eq!(dmabuf.width(), 64);
+        assert_eq!(dmabuf.height(), 32);
+        assert_eq!(dmabuf.format().modifier, Modifier::Linear);
+        let stride = dmabuf.strides().next().unwrap();
+        assert!(stride >= 64 * 4);
+
+        {
+            let mut mapping = dmabuf.map_plane(0, DmabufMappingMode::Write).unwrap();
+            mapping.as_mut_slice().unwrap()[..4].copy_from_slice(&[1, 2, 3, 4]);
+        }
+        let mapping = dmabuf.map_plane(0, DmabufMappingMode::Read).unwrap();
+        assert_eq!(&mapping.as_slice()[..4], &[1, 2, 3, 4]);
+        assert!(mapping.as_slice().len() >= stride as usize * 32);
+    }
+
+    #[test]
+    fn unsupported_modifiers() {
+        let mut allocator = match test_allocator() {
+            Some(allocator) => allocator,
+            None => return,
+        };
+
+        assert!(allocator
+            .create_buffer(64, 32, Fourcc::Argb8888, &[Modifier::I915_x_tiled])
+            .is_err());
+    }
+}
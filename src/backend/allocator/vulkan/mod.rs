@@ -1,7 +1,8 @@
 //! Module for Buffers created using Vulkan.
 //!
 //! The [`VulkanAllocator`] type implements the [`Allocator`] trait and [`VulkanImage`] implements [`Buffer`].
-//! A [`VulkanImage`] may be exported as a [dmabuf](super::dmabuf).
+//! A [`VulkanImage`] may be exported as a [dmabuf](super::dmabuf) and foreign dmabufs may be imported
+//! using [`VulkanAllocator::import_dmabuf`].
 //!
 //! The Vulkan allocator supports up to Vulkan 1.3.
 //!
@@ -12,6 +13,7 @@
 //!
 //! Additionally the Vulkan allocator may enable the following extensions if available:
 //! - `VK_EXT_4444_formats`
+//! - `VK_KHR_external_semaphore_fd`, to export sync files (see [`VulkanAllocator::export_sync_file`])
 //!
 //! To get the required extensions a device must support to use the Vulkan allocator, use
 //! [`VulkanAllocator::required_extensions`].
@@ -22,8 +24,8 @@ pub mod format;
 
 use std::{
     ffi::CStr,
-    fmt,
-    os::unix::io::FromRawFd,
+    fmt, mem,
+    os::unix::io::{AsRawFd, FromRawFd},
     sync::{mpsc, Arc, Weak},
 };
 
@@ -116,6 +118,20 @@ pub enum Error {
     #[error("format is not supported")]
     UnsupportedFormat,
 
+    /// The planes of an imported dmabuf do not reside in the same memory object.
+    #[error("disjoint dmabufs are not supported")]
+    DisjointDmabuf,
+
+    /// No memory type is suitable for importing the dmabuf.
+    #[error("no suitable memory type for the dmabuf")]
+    NoSuitableMemoryType,
+
+    /// The device does not support exporting sync files.
+    ///
+    /// This requires `VK_KHR_external_semaphore_fd` and support for exporting semaphores as sync files.
+    #[error("sync file export is not supported")]
+    SyncFileUnsupported,
+
     /// Some error from the Vulkan driver.
     #[error(transparent)]
     Vk(#[from] vk::Result),
@@ -130,6 +146,10 @@ pub struct VulkanAllocator {
     extension_fns: ExtensionFns,
     dropped_recv: mpsc::Receiver<ImageInner>,
     dropped_sender: mpsc::Sender<ImageInner>,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    /// Semaphores of exported sync files, which are destroyed once their fence signals.
+    pending_syncs: Vec<(vk::Semaphore, vk::Fence)>,
+    queue: vk::Queue,
     phd: PhysicalDevice,
     device: Arc<ash::Device>,
 }
@@ -142,6 +162,7 @@ impl fmt::Debug for VulkanAllocator {
             .field("remaining_allocations", &self.remaining_allocations)
             .field("dropped_recv", &self.dropped_recv)
             .field("dropped_sender", &self.dropped_sender)
+            .field("pending_syncs", &self.pending_syncs)
             .field("phd", &self.phd)
             .finish()
     }
@@ -180,6 +201,13 @@ impl VulkanAllocator {
             }
         }
 
+        // VK_KHR_external_semaphore_fd depends on VK_KHR_external_semaphore, which is part of the core API in Vulkan 1.1
+        if phd.api_version() >= Version::VERSION_1_1
+            && phd.has_device_extension(vk::KhrExternalSemaphoreFdFn::name())
+        {
+            extensions.push(vk::KhrExternalSemaphoreFdFn::name());
+        }
+
         extensions
     }
 
@@ -241,8 +269,17 @@ impl VulkanAllocator {
         let extension_fns = ExtensionFns {
             ext_image_format_modifier: ext::ImageDrmFormatModifier::new(instance, &device),
             khr_external_memory_fd: khr::ExternalMemoryFd::new(instance, &device),
+            khr_external_semaphore_fd: if extensions.contains(&vk::KhrExternalSemaphoreFdFn::name())
+                && Self::supports_sync_fd_semaphores(phd)
+            {
+                Some(khr::ExternalSemaphoreFd::new(instance, &device))
+            } else {
+                None
+            },
         };
 
+        let queue = unsafe { device.get_device_queue(queue_family_index as u32, 0) };
+        let memory_properties = unsafe { instance.get_physical_device_memory_properties(phd.handle()) };
         let (dropped_sender, dropped_recv) = mpsc::channel();
 
         let mut allocator = VulkanAllocator {
@@ -253,6 +290,9 @@ impl VulkanAllocator {
             extension_fns,
             dropped_recv,
             dropped_sender,
+            memory_properties,
+            pending_syncs: Vec::new(),
+            queue,
             phd: phd.clone(),
             device: Arc::new(device),
         };
@@ -330,6 +370,134 @@ impl VulkanAllocator {
         Ok(unsafe { self.create_image(width, height, vk_format, vk_usage, fourcc, &modifiers[..]) }?)
     }
 
+    /// Imports a dmabuf as a [`VulkanImage`] using the default usage flags of the allocator.
+    ///
+    /// See [`VulkanAllocator::import_dmabuf_with_usage`] for details.
+    pub fn import_dmabuf(&mut self, dmabuf: &Dmabuf) -> Result<VulkanImage, Error> {
+        self.import_dmabuf_with_usage(dmabuf, self.default_usage)
+    }
+
+    /// Imports a dmabuf, e.g. of a client or another device, as a [`VulkanImage`] with the given usage flags.
+    ///
+    /// All planes of the dmabuf must reside in the same memory object, disjoint dmabufs are not supported.
+    ///
+    /// This may return [`Err`] for one of the following reasons:
+    /// - The `usage` is empty.
+    /// - The format and modifier of the dmabuf are not supported with the `usage`.
+    /// - The size of the dmabuf is too large for the `usage`, format or modifier.
+    /// - The dmabuf is disjoint.
+    pub fn import_dmabuf_with_usage(
+        &mut self,
+        dmabuf: &Dmabuf,
+        usage: ImageUsageFlags,
+    ) -> Result<VulkanImage, Error> {
+        self.cleanup();
+
+        let format = dmabuf.format();
+        let vk_format = format::get_vk_format(format.code).ok_or(Error::UnsupportedFormat)?;
+        let vk_usage = vk::ImageUsageFlags::from_raw(usage.bits());
+        let (width, height) = (dmabuf.width(), dmabuf.height());
+
+        // VUID-VkImageCreateInfo-extent-00944, VUID-VkImageCreateInfo-extent-00945
+        if width == 0 || height == 0 {
+            return Err(Error::InvalidSize);
+        }
+
+        // VUID-VkImageCreateInfo-usage-00964, VUID-VkImageCreateInfo-usage-00965
+        if usage.contains(ImageUsageFlags::COLOR_ATTACHMENT) {
+            let limits = self.phd.limits();
+
+            if width > limits.max_framebuffer_width || height > limits.max_framebuffer_height {
+                return Err(Error::InvalidSize);
+            }
+        }
+
+        let format_plane_count = self
+            .formats
+            .iter()
+            .find(|entry| entry.format == format)
+            .ok_or(Error::UnsupportedFormat)?
+            .modifier_properties
+            .drm_format_modifier_plane_count;
+        check_import(dmabuf, usage, format_plane_count)?;
+
+        if self
+            .filter_modifiers(width, height, vk_usage, format.code, &[format.modifier])
+            .is_empty()
+        {
+            return Err(Error::UnsupportedFormat);
+        }
+
+        unsafe { self.import_image(dmabuf, vk_format, vk_usage, format_plane_count) }
+    }
+
+    /// Returns whether the allocator is able to export sync files, see [`VulkanAllocator::export_sync_file`].
+    pub fn supports_sync_file_export(&self) -> bool {
+        self.extension_fns.khr_external_semaphore_fd.is_some()
+    }
+
+    /// Exports a sync file, which signals once all work previously submitted to the queue
+    /// of the allocator is complete.
+    ///
+    /// The sync file is exported from a semaphore using `VK_KHR_external_semaphore_fd`
+    /// and may be handed to other apis, e.g. as a
+    /// [`SyncPoint`](crate::backend::renderer::sync::SyncPoint) or the `IN_FENCE_FD` of a drm commit.
+    ///
+    /// Only work submitted to the queue of the allocator is covered, which includes the
+    /// layout transitions of imported and allocated images. Work submitted to other queues,
+    /// e.g. of a renderer using the same device, is not fenced by the sync file and needs
+    /// to be synchronized separately.
+    ///
+    /// Returns `None`, if the work is already complete.
+    pub fn export_sync_file(&mut self) -> Result<Option<OwnedFd>, Error> {
+        self.cleanup();
+
+        let khr_external_semaphore_fd = self
+            .extension_fns
+            .khr_external_semaphore_fd
+            .as_ref()
+            .ok_or(Error::SyncFileUnsupported)?;
+
+        let mut export_info = vk::ExportSemaphoreCreateInfo::builder()
+            .handle_types(vk::ExternalSemaphoreHandleTypeFlags::SYNC_FD);
+        let semaphore_info = vk::SemaphoreCreateInfo::builder().push_next(&mut export_info);
+        let device = self.device.clone();
+        let semaphore = scopeguard::guard(
+            unsafe { self.device.create_semaphore(&semaphore_info, None) }?,
+            |semaphore| unsafe { device.destroy_semaphore(semaphore, None) },
+        );
+        let fence = scopeguard::guard(
+            unsafe { self.device.create_fence(&vk::FenceCreateInfo::builder(), None) }?,
+            |fence| unsafe { device.destroy_fence(fence, None) },
+        );
+
+        // VUID-VkSemaphoreGetFdInfoKHR-handleType-01133: The semaphore must have a pending signal operation
+        let signal_semaphores = [*semaphore];
+        let submit_info = [vk::SubmitInfo::builder()
+            .signal_semaphores(&signal_semaphores)
+            .build()];
+        unsafe { self.device.queue_submit(self.queue, &submit_info, *fence) }?;
+
+        // The semaphore may only be destroyed once the submission completed.
+        let (semaphore, fence) = (
+            scopeguard::ScopeGuard::into_inner(semaphore),
+            scopeguard::ScopeGuard::into_inner(fence),
+        );
+        self.pending_syncs.push((semaphore, fence));
+
+        let get_fd_info = vk::SemaphoreGetFdInfoKHR::builder()
+            .semaphore(semaphore)
+            .handle_type(vk::ExternalSemaphoreHandleTypeFlags::SYNC_FD);
+        let fd = unsafe { khr_external_semaphore_fd.get_semaphore_fd(&get_fd_info) }?;
+
+        // An fd of -1 indicates, that the semaphore was already signaled.
+        if fd == -1 {
+            return Ok(None);
+        }
+        // SAFETY: `vkGetSemaphoreFdKHR` creates a new file descriptor owned by the caller.
+        Ok(Some(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
     /// Returns the [`PhysicalDevice`] this allocator was created with.
     pub fn physical_device(&self) -> &PhysicalDevice {
         &self.phd
@@ -353,6 +521,13 @@ impl Allocator<VulkanImage> for VulkanAllocator {
 impl Drop for VulkanAllocator {
     fn drop(&mut self) {
         unsafe {
+            // Wait for the submissions of exported sync files to complete.
+            let _ = self.device.device_wait_idle();
+            for (semaphore, fence) in &self.pending_syncs {
+                self.device.destroy_semaphore(*semaphore, None);
+                self.device.destroy_fence(*fence, None);
+            }
+
             for image in &self.images {
                 self.device.destroy_image(image.image, None);
                 self.device.free_memory(image.memory, None);
@@ -373,6 +548,8 @@ pub struct VulkanImage {
     format: DrmFormat,
     /// The number of planes the image has for dmabuf export.
     format_plane_count: u32,
+    /// The dmabuf the image was imported from, if any.
+    dmabuf: Option<Dmabuf>,
     khr_external_memory_fd: khr::ExternalMemoryFd,
     dropped_sender: mpsc::Sender<ImageInner>,
     device: Weak<ash::Device>,
@@ -413,6 +590,11 @@ impl AsDmabuf for VulkanImage {
     fn export(&self) -> Result<Dmabuf, Self::Error> {
         let device = self.device.upgrade().ok_or(ExportError::AllocatorDestroyed)?;
 
+        // Imported memory cannot be exported again, but the original dmabuf can be shared instead.
+        if let Some(dmabuf) = self.dmabuf.as_ref() {
+            return Ok(dmabuf.clone());
+        }
+
         // Implementation may be broken if the plane count is wrong.
         if self.format_plane_count == 0 {
             return Err(ExportError::Failed);
@@ -506,9 +688,38 @@ struct ExtensionFns {
     ///
     /// If this is [`Some`], then the allocator will support dmabuf import and export operations.
     khr_external_memory_fd: khr::ExternalMemoryFd,
+    /// Functions used for sync file export.
+    ///
+    /// If this is [`Some`], then the allocator supports exporting semaphores as sync files.
+    khr_external_semaphore_fd: Option<khr::ExternalSemaphoreFd>,
 }
 
 impl VulkanAllocator {
+    /// Returns whether semaphores of the device can be exported as sync files.
+    fn supports_sync_fd_semaphores(phd: &PhysicalDevice) -> bool {
+        // vkGetPhysicalDeviceExternalSemaphoreProperties is part of the core API in Vulkan 1.1
+        if phd.instance().api_version() < Version::VERSION_1_1 {
+            return false;
+        }
+
+        let info = vk::PhysicalDeviceExternalSemaphoreInfo::builder()
+            .handle_type(vk::ExternalSemaphoreHandleTypeFlags::SYNC_FD);
+        let mut properties = vk::ExternalSemaphoreProperties::default();
+        unsafe {
+            phd.instance()
+                .handle()
+                .get_physical_device_external_semaphore_properties(phd.handle(), &info, &mut properties)
+        };
+
+        properties
+            .external_semaphore_features
+            .contains(vk::ExternalSemaphoreFeatureFlags::EXPORTABLE)
+    }
+
+    fn find_memory_type(&self, type_bits: u32) -> Option<u32> {
+        (0..self.memory_properties.memory_type_count).find(|idx| type_bits & (1 << idx) != 0)
+    }
+
     fn init_formats(&mut self) {
         for &fourcc in format::known_formats() {
             let vk_format = format::get_vk_format(fourcc).unwrap();
@@ -747,6 +958,141 @@ impl VulkanAllocator {
             height,
             format,
             format_plane_count,
+            dmabuf: None,
+            khr_external_memory_fd: self.extension_fns.khr_external_memory_fd.clone(),
+            dropped_sender: self.dropped_sender.clone(),
+            device: Arc::downgrade(&self.device),
+        })
+    }
+
+    /// # Safety
+    ///
+    /// * The format, modifier and usage flags of the dmabuf must be supported and the plane count must
+    ///   match the modifier.
+    /// * The extent of the image must be within the maximum extents Vulkan tells.
+    /// * All planes of the dmabuf must reside in the same memory object.
+    unsafe fn import_image(
+        &mut self,
+        dmabuf: &Dmabuf,
+        vk_format: vk::Format,
+        vk_usage: vk::ImageUsageFlags,
+        format_plane_count: u32,
+    ) -> Result<VulkanImage, Error> {
+        // Ensure maximum allocations are not exceeded.
+        if self.remaining_allocations == 0 {
+            return Err(Error::Vk(vk::Result::ERROR_TOO_MANY_OBJECTS));
+        }
+
+        let format = dmabuf.format();
+        let plane_layouts = dmabuf
+            .offsets()
+            .zip(dmabuf.strides())
+            .map(|(offset, stride)| vk::SubresourceLayout {
+                offset: offset as u64,
+                // VUID-VkImageDrmFormatModifierExplicitCreateInfoEXT-size-02267
+                size: 0,
+                row_pitch: stride as u64,
+                // VUID-VkImageDrmFormatModifierExplicitCreateInfoEXT-arrayPitch-02268
+                array_pitch: 0,
+                // VUID-VkImageDrmFormatModifierExplicitCreateInfoEXT-depthPitch-02269
+                depth_pitch: 0,
+            })
+            .collect::<Vec<_>>();
+        let mut modifier_info = vk::ImageDrmFormatModifierExplicitCreateInfoEXT::builder()
+            .drm_format_modifier(format.modifier.into())
+            .plane_layouts(&plane_layouts);
+        let mut external_memory_image_create_info = vk::ExternalMemoryImageCreateInfo::builder()
+            .handle_types(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT);
+
+        let image_create_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(vk_format)
+            .extent(vk::Extent3D {
+                width: dmabuf.width(),
+                height: dmabuf.height(),
+                // VUID-VkImageCreateInfo-extent-00946
+                // VUID-VkImageCreateInfo-imageType-00957
+                depth: 1,
+            })
+            // VUID-VkImageCreateInfo-samples-parameter
+            .samples(vk::SampleCountFlags::TYPE_1)
+            // VUID-VkImageCreateInfo-mipLevels-00947
+            .mip_levels(1)
+            // VUID-VkImageCreateInfo-arrayLayers-00948
+            .array_layers(1)
+            // VUID-VkImageCreateInfo-pNext-02262
+            .tiling(vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT)
+            .usage(vk_usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            // VUID-VkImageCreateInfo-pNext-01443
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            // VUID-VkImageCreateInfo-tiling-02261
+            .push_next(&mut modifier_info)
+            .push_next(&mut external_memory_image_create_info);
+
+        // The image is placed in a scope guard to safely handle future allocation failures.
+        let device = self.device.clone();
+        let mut guard = scopeguard::guard(
+            ImageInner {
+                image: unsafe { self.device.create_image(&image_create_info, None) }?,
+                memory: vk::DeviceMemory::null(),
+            },
+            |inner| unsafe {
+                device.destroy_image(inner.image, None);
+                if inner.memory != vk::DeviceMemory::null() {
+                    device.free_memory(inner.memory, None);
+                }
+            },
+        );
+
+        // Vulkan takes ownership of the file descriptor on a successful import
+        let fd = nix::unistd::dup(dmabuf.handles().next().unwrap().as_raw_fd())
+            .map_err(|_| Error::Vk(vk::Result::ERROR_TOO_MANY_OBJECTS))?;
+        // SAFETY: dup created a new file descriptor owned by nobody else
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let fd_properties = unsafe {
+            self.extension_fns
+                .khr_external_memory_fd
+                .get_memory_fd_properties(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT, fd.as_raw_fd())
+        }?;
+        let memory_reqs = unsafe { self.device.get_image_memory_requirements(guard.image) };
+        let memory_type = self
+            .find_memory_type(memory_reqs.memory_type_bits & fd_properties.memory_type_bits)
+            .ok_or(Error::NoSuitableMemoryType)?;
+
+        let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::builder().image(guard.image);
+        let mut import_info = vk::ImportMemoryFdInfoKHR::builder()
+            .handle_type(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT)
+            .fd(fd.as_raw_fd());
+        let alloc_create_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(memory_reqs.size)
+            .memory_type_index(memory_type)
+            .push_next(&mut dedicated_info)
+            .push_next(&mut import_info);
+
+        unsafe {
+            guard.memory = self.device.allocate_memory(&alloc_create_info, None)?;
+            // The import succeeded, the fd is now owned by the driver.
+            mem::forget(fd);
+            self.device.bind_image_memory(guard.image, guard.memory, 0)?;
+        }
+
+        // Initialization is complete, prevent the scope guard from running it's dropfn.
+        let inner = scopeguard::ScopeGuard::into_inner(guard);
+
+        // Track the image for destruction.
+        self.images.push(inner);
+
+        self.remaining_allocations -= 1;
+
+        Ok(VulkanImage {
+            inner,
+            width: dmabuf.width(),
+            height: dmabuf.height(),
+            format,
+            format_plane_count,
+            dmabuf: Some(dmabuf.clone()),
             khr_external_memory_fd: self.extension_fns.khr_external_memory_fd.clone(),
             dropped_sender: self.dropped_sender.clone(),
             device: Arc::downgrade(&self.device),
@@ -754,6 +1100,19 @@ impl VulkanAllocator {
     }
 
     fn cleanup(&mut self) {
+        // Destroy the semaphores of exported sync files, once their submission completed
+        let device = self.device.clone();
+        self.pending_syncs.retain(|(semaphore, fence)| {
+            let signaled = unsafe { device.get_fence_status(*fence) }.unwrap_or(false);
+            if signaled {
+                unsafe {
+                    device.destroy_semaphore(*semaphore, None);
+                    device.destroy_fence(*fence, None);
+                }
+            }
+            !signaled
+        });
+
         let dropped = self.dropped_recv.try_iter().collect::<Vec<_>>();
 
         self.images.retain(|image| {
@@ -782,3 +1141,98 @@ impl VulkanAllocator {
         })
     }
 }
+
+// Checks of `import_dmabuf_with_usage`, which do not depend on the device
+fn check_import(dmabuf: &Dmabuf, usage: ImageUsageFlags, format_plane_count: u32) -> Result<(), Error> {
+    // VUID-VkPhysicalDeviceImageFormatInfo2-usage-requiredbitmask
+    if usage.is_empty() {
+        return Err(Error::UnsupportedFormat);
+    }
+
+    // The plane layouts must match the plane count of the modifier
+    // (VUID-VkImageDrmFormatModifierExplicitCreateInfoEXT-drmFormatModifierPlaneCount-02265)
+    if format_plane_count as usize != dmabuf.num_planes() {
+        return Err(Error::UnsupportedFormat);
+    }
+
+    // All planes have to reside in the same memory object
+    let stat = nix::sys::stat::fstat(dmabuf.handles().next().unwrap().as_raw_fd())
+        .map_err(|_| Error::DisjointDmabuf)?;
+    for handle in dmabuf.handles().skip(1) {
+        match nix::sys::stat::fstat(handle.as_raw_fd()) {
+            Ok(plane) if plane.st_dev == stat.st_dev && plane.st_ino == stat.st_ino => {}
+            _ => return Err(Error::DisjointDmabuf),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_import, Error, ImageUsageFlags};
+    use crate::backend::allocator::{
+        dmabuf::{Dmabuf, DmabufFlags},
+        Fourcc, Modifier,
+    };
+    use io_lifetimes::OwnedFd;
+    use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+    use std::ffi::CStr;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    fn memfd() -> OwnedFd {
+        let fd = memfd_create(
+            CStr::from_bytes_with_nul(b"smithay-test\0").unwrap(),
+            MemFdCreateFlag::MFD_CLOEXEC,
+        )
+        .unwrap();
+        unsafe { OwnedFd::from_raw_fd(fd) }
+    }
+
+    // a dmabuf with one plane per fd, planes sharing a fd refer to the same memory object
+    fn dmabuf(fds: Vec<OwnedFd>) -> Dmabuf {
+        let mut builder = Dmabuf::builder((16, 16), Fourcc::Argb8888, DmabufFlags::empty());
+        for (idx, fd) in fds.into_iter().enumerate() {
+            builder.add_plane(fd, idx as u32, 0, 64, Modifier::Linear);
+        }
+        builder.build().unwrap()
+    }
+
+    fn dup(fd: &OwnedFd) -> OwnedFd {
+        unsafe { OwnedFd::from_raw_fd(nix::unistd::dup(fd.as_raw_fd()).unwrap()) }
+    }
+
+    #[test]
+    fn import_requires_usage() {
+        let dmabuf = dmabuf(vec![memfd()]);
+        assert!(matches!(
+            check_import(&dmabuf, ImageUsageFlags::empty(), 1),
+            Err(Error::UnsupportedFormat)
+        ));
+        assert!(check_import(&dmabuf, ImageUsageFlags::SAMPLED, 1).is_ok());
+    }
+
+    #[test]
+    fn import_requires_matching_plane_count() {
+        let fd = memfd();
+        let dmabuf = dmabuf(vec![dup(&fd), fd]);
+        assert!(matches!(
+            check_import(&dmabuf, ImageUsageFlags::SAMPLED, 1),
+            Err(Error::UnsupportedFormat)
+        ));
+        assert!(matches!(
+            check_import(&dmabuf, ImageUsageFlags::SAMPLED, 3),
+            Err(Error::UnsupportedFormat)
+        ));
+        assert!(check_import(&dmabuf, ImageUsageFlags::SAMPLED, 2).is_ok());
+    }
+
+    #[test]
+    fn import_rejects_disjoint_dmabuf() {
+        let dmabuf = dmabuf(vec![memfd(), memfd()]);
+        assert!(matches!(
+            check_import(&dmabuf, ImageUsageFlags::SAMPLED, 2),
+            Err(Error::DisjointDmabuf)
+        ));
+    }
+}
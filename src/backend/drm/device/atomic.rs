@@ -8,11 +8,11 @@ use std::sync::{
 use drm::control::atomic::AtomicModeReq;
 use drm::control::{
     connector, crtc, framebuffer, plane, property, AtomicCommitFlags, Device as ControlDevice,
-    PropertyValueSet, ResourceHandle,
+    PropertyValueSet, RawResourceHandle, ResourceHandle,
 };
 
 use super::{DevPath, FdWrapper};
-use crate::backend::drm::error::Error;
+use crate::backend::drm::{error::Error, lease::LeasedResources};
 
 use slog::{error, o, trace};
 
@@ -34,6 +34,7 @@ pub struct AtomicDrmDevice<A: AsRawFd + 'static> {
     pub(crate) active: Arc<AtomicBool>,
     old_state: OldState,
     pub(crate) prop_mapping: Mapping,
+    pub(super) disable_connectors: bool,
     logger: ::slog::Logger,
 }
 
@@ -49,6 +50,7 @@ impl<A: AsRawFd + 'static> AtomicDrmDevice<A> {
             active,
             old_state: (Vec::new(), Vec::new(), Vec::new(), Vec::new()),
             prop_mapping: (HashMap::new(), HashMap::new(), HashMap::new()),
+            disable_connectors,
             logger: logger.new(o!("smithay_module" => "backend_drm_atomic", "drm_module" => "device")),
         };
 
@@ -123,57 +125,15 @@ impl<A: AsRawFd + 'static> AtomicDrmDevice<A> {
             source,
         })?;
 
-        // Disable all connectors (otherwise we might run into conflicting commits when restarting the rendering loop)
         let mut req = AtomicModeReq::new();
-        for conn in res_handles.connectors() {
-            let prop = self
-                .prop_mapping
-                .0
-                .get(conn)
-                .expect("Unknown handle")
-                .get("CRTC_ID")
-                .expect("Unknown property CRTC_ID");
-            req.add_property(*conn, *prop, property::Value::CRTC(None));
-        }
-        // Disable all planes
-        for plane in plane_handles {
-            let prop = self
-                .prop_mapping
-                .2
-                .get(&plane)
-                .expect("Unknown handle")
-                .get("CRTC_ID")
-                .expect("Unknown property CRTC_ID");
-            req.add_property(plane, *prop, property::Value::CRTC(None));
-
-            let prop = self
-                .prop_mapping
-                .2
-                .get(&plane)
-                .expect("Unknown handle")
-                .get("FB_ID")
-                .expect("Unknown property FB_ID");
-            req.add_property(plane, *prop, property::Value::Framebuffer(None));
-        }
-        // A crtc without a connector has no mode, we also need to reset that.
-        // Otherwise the commit will not be accepted.
-        for crtc in res_handles.crtcs() {
-            let mode_prop = self
-                .prop_mapping
-                .1
-                .get(crtc)
-                .expect("Unknown handle")
-                .get("MODE_ID")
-                .expect("Unknown property MODE_ID");
-            let active_prop = self
-                .prop_mapping
-                .1
-                .get(crtc)
-                .expect("Unknown handle")
-                .get("ACTIVE")
-                .expect("Unknown property ACTIVE");
-            req.add_property(*crtc, *active_prop, property::Value::Boolean(false));
-            req.add_property(*crtc, *mode_prop, property::Value::Unknown(0));
+        for (handle, prop) in reset_properties(
+            &self.prop_mapping,
+            &self.fd.leased,
+            res_handles.connectors(),
+            res_handles.crtcs(),
+            &plane_handles,
+        ) {
+            req.add_raw_property(handle, prop, 0);
         }
         self.fd
             .atomic_commit(AtomicCommitFlags::ALLOW_MODESET, req)
@@ -185,6 +145,54 @@ impl<A: AsRawFd + 'static> AtomicDrmDevice<A> {
 
         Ok(())
     }
+
+    pub(super) fn restore_state(&self) -> Result<(), Error> {
+        // Resources might have disappeared in the meantime (e.g. DisplayPort MST connectors),
+        // so we only restore those, which still exist.
+        let res_handles = self.fd.resource_handles().map_err(|source| Error::Access {
+            errmsg: "Error loading drm resources",
+            dev: self.fd.dev_path(),
+            source,
+        })?;
+        let plane_handles = self.fd.plane_handles().map_err(|source| Error::Access {
+            errmsg: "Error loading drm plane resources",
+            dev: self.fd.dev_path(),
+            source,
+        })?;
+
+        // create an atomic mode request consisting of all properties we captured on creation.
+        let mut req = AtomicModeReq::new();
+        fn add_multiple_props<T: ResourceHandle + PartialEq>(
+            req: &mut AtomicModeReq,
+            old_state: &[(T, PropertyValueSet)],
+            existing: &[T],
+            leased: &LeasedResources,
+        ) {
+            for (handle, set) in old_state
+                .iter()
+                .filter(|(handle, _)| existing.contains(handle) && !leased.contains(*handle))
+            {
+                let (prop_handles, values) = set.as_props_and_values();
+                for (&prop_handle, &val) in prop_handles.iter().zip(values.iter()) {
+                    req.add_raw_property((*handle).into(), prop_handle, val);
+                }
+            }
+        }
+
+        let leased = &self.fd.leased;
+        add_multiple_props(&mut req, &self.old_state.0, res_handles.connectors(), leased);
+        add_multiple_props(&mut req, &self.old_state.1, res_handles.crtcs(), leased);
+        add_multiple_props(&mut req, &self.old_state.2, res_handles.framebuffers(), leased);
+        add_multiple_props(&mut req, &self.old_state.3, &plane_handles, leased);
+
+        self.fd
+            .atomic_commit(AtomicCommitFlags::ALLOW_MODESET, req)
+            .map_err(|source| Error::Access {
+                errmsg: "Failed to restore previous state",
+                dev: self.fd.dev_path(),
+                source,
+            })
+    }
 }
 
 impl<A: AsRawFd + 'static> Drop for AtomicDrmDevice<A> {
@@ -195,34 +203,52 @@ impl<A: AsRawFd + 'static> Drop for AtomicDrmDevice<A> {
             // so that getty will be visible.
             // We do exit correctly if this fails, but the user will be presented with
             // a black screen if no display handler takes control again.
-
-            // create an atomic mode request consisting of all properties we captured on creation.
-            // TODO, check current connector status and remove deactivated connectors from this req.
-            let mut req = AtomicModeReq::new();
-            fn add_multiple_props<T: ResourceHandle>(
-                req: &mut AtomicModeReq,
-                old_state: &[(T, PropertyValueSet)],
-            ) {
-                for (handle, set) in old_state {
-                    let (prop_handles, values) = set.as_props_and_values();
-                    for (&prop_handle, &val) in prop_handles.iter().zip(values.iter()) {
-                        req.add_raw_property((*handle).into(), prop_handle, val);
-                    }
-                }
-            }
-
-            add_multiple_props(&mut req, &self.old_state.0);
-            add_multiple_props(&mut req, &self.old_state.1);
-            add_multiple_props(&mut req, &self.old_state.2);
-            add_multiple_props(&mut req, &self.old_state.3);
-
-            if let Err(err) = self.fd.atomic_commit(AtomicCommitFlags::ALLOW_MODESET, req) {
+            if let Err(err) = self.restore_state() {
                 error!(self.logger, "Failed to restore previous state. Error: {}", err);
             }
         }
     }
 }
 
+// Properties resetting the given resources, all of them are set to zero:
+// Connectors and planes are detached from their crtc, planes have no framebuffer attached,
+// crtcs are disabled and have no mode (a crtc without a connector has no mode,
+// otherwise the commit will not be accepted).
+//
+// Leased resources are skipped, they are controlled by the lessee. So are resources created
+// after the device (e.g. DisplayPort MST connectors appearing while the session was paused),
+// which are not part of the mapping. They are disabled on creation by the kernel.
+fn reset_properties(
+    mapping: &Mapping,
+    leased: &LeasedResources,
+    connectors: &[connector::Handle],
+    crtcs: &[crtc::Handle],
+    planes: &[plane::Handle],
+) -> Vec<(RawResourceHandle, property::Handle)> {
+    fn props<T: ResourceHandle + Eq + std::hash::Hash>(
+        mapping: &HashMap<T, HashMap<String, property::Handle>>,
+        leased: &LeasedResources,
+        handles: &[T],
+        names: &[&str],
+    ) -> Vec<(RawResourceHandle, property::Handle)> {
+        handles
+            .iter()
+            .filter(|handle| !leased.contains(**handle))
+            .filter_map(|handle| mapping.get(handle).map(|props| (handle, props)))
+            .flat_map(|(handle, props)| {
+                names
+                    .iter()
+                    .filter_map(move |name| props.get(*name).map(|prop| ((*handle).into(), *prop)))
+            })
+            .collect()
+    }
+
+    let mut properties = props(&mapping.0, leased, connectors, &["CRTC_ID"]);
+    properties.extend(props(&mapping.2, leased, planes, &["CRTC_ID", "FB_ID"]));
+    properties.extend(props(&mapping.1, leased, crtcs, &["ACTIVE", "MODE_ID"]));
+    properties
+}
+
 // Add all properties of given handles to a given drm resource type to state.
 // You may use this to snapshot the current state of the drm device (fully or partially).
 fn add_props<D, T>(fd: &D, handles: &[T], state: &mut Vec<(T, PropertyValueSet)>) -> Result<(), Error>
@@ -287,3 +313,79 @@ where
             source,
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{reset_properties, Mapping};
+    use crate::backend::drm::lease::LeasedResources;
+    use drm::control::{connector, crtc, plane, property, RawResourceHandle};
+    use std::collections::HashMap;
+
+    fn handle<T: From<RawResourceHandle>>(id: u32) -> T {
+        T::from(RawResourceHandle::new(id).unwrap())
+    }
+
+    fn props(names: &[(&str, u32)]) -> HashMap<String, property::Handle> {
+        names
+            .iter()
+            .map(|(name, id)| (name.to_string(), handle(*id)))
+            .collect()
+    }
+
+    fn mapping() -> Mapping {
+        (
+            HashMap::from([(handle(1), props(&[("CRTC_ID", 100)]))]),
+            HashMap::from([(handle(2), props(&[("ACTIVE", 101), ("MODE_ID", 102)]))]),
+            HashMap::from([(handle(3), props(&[("CRTC_ID", 103), ("FB_ID", 104)]))]),
+        )
+    }
+
+    fn ids(properties: Vec<(RawResourceHandle, property::Handle)>) -> Vec<(u32, u32)> {
+        properties
+            .into_iter()
+            .map(|(handle, prop)| (handle.get(), RawResourceHandle::from(prop).get()))
+            .collect()
+    }
+
+    #[test]
+    fn reset_disables_known_resources() {
+        let properties = reset_properties(
+            &mapping(),
+            &LeasedResources::default(),
+            &[handle::<connector::Handle>(1)],
+            &[handle::<crtc::Handle>(2)],
+            &[handle::<plane::Handle>(3)],
+        );
+        assert_eq!(
+            ids(properties),
+            vec![(1, 100), (3, 103), (3, 104), (2, 101), (2, 102)]
+        );
+    }
+
+    #[test]
+    fn reset_skips_unknown_resources() {
+        // e.g. an mst connector, that appeared while the session was paused
+        let properties = reset_properties(
+            &mapping(),
+            &LeasedResources::default(),
+            &[handle::<connector::Handle>(1), handle(10)],
+            &[handle::<crtc::Handle>(11), handle(2)],
+            &[handle::<plane::Handle>(12)],
+        );
+        assert_eq!(ids(properties), vec![(1, 100), (2, 101), (2, 102)]);
+    }
+
+    #[test]
+    fn reset_skips_leased_resources() {
+        let leased = LeasedResources::default();
+        leased.insert(42, &[1, 2]);
+        let properties = reset_properties(
+            &mapping(),
+            &leased,
+            &[handle::<connector::Handle>(1)],
+            &[handle::<crtc::Handle>(2)],
+            &[handle::<plane::Handle>(3)],
+        );
+        assert_eq!(ids(properties), vec![(3, 103), (3, 104)]);
+    }
+}
@@ -17,6 +17,7 @@ pub struct LegacyDrmDevice<A: AsRawFd + 'static> {
     pub(crate) fd: Arc<FdWrapper<A>>,
     pub(crate) active: Arc<AtomicBool>,
     old_state: HashMap<crtc::Handle, (crtc::Info, Vec<connector::Handle>)>,
+    pub(super) disable_connectors: bool,
     logger: ::slog::Logger,
 }
 
@@ -31,6 +32,7 @@ impl<A: AsRawFd + 'static> LegacyDrmDevice<A> {
             fd,
             active,
             old_state: HashMap::new(),
+            disable_connectors,
             logger: logger.new(o!("smithay_module" => "backend_drm_legacy", "drm_module" => "device")),
         };
 
@@ -89,9 +91,19 @@ impl<A: AsRawFd + 'static> LegacyDrmDevice<A> {
             dev: self.fd.dev_path(),
             source,
         })?;
-        set_connector_state(&*self.fd, res_handles.connectors().iter().copied(), false)?;
+        // leased resources are controlled by the lessee
+        let leased = &self.fd.leased;
+        set_connector_state(
+            &*self.fd,
+            res_handles
+                .connectors()
+                .iter()
+                .copied()
+                .filter(|conn| !leased.contains(*conn)),
+            false,
+        )?;
 
-        for crtc in res_handles.crtcs() {
+        for crtc in res_handles.crtcs().iter().filter(|crtc| !leased.contains(**crtc)) {
             #[allow(deprecated)]
             let _ = self
                 .fd
@@ -108,6 +120,23 @@ impl<A: AsRawFd + 'static> LegacyDrmDevice<A> {
 
         Ok(())
     }
+
+    pub(super) fn restore_state(&self) {
+        for (handle, (info, connectors)) in self.old_state.iter() {
+            if self.fd.leased.contains(*handle) {
+                continue;
+            }
+            if let Err(err) = self.fd.set_crtc(
+                *handle,
+                info.framebuffer(),
+                info.position(),
+                connectors,
+                info.mode(),
+            ) {
+                error!(self.logger, "Failed to reset crtc ({:?}). Error: {}", handle, err);
+            }
+        }
+    }
 }
 
 impl<A: AsRawFd + 'static> Drop for LegacyDrmDevice<A> {
@@ -119,17 +148,7 @@ impl<A: AsRawFd + 'static> Drop for LegacyDrmDevice<A> {
             // so that getty will be visible.
             // We do exit correctly, if this fails, but the user will be presented with
             // a black screen, if no display handler takes control again.
-            for (handle, (info, connectors)) in self.old_state.drain() {
-                if let Err(err) = self.fd.set_crtc(
-                    handle,
-                    info.framebuffer(),
-                    info.position(),
-                    &connectors,
-                    info.mode(),
-                ) {
-                    error!(self.logger, "Failed to reset crtc ({:?}). Error: {}", handle, err);
-                }
-            }
+            self.restore_state();
         }
     }
 }
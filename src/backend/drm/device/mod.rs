@@ -3,11 +3,16 @@ use std::cell::RefCell;
 use std::io;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::PathBuf;
-use std::sync::{atomic::AtomicBool, Arc};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 use std::time::{Instant, SystemTime};
 
 use calloop::{EventSource, Interest, Poll, PostAction, Readiness, Token, TokenFactory};
-use drm::control::{connector, crtc, Device as ControlDevice, Event, Mode, ResourceHandles};
+use drm::control::{
+    connector, crtc, plane, Device as ControlDevice, Event, Mode, RawResourceHandle, ResourceHandles,
+};
 use drm::{ClientCapability, Device as BasicDevice, DriverCapability};
 use nix::libc::dev_t;
 use nix::sys::stat::fstat;
@@ -17,12 +22,19 @@ pub(super) mod legacy;
 use crate::utils::{Physical, Size};
 
 use super::surface::{atomic::AtomicDrmSurface, legacy::LegacyDrmSurface, DrmSurface, DrmSurfaceInternal};
-use super::{error::Error, planes, Planes};
+use super::{
+    error::Error,
+    lease::{DrmLease, LeasedResources},
+    planes, Planes,
+};
 use atomic::AtomicDrmDevice;
 use legacy::LegacyDrmDevice;
 
 use slog::{error, info, o, trace, warn};
 
+// `DRM_CLIENT_CAP_WRITEBACK_CONNECTORS`, not yet part of the drm bindings
+const DRM_CLIENT_CAP_WRITEBACK_CONNECTORS: u64 = 5;
+
 /// An open drm device
 #[derive(Debug)]
 pub struct DrmDevice<A: AsRawFd + 'static> {
@@ -53,6 +65,7 @@ impl<A: AsRawFd + 'static> ControlDevice for DrmDevice<A> {}
 pub struct FdWrapper<A: AsRawFd + 'static> {
     fd: A,
     pub(super) privileged: bool,
+    pub(super) leased: LeasedResources,
     logger: ::slog::Logger,
 }
 
@@ -92,6 +105,36 @@ impl<A: AsRawFd + 'static> AsRawFd for DrmDeviceInternal<A> {
 impl<A: AsRawFd + 'static> BasicDevice for DrmDeviceInternal<A> {}
 impl<A: AsRawFd + 'static> ControlDevice for DrmDeviceInternal<A> {}
 
+impl<A: AsRawFd + 'static> DrmDeviceInternal<A> {
+    /// Restores the state captured on device creation
+    pub(super) fn restore_state(&self) -> Result<(), Error> {
+        match self {
+            DrmDeviceInternal::Atomic(dev) => dev.restore_state(),
+            DrmDeviceInternal::Legacy(dev) => {
+                dev.restore_state();
+                Ok(())
+            }
+        }
+    }
+
+    /// Disables all connectors, crtcs and planes, that are not leased
+    pub(super) fn reset_state(&self) -> Result<(), Error> {
+        match self {
+            DrmDeviceInternal::Atomic(dev) => dev.reset_state(),
+            DrmDeviceInternal::Legacy(dev) => dev.reset_state(),
+        }
+    }
+
+    /// Whether the device was created with `disable_connectors`,
+    /// see [`DrmDevice::new`]
+    pub(super) fn disable_connectors(&self) -> bool {
+        match self {
+            DrmDeviceInternal::Atomic(dev) => dev.disable_connectors,
+            DrmDeviceInternal::Legacy(dev) => dev.disable_connectors,
+        }
+    }
+}
+
 impl<A: AsRawFd + 'static> DrmDevice<A> {
     /// Create a new [`DrmDevice`] from an open drm node
     ///
@@ -103,7 +146,8 @@ impl<A: AsRawFd + 'static> DrmDevice<A> {
     ///     to a surface, and disables them, when detached. Setting this to `false` \
     ///     requires usage of `drm-rs` to disable unused connectors to prevent them \
     ///     showing garbage, but will also prevent flickering of already turned on \
-    ///     connectors (assuming you won't change the resolution).
+    ///     connectors (assuming you won't change the resolution). \
+    ///     The same applies, when the session is activated again.
     /// - `logger` - Optional [`slog::Logger`] to be used by this device.
     ///
     /// # Return
@@ -124,6 +168,7 @@ impl<A: AsRawFd + 'static> DrmDevice<A> {
             let mut dev = FdWrapper {
                 fd,
                 privileged: false,
+                leased: LeasedResources::default(),
                 logger: log.clone(),
             };
 
@@ -197,6 +242,12 @@ impl<A: AsRawFd + 'static> DrmDevice<A> {
 
         Ok(
             if !force_legacy && dev.set_client_capability(ClientCapability::Atomic, true).is_ok() {
+                // writeback connectors are only exposed to atomic clients asking for them
+                if drm_ffi::set_capability(dev.as_raw_fd(), DRM_CLIENT_CAP_WRITEBACK_CONNECTORS, true)
+                    .is_err()
+                {
+                    info!(log, "Writeback connectors are not supported");
+                }
                 DrmDeviceInternal::Atomic(AtomicDrmDevice::new(dev, active, disable_connectors, log)?)
             } else {
                 info!(log, "Falling back to LegacyDrmDevice");
@@ -310,6 +361,113 @@ impl<A: AsRawFd + 'static> DrmDevice<A> {
         })
     }
 
+    /// Leases the given resources to another process.
+    ///
+    /// The lessee gains exclusive control over the given [`connector`](drm::control::connector)s,
+    /// [`crtc`](drm::control::crtc)s and [`plane`](drm::control::plane)s through the fd of the
+    /// returned [`DrmLease`]. Leased resources must not be used by any [`DrmSurface`] of this device
+    /// until the lease is revoked. Until then they are also skipped, when the state of the device
+    /// is reset or restored.
+    ///
+    /// Usually a lease consists of a connector, a crtc compatible with it
+    /// and the [primary plane](DrmDevice::planes) of that crtc.
+    pub fn create_lease(
+        &self,
+        connectors: &[connector::Handle],
+        crtcs: &[crtc::Handle],
+        planes: &[plane::Handle],
+    ) -> Result<DrmLease, Error> {
+        let (active, leased) = match &*self.internal {
+            DrmDeviceInternal::Atomic(dev) => (dev.active.clone(), dev.fd.leased.clone()),
+            DrmDeviceInternal::Legacy(dev) => (dev.active.clone(), dev.fd.leased.clone()),
+        };
+        if !active.load(Ordering::SeqCst) {
+            return Err(Error::DeviceInactive);
+        }
+
+        let objects = connectors
+            .iter()
+            .map(|conn| RawResourceHandle::from(*conn))
+            .chain(crtcs.iter().map(|crtc| RawResourceHandle::from(*crtc)))
+            .chain(planes.iter().map(|plane| RawResourceHandle::from(*plane)))
+            .map(|handle| handle.get())
+            .collect::<Vec<u32>>();
+
+        let lease = drm_ffi::mode::create_lease(self.as_raw_fd(), &objects, nix::libc::O_CLOEXEC as u32)
+            .map_err(|source| Error::Access {
+                errmsg: "Failed to create lease",
+                dev: self.dev_path(),
+                source: source.into(),
+            })?;
+        info!(
+            self.logger,
+            "Created lease {} for connectors {:?}, crtcs {:?}, planes {:?}",
+            lease.lessee_id,
+            connectors,
+            crtcs,
+            planes
+        );
+        leased.insert(lease.lessee_id, &objects);
+
+        Ok(DrmLease {
+            id: lease.lessee_id,
+            fd: Some(lease.fd as RawFd),
+            connectors: connectors.to_vec(),
+            crtcs: crtcs.to_vec(),
+            planes: planes.to_vec(),
+            device: self.internal.clone(),
+            active,
+            leased,
+            logger: self.logger.clone(),
+        })
+    }
+
+    /// Restores the state of the device captured on its creation
+    ///
+    /// This is done automatically, when the device is dropped or the session is paused
+    /// (see [`Signal::PauseSession`](crate::backend::session::Signal::PauseSession)),
+    /// so that e.g. the framebuffer console is shown again. Leased resources are left untouched.
+    /// Any [`DrmSurface`] of this device needs to be [reset](DrmSurface::reset_state) before being used again.
+    pub fn restore_state(&self) -> Result<(), Error> {
+        let active = match &*self.internal {
+            DrmDeviceInternal::Atomic(dev) => &dev.active,
+            DrmDeviceInternal::Legacy(dev) => &dev.active,
+        };
+        if !active.load(Ordering::SeqCst) {
+            return Err(Error::DeviceInactive);
+        }
+        self.internal.restore_state()
+    }
+
+    /// Returns the writeback connectors of this device
+    ///
+    /// Writeback connectors capture the output of a crtc into a buffer instead of driving a display,
+    /// see [`DrmSurface::queue_writeback`]. They are only available on atomic devices
+    /// and are never reported as part of the connectors of a [`ConnectorScanner`](super::scanner::ConnectorScanner).
+    pub fn writeback_connectors(&self) -> Result<Vec<connector::Handle>, Error> {
+        if !self.is_atomic() {
+            return Err(Error::WritebackNotSupported);
+        }
+
+        let res_handles = self.resource_handles().map_err(|source| Error::Access {
+            errmsg: "Error loading drm resources",
+            dev: self.dev_path(),
+            source,
+        })?;
+        let mut connectors = Vec::new();
+        for conn in res_handles.connectors() {
+            let info = self.get_connector(*conn, false).map_err(|source| Error::Access {
+                errmsg: "Error loading connector info",
+                dev: self.dev_path(),
+                source,
+            })?;
+            if info.interface() == connector::Interface::Writeback {
+                connectors.push(*conn);
+            }
+        }
+        Ok(connectors)
+    }
+
     /// Returns the device_id of the underlying drm node
     pub fn device_id(&self) -> dev_t {
         self.dev_id
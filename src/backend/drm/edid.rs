@@ -0,0 +1,577 @@
+//! Parsing of the EDID (Extended Display Identification Data) of a connected monitor
+//!
+//! Most monitors report their identity and capabilities through an EDID blob,
+//! which is exposed by drm through the `EDID` property of a [`connector`](drm::control::connector).
+//! [`Edid::from_connector`] reads and parses this blob, [`Edid::parse`] may be used on raw data
+//! obtained by other means (e.g. from sysfs).
+//!
+//! The parsed information can be used to create the [`PhysicalProperties`] of an [`Output`](crate::output::Output)
+//! via [`Edid::physical_properties`], giving outputs stable names for per-monitor configuration.
+
+use drm::control::{connector, property, Device as ControlDevice};
+
+use super::{DevPath, DrmError};
+use crate::output::{PhysicalProperties, Subpixel};
+
+const BLOCK_SIZE: usize = 128;
+const HEADER: [u8; 8] = [0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00];
+
+/// Timings advertised by the established timings bitmap, in bitmap order
+const ESTABLISHED_TIMINGS: [(u32, u32, u32); 17] = [
+    (720, 400, 70),
+    (720, 400, 88),
+    (640, 480, 60),
+    (640, 480, 67),
+    (640, 480, 72),
+    (640, 480, 75),
+    (800, 600, 56),
+    (800, 600, 60),
+    (800, 600, 72),
+    (800, 600, 75),
+    (832, 624, 75),
+    (1024, 768, 87),
+    (1024, 768, 60),
+    (1024, 768, 70),
+    (1024, 768, 75),
+    (1280, 1024, 75),
+    (1152, 870, 75),
+];
+
+/// Errors thrown while reading or parsing an EDID blob
+#[derive(Debug, thiserror::Error)]
+pub enum EdidError {
+    /// Reading the property of the connector failed
+    #[error("Failed to read the EDID property: {0}")]
+    Drm(#[from] DrmError),
+    /// The connector has no EDID blob, e.g. because no monitor is connected
+    #[error("The connector has no EDID")]
+    NoEdid,
+    /// The blob is shorter than the blocks it announces
+    #[error("The EDID blob is too short ({0} bytes)")]
+    TooShort(usize),
+    /// The blob does not start with the fixed EDID header
+    #[error("The EDID blob has an invalid header")]
+    InvalidHeader,
+    /// The checksum of the given block does not match
+    #[error("The checksum of EDID block {0} is invalid")]
+    InvalidChecksum(usize),
+}
+
+/// Information parsed from an EDID blob
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edid {
+    /// Three letter PNP id of the manufacturer, e.g. `"DEL"`
+    pub manufacturer: String,
+    /// Manufacturer specific product code
+    pub product_code: u16,
+    /// Numeric serial number, if set
+    pub serial_number: Option<u32>,
+    /// Serial number from the display descriptors, if set
+    pub serial: Option<String>,
+    /// Name of the monitor from the display descriptors, if set
+    pub model: Option<String>,
+    /// Year of manufacture (or the model year)
+    pub year: u16,
+    /// Week of manufacture, if set
+    pub week: Option<u8>,
+    /// EDID version as `(version, revision)`
+    pub version: (u8, u8),
+    /// Physical size of the screen in millimeters, `(0, 0)` if unknown
+    pub size: (u32, u32),
+    /// Timings of the established and standard timings sections
+    pub timings: Vec<Timing>,
+    /// Detailed timings of the base block and the CTA-861 extension, with the preferred timing first
+    pub detailed_timings: Vec<DetailedTiming>,
+    /// HDR static metadata of the CTA-861 extension, if present
+    pub hdr: Option<HdrMetadata>,
+}
+
+/// A timing only described by its resolution and refresh rate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Timing {
+    /// Horizontal resolution in pixels
+    pub width: u32,
+    /// Vertical resolution in pixels
+    pub height: u32,
+    /// Refresh rate in Hz
+    pub refresh: u32,
+}
+
+/// A fully described timing of a detailed timing descriptor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DetailedTiming {
+    /// Pixel clock in kHz
+    pub clock: u32,
+    /// Horizontal addressable pixels
+    pub hactive: u16,
+    /// Horizontal blanking pixels
+    pub hblank: u16,
+    /// Horizontal front porch in pixels
+    pub hsync_offset: u16,
+    /// Horizontal sync pulse width in pixels
+    pub hsync_width: u16,
+    /// Vertical addressable lines
+    pub vactive: u16,
+    /// Vertical blanking lines
+    pub vblank: u16,
+    /// Vertical front porch in lines
+    pub vsync_offset: u16,
+    /// Vertical sync pulse width in lines
+    pub vsync_width: u16,
+    /// Physical size of the image in millimeters
+    pub size: (u16, u16),
+    /// Whether the timing is interlaced
+    pub interlaced: bool,
+}
+
+impl DetailedTiming {
+    /// Refresh rate in mHz
+    pub fn refresh(&self) -> u32 {
+        let total = (self.hactive as u64 + self.hblank as u64) * (self.vactive as u64 + self.vblank as u64);
+        if total == 0 {
+            return 0;
+        }
+        (self.clock as u64 * 1_000_000 / total) as u32
+    }
+}
+
+/// Electro-optical transfer functions supported by a HDR capable monitor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Eotf {
+    /// Traditional gamma with SDR luminance range
+    TraditionalSdr,
+    /// Traditional gamma with HDR luminance range
+    TraditionalHdr,
+    /// SMPTE ST 2084 (perceptual quantizer)
+    Pq,
+    /// Hybrid log-gamma
+    Hlg,
+}
+
+/// HDR static metadata of a CTA-861 extension block
+#[derive(Debug, Clone, PartialEq)]
+pub struct HdrMetadata {
+    /// Supported transfer functions
+    pub eotfs: Vec<Eotf>,
+    /// Whether static metadata type 1 is supported
+    pub static_metadata_type1: bool,
+    /// Desired content max luminance in cd/m², if provided
+    pub max_luminance: Option<f32>,
+    /// Desired content max frame-average luminance in cd/m², if provided
+    pub max_frame_average_luminance: Option<f32>,
+    /// Desired content min luminance in cd/m², if provided
+    pub min_luminance: Option<f32>,
+}
+
+impl Edid {
+    /// Reads and parses the `EDID` property of the given connector
+    pub fn from_connector<D>(device: &D, connector: connector::Handle) -> Result<Edid, EdidError>
+    where
+        D: ControlDevice,
+    {
+        let props = device
+            .get_properties(connector)
+            .map_err(|source| DrmError::Access {
+                errmsg: "Failed to read connector properties",
+                dev: device.dev_path(),
+                source,
+            })?;
+        let (ids, vals) = props.as_props_and_values();
+        for (&id, &val) in ids.iter().zip(vals.iter()) {
+            let info = device.get_property(id).map_err(|source| DrmError::Access {
+                errmsg: "Failed to read connector property",
+                dev: device.dev_path(),
+                source,
+            })?;
+            if info.name().to_str() != Ok("EDID") {
+                continue;
+            }
+            return match info.value_type().convert_value(val) {
+                property::Value::Blob(blob) if blob != 0 => {
+                    let data = device
+                        .get_property_blob(blob)
+                        .map_err(|source| DrmError::Access {
+                            errmsg: "Failed to query property blob data",
+                            dev: device.dev_path(),
+                            source,
+                        })?;
+                    Edid::parse(&data)
+                }
+                _ => Err(EdidError::NoEdid),
+            };
+        }
+        Err(EdidError::NoEdid)
+    }
+
+    /// Parses a raw EDID blob
+    ///
+    /// Extension blocks other than CTA-861 are ignored.
+    pub fn parse(data: &[u8]) -> Result<Edid, EdidError> {
+        if data.len() < BLOCK_SIZE {
+            return Err(EdidError::TooShort(data.len()));
+        }
+        if data[0..8] != HEADER {
+            return Err(EdidError::InvalidHeader);
+        }
+        let extensions = data[126] as usize;
+        if data.len() < BLOCK_SIZE * (extensions + 1) {
+            return Err(EdidError::TooShort(data.len()));
+        }
+        for (i, block) in data.chunks_exact(BLOCK_SIZE).take(extensions + 1).enumerate() {
+            if block.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+                return Err(EdidError::InvalidChecksum(i));
+            }
+        }
+
+        let base = &data[0..BLOCK_SIZE];
+        let id = u16::from_be_bytes([base[8], base[9]]);
+        let manufacturer = [(id >> 10) & 0x1f, (id >> 5) & 0x1f, id & 0x1f]
+            .iter()
+            .map(|letter| (b'A' - 1 + *letter as u8) as char)
+            .collect();
+        let serial_number = match u32::from_le_bytes([base[12], base[13], base[14], base[15]]) {
+            0 => None,
+            serial => Some(serial),
+        };
+        let version = (base[18], base[19]);
+
+        let mut edid = Edid {
+            manufacturer,
+            product_code: u16::from_le_bytes([base[10], base[11]]),
+            serial_number,
+            serial: None,
+            model: None,
+            year: 1990 + base[17] as u16,
+            // 0xff marks the year as model year
+            week: Some(base[16]).filter(|week| *week != 0 && *week != 0xff),
+            version,
+            // a single zero value encodes an aspect ratio, not a size
+            size: if base[21] != 0 && base[22] != 0 {
+                (base[21] as u32 * 10, base[22] as u32 * 10)
+            } else {
+                (0, 0)
+            },
+            timings: Vec::new(),
+            detailed_timings: Vec::new(),
+            hdr: None,
+        };
+
+        let established = u32::from_be_bytes([base[35], base[36], base[37], 0]);
+        for (i, (width, height, refresh)) in ESTABLISHED_TIMINGS.iter().enumerate() {
+            if established & (1 << (31 - i)) != 0 {
+                edid.timings.push(Timing {
+                    width: *width,
+                    height: *height,
+                    refresh: *refresh,
+                });
+            }
+        }
+        for timing in base[38..54].chunks_exact(2) {
+            if let Some(timing) = parse_standard_timing(timing, version) {
+                edid.timings.push(timing);
+            }
+        }
+
+        for descriptor in base[54..126].chunks_exact(18) {
+            if descriptor[0] != 0 || descriptor[1] != 0 {
+                edid.detailed_timings.push(parse_detailed_timing(descriptor));
+                continue;
+            }
+            match descriptor[3] {
+                0xff => edid.serial = parse_descriptor_text(descriptor),
+                0xfc => edid.model = parse_descriptor_text(descriptor),
+                _ => {}
+            }
+        }
+
+        for block in data[BLOCK_SIZE..].chunks_exact(BLOCK_SIZE).take(extensions) {
+            // CTA-861 extension
+            if block[0] == 0x02 {
+                parse_cta_block(block, &mut edid);
+            }
+        }
+
+        // the size in the preferred timing is more precise
+        if let Some(timing) = edid.detailed_timings.first() {
+            if timing.size.0 != 0 && timing.size.1 != 0 {
+                edid.size = (timing.size.0 as u32, timing.size.1 as u32);
+            }
+        }
+
+        Ok(edid)
+    }
+
+    /// Returns the preferred timing of the monitor, if any
+    pub fn preferred_timing(&self) -> Option<&DetailedTiming> {
+        self.detailed_timings.first()
+    }
+
+    /// Name of the monitor, falling back to the product code if no name is provided
+    pub fn model_name(&self) -> String {
+        self.model
+            .clone()
+            .unwrap_or_else(|| format!("0x{:04X}", self.product_code))
+    }
+
+    /// Serial of the monitor, preferring the textual serial over the numeric serial number
+    pub fn serial_name(&self) -> Option<String> {
+        self.serial
+            .clone()
+            .or_else(|| self.serial_number.map(|serial| format!("0x{:08X}", serial)))
+    }
+
+    /// An identifier for this monitor, which is stable across reboots and the port it is connected to
+    ///
+    /// Consists of the manufacturer, model and serial, if available. Identical monitors without serial
+    /// share the same identifier.
+    pub fn identifier(&self) -> String {
+        match self.serial_name() {
+            Some(serial) => format!("{} {} {}", self.manufacturer, self.model_name(), serial),
+            None => format!("{} {}", self.manufacturer, self.model_name()),
+        }
+    }
+
+    /// Creates the [`PhysicalProperties`] of an output displaying on this monitor
+    pub fn physical_properties(&self, subpixel: Subpixel) -> PhysicalProperties {
+        PhysicalProperties {
+            size: (self.size.0 as i32, self.size.1 as i32).into(),
+            subpixel,
+            make: self.manufacturer.clone(),
+            model: self.model_name(),
+        }
+    }
+}
+
+fn parse_standard_timing(data: &[u8], version: (u8, u8)) -> Option<Timing> {
+    // unused entries are filled with 0x01
+    if data[0] <= 0x01 {
+        return None;
+    }
+    let width = (data[0] as u32 + 31) * 8;
+    let height = match data[1] >> 6 {
+        0b00 if version < (1, 3) => width,
+        0b00 => width * 10 / 16,
+        0b01 => width * 3 / 4,
+        0b10 => width * 4 / 5,
+        _ => width * 9 / 16,
+    };
+    Some(Timing {
+        width,
+        height,
+        refresh: (data[1] & 0x3f) as u32 + 60,
+    })
+}
+
+fn parse_detailed_timing(data: &[u8]) -> DetailedTiming {
+    let low_high = |low: u8, high: u8, shift: u8| low as u16 | (((high >> shift) as u16 & 0x0f) << 8);
+    DetailedTiming {
+        clock: u16::from_le_bytes([data[0], data[1]]) as u32 * 10,
+        hactive: low_high(data[2], data[4], 4),
+        hblank: low_high(data[3], data[4], 0),
+        vactive: low_high(data[5], data[7], 4),
+        vblank: low_high(data[6], data[7], 0),
+        hsync_offset: data[8] as u16 | ((data[11] as u16 & 0xc0) << 2),
+        hsync_width: data[9] as u16 | ((data[11] as u16 & 0x30) << 4),
+        vsync_offset: (data[10] >> 4) as u16 | ((data[11] as u16 & 0x0c) << 2),
+        vsync_width: (data[10] & 0x0f) as u16 | ((data[11] as u16 & 0x03) << 4),
+        size: (low_high(data[12], data[14], 4), low_high(data[13], data[14], 0)),
+        interlaced: data[17] & 0x80 != 0,
+    }
+}
+
+fn parse_descriptor_text(data: &[u8]) -> Option<String> {
+    let text = &data[5..18];
+    let end = text.iter().position(|c| *c == b'\n').unwrap_or(text.len());
+    let text = String::from_utf8_lossy(&text[..end]).trim().to_string();
+    Some(text).filter(|text| !text.is_empty())
+}
+
+fn parse_cta_block(block: &[u8], edid: &mut Edid) {
+    let dtd_offset = (block[2] as usize).min(127);
+    // data blocks are only present if the dtds do not start right after the header
+    let mut offset = 4;
+    while dtd_offset >= 4 && offset < dtd_offset {
+        let tag = block[offset] >> 5;
+        let len = (block[offset] & 0x1f) as usize;
+        let payload = match block.get(offset + 1..offset + 1 + len) {
+            Some(payload) => payload,
+            None => break,
+        };
+        // extended tag, HDR static metadata
+        if tag == 7 && len >= 3 && payload[0] == 6 {
+            edid.hdr = Some(parse_hdr_metadata(&payload[1..]));
+        }
+        offset += len + 1;
+    }
+
+    if dtd_offset >= 4 {
+        for descriptor in block[dtd_offset..127].chunks_exact(18) {
+            if descriptor[0] == 0 && descriptor[1] == 0 {
+                break;
+            }
+            edid.detailed_timings.push(parse_detailed_timing(descriptor));
+        }
+    }
+}
+
+fn parse_hdr_metadata(data: &[u8]) -> HdrMetadata {
+    let eotfs = [Eotf::TraditionalSdr, Eotf::TraditionalHdr, Eotf::Pq, Eotf::Hlg]
+        .into_iter()
+        .enumerate()
+        .filter(|(bit, _)| data[0] & (1 << bit) != 0)
+        .map(|(_, eotf)| eotf)
+        .collect();
+
+    // luminance values are encoded as defined by CTA-861.3
+    let max_luminance = data
+        .get(2)
+        .filter(|cv| **cv != 0)
+        .map(|cv| 50.0 * 2f32.powf(*cv as f32 / 32.0));
+    let max_frame_average_luminance = data
+        .get(3)
+        .filter(|cv| **cv != 0)
+        .map(|cv| 50.0 * 2f32.powf(*cv as f32 / 32.0));
+    let min_luminance = data
+        .get(4)
+        .zip(max_luminance)
+        .map(|(cv, max)| max * (*cv as f32 / 255.0).powi(2) / 100.0);
+
+    HdrMetadata {
+        eotfs,
+        static_metadata_type1: data[1] & 0x01 != 0,
+        max_luminance,
+        max_frame_average_luminance,
+        min_luminance,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finish_block(block: &mut [u8]) {
+        let sum = block[..127].iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        block[127] = 0u8.wrapping_sub(sum);
+    }
+
+    fn test_edid() -> Vec<u8> {
+        let mut data = vec![0u8; 256];
+        let base = &mut data[0..128];
+        base[0..8].copy_from_slice(&HEADER);
+        // "DEL"
+        base[8..10].copy_from_slice(&((4u16 << 10) | (5 << 5) | 12).to_be_bytes());
+        base[10..12].copy_from_slice(&0xa0c4u16.to_le_bytes());
+        base[12..16].copy_from_slice(&1234u32.to_le_bytes());
+        base[16] = 12;
+        base[17] = 30;
+        base[18] = 1;
+        base[19] = 4;
+        base[21] = 60;
+        base[22] = 34;
+        // 640x480@60, 800x600@60
+        base[35] = 0b0010_0001;
+        // 1920x1080@60 (16:9), rest unused
+        base[38] = (1920 / 8 - 31) as u8;
+        base[39] = 0b1100_0000;
+        for byte in base[40..54].iter_mut() {
+            *byte = 0x01;
+        }
+        // 1920x1080@60, 148.5 MHz, 598x336 mm
+        base[54..72].copy_from_slice(&[
+            0x02, 0x3a, 0x80, 0x18, 0x71, 0x38, 0x2d, 0x40, 0x58, 0x2c, 0x45, 0x00, 0x56, 0x50, 0x21, 0x00,
+            0x00, 0x1e,
+        ]);
+        base[72..90].copy_from_slice(&[
+            0, 0, 0, 0xfc, 0, b'D', b'E', b'L', b'L', b' ', b'U', b'2', b'7', b'2', b'0', b'Q', b'\n', b' ',
+        ]);
+        base[90..108].copy_from_slice(&[
+            0, 0, 0, 0xff, 0, b'A', b'B', b'C', b'1', b'2', b'3', b'\n', b' ', b' ', b' ', b' ', b' ', b' ',
+        ]);
+        base[108..126].copy_from_slice(&[0, 0, 0, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        base[126] = 1;
+        finish_block(base);
+
+        let cta = &mut data[128..256];
+        cta[0] = 0x02;
+        cta[1] = 0x03;
+        // extended tag block: HDR static metadata with SDR + PQ
+        cta[4..11].copy_from_slice(&[(7 << 5) | 6, 6, 0b0000_0101, 0x01, 96, 80, 12]);
+        cta[2] = 11;
+        finish_block(cta);
+
+        data
+    }
+
+    #[test]
+    fn parse_identification() {
+        let edid = Edid::parse(&test_edid()).unwrap();
+        assert_eq!(edid.manufacturer, "DEL");
+        assert_eq!(edid.product_code, 0xa0c4);
+        assert_eq!(edid.serial_number, Some(1234));
+        assert_eq!(edid.serial.as_deref(), Some("ABC123"));
+        assert_eq!(edid.model.as_deref(), Some("DELL U2720Q"));
+        assert_eq!(edid.year, 2020);
+        assert_eq!(edid.week, Some(12));
+        assert_eq!(edid.version, (1, 4));
+        assert_eq!(edid.identifier(), "DEL DELL U2720Q ABC123");
+
+        let props = edid.physical_properties(Subpixel::Unknown);
+        assert_eq!(props.make, "DEL");
+        assert_eq!(props.model, "DELL U2720Q");
+        assert_eq!(props.size, (598, 336).into());
+    }
+
+    #[test]
+    fn parse_timings() {
+        let edid = Edid::parse(&test_edid()).unwrap();
+        assert_eq!(
+            edid.timings,
+            vec![
+                Timing {
+                    width: 640,
+                    height: 480,
+                    refresh: 60
+                },
+                Timing {
+                    width: 800,
+                    height: 600,
+                    refresh: 60
+                },
+                Timing {
+                    width: 1920,
+                    height: 1080,
+                    refresh: 60
+                },
+            ]
+        );
+
+        let preferred = edid.preferred_timing().unwrap();
+        assert_eq!(preferred.clock, 148_500);
+        assert_eq!((preferred.hactive, preferred.vactive), (1920, 1080));
+        assert_eq!((preferred.hblank, preferred.vblank), (280, 45));
+        assert_eq!((preferred.hsync_offset, preferred.hsync_width), (88, 44));
+        assert_eq!((preferred.vsync_offset, preferred.vsync_width), (4, 5));
+        assert_eq!(preferred.refresh(), 60_000);
+        assert!(!preferred.interlaced);
+    }
+
+    #[test]
+    fn parse_hdr() {
+        let hdr = Edid::parse(&test_edid()).unwrap().hdr.unwrap();
+        assert_eq!(hdr.eotfs, vec![Eotf::TraditionalSdr, Eotf::Pq]);
+        assert!(hdr.static_metadata_type1);
+        assert!((hdr.max_luminance.unwrap() - 400.0).abs() < 0.5);
+        assert!(hdr.min_luminance.unwrap() < 0.1);
+    }
+
+    #[test]
+    fn reject_invalid() {
+        let mut data = test_edid();
+        assert!(matches!(Edid::parse(&data[..100]), Err(EdidError::TooShort(100))));
+        assert!(matches!(Edid::parse(&data[..128]), Err(EdidError::TooShort(128))));
+        data[130] ^= 0xff;
+        assert!(matches!(Edid::parse(&data), Err(EdidError::InvalidChecksum(1))));
+        data[0] = 0xff;
+        assert!(matches!(Edid::parse(&data), Err(EdidError::InvalidHeader)));
+    }
+}
@@ -59,12 +59,39 @@ pub enum Error {
     /// Atomic Test failed for new properties
     #[error("Atomic Test failed for new properties on crtc ({0:?})")]
     TestFailed(crtc::Handle),
+    /// Variable refresh rate is not supported by the crtc or one of its connectors
+    #[error("Variable refresh rate is not supported on crtc `{0:?}`")]
+    VrrNotSupported(crtc::Handle),
+    /// Asynchronous page flips are not supported by the device or for the requested planes
+    #[error("Asynchronous page flips are not supported on crtc `{0:?}`")]
+    AsyncPageFlipNotSupported(crtc::Handle),
+    /// Writeback connectors are not supported by the device
+    #[error("Writeback connectors are not supported by the device")]
+    WritebackNotSupported,
+    /// The given connector is not a writeback connector
+    #[error("Connector `{0:?}` is not a writeback connector")]
+    NoWritebackConnector(connector::Handle),
+    /// The provided gamma ramp does not match the gamma size of the crtc
+    #[error("Gamma ramp of size {got} does not match the gamma size {expected} of crtc `{crtc:?}`")]
+    GammaSizeMismatch {
+        /// CRTC
+        crtc: crtc::Handle,
+        /// Gamma size of the crtc
+        expected: u32,
+        /// Size of the provided ramp
+        got: usize,
+    },
+    /// The in-fence of a commit or page flip did not signal in time
+    #[error("Timed out waiting for the in-fence of crtc `{0:?}`")]
+    FenceTimeout(crtc::Handle),
 }
 
 impl From<Error> for SwapBuffersError {
     fn from(err: Error) -> SwapBuffersError {
         match err {
-            x @ Error::DeviceInactive => SwapBuffersError::TemporaryFailure(Box::new(x)),
+            x @ Error::DeviceInactive | x @ Error::FenceTimeout(_) => {
+                SwapBuffersError::TemporaryFailure(Box::new(x))
+            }
             Error::Access {
                 errmsg, dev, source, ..
             } if matches!(
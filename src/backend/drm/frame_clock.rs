@@ -0,0 +1,252 @@
+//! Frame scheduling based on the presentation times of page flips
+//!
+//! Rendering right after a vblank event wastes most of the frame budget, as the rendered frame
+//! is only presented at the next vblank anyway. A [`FrameClock`] records the presentation times
+//! reported through the [`EventMetadata`] of [`DrmEvent::VBlank`](super::DrmEvent::VBlank)
+//! and predicts the next vblank from the refresh rate of the output, so rendering can be delayed
+//! until shortly before the deadline to minimize latency.
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use smithay::backend::drm::{DrmEvent, DrmDevice, frame_clock::FrameClock};
+//! # use smithay::reexports::calloop::{EventLoop, timer::TimeoutAction};
+//! # let mut event_loop = EventLoop::<()>::try_new().unwrap();
+//! # let device: DrmDevice<std::fs::File> = unimplemented!();
+//! # let mode: smithay::reexports::drm::control::Mode = unimplemented!();
+//! let mut clock = FrameClock::from_mode(&mode);
+//! # let handle = event_loop.handle();
+//! event_loop.handle().insert_source(device, move |event, metadata, _| {
+//!     if let DrmEvent::VBlank(crtc) = event {
+//!         if let Some(metadata) = metadata {
+//!             clock.presented(metadata);
+//!         }
+//!         // render 5ms before the next vblank
+//!         handle.insert_source(clock.render_timer(Duration::from_millis(5)), |_, _, _| {
+//!             // render and queue the next frame
+//!             TimeoutAction::Drop
+//!         });
+//!     }
+//! });
+//! ```
+
+use std::time::{Duration, Instant, SystemTime};
+
+use calloop::timer::Timer;
+use drm::control::Mode;
+
+use super::device::{EventMetadata, Time};
+
+/// Tracks presentation times of an output and predicts upcoming vblanks
+#[derive(Debug, Clone)]
+pub struct FrameClock {
+    refresh_interval: Option<Duration>,
+    last_presentation: Option<Instant>,
+    last_sequence: Option<u32>,
+    estimated_interval: Option<Duration>,
+    missed_frames: u32,
+}
+
+impl FrameClock {
+    /// Creates a new frame clock for an output refreshing at the given interval
+    ///
+    /// If the interval is unknown, it is estimated from the recorded presentations.
+    pub fn new(refresh_interval: Option<Duration>) -> FrameClock {
+        FrameClock {
+            refresh_interval,
+            last_presentation: None,
+            last_sequence: None,
+            estimated_interval: None,
+            missed_frames: 0,
+        }
+    }
+
+    /// Creates a new frame clock for an output driven with the given [`Mode`]
+    pub fn from_mode(mode: &Mode) -> FrameClock {
+        FrameClock::new(refresh_interval(mode))
+    }
+
+    /// Sets the refresh interval, e.g. after a mode change
+    pub fn set_refresh_interval(&mut self, refresh_interval: Option<Duration>) {
+        self.refresh_interval = refresh_interval;
+        self.estimated_interval = None;
+    }
+
+    /// Returns the refresh interval used for predictions
+    ///
+    /// This is either the interval the clock was created with or an estimate
+    /// based on the last presentations.
+    pub fn refresh_interval(&self) -> Option<Duration> {
+        self.refresh_interval.or(self.estimated_interval)
+    }
+
+    /// Records a presentation from the metadata of a vblank event
+    pub fn presented(&mut self, metadata: &EventMetadata) {
+        let time = match metadata.time {
+            Time::Monotonic(instant) => instant,
+            Time::Realtime(time) => realtime_to_instant(time),
+        };
+        self.presented_at(time, Some(metadata.sequence));
+    }
+
+    /// Records a presentation at the given time
+    ///
+    /// The `sequence` number of the vblank is used to detect missed frames and, if no refresh interval
+    /// is known, to estimate it.
+    pub fn presented_at(&mut self, time: Instant, sequence: Option<u32>) {
+        if let (Some(last_time), Some(last_sequence), Some(sequence)) =
+            (self.last_presentation, self.last_sequence, sequence)
+        {
+            let frames = sequence.wrapping_sub(last_sequence);
+            if frames > 0 && time > last_time {
+                self.missed_frames = frames - 1;
+                self.estimated_interval = Some((time - last_time) / frames);
+            }
+        }
+        self.last_presentation = Some(time);
+        self.last_sequence = sequence;
+    }
+
+    /// Returns the time of the last recorded presentation
+    pub fn last_presentation(&self) -> Option<Instant> {
+        self.last_presentation
+    }
+
+    /// Returns the number of vblanks missed between the last two recorded presentations
+    pub fn missed_frames(&self) -> u32 {
+        self.missed_frames
+    }
+
+    /// Predicts the time of the next vblank
+    ///
+    /// Returns `None` if no presentation was recorded yet or the refresh interval is unknown.
+    pub fn next_presentation(&self) -> Option<Instant> {
+        self.next_presentation_after(Instant::now())
+    }
+
+    /// Predicts the time of the first vblank after `now`
+    pub fn next_presentation_after(&self, now: Instant) -> Option<Instant> {
+        let last = self.last_presentation?;
+        let interval = self.refresh_interval().filter(|interval| !interval.is_zero())?;
+        if now < last {
+            return Some(last);
+        }
+        let elapsed = (now - last).as_nanos();
+        let frames = elapsed / interval.as_nanos() + 1;
+        Some(last + Duration::from_nanos((interval.as_nanos() * frames) as u64))
+    }
+
+    /// Returns the time rendering should start at to be presented with the next possible vblank
+    ///
+    /// `margin` is the time required to render and queue a frame. If the next vblank is closer than
+    /// `margin`, the deadline is aligned to the following one.
+    /// Returns `None` if no vblank can be predicted.
+    pub fn render_deadline(&self, margin: Duration) -> Option<Instant> {
+        self.render_deadline_after(Instant::now(), margin)
+    }
+
+    fn render_deadline_after(&self, now: Instant, margin: Duration) -> Option<Instant> {
+        let mut next = self.next_presentation_after(now)?;
+        let interval = self.refresh_interval()?;
+        while next
+            .checked_sub(margin)
+            .map(|deadline| deadline < now)
+            .unwrap_or(true)
+        {
+            if margin >= interval {
+                // rendering takes longer than a frame, start right away
+                return Some(now);
+            }
+            next += interval;
+        }
+        Some(next - margin)
+    }
+
+    /// Creates a [`Timer`] firing `margin` before the next possible vblank
+    ///
+    /// The timer fires immediately, if no vblank can be predicted yet.
+    pub fn render_timer(&self, margin: Duration) -> Timer {
+        match self.render_deadline(margin) {
+            Some(deadline) => Timer::from_deadline(deadline),
+            None => Timer::immediate(),
+        }
+    }
+}
+
+/// Calculates the refresh interval of a [`Mode`] from its pixel clock and timings
+pub fn refresh_interval(mode: &Mode) -> Option<Duration> {
+    let htotal = mode.hsync().2 as u64;
+    let vtotal = mode.vsync().2 as u64;
+    let clock = mode.clock() as u64;
+    if htotal != 0 && vtotal != 0 && clock != 0 {
+        // the clock is given in kHz
+        Some(Duration::from_nanos(htotal * vtotal * 1_000_000 / clock))
+    } else if mode.vrefresh() != 0 {
+        Some(Duration::from_secs(1) / mode.vrefresh())
+    } else {
+        None
+    }
+}
+
+fn realtime_to_instant(time: SystemTime) -> Instant {
+    let now = Instant::now();
+    match SystemTime::now().duration_since(time) {
+        Ok(elapsed) => now.checked_sub(elapsed).unwrap_or(now),
+        Err(err) => now + err.duration(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FrameClock;
+    use std::time::{Duration, Instant};
+
+    const INTERVAL: Duration = Duration::from_millis(16);
+
+    #[test]
+    fn predicts_next_vblank() {
+        let start = Instant::now();
+        let mut clock = FrameClock::new(Some(INTERVAL));
+        assert_eq!(clock.next_presentation_after(start), None);
+
+        clock.presented_at(start, Some(1));
+        assert_eq!(
+            clock.next_presentation_after(start + Duration::from_millis(4)),
+            Some(start + INTERVAL)
+        );
+        // skipped frames are accounted for
+        assert_eq!(
+            clock.next_presentation_after(start + Duration::from_millis(40)),
+            Some(start + INTERVAL * 3)
+        );
+    }
+
+    #[test]
+    fn render_deadline_honors_margin() {
+        let start = Instant::now();
+        let mut clock = FrameClock::new(Some(INTERVAL));
+        clock.presented_at(start, Some(1));
+
+        let margin = Duration::from_millis(5);
+        assert_eq!(
+            clock.render_deadline_after(start + Duration::from_millis(2), margin),
+            Some(start + INTERVAL - margin)
+        );
+        // too late for the next vblank, aim for the one after
+        assert_eq!(
+            clock.render_deadline_after(start + Duration::from_millis(13), margin),
+            Some(start + INTERVAL * 2 - margin)
+        );
+    }
+
+    #[test]
+    fn estimates_interval_and_missed_frames() {
+        let start = Instant::now();
+        let mut clock = FrameClock::new(None);
+        clock.presented_at(start, Some(10));
+        assert_eq!(clock.refresh_interval(), None);
+
+        clock.presented_at(start + INTERVAL * 3, Some(13));
+        assert_eq!(clock.refresh_interval(), Some(INTERVAL));
+        assert_eq!(clock.missed_frames(), 2);
+    }
+}
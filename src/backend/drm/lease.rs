@@ -0,0 +1,258 @@
+//! Leasing of drm resources to other processes
+//!
+//! A [`DrmLease`] grants another process (e.g. a VR runtime) exclusive control over a set of
+//! connectors, crtcs and planes of a [`DrmDevice`](super::DrmDevice), as if it was the drm master of
+//! a device only consisting of these resources. The lessee accesses the resources through the
+//! [`fd`](DrmLease::fd) of the lease.
+//!
+//! Leases are created via [`DrmDevice::create_lease`](super::DrmDevice::create_lease) and stay valid until
+//! they are [revoked](DrmLease::revoke) or dropped.
+//!
+//! Usually only connectors flagged as [`non-desktop`](is_non_desktop) (e.g. of VR headsets) are leased,
+//! as they are not meant to be used as regular outputs by the compositor.
+//!
+//! See `wayland::drm_lease` to offer connectors to wayland clients.
+
+use std::collections::HashMap;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
+use drm::control::{connector, crtc, plane, Device as ControlDevice, RawResourceHandle};
+use slog::{debug, warn};
+
+use super::{device::DevPath, error::Error};
+
+/// An active lease of drm resources
+pub struct DrmLease {
+    pub(super) id: u32,
+    pub(super) fd: Option<RawFd>,
+    pub(super) connectors: Vec<connector::Handle>,
+    pub(super) crtcs: Vec<crtc::Handle>,
+    pub(super) planes: Vec<plane::Handle>,
+    pub(super) device: Arc<dyn AsRawFd>,
+    pub(super) active: Arc<AtomicBool>,
+    pub(super) leased: LeasedResources,
+    pub(super) logger: ::slog::Logger,
+}
+
+/// Resources of a device, that are currently leased out
+///
+/// The lessor must not touch them, e.g. when resetting or restoring the state of the device.
+#[derive(Debug, Default, Clone)]
+pub(super) struct LeasedResources(Arc<Mutex<HashMap<u32, u32>>>);
+
+impl LeasedResources {
+    // Marks the given objects as leased to the given lessee
+    pub(super) fn insert(&self, lessee: u32, objects: &[u32]) {
+        let mut leased = self.0.lock().unwrap();
+        for object in objects {
+            leased.insert(*object, lessee);
+        }
+    }
+
+    // Returns all objects of the given lessee to the lessor
+    pub(super) fn remove(&self, lessee: u32) {
+        self.0.lock().unwrap().retain(|_, id| *id != lessee);
+    }
+
+    pub(super) fn contains(&self, handle: impl Into<RawResourceHandle>) -> bool {
+        self.0.lock().unwrap().contains_key(&handle.into().get())
+    }
+}
+
+impl std::fmt::Debug for DrmLease {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DrmLease")
+            .field("id", &self.id)
+            .field("fd", &self.fd)
+            .field("connectors", &self.connectors)
+            .field("crtcs", &self.crtcs)
+            .field("planes", &self.planes)
+            .finish()
+    }
+}
+
+impl DrmLease {
+    /// Id of the lessee, as used by the kernel
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Returns the file descriptor of the lease, if it was not [taken](DrmLease::take_fd) yet
+    ///
+    /// The fd gives access to the leased resources and needs to be handed to the lessee.
+    pub fn fd(&self) -> Option<RawFd> {
+        self.fd
+    }
+
+    /// Takes ownership of the file descriptor of the lease
+    ///
+    /// The caller is responsible for closing the fd. Otherwise it is closed once the lease is dropped.
+    /// Note that closing all copies of the fd ends the lease, but revoking the lease invalidates
+    /// all copies of the fd.
+    pub fn take_fd(&mut self) -> Option<RawFd> {
+        self.fd.take()
+    }
+
+    /// Leased [`connector`](drm::control::connector)s
+    pub fn connectors(&self) -> &[connector::Handle] {
+        &self.connectors
+    }
+
+    /// Leased [`crtc`](drm::control::crtc)s
+    pub fn crtcs(&self) -> &[crtc::Handle] {
+        &self.crtcs
+    }
+
+    /// Leased [`plane`](drm::control::plane)s
+    pub fn planes(&self) -> &[plane::Handle] {
+        &self.planes
+    }
+
+    /// Revokes the lease, returning control over the leased resources to the lessor
+    ///
+    /// This is also done automatically, when the lease is dropped.
+    pub fn revoke(mut self) -> Result<(), Error> {
+        self.revoke_internal()
+    }
+
+    fn revoke_internal(&mut self) -> Result<(), Error> {
+        if let Some(fd) = self.fd.take() {
+            let _ = nix::unistd::close(fd);
+        }
+        if self.id == 0 {
+            return Ok(());
+        }
+        if !self.active.load(Ordering::SeqCst) {
+            // revoking requires drm master, the lease is revoked by the kernel,
+            // once we lose master permanently.
+            return Err(Error::DeviceInactive);
+        }
+
+        debug!(self.logger, "Revoking lease {}", self.id);
+        drm_ffi::mode::revoke_lease(self.device.as_raw_fd(), self.id).map_err(|source| Error::Access {
+            errmsg: "Failed to revoke lease",
+            dev: self.device.as_raw_fd().dev_path(),
+            source: source.into(),
+        })?;
+        self.leased.remove(self.id);
+        self.id = 0;
+        Ok(())
+    }
+}
+
+/// Returns true, if the given connector is flagged as `non-desktop`
+///
+/// Displays of such connectors (e.g. VR headsets) should not be used as regular outputs,
+/// but may be leased to clients instead.
+pub fn is_non_desktop<D: ControlDevice>(device: &D, connector: connector::Handle) -> Result<bool, Error> {
+    let props = device.get_properties(connector).map_err(|source| Error::Access {
+        errmsg: "Failed to read connector properties",
+        dev: device.dev_path(),
+        source,
+    })?;
+    let (ids, vals) = props.as_props_and_values();
+    for (&id, &val) in ids.iter().zip(vals.iter()) {
+        let info = device.get_property(id).map_err(|source| Error::Access {
+            errmsg: "Failed to read connector property",
+            dev: device.dev_path(),
+            source,
+        })?;
+        if info.name().to_str() == Ok("non-desktop") {
+            return Ok(val != 0);
+        }
+    }
+    // drivers without support for the property do not know of any non-desktop displays
+    Ok(false)
+}
+
+impl Drop for DrmLease {
+    fn drop(&mut self) {
+        match self.revoke_internal() {
+            Ok(()) | Err(Error::DeviceInactive) => {}
+            Err(err) => warn!(self.logger, "Failed to revoke lease: {}", err),
+        }
+        // the lease cannot be tracked anymore
+        self.leased.remove(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DrmLease, LeasedResources};
+    use crate::backend::drm::DrmError;
+    use drm::control::RawResourceHandle;
+    use std::fs::File;
+    use std::os::unix::io::AsRawFd;
+    use std::sync::{atomic::AtomicBool, Arc};
+
+    fn lease(id: u32, active: bool) -> DrmLease {
+        let device = File::open("/dev/null").unwrap();
+        let fd = nix::unistd::dup(device.as_raw_fd()).unwrap();
+        DrmLease {
+            id,
+            fd: Some(fd),
+            connectors: Vec::new(),
+            crtcs: Vec::new(),
+            planes: Vec::new(),
+            device: Arc::new(device),
+            active: Arc::new(AtomicBool::new(active)),
+            leased: LeasedResources::default(),
+            logger: ::slog::Logger::root(::slog::Discard, ::slog::o!()),
+        }
+    }
+
+    #[test]
+    fn inactive_device_keeps_lease() {
+        let mut lease = lease(42, false);
+        assert!(matches!(lease.revoke_internal(), Err(DrmError::DeviceInactive)));
+        // the fd is closed anyway, but the lease may still be revoked once the device is active again
+        assert_eq!(lease.fd(), None);
+        assert_eq!(lease.id(), 42);
+    }
+
+    #[test]
+    fn failed_revoke_keeps_lease() {
+        let mut lease = lease(42, true);
+        assert!(matches!(lease.revoke_internal(), Err(DrmError::Access { .. })));
+        assert_eq!(lease.id(), 42);
+        // don't try again on drop
+        lease.id = 0;
+    }
+
+    #[test]
+    fn leased_resources_are_tracked_until_drop() {
+        let handle = RawResourceHandle::new(7).unwrap();
+        let mut lease = lease(42, false);
+        lease.leased.insert(42, &[handle.get()]);
+        let leased = lease.leased.clone();
+
+        // the lease can not be revoked on an inactive device, the lessee keeps its resources
+        assert!(lease.revoke_internal().is_err());
+        assert!(leased.contains(handle));
+
+        std::mem::drop(lease);
+        assert!(!leased.contains(handle));
+    }
+
+    #[test]
+    fn leased_resources_of_other_lessees_are_kept() {
+        let leased = LeasedResources::default();
+        leased.insert(1, &[1, 2]);
+        leased.insert(2, &[3]);
+        leased.remove(1);
+        assert!(!leased.contains(RawResourceHandle::new(1).unwrap()));
+        assert!(!leased.contains(RawResourceHandle::new(2).unwrap()));
+        assert!(leased.contains(RawResourceHandle::new(3).unwrap()));
+    }
+
+    #[test]
+    fn revoked_lease_is_not_revoked_again() {
+        let mut lease = lease(0, false);
+        assert!(lease.revoke_internal().is_ok());
+        assert!(lease.revoke().is_ok());
+    }
+}
@@ -70,19 +70,27 @@
 //! [`DrmDevice`] instead.
 
 pub(crate) mod device;
+pub mod edid;
 pub(self) mod error;
+pub mod frame_clock;
+pub(self) mod lease;
 pub mod node;
+pub mod scanner;
 
 #[cfg(feature = "backend_session")]
 pub(self) mod session;
 pub(self) mod surface;
+pub(self) mod writeback;
 
 pub use device::{DevPath, DrmDevice, DrmEvent, EventMetadata as DrmEventMetadata, Time as DrmEventTime};
 pub use error::Error as DrmError;
+pub use lease::{is_non_desktop, DrmLease};
 pub use node::{CreateDrmNodeError, DrmNode, NodeType};
+pub use surface::dumb::{DumbBufferedSurface, DumbFrame, Error as DumbBufferedSurfaceError};
 #[cfg(feature = "backend_gbm")]
 pub use surface::gbm::{Error as GbmBufferedSurfaceError, GbmBufferedSurface};
 pub use surface::DrmSurface;
+pub use writeback::WritebackJob;
 
 use drm::control::{crtc, plane, Device as ControlDevice, PlaneType};
 
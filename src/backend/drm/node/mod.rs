@@ -73,6 +73,15 @@ impl DrmNode {
         Ok(DrmNode { dev, ty })
     }
 
+    // render node of a made up device, for tests not requiring any hardware
+    #[cfg(all(test, feature = "renderer_multi"))]
+    pub(crate) fn dummy(minor: u64) -> DrmNode {
+        DrmNode {
+            dev: nix::sys::stat::makedev(226, 128 + minor),
+            ty: NodeType::Render,
+        }
+    }
+
     /// Returns the type of the DRM node.
     pub fn ty(&self) -> NodeType {
         self.ty
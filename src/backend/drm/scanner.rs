@@ -0,0 +1,304 @@
+//! Tracking of connector state and automatic crtc assignment
+//!
+//! A [`ConnectorScanner`] remembers the connected [`connector`]s of a device between scans
+//! (e.g. on every [`UdevEvent::Changed`](crate::backend::udev::UdevEvent::Changed)) and reports
+//! the differences as [`ConnectorEvent`]s.
+//!
+//! Every connected connector is proposed a [`crtc`] to drive it. The proposed mapping is valid,
+//! meaning every crtc is reachable through one of the encoders of its connector and no crtc is used twice.
+//! Crtcs of already connected connectors are kept as long as they stay connected, so adding a
+//! new connector never requires reconfiguring existing outputs.
+//!
+//! ```no_run
+//! # use smithay::backend::drm::{DrmDevice, scanner::{ConnectorScanner, ConnectorEvent}};
+//! # fn test(device: &DrmDevice<std::fs::File>) {
+//! let mut scanner = ConnectorScanner::new();
+//! for event in scanner.scan(device).unwrap() {
+//!     match event {
+//!         ConnectorEvent::Connected { connector, crtc: Some(crtc) } => {
+//!             // create a surface for the connector on the crtc
+//!         }
+//!         ConnectorEvent::Disconnected { crtc: Some(crtc), .. } => {
+//!             // drop the surface of the crtc
+//!         }
+//!         _ => {}
+//!     }
+//! }
+//! # }
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use drm::control::{connector, crtc, Device as ControlDevice, ResourceHandles};
+
+use super::{device::DevPath, error::Error};
+
+/// Change of a connector detected by a [`ConnectorScanner`]
+#[derive(Debug, Clone)]
+pub enum ConnectorEvent {
+    /// A connector was connected
+    Connected {
+        /// Info of the connector
+        connector: connector::Info,
+        /// Proposed crtc to drive the connector, `None` if all suitable crtcs are in use
+        crtc: Option<crtc::Handle>,
+    },
+    /// A connector was disconnected or removed (e.g. a DisplayPort MST connector)
+    Disconnected {
+        /// Last known info of the connector
+        connector: connector::Info,
+        /// Crtc that was assigned to the connector and is free to use again
+        crtc: Option<crtc::Handle>,
+    },
+    /// A connector stayed connected, but its modes or assigned crtc changed
+    ///
+    /// This usually happens, if a display was swapped without the disconnect being noticed
+    /// or if a connector without a crtc was assigned one, because another connector was disconnected.
+    Changed {
+        /// Updated info of the connector
+        connector: connector::Info,
+        /// Proposed crtc to drive the connector
+        crtc: Option<crtc::Handle>,
+    },
+}
+
+/// Tracks the connectors of a drm device and assigns crtcs to them
+#[derive(Debug, Default)]
+pub struct ConnectorScanner {
+    connectors: HashMap<connector::Handle, connector::Info>,
+    crtcs: HashMap<connector::Handle, crtc::Handle>,
+}
+
+impl ConnectorScanner {
+    /// Creates a new scanner without any known connectors
+    ///
+    /// The first [`scan`](ConnectorScanner::scan) reports all connected connectors.
+    pub fn new() -> ConnectorScanner {
+        ConnectorScanner::default()
+    }
+
+    /// Scans the connectors of the device and returns the changes since the last scan
+    ///
+    /// Disconnected connectors are reported before connected ones, so that their crtcs may be re-used.
+    pub fn scan<D: ControlDevice>(&mut self, device: &D) -> Result<Vec<ConnectorEvent>, Error> {
+        let res_handles = device.resource_handles().map_err(|source| Error::Access {
+            errmsg: "Error loading drm resources",
+            dev: device.dev_path(),
+            source,
+        })?;
+
+        let mut connected = HashMap::new();
+        for conn in res_handles.connectors() {
+            let info = device
+                .get_connector(*conn, true)
+                .map_err(|source| Error::Access {
+                    errmsg: "Error loading connector info",
+                    dev: device.dev_path(),
+                    source,
+                })?;
+            // writeback connectors do not drive any display
+            if info.interface() == connector::Interface::Writeback {
+                continue;
+            }
+            if info.state() == connector::State::Connected {
+                connected.insert(*conn, info);
+            }
+        }
+
+        let mut events = Vec::new();
+        let mut changed = HashSet::new();
+
+        let removed = self
+            .connectors
+            .keys()
+            .filter(|conn| !connected.contains_key(conn))
+            .copied()
+            .collect::<Vec<_>>();
+        for conn in removed {
+            let connector = self.connectors.remove(&conn).unwrap();
+            let crtc = self.crtcs.remove(&conn);
+            events.push(ConnectorEvent::Disconnected { connector, crtc });
+        }
+
+        let mut added = Vec::new();
+        for (conn, info) in connected {
+            match self.connectors.insert(conn, info.clone()) {
+                Some(old) => {
+                    if old.modes() != info.modes() || old.size() != info.size() {
+                        changed.insert(conn);
+                    }
+                }
+                None => added.push(conn),
+            }
+        }
+
+        // try to find crtcs for new connectors and those, which did not get one previously
+        let unassigned = self
+            .connectors
+            .keys()
+            .filter(|conn| !self.crtcs.contains_key(conn))
+            .copied()
+            .collect::<Vec<_>>();
+        let used = self.crtcs.values().copied().collect::<HashSet<_>>();
+        let mapping = propose_crtcs(device, &res_handles, &unassigned, &used)?;
+        for (conn, crtc) in mapping {
+            self.crtcs.insert(conn, crtc);
+            if !added.contains(&conn) {
+                changed.insert(conn);
+            }
+        }
+
+        for conn in added {
+            events.push(ConnectorEvent::Connected {
+                connector: self.connectors[&conn].clone(),
+                crtc: self.crtcs.get(&conn).copied(),
+            });
+        }
+        for conn in changed {
+            events.push(ConnectorEvent::Changed {
+                connector: self.connectors[&conn].clone(),
+                crtc: self.crtcs.get(&conn).copied(),
+            });
+        }
+
+        Ok(events)
+    }
+
+    /// Returns the info of all currently connected connectors
+    pub fn connectors(&self) -> impl Iterator<Item = &connector::Info> {
+        self.connectors.values()
+    }
+
+    /// Returns the crtc assigned to a connected connector
+    pub fn crtc(&self, connector: connector::Handle) -> Option<crtc::Handle> {
+        self.crtcs.get(&connector).copied()
+    }
+
+    /// Returns the current connector to crtc mapping
+    pub fn crtcs(&self) -> &HashMap<connector::Handle, crtc::Handle> {
+        &self.crtcs
+    }
+}
+
+/// Proposes a valid mapping of connectors to crtcs
+///
+/// Every connector is mapped to a crtc reachable through one of its encoders, which is not part of `used`.
+/// The crtc currently driving a connector is preferred. Connectors are missing from the result,
+/// if no free crtc could be found for them.
+pub fn propose_crtcs<D: ControlDevice>(
+    device: &D,
+    res_handles: &ResourceHandles,
+    connectors: &[connector::Handle],
+    used: &HashSet<crtc::Handle>,
+) -> Result<HashMap<connector::Handle, crtc::Handle>, Error> {
+    let mut candidates = Vec::with_capacity(connectors.len());
+    for conn in connectors {
+        let info = device
+            .get_connector(*conn, false)
+            .map_err(|source| Error::Access {
+                errmsg: "Error loading connector info",
+                dev: device.dev_path(),
+                source,
+            })?;
+
+        let mut crtcs = Vec::new();
+        for enc in info
+            .current_encoder()
+            .into_iter()
+            .chain(info.encoders().iter().copied())
+        {
+            let enc_info = device.get_encoder(enc).map_err(|source| Error::Access {
+                errmsg: "Error loading encoder info",
+                dev: device.dev_path(),
+                source,
+            })?;
+            // prefer the crtc already in use, this avoids modesets
+            if Some(enc) == info.current_encoder() {
+                crtcs.extend(enc_info.crtc());
+            }
+            for crtc in res_handles.filter_crtcs(enc_info.possible_crtcs()) {
+                if !crtcs.contains(&crtc) {
+                    crtcs.push(crtc);
+                }
+            }
+        }
+        candidates.push((*conn, crtcs));
+    }
+
+    Ok(match_crtcs(&candidates, used))
+}
+
+// Finds a maximum matching between connectors and crtcs using augmenting paths.
+// Earlier candidates of every connector are tried first.
+fn match_crtcs<C, T>(candidates: &[(C, Vec<T>)], used: &HashSet<T>) -> HashMap<C, T>
+where
+    C: Copy + Eq + Hash,
+    T: Copy + Eq + Hash,
+{
+    let mut assigned = HashMap::new();
+    for idx in 0..candidates.len() {
+        let mut visited = HashSet::new();
+        try_assign(idx, candidates, used, &mut assigned, &mut visited);
+    }
+    assigned
+        .into_iter()
+        .map(|(crtc, idx)| (candidates[idx].0, crtc))
+        .collect()
+}
+
+fn try_assign<C, T>(
+    idx: usize,
+    candidates: &[(C, Vec<T>)],
+    used: &HashSet<T>,
+    assigned: &mut HashMap<T, usize>,
+    visited: &mut HashSet<T>,
+) -> bool
+where
+    T: Copy + Eq + Hash,
+{
+    for crtc in candidates[idx].1.iter() {
+        if used.contains(crtc) || !visited.insert(*crtc) {
+            continue;
+        }
+        let free = match assigned.get(crtc) {
+            None => true,
+            Some(&other) => try_assign(other, candidates, used, assigned, visited),
+        };
+        if free {
+            assigned.insert(*crtc, idx);
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::match_crtcs;
+    use std::collections::{HashMap, HashSet};
+
+    #[test]
+    fn reassigns_contended_crtcs() {
+        // the first connector could take crtc 1, but the second one has no other choice
+        let candidates = [("a", vec![1, 2]), ("b", vec![1])];
+        let mapping = match_crtcs(&candidates, &HashSet::new());
+        assert_eq!(mapping, HashMap::from([("a", 2), ("b", 1)]));
+    }
+
+    #[test]
+    fn honors_used_crtcs() {
+        let candidates = [("a", vec![1, 2]), ("b", vec![2, 3])];
+        let mapping = match_crtcs(&candidates, &HashSet::from([2]));
+        assert_eq!(mapping, HashMap::from([("a", 1), ("b", 3)]));
+    }
+
+    #[test]
+    fn leaves_connectors_without_crtc() {
+        let candidates = [("a", vec![1]), ("b", vec![1]), ("c", vec![2])];
+        let mapping = match_crtcs(&candidates, &HashSet::new());
+        assert_eq!(mapping.len(), 2);
+        assert_eq!(mapping.get("c"), Some(&2));
+        assert!(mapping.contains_key("a") ^ mapping.contains_key("b"));
+    }
+}
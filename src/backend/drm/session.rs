@@ -4,10 +4,7 @@ use std::sync::{
     Arc, Weak,
 };
 
-use drm::{
-    control::{crtc, Device as ControlDevice},
-    Device as BasicDevice,
-};
+use drm::control::crtc;
 use nix::libc::dev_t;
 use nix::sys::stat;
 
@@ -18,7 +15,7 @@ use crate::{
     utils::signaling::{Linkable, Signaler},
 };
 
-use slog::{crit, error, info, o, warn};
+use slog::{crit, error, info, o, trace, warn};
 
 struct DrmDeviceObserver<A: AsRawFd + 'static> {
     dev_id: dev_t,
@@ -66,6 +63,15 @@ impl<A: AsRawFd + 'static> DrmDeviceObserver<A> {
             if major as u64 != stat::major(self.dev_id) || minor as u64 != stat::minor(self.dev_id) {
                 return;
             }
+        } else if let Some(device) = self.dev.upgrade() {
+            // Hand the device over in the state we found it in, e.g. to show the framebuffer console again.
+            // If only the device is paused, we already lost access to it.
+            if let Err(err) = device.restore_state() {
+                warn!(
+                    self.logger,
+                    "Failed to restore previous state of drm device: {}", err
+                );
+            }
         }
 
         self.active.store(false, Ordering::SeqCst);
@@ -99,6 +105,15 @@ impl<A: AsRawFd + 'static> DrmDeviceObserver<A> {
         }
 
         self.active.store(true, Ordering::SeqCst);
+
+        // The previous session might have left the device in any state,
+        // so we start from a known state, if the device was created with `disable_connectors`.
+        // Surfaces set their state again on their next commit.
+        if let Some(device) = self.dev.upgrade().filter(|device| device.disable_connectors()) {
+            if let Err(err) = device.reset_state() {
+                warn!(self.logger, "Failed to reset state of drm device: {}", err);
+            }
+        }
     }
 }
 
@@ -109,15 +124,6 @@ struct DrmSurfaceObserver<A: AsRawFd + 'static> {
     logger: ::slog::Logger,
 }
 
-struct FdHack(RawFd);
-impl AsRawFd for FdHack {
-    fn as_raw_fd(&self) -> RawFd {
-        self.0
-    }
-}
-impl BasicDevice for FdHack {}
-impl ControlDevice for FdHack {}
-
 impl<A: AsRawFd + 'static> Linkable<SessionSignal> for DrmSurface<A> {
     fn link(&mut self, signaler: Signaler<SessionSignal>) {
         let logger = match &*self.internal {
@@ -140,34 +146,31 @@ impl<A: AsRawFd + 'static> DrmSurfaceObserver<A> {
     fn signal(&mut self, signal: SessionSignal) {
         match signal {
             SessionSignal::ActivateSession => self.activate(None),
-            SessionSignal::ActivateDevice { major, minor, new_fd } => {
-                self.activate(Some((major, minor, new_fd)))
-            }
+            SessionSignal::ActivateDevice { major, minor, .. } => self.activate(Some((major, minor))),
             _ => {}
         }
     }
 
-    fn activate(&mut self, devnum: Option<(u32, u32, Option<RawFd>)>) {
+    fn activate(&mut self, devnum: Option<(u32, u32)>) {
         if let Some(surf) = self.surf.upgrade() {
-            // The device will reset the _fd, but the observer order is not deterministic,
-            // so we might need to use it anyway.
-            let fd = if let Some((major, minor, fd)) = devnum {
+            if let Some((major, minor)) = devnum {
                 if major as u64 != stat::major(self.dev_id) || minor as u64 != stat::minor(self.dev_id) {
                     return;
                 }
-                fd.map(FdHack)
-            } else {
-                None
-            };
-
-            if let Err(err) = match &*surf {
-                DrmSurfaceInternal::Atomic(surf) => surf.reset_state(fd.as_ref()),
-                DrmSurfaceInternal::Legacy(surf) => surf.reset_state(fd.as_ref()),
-            } {
-                warn!(
-                    self.logger,
-                    "Failed to reset state of surface ({:?}/{:?}): {}", self.dev_id, self.crtc, err
-                );
+            }
+
+            // The device resets its state on activation, but the observer order is not deterministic,
+            // so reading the current state might still return the state of the previous session.
+            // Instead we assume everything to be disabled, so the next commit sets our complete state again.
+            trace!(
+                self.logger,
+                "Clearing state of surface ({:?}/{:?})",
+                self.dev_id,
+                self.crtc
+            );
+            match &*surf {
+                DrmSurfaceInternal::Atomic(surf) => surf.clear_state(),
+                DrmSurfaceInternal::Legacy(surf) => surf.clear_state(),
             }
         }
     }
@@ -1,33 +1,49 @@
 use drm::control::atomic::AtomicModeReq;
 use drm::control::Device as ControlDevice;
 use drm::control::{
-    connector, crtc, dumbbuffer::DumbBuffer, framebuffer, plane, property, AtomicCommitFlags, Mode, PlaneType,
+    connector, crtc, dumbbuffer::DumbBuffer, framebuffer, plane, property, AtomicCommitFlags, Mode,
+    PageFlipFlags, PlaneType,
 };
+use drm::DriverCapability;
 
 use std::collections::HashSet;
-use std::os::unix::io::AsRawFd;
+use std::convert::TryFrom;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc, Mutex, RwLock,
 };
 
+use io_lifetimes::OwnedFd;
+
 use crate::backend::{
-    allocator::format::{get_bpp, get_depth},
+    allocator::{
+        dmabuf::Dmabuf,
+        format::{get_bpp, get_depth},
+        Fourcc,
+    },
     drm::{
         device::atomic::{map_props, Mapping},
         device::{DevPath, DrmDeviceInternal},
         error::Error,
         plane_type,
+        writeback::{self, WritebackJob, WritebackState},
     },
 };
 
+use super::{legacy::read_gamma, wait_for_fence};
+
 use slog::{debug, info, o, trace, warn};
 
+// `DRM_CAP_ATOMIC_ASYNC_PAGE_FLIP`, introduced with linux 6.8 and not yet part of the drm bindings
+const DRM_CAP_ATOMIC_ASYNC_PAGE_FLIP: u64 = 0x15;
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct State {
     pub mode: Mode,
     pub blob: property::Value<'static>,
     pub connectors: HashSet<connector::Handle>,
+    pub vrr: bool,
 }
 
 impl State {
@@ -96,12 +112,83 @@ impl State {
                 }
             }
         }
+        // variable refresh rate is only reported by drivers supporting it
+        let current_vrr = prop_mapping
+            .1
+            .get(&crtc)
+            .and_then(|props| props.get("VRR_ENABLED"))
+            .and_then(|vrr_prop| {
+                let props = fd.get_properties(crtc).ok()?;
+                let (ids, vals) = props.as_props_and_values();
+                ids.iter()
+                    .zip(vals.iter())
+                    .find(|(id, _)| *id == vrr_prop)
+                    .map(|(_, val)| *val != 0)
+            })
+            .unwrap_or(false);
+
         Ok(State {
             mode: current_mode,
             blob: current_blob,
             connectors: current_connectors,
+            vrr: current_vrr,
         })
     }
+
+    // Forgets the state of the crtc, e.g. after the device was reset.
+    // The mode blob is not destroyed, as it is usually shared with the pending state.
+    fn clear(&mut self) {
+        self.mode = unsafe { std::mem::zeroed() };
+        self.blob = property::Value::Unknown(0);
+        self.connectors.clear();
+        self.vrr = false;
+    }
+
+    // The mode blob of this state, that is not needed anymore once `next` was committed
+    fn obsolete_blob(&self, next: &State) -> Option<u64> {
+        let blob: u64 = self.blob.into();
+        if blob != 0 && self.blob != next.blob {
+            Some(blob)
+        } else {
+            None
+        }
+    }
+}
+
+// The gamma ramp of a crtc before the surface was created, restored by `reset_gamma`
+#[derive(Debug)]
+enum OriginalGamma {
+    // contents of the `GAMMA_LUT` blob, `None` if no lut was set
+    Lut(Option<Vec<u8>>),
+    // red, green and blue channel of the legacy gamma ramp
+    Legacy([Vec<u16>; 3]),
+}
+
+impl OriginalGamma {
+    fn read<A: AsRawFd + ControlDevice>(
+        fd: &A,
+        crtc: crtc::Handle,
+        prop_mapping: &Mapping,
+    ) -> Result<Option<Self>, drm::SystemError> {
+        let lut_prop = match crtc_prop_handle(prop_mapping, crtc, "GAMMA_LUT") {
+            Ok(prop) => prop,
+            // drivers without color management only support the legacy gamma ioctl
+            Err(_) => return Ok(read_gamma(fd, crtc)?.map(OriginalGamma::Legacy)),
+        };
+        let props = fd.get_properties(crtc)?;
+        let (ids, vals) = props.as_props_and_values();
+        let blob = ids
+            .iter()
+            .zip(vals.iter())
+            .find(|(id, _)| **id == lut_prop)
+            .map(|(_, val)| *val)
+            .unwrap_or(0);
+        Ok(Some(OriginalGamma::Lut(if blob != 0 {
+            Some(fd.get_property_blob(blob)?)
+        } else {
+            None
+        })))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -123,6 +210,14 @@ pub struct AtomicDrmSurface<A: AsRawFd + 'static> {
     prop_mapping: RwLock<Mapping>,
     state: RwLock<State>,
     pending: RwLock<State>,
+    gamma_blob: Mutex<Option<u64>>,
+    pending_gamma: Mutex<Option<u64>>,
+    original_gamma: Option<OriginalGamma>,
+    atomic_async_flip: bool,
+    legacy_async_flip: bool,
+    pending_writeback: Mutex<Option<Arc<WritebackState<A>>>>,
+    writeback_connector: Mutex<Option<connector::Handle>>,
+    in_fence: Mutex<Option<OwnedFd>>,
     pub(crate) logger: ::slog::Logger,
 }
 
@@ -158,8 +253,27 @@ impl<A: AsRawFd + 'static> AtomicDrmSurface<A> {
             mode,
             blob,
             connectors: connectors.iter().copied().collect(),
+            // keep the current setting, until changed by `use_vrr`
+            vrr: state.vrr,
+        };
+
+        let original_gamma = match OriginalGamma::read(&*fd, crtc, &prop_mapping) {
+            Ok(gamma) => gamma,
+            Err(err) => {
+                warn!(logger, "Failed to read gamma ramp of {:?}: {}", crtc, err);
+                None
+            }
         };
 
+        let atomic_async_flip = drm_ffi::get_capability(fd.as_raw_fd(), DRM_CAP_ATOMIC_ASYNC_PAGE_FLIP)
+            .map(|cap| cap.value != 0)
+            .unwrap_or(false);
+        let legacy_async_flip = matches!(fd.get_driver_capability(DriverCapability::ASyncPageFlip), Ok(1));
+        debug!(
+            logger,
+            "Async page flips supported: atomic {}, legacy {}", atomic_async_flip, legacy_async_flip
+        );
+
         let surface = AtomicDrmSurface {
             fd,
             active,
@@ -169,6 +283,14 @@ impl<A: AsRawFd + 'static> AtomicDrmSurface<A> {
             prop_mapping: RwLock::new(prop_mapping),
             state: RwLock::new(state),
             pending: RwLock::new(pending),
+            gamma_blob: Mutex::new(None),
+            pending_gamma: Mutex::new(None),
+            original_gamma,
+            atomic_async_flip,
+            legacy_async_flip,
+            pending_writeback: Mutex::new(None),
+            writeback_connector: Mutex::new(None),
+            in_fence: Mutex::new(None),
             logger,
         };
 
@@ -430,6 +552,55 @@ impl<A: AsRawFd + 'static> AtomicDrmSurface<A> {
         Ok(())
     }
 
+    pub fn vrr_supported(&self, conn: connector::Handle) -> Result<bool, Error> {
+        let prop_mapping = self.prop_mapping.read().unwrap();
+        let capable_prop = match conn_prop_handle(&*prop_mapping, conn, "vrr_capable") {
+            Ok(prop) => prop,
+            Err(Error::UnknownProperty { .. }) => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        if crtc_prop_handle(&*prop_mapping, self.crtc, "VRR_ENABLED").is_err() {
+            return Ok(false);
+        }
+
+        let props = self.fd.get_properties(conn).map_err(|source| Error::Access {
+            errmsg: "Failed to read connector properties",
+            dev: self.fd.dev_path(),
+            source,
+        })?;
+        let (ids, vals) = props.as_props_and_values();
+        Ok(ids
+            .iter()
+            .zip(vals.iter())
+            .any(|(id, val)| *id == capable_prop && *val != 0))
+    }
+
+    pub fn vrr_enabled(&self) -> bool {
+        self.state.read().unwrap().vrr
+    }
+
+    pub fn pending_vrr(&self) -> bool {
+        self.pending.read().unwrap().vrr
+    }
+
+    pub fn use_vrr(&self, vrr: bool) -> Result<(), Error> {
+        if !self.active.load(Ordering::SeqCst) {
+            return Err(Error::DeviceInactive);
+        }
+
+        let mut pending = self.pending.write().unwrap();
+        if vrr {
+            for conn in pending.connectors.iter() {
+                if !self.vrr_supported(*conn)? {
+                    return Err(Error::VrrNotSupported(self.crtc));
+                }
+            }
+        }
+        pending.vrr = vrr;
+
+        Ok(())
+    }
+
     pub fn use_plane(
         &self,
         plane: plane::Handle,
@@ -489,7 +660,181 @@ impl<A: AsRawFd + 'static> AtomicDrmSurface<A> {
     }
 
     pub fn commit_pending(&self) -> bool {
-        *self.pending.read().unwrap() != *self.state.read().unwrap()
+        let pending = self.pending.read().unwrap();
+        let current = self.state.read().unwrap();
+        // vrr does not require a modeset and is also applied by page flips
+        pending.mode != current.mode
+            || pending.blob != current.blob
+            || pending.connectors != current.connectors
+            || self.writeback_needs_modeset()
+    }
+
+    // attaching a new writeback connector to the crtc requires a modeset
+    fn writeback_needs_modeset(&self) -> bool {
+        match &*self.pending_writeback.lock().unwrap() {
+            Some(writeback) => *self.writeback_connector.lock().unwrap() != Some(writeback.connector),
+            None => false,
+        }
+    }
+
+    pub fn writeback_formats(&self, conn: connector::Handle) -> Result<Vec<Fourcc>, Error> {
+        self.ensure_props_known(&[conn])?;
+        let prop = match conn_prop_handle(
+            &*self.prop_mapping.read().unwrap(),
+            conn,
+            "WRITEBACK_PIXEL_FORMATS",
+        ) {
+            Ok(prop) => prop,
+            Err(Error::UnknownProperty { .. }) => return Err(Error::NoWritebackConnector(conn)),
+            Err(err) => return Err(err),
+        };
+
+        let props = self.fd.get_properties(conn).map_err(|source| Error::Access {
+            errmsg: "Failed to read connector properties",
+            dev: self.fd.dev_path(),
+            source,
+        })?;
+        let (ids, vals) = props.as_props_and_values();
+        let blob = match ids.iter().zip(vals.iter()).find(|(id, _)| **id == prop) {
+            Some((_, blob)) => *blob,
+            None => return Ok(Vec::new()),
+        };
+        let data = self.fd.get_property_blob(blob).map_err(|source| Error::Access {
+            errmsg: "Failed to query property blob data",
+            dev: self.fd.dev_path(),
+            source,
+        })?;
+
+        // the blob is an array of fourcc codes
+        Ok(data
+            .chunks_exact(4)
+            .filter_map(|code| {
+                Fourcc::try_from(u32::from_ne_bytes([code[0], code[1], code[2], code[3]])).ok()
+            })
+            .collect())
+    }
+
+    pub fn queue_writeback(
+        &self,
+        conn: connector::Handle,
+        buffer: &Dmabuf,
+    ) -> Result<WritebackJob<A>, Error> {
+        if !self.active.load(Ordering::SeqCst) {
+            return Err(Error::DeviceInactive);
+        }
+
+        self.ensure_props_known(&[conn])?;
+        if conn_prop_handle(&*self.prop_mapping.read().unwrap(), conn, "WRITEBACK_FB_ID").is_err() {
+            return Err(Error::NoWritebackConnector(conn));
+        }
+
+        let job = writeback::create_job(&self.fd, conn, buffer)?;
+        trace!(
+            self.logger,
+            "Queueing writeback of {:?} on {:?} to framebuffer {:?}",
+            self.crtc,
+            conn,
+            job.framebuffer()
+        );
+        // a previously queued, but not yet submitted job is replaced
+        *self.pending_writeback.lock().unwrap() = Some(job.state.clone());
+        Ok(job)
+    }
+
+    // Adds the properties to route the crtc to the writeback connector and to write into the framebuffer.
+    fn add_writeback(&self, req: &mut AtomicModeReq, writeback: &WritebackState<A>) -> Result<(), Error> {
+        let prop_mapping = self.prop_mapping.read().unwrap();
+        let attached = *self.writeback_connector.lock().unwrap();
+        if let Some(old) = attached.filter(|conn| *conn != writeback.connector) {
+            req.add_property(
+                old,
+                conn_prop_handle(&*prop_mapping, old, "CRTC_ID")?,
+                property::Value::CRTC(None),
+            );
+        }
+        req.add_property(
+            writeback.connector,
+            conn_prop_handle(&*prop_mapping, writeback.connector, "CRTC_ID")?,
+            property::Value::CRTC(Some(self.crtc)),
+        );
+        req.add_property(
+            writeback.connector,
+            conn_prop_handle(&*prop_mapping, writeback.connector, "WRITEBACK_FB_ID")?,
+            property::Value::Framebuffer(Some(writeback.fb)),
+        );
+        Ok(())
+    }
+
+    // The kernel writes the fd of the out-fence to the given pointer during the commit.
+    fn add_writeback_fence(
+        &self,
+        req: &mut AtomicModeReq,
+        writeback: &WritebackState<A>,
+        out_fence: &mut RawFd,
+    ) -> Result<(), Error> {
+        let prop_mapping = self.prop_mapping.read().unwrap();
+        req.add_property(
+            writeback.connector,
+            conn_prop_handle(&*prop_mapping, writeback.connector, "WRITEBACK_OUT_FENCE_PTR")?,
+            property::Value::UnsignedRange(out_fence as *mut RawFd as u64),
+        );
+        Ok(())
+    }
+
+    fn writeback_submitted(&self, writeback: Arc<WritebackState<A>>, out_fence: RawFd) {
+        trace!(
+            self.logger,
+            "Submitted writeback to framebuffer {:?} (fence: {})",
+            writeback.fb,
+            out_fence
+        );
+        *self.writeback_connector.lock().unwrap() = Some(writeback.connector);
+        writeback.submitted(out_fence);
+    }
+
+    // puts a job back in place, if the commit failed and no other job was queued in the meantime
+    fn restore_writeback(&self, writeback: Option<Arc<WritebackState<A>>>) {
+        let mut pending = self.pending_writeback.lock().unwrap();
+        if pending.is_none() {
+            *pending = writeback;
+        }
+    }
+
+    pub fn set_in_fence(&self, fence: OwnedFd) -> Result<(), Error> {
+        if !self.active.load(Ordering::SeqCst) {
+            return Err(Error::DeviceInactive);
+        }
+
+        if !self.in_fence_supported() {
+            // no way to hand the fence to the kernel, so we need to wait ourselves
+            debug!(self.logger, "IN_FENCE_FD not supported, waiting for fence");
+            return wait_for_fence(fence.as_raw_fd(), self.crtc);
+        }
+        *self.in_fence.lock().unwrap() = Some(fence);
+        Ok(())
+    }
+
+    pub fn in_fence_supported(&self) -> bool {
+        plane_prop_handle(&*self.prop_mapping.read().unwrap(), self.plane, "IN_FENCE_FD").is_ok()
+    }
+
+    // The fence is consumed by the next commit or page flip, test commits never include it.
+    fn add_in_fence(&self, req: &mut AtomicModeReq, fence: &OwnedFd) -> Result<(), Error> {
+        let prop_mapping = self.prop_mapping.read().unwrap();
+        req.add_property(
+            self.plane,
+            plane_prop_handle(&*prop_mapping, self.plane, "IN_FENCE_FD")?,
+            property::Value::SignedRange(fence.as_raw_fd() as i64),
+        );
+        Ok(())
+    }
+
+    // puts a fence back in place, if the commit failed and no other fence was set in the meantime
+    fn restore_in_fence(&self, fence: Option<OwnedFd>) {
+        let mut pending = self.in_fence.lock().unwrap();
+        if pending.is_none() {
+            *pending = fence;
+        }
     }
 
     pub fn commit<'a>(
@@ -537,9 +882,14 @@ impl<A: AsRawFd + 'static> AtomicDrmSurface<A> {
 
         trace!(self.logger, "Testing screen config");
 
+        let writeback = self.pending_writeback.lock().unwrap().clone();
+        // locked until the commit is done, so the staged lut cannot be destroyed in the meantime
+        let mut pending_gamma = self.pending_gamma.lock().unwrap();
+        let gamma = *pending_gamma;
+
         // test the new config and return the request if it would be accepted by the driver.
-        let req = {
-            let req = self.build_request(
+        let mut req = {
+            let mut req = self.build_request(
                 &mut added,
                 &mut removed,
                 self.plane,
@@ -548,6 +898,15 @@ impl<A: AsRawFd + 'static> AtomicDrmSurface<A> {
                 Some(pending.mode),
                 Some(pending.blob),
             )?;
+            if let Ok(vrr_prop) =
+                crtc_prop_handle(&*self.prop_mapping.read().unwrap(), self.crtc, "VRR_ENABLED")
+            {
+                req.add_property(self.crtc, vrr_prop, property::Value::Boolean(pending.vrr));
+            }
+            if let Some(writeback) = writeback.as_ref() {
+                self.add_writeback(&mut req, writeback)?;
+            }
+            self.add_gamma_lut(&mut req, gamma)?;
 
             if let Err(err) = self
                 .fd
@@ -564,17 +923,24 @@ impl<A: AsRawFd + 'static> AtomicDrmSurface<A> {
 
                 return Err(err);
             } else {
-                if current.mode != pending.mode {
-                    if let Err(err) = self.fd.destroy_property_blob(current.blob.into()) {
-                        warn!(self.logger, "Failed to destory old mode property blob: {}", err);
-                    }
-                }
-
                 // new config
                 req
             }
         };
 
+        // the out-fence is not part of the test, as test commits do not create any fences
+        let mut out_fence: RawFd = -1;
+        if let Some(writeback) = writeback.as_ref() {
+            self.add_writeback_fence(&mut req, writeback, &mut out_fence)?;
+        }
+        let in_fence = self.in_fence.lock().unwrap().take();
+        if let Some(fence) = in_fence.as_ref() {
+            if let Err(err) = self.add_in_fence(&mut req, fence) {
+                self.restore_in_fence(in_fence);
+                return Err(err);
+            }
+        }
+
         debug!(self.logger, "Setting screen: {:?}", req);
         let result = self
             .fd
@@ -601,24 +967,63 @@ impl<A: AsRawFd + 'static> AtomicDrmSurface<A> {
                 source,
             });
 
-        if result.is_ok() {
+        if result.is_err() {
+            self.restore_in_fence(in_fence);
+        } else {
+            // the old mode blob may still be referenced by the request, until it succeeded
+            if let Some(blob) = current.obsolete_blob(&pending) {
+                if let Err(err) = self.fd.destroy_property_blob(blob) {
+                    warn!(self.logger, "Failed to destroy old mode property blob: {}", err);
+                }
+            }
             *current = pending.clone();
+            if let Some(gamma) = pending_gamma.take() {
+                self.gamma_submitted(gamma);
+            }
+            if let Some(writeback) = writeback {
+                let mut pending_writeback = self.pending_writeback.lock().unwrap();
+                if matches!(&*pending_writeback, Some(pending) if Arc::ptr_eq(pending, &writeback)) {
+                    pending_writeback.take();
+                }
+                std::mem::drop(pending_writeback);
+                self.writeback_submitted(writeback, out_fence);
+            }
         }
 
         result
     }
 
+    pub fn async_page_flip_supported(&self) -> bool {
+        self.atomic_async_flip || self.legacy_async_flip
+    }
+
     pub fn page_flip<'a>(
         &self,
         framebuffers: impl Iterator<Item = &'a (framebuffer::Handle, plane::Handle)>,
         event: bool,
+        async_flip: bool,
     ) -> Result<(), Error> {
         if !self.active.load(Ordering::SeqCst) {
             return Err(Error::DeviceInactive);
         }
 
+        if async_flip && !self.atomic_async_flip {
+            if !self.legacy_async_flip {
+                return Err(Error::AsyncPageFlipNotSupported(self.crtc));
+            }
+            // the legacy ioctl takes no fences
+            if let Some(fence) = self.in_fence.lock().unwrap().take() {
+                if let Err(err) = wait_for_fence(fence.as_raw_fd(), self.crtc) {
+                    // keep the fence, the flip may be retried once it signaled
+                    self.restore_in_fence(Some(fence));
+                    return Err(err);
+                }
+            }
+            return self.legacy_async_page_flip(framebuffers, event);
+        }
+
         // page flips work just like commits with fewer parameters..
-        let req = self.build_request(
+        let mut req = self.build_request(
             &mut [].iter(),
             &mut [].iter(),
             self.plane,
@@ -628,28 +1033,114 @@ impl<A: AsRawFd + 'static> AtomicDrmSurface<A> {
             None,
         )?;
 
+        // writeback jobs are only part of page flips, if the connector is already attached.
+        // Otherwise they are delayed until the next commit, see `commit_pending`.
+        let writeback = if !async_flip && !self.writeback_needs_modeset() {
+            self.pending_writeback.lock().unwrap().take()
+        } else {
+            None
+        };
+        let mut out_fence: RawFd = -1;
+        if let Some(writeback) = writeback.as_ref() {
+            if let Err(err) = self
+                .add_writeback(&mut req, writeback)
+                .and_then(|_| self.add_writeback_fence(&mut req, writeback, &mut out_fence))
+            {
+                self.restore_writeback(Some(writeback.clone()));
+                return Err(err);
+            }
+        }
+        let in_fence = self.in_fence.lock().unwrap().take();
+        if let Some(fence) = in_fence.as_ref() {
+            if let Err(err) = self.add_in_fence(&mut req, fence) {
+                self.restore_writeback(writeback);
+                self.restore_in_fence(in_fence);
+                return Err(err);
+            }
+        }
+        // async commits may only change framebuffers,
+        // changes of the vrr state and lut are delayed until the next vblank synced one
+        let mut current = self.state.write().unwrap();
+        let vrr = self.pending.read().unwrap().vrr;
+        let vrr_changed = !async_flip && vrr != current.vrr;
+        if vrr_changed {
+            if let Ok(vrr_prop) =
+                crtc_prop_handle(&*self.prop_mapping.read().unwrap(), self.crtc, "VRR_ENABLED")
+            {
+                req.add_property(self.crtc, vrr_prop, property::Value::Boolean(vrr));
+            }
+        }
+        let mut pending_gamma = self.pending_gamma.lock().unwrap();
+        let gamma = if !async_flip { *pending_gamma } else { None };
+        if let Err(err) = self.add_gamma_lut(&mut req, gamma) {
+            self.restore_writeback(writeback);
+            self.restore_in_fence(in_fence);
+            return Err(err);
+        }
+
         // .. and without `AtomicCommitFlags::AllowModeset`.
         // If we would set anything here, that would require a modeset, this would fail,
         // indicating a problem in our assumptions.
         trace!(self.logger, "Queueing page flip: {:?}", req);
-        self.fd
-            .atomic_commit(
-                if event {
-                    AtomicCommitFlags::PAGE_FLIP_EVENT | AtomicCommitFlags::NONBLOCK
-                } else {
-                    AtomicCommitFlags::NONBLOCK
-                },
-                req,
-            )
-            .map_err(|source| Error::Access {
+        let mut flags = AtomicCommitFlags::NONBLOCK;
+        if event {
+            flags |= AtomicCommitFlags::PAGE_FLIP_EVENT;
+        }
+        if async_flip {
+            flags |= AtomicCommitFlags::PAGE_FLIP_ASYNC;
+        }
+        if let Err(source) = self.fd.atomic_commit(flags, req) {
+            self.restore_writeback(writeback);
+            self.restore_in_fence(in_fence);
+            return Err(Error::Access {
                 errmsg: "Page flip commit failed",
                 dev: self.fd.dev_path(),
                 source,
-            })?;
+            });
+        }
+        if let Some(writeback) = writeback {
+            self.writeback_submitted(writeback, out_fence);
+        }
+        if gamma.is_some() {
+            if let Some(gamma) = pending_gamma.take() {
+                self.gamma_submitted(gamma);
+            }
+        }
+        if vrr_changed {
+            current.vrr = vrr;
+        }
 
         Ok(())
     }
 
+    // Drivers without support for atomic async commits might still support async flips
+    // through the legacy ioctl, which is limited to the primary plane.
+    fn legacy_async_page_flip<'a>(
+        &self,
+        framebuffers: impl Iterator<Item = &'a (framebuffer::Handle, plane::Handle)>,
+        event: bool,
+    ) -> Result<(), Error> {
+        let framebuffers = framebuffers.collect::<Vec<_>>();
+        let fb = match framebuffers.as_slice() {
+            [(fb, plane)] if *plane == self.plane => *fb,
+            _ => return Err(Error::AsyncPageFlipNotSupported(self.crtc)),
+        };
+        if !self.additional_planes.lock().unwrap().is_empty() {
+            return Err(Error::AsyncPageFlipNotSupported(self.crtc));
+        }
+
+        trace!(self.logger, "Queueing legacy async page flip");
+        let mut flags = PageFlipFlags::ASYNC;
+        if event {
+            flags |= PageFlipFlags::EVENT;
+        }
+        ControlDevice::page_flip(&*self.fd, self.crtc, fb, flags, None).map_err(|source| Error::Access {
+            errmsg: "Failed to page flip",
+            dev: self.fd.dev_path(),
+            source,
+        })
+    }
+
     pub fn test_buffer(&self, fb: framebuffer::Handle, mode: &Mode) -> Result<bool, Error> {
         if !self.active.load(Ordering::SeqCst) {
             return Err(Error::DeviceInactive);
@@ -950,6 +1441,179 @@ impl<A: AsRawFd + 'static> AtomicDrmSurface<A> {
         result
     }
 
+    pub fn gamma_size(&self) -> Result<u32, Error> {
+        let prop_mapping = self.prop_mapping.read().unwrap();
+        let size_prop = match crtc_prop_handle(&*prop_mapping, self.crtc, "GAMMA_LUT_SIZE") {
+            Ok(prop) => prop,
+            // drivers without color management only support the legacy gamma ioctl
+            Err(Error::UnknownProperty { .. }) => {
+                return self
+                    .fd
+                    .get_crtc(self.crtc)
+                    .map(|info| info.gamma_length())
+                    .map_err(|source| Error::Access {
+                        errmsg: "Error loading crtc info",
+                        dev: self.fd.dev_path(),
+                        source,
+                    })
+            }
+            Err(err) => return Err(err),
+        };
+        let props = self
+            .fd
+            .get_properties(self.crtc)
+            .map_err(|source| Error::Access {
+                errmsg: "Failed to read crtc properties",
+                dev: self.fd.dev_path(),
+                source,
+            })?;
+        let (ids, vals) = props.as_props_and_values();
+        ids.iter()
+            .zip(vals.iter())
+            .find(|(id, _)| **id == size_prop)
+            .map(|(_, val)| *val as u32)
+            .ok_or(Error::UnknownProperty {
+                handle: self.crtc.into(),
+                name: "GAMMA_LUT_SIZE",
+            })
+    }
+
+    pub fn set_gamma(&self, red: &[u16], green: &[u16], blue: &[u16]) -> Result<(), Error> {
+        if !self.active.load(Ordering::SeqCst) {
+            return Err(Error::DeviceInactive);
+        }
+
+        let size = self.gamma_size()?;
+        if let Some(got) = [red.len(), green.len(), blue.len()]
+            .into_iter()
+            .find(|len| *len != size as usize)
+        {
+            return Err(Error::GammaSizeMismatch {
+                crtc: self.crtc,
+                expected: size,
+                got,
+            });
+        }
+
+        if !self.has_gamma_lut() {
+            trace!(self.logger, "Setting legacy gamma ramp on {:?}", self.crtc);
+            return self
+                .fd
+                .set_gamma(self.crtc, red, green, blue)
+                .map_err(|source| Error::Access {
+                    errmsg: "Failed to set gamma ramp",
+                    dev: self.fd.dev_path(),
+                    source,
+                });
+        }
+
+        // the blob is an array of `struct drm_color_lut { u16 red, green, blue, reserved }`
+        let data = red
+            .iter()
+            .zip(green.iter())
+            .zip(blue.iter())
+            .flat_map(|((r, g), b)| [*r, *g, *b, 0])
+            .flat_map(u16::to_ne_bytes)
+            .collect::<Vec<u8>>();
+        self.stage_gamma_lut(data)
+    }
+
+    pub fn reset_gamma(&self) -> Result<(), Error> {
+        if !self.active.load(Ordering::SeqCst) {
+            return Err(Error::DeviceInactive);
+        }
+
+        match &self.original_gamma {
+            Some(OriginalGamma::Lut(Some(data))) => self.stage_gamma_lut(data.clone()),
+            Some(OriginalGamma::Lut(None)) => {
+                // a zero blob disables the lut
+                self.replace_pending_gamma(0);
+                Ok(())
+            }
+            Some(OriginalGamma::Legacy([red, green, blue])) => self.set_gamma(red, green, blue),
+            None if self.has_gamma_lut() => {
+                self.replace_pending_gamma(0);
+                Ok(())
+            }
+            // we failed to read the original ramp, so a linear one is the best we can do
+            None => {
+                let size = self.gamma_size()?;
+                if size < 2 {
+                    return Ok(());
+                }
+                let ramp = (0..size)
+                    .map(|i| (i as u64 * u16::MAX as u64 / (size - 1) as u64) as u16)
+                    .collect::<Vec<u16>>();
+                self.set_gamma(&ramp, &ramp, &ramp)
+            }
+        }
+    }
+
+    fn has_gamma_lut(&self) -> bool {
+        crtc_prop_handle(&*self.prop_mapping.read().unwrap(), self.crtc, "GAMMA_LUT").is_ok()
+    }
+
+    // The lut is not committed on its own, as that would fail while a page flip is pending,
+    // but send with the next commit or page flip.
+    fn stage_gamma_lut(&self, mut data: Vec<u8>) -> Result<(), Error> {
+        let blob = drm_ffi::mode::create_property_blob(self.fd.as_raw_fd(), &mut data)
+            .map_err(|source| Error::Access {
+                errmsg: "Failed to create Property Blob for gamma lut",
+                dev: self.fd.dev_path(),
+                source: source.into(),
+            })?
+            .blob_id as u64;
+        self.replace_pending_gamma(blob);
+        Ok(())
+    }
+
+    fn replace_pending_gamma(&self, blob: u64) {
+        trace!(self.logger, "Staging gamma lut on {:?}: {:?}", self.crtc, blob);
+        let old_blob = self.pending_gamma.lock().unwrap().replace(blob);
+        self.destroy_gamma_blob(old_blob);
+    }
+
+    fn add_gamma_lut(&self, req: &mut AtomicModeReq, gamma: Option<u64>) -> Result<(), Error> {
+        if let Some(blob) = gamma {
+            let prop_mapping = self.prop_mapping.read().unwrap();
+            req.add_property(
+                self.crtc,
+                crtc_prop_handle(&*prop_mapping, self.crtc, "GAMMA_LUT")?,
+                property::Value::Blob(blob),
+            );
+        }
+        Ok(())
+    }
+
+    // replaces the blob in use by the crtc, once the staged lut was committed
+    fn gamma_submitted(&self, blob: u64) {
+        let old_blob = std::mem::replace(
+            &mut *self.gamma_blob.lock().unwrap(),
+            if blob != 0 { Some(blob) } else { None },
+        );
+        self.destroy_gamma_blob(old_blob);
+    }
+
+    // the kernel keeps the lut alive as long as it is in use by the crtc
+    fn destroy_gamma_blob(&self, blob: Option<u64>) {
+        if let Some(blob) = blob.filter(|blob| *blob != 0) {
+            if let Err(err) = self.fd.destroy_property_blob(blob) {
+                warn!(self.logger, "Failed to destroy gamma lut blob: {}", err);
+            }
+        }
+    }
+
+    // Assumes all resources used by the surface to be disabled, e.g. after the device was reset.
+    // The next commit will set the complete pending state.
+    pub(crate) fn clear_state(&self) {
+        let mut current = self.state.write().unwrap();
+        if let Some(blob) = current.obsolete_blob(&*self.pending.read().unwrap()) {
+            let _ = self.fd.destroy_property_blob(blob);
+        }
+        current.clear();
+        *self.writeback_connector.lock().unwrap() = None;
+    }
+
     pub(crate) fn reset_state<B: AsRawFd + ControlDevice + 'static>(
         &self,
         fd: Option<&B>,
@@ -959,6 +1623,8 @@ impl<A: AsRawFd + 'static> AtomicDrmSurface<A> {
         } else {
             State::current_state(&*self.fd, self.crtc, &mut *self.prop_mapping.write().unwrap())?
         };
+        // attached writeback connectors are part of the restored state
+        *self.writeback_connector.lock().unwrap() = None;
         Ok(())
     }
 }
@@ -978,6 +1644,15 @@ impl<A: AsRawFd + 'static> Drop for TestBuffer<A> {
 
 impl<A: AsRawFd + 'static> Drop for AtomicDrmSurface<A> {
     fn drop(&mut self) {
+        // the kernel keeps the lut alive as long as it is in use by the crtc
+        let blobs = [
+            self.gamma_blob.lock().unwrap().take(),
+            self.pending_gamma.lock().unwrap().take(),
+        ];
+        for blob in blobs.into_iter().flatten().filter(|blob| *blob != 0) {
+            let _ = self.fd.destroy_property_blob(blob);
+        }
+
         if !self.active.load(Ordering::SeqCst) {
             // the device is gone or we are on another tty
             // old state has been restored, we shouldn't touch it.
@@ -1018,6 +1693,11 @@ impl<A: AsRawFd + 'static> Drop for AtomicDrmSurface<A> {
                 .expect("Unknown property CRTC_ID");
             req.add_property(*conn, *prop, property::Value::CRTC(None));
         }
+        if let Some(conn) = *self.writeback_connector.lock().unwrap() {
+            if let Ok(prop) = conn_prop_handle(&*prop_mapping, conn, "CRTC_ID") {
+                req.add_property(conn, prop, property::Value::CRTC(None));
+            }
+        }
         let active_prop = prop_mapping
             .1
             .get(&self.crtc)
@@ -1091,7 +1771,9 @@ pub(crate) fn plane_prop_handle(
 }
 #[cfg(test)]
 mod test {
-    use super::AtomicDrmSurface;
+    use super::{AtomicDrmSurface, State};
+    use drm::control::{connector, property, Mode};
+    use std::collections::HashSet;
     use std::fs::File;
 
     fn is_send<S: Send>() {}
@@ -1100,4 +1782,37 @@ mod test {
     fn surface_is_send() {
         is_send::<AtomicDrmSurface<File>>();
     }
+
+    fn state(hdisplay: u16, blob: u64) -> State {
+        let mut mode: drm_ffi::drm_mode_modeinfo = unsafe { std::mem::zeroed() };
+        mode.hdisplay = hdisplay;
+        State {
+            mode: Mode::from(mode),
+            blob: property::Value::Blob(blob),
+            connectors: HashSet::from([connector::Handle::from(
+                drm::control::RawResourceHandle::new(1).unwrap(),
+            )]),
+            vrr: false,
+        }
+    }
+
+    #[test]
+    fn resume_keeps_committed_mode_blob() {
+        // after a commit, current and pending state share the mode blob
+        let pending = state(1920, 5);
+        let mut current = pending.clone();
+        // suspend
+        current.clear();
+        // resuming needs a commit, which may not destroy the blob referenced by itself
+        assert_ne!(current, pending);
+        assert_eq!(current.obsolete_blob(&pending), None);
+    }
+
+    #[test]
+    fn mode_change_destroys_old_blob() {
+        let current = state(1920, 5);
+        let pending = state(1280, 6);
+        assert_eq!(current.obsolete_blob(&pending), Some(5));
+        assert_eq!(pending.obsolete_blob(&pending.clone()), None);
+    }
 }
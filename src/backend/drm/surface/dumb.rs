@@ -0,0 +1,389 @@
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
+
+use drm::buffer::Buffer as DrmBuffer;
+use drm::control::{
+    connector, crtc, dumbbuffer::DumbBuffer as DrmDumbBuffer, dumbbuffer::DumbMapping, framebuffer, plane,
+    Device, Mode,
+};
+
+use crate::backend::allocator::{
+    dumb::{DumbAllocator, DumbBuffer},
+    format::{get_bpp, get_depth},
+    Buffer, Fourcc, Modifier, Slot, Swapchain,
+};
+use crate::backend::drm::{device::DevPath, surface::DrmSurfaceInternal, DrmError, DrmSurface};
+use crate::backend::SwapBuffersError;
+use crate::utils::{Buffer as BufferCoords, Size};
+
+use slog::{debug, o, warn};
+
+/// Simplified abstraction of a swapchain for cpu accessible dumb buffers displayed on a [`DrmSurface`].
+///
+/// Unlike [`GbmBufferedSurface`](super::gbm::GbmBufferedSurface) this requires neither gbm nor
+/// any renderer, which makes it suitable for boot splashes, recovery modes or software rendering.
+#[derive(Debug)]
+pub struct DumbBufferedSurface<D: AsRawFd + 'static> {
+    current_fb: Slot<DumbBuffer<D>>,
+    pending_fb: Option<Slot<DumbBuffer<D>>>,
+    queued_fb: Option<Slot<DumbBuffer<D>>>,
+    next_fb: Option<(Slot<DumbBuffer<D>>, DrmDumbBuffer)>,
+    swapchain: Swapchain<DumbAllocator<D>, DumbBuffer<D>>,
+    drm: Arc<DrmSurface<D>>,
+}
+
+// Formats without alpha are preferred, as there is nothing to blend the primary plane with.
+const SUPPORTED_FORMATS: &[Fourcc] = &[Fourcc::Xrgb8888, Fourcc::Argb8888];
+
+impl<D: AsRawFd + 'static> DumbBufferedSurface<D> {
+    /// Create a new `DumbBufferedSurface` from a given surface.
+    ///
+    /// The first supported format of `XRGB8888` and `ARGB8888` is used for the buffers.
+    pub fn new<L>(drm: DrmSurface<D>, log: L) -> Result<DumbBufferedSurface<D>, Error>
+    where
+        L: Into<Option<::slog::Logger>>,
+    {
+        let log = crate::slog_or_fallback(log).new(o!("backend" => "drm_dumb"));
+        let drm = Arc::new(drm);
+
+        let plane_formats = drm.supported_formats(drm.plane())?;
+        let code = SUPPORTED_FORMATS
+            .iter()
+            .copied()
+            .find(|code| plane_formats.iter().any(|fmt| fmt.code == *code))
+            .ok_or(Error::NoSupportedPlaneFormat)?;
+        debug!(log, "Choosen format: {}", code);
+
+        let allocator = match &*drm.internal {
+            DrmSurfaceInternal::Atomic(surf) => DumbAllocator::from_internal(&*surf.fd),
+            DrmSurfaceInternal::Legacy(surf) => DumbAllocator::from_internal(&*surf.fd),
+        };
+        let mode = drm.pending_mode();
+        let mut swapchain = Swapchain::new(
+            allocator,
+            mode.size().0 as u32,
+            mode.size().1 as u32,
+            code,
+            vec![Modifier::Linear],
+        );
+
+        // Test format
+        let buffer = acquire(&drm, &mut swapchain)?;
+        // dumb buffers are zero-initialized by the kernel, so this shows a black screen
+        let fb = attach_framebuffer(&drm, buffer.handle(), code)?;
+        let fb_handle = fb.fb;
+        buffer.userdata().insert_if_missing(|| fb);
+
+        match drm.test_buffer(fb_handle, &mode, true) {
+            Ok(_) => Ok(DumbBufferedSurface {
+                current_fb: buffer,
+                pending_fb: None,
+                queued_fb: None,
+                next_fb: None,
+                swapchain,
+                drm,
+            }),
+            Err(err) => {
+                warn!(
+                    log,
+                    "Mode-setting failed with dumb buffer format {}: {}", code, err
+                );
+                Err(err.into())
+            }
+        }
+    }
+
+    /// Retrieves the next buffer to be drawn into, mapped into memory.
+    ///
+    /// The returned [`DumbFrame`] also carries the age of the buffer, which can be used
+    /// to only redraw the damaged parts of the buffer.
+    ///
+    /// *Note*: This function can be called multiple times and
+    /// will return the same buffer until it is queued (see [`DumbBufferedSurface::queue_buffer`]).
+    pub fn next_buffer(&mut self) -> Result<DumbFrame<'_>, Error> {
+        if self.next_fb.is_none() {
+            let slot = acquire(&self.drm, &mut self.swapchain)?;
+            let handle = *slot.handle();
+            if slot.userdata().get::<FbHandle<D>>().is_none() {
+                let fb = attach_framebuffer(&self.drm, &handle, slot.format().code)?;
+                slot.userdata().insert_if_missing(|| fb);
+            }
+            self.next_fb = Some((slot, handle));
+        }
+
+        let (slot, handle) = self.next_fb.as_mut().unwrap();
+        let age = slot.age();
+        let format = slot.format().code;
+        let size = slot.size();
+        let pitch = handle.pitch();
+        let mapping = self
+            .drm
+            .map_dumb_buffer(handle)
+            .map_err(|source| DrmError::Access {
+                errmsg: "Failed to map dumb buffer",
+                dev: self.drm.dev_path(),
+                source,
+            })?;
+
+        Ok(DumbFrame {
+            mapping,
+            pitch,
+            size,
+            format,
+            age,
+        })
+    }
+
+    /// Queues the current buffer for scan-out.
+    ///
+    /// *Note*: This function needs to be followed up with [`DumbBufferedSurface::frame_submitted`]
+    /// when a vblank event is received, that denotes successful scanout of the buffer.
+    /// Otherwise the underlying swapchain will eventually run out of buffers.
+    pub fn queue_buffer(&mut self) -> Result<(), Error> {
+        self.queued_fb = self.next_fb.take().map(|(slot, _)| slot);
+        if self.pending_fb.is_none() && self.queued_fb.is_some() {
+            self.submit()?;
+        }
+        Ok(())
+    }
+
+    /// Marks the current frame as submitted.
+    ///
+    /// *Note*: Needs to be called, after the vblank event of the matching [`DrmDevice`](super::super::DrmDevice)
+    /// was received after calling [`DumbBufferedSurface::queue_buffer`] on this surface.
+    /// Otherwise the underlying swapchain will run out of buffers eventually.
+    pub fn frame_submitted(&mut self) -> Result<(), Error> {
+        if let Some(mut pending) = self.pending_fb.take() {
+            std::mem::swap(&mut pending, &mut self.current_fb);
+            if self.queued_fb.is_some() {
+                self.submit()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn submit(&mut self) -> Result<(), Error> {
+        // yes it does not look like it, but both of these lines should be safe in all cases.
+        let slot = self.queued_fb.take().unwrap();
+        let fb = slot.userdata().get::<FbHandle<D>>().unwrap().fb;
+
+        let planes = [(fb, self.drm.plane())];
+        let flip = if self.drm.commit_pending() {
+            self.drm.commit(planes.iter(), true)
+        } else {
+            self.drm.page_flip(planes.iter(), true, false)
+        };
+        if flip.is_ok() {
+            self.swapchain.submitted(&slot);
+            self.pending_fb = Some(slot);
+        }
+        flip.map_err(Error::DrmError)
+    }
+
+    /// Reset the underlying buffers
+    pub fn reset_buffers(&mut self) {
+        self.swapchain.reset_buffers()
+    }
+
+    /// Returns the underlying [`crtc`](drm::control::crtc) of this surface
+    pub fn crtc(&self) -> crtc::Handle {
+        self.drm.crtc()
+    }
+
+    /// Returns the underlying [`plane`](drm::control::plane) of this surface
+    pub fn plane(&self) -> plane::Handle {
+        self.drm.plane()
+    }
+
+    /// Currently used [`connector`](drm::control::connector)s of this `Surface`
+    pub fn current_connectors(&self) -> impl IntoIterator<Item = connector::Handle> {
+        self.drm.current_connectors()
+    }
+
+    /// Returns the pending [`connector`](drm::control::connector)s
+    /// used for the next frame queued via [`queue_buffer`](DumbBufferedSurface::queue_buffer).
+    pub fn pending_connectors(&self) -> impl IntoIterator<Item = connector::Handle> {
+        self.drm.pending_connectors()
+    }
+
+    /// Tries to add a new [`connector`](drm::control::connector)
+    /// to be used after the next commit.
+    ///
+    /// See [`DrmSurface::add_connector`] for details.
+    pub fn add_connector(&self, connector: connector::Handle) -> Result<(), Error> {
+        self.drm.add_connector(connector).map_err(Error::DrmError)
+    }
+
+    /// Tries to mark a [`connector`](drm::control::connector)
+    /// for removal on the next commit.
+    pub fn remove_connector(&self, connector: connector::Handle) -> Result<(), Error> {
+        self.drm.remove_connector(connector).map_err(Error::DrmError)
+    }
+
+    /// Tries to replace the current connector set with the newly provided one on the next commit.
+    ///
+    /// See [`DrmSurface::set_connectors`] for details.
+    pub fn set_connectors(&self, connectors: &[connector::Handle]) -> Result<(), Error> {
+        self.drm.set_connectors(connectors).map_err(Error::DrmError)
+    }
+
+    /// Returns the currently active [`Mode`](drm::control::Mode)
+    /// of the underlying [`crtc`](drm::control::crtc)
+    pub fn current_mode(&self) -> Mode {
+        self.drm.current_mode()
+    }
+
+    /// Returns the currently pending [`Mode`](drm::control::Mode)
+    /// to be used after the next commit.
+    pub fn pending_mode(&self) -> Mode {
+        self.drm.pending_mode()
+    }
+
+    /// Tries to set a new [`Mode`](drm::control::Mode)
+    /// to be used after the next commit.
+    ///
+    /// Buffers returned by [`next_buffer`](DumbBufferedSurface::next_buffer) afterwards match the size of the new mode.
+    ///
+    /// Fails if the mode is not compatible with the underlying
+    /// [`crtc`](drm::control::crtc) or any of the
+    /// pending [`connector`](drm::control::connector)s.
+    pub fn use_mode(&mut self, mode: Mode) -> Result<(), Error> {
+        self.drm.use_mode(mode).map_err(Error::DrmError)?;
+        let (w, h) = mode.size();
+        self.swapchain.resize(w as _, h as _);
+        Ok(())
+    }
+}
+
+/// Mapped back buffer of a [`DumbBufferedSurface`]
+///
+/// Dereferences to the pixel data of the buffer, which is laid out in rows of [`pitch`](DumbFrame::pitch) bytes.
+/// The buffer is unmapped once the frame is dropped.
+pub struct DumbFrame<'a> {
+    mapping: DumbMapping<'a>,
+    pitch: u32,
+    size: Size<i32, BufferCoords>,
+    format: Fourcc,
+    age: u8,
+}
+
+impl<'a> fmt::Debug for DumbFrame<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DumbFrame")
+            .field("pitch", &self.pitch)
+            .field("size", &self.size)
+            .field("format", &self.format)
+            .field("age", &self.age)
+            .finish()
+    }
+}
+
+impl<'a> DumbFrame<'a> {
+    /// Number of bytes per row of the buffer
+    pub fn pitch(&self) -> u32 {
+        self.pitch
+    }
+
+    /// Size of the buffer in pixels
+    pub fn size(&self) -> Size<i32, BufferCoords> {
+        self.size
+    }
+
+    /// Pixel format of the buffer
+    pub fn format(&self) -> Fourcc {
+        self.format
+    }
+
+    /// Age of the buffer
+    ///
+    /// This is the number of frames queued since the content of the buffer was last queued,
+    /// or `0` if the buffer content is undefined.
+    pub fn age(&self) -> u8 {
+        self.age
+    }
+}
+
+impl<'a> Deref for DumbFrame<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.mapping.as_ref()
+    }
+}
+
+impl<'a> DerefMut for DumbFrame<'a> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.mapping.as_mut()
+    }
+}
+
+#[derive(Debug)]
+struct FbHandle<D: AsRawFd + 'static> {
+    drm: Arc<DrmSurface<D>>,
+    fb: framebuffer::Handle,
+}
+
+impl<D: AsRawFd + 'static> Drop for FbHandle<D> {
+    fn drop(&mut self) {
+        let _ = self.drm.destroy_framebuffer(self.fb);
+    }
+}
+
+fn acquire<D: AsRawFd + 'static>(
+    drm: &Arc<DrmSurface<D>>,
+    swapchain: &mut Swapchain<DumbAllocator<D>, DumbBuffer<D>>,
+) -> Result<Slot<DumbBuffer<D>>, Error> {
+    swapchain
+        .acquire()
+        .map_err(|source| DrmError::Access {
+            errmsg: "Failed to allocate dumb buffer",
+            dev: drm.dev_path(),
+            source,
+        })?
+        .ok_or(Error::NoFreeSlotsError)
+}
+
+fn attach_framebuffer<D: AsRawFd + 'static>(
+    drm: &Arc<DrmSurface<D>>,
+    buffer: &DrmDumbBuffer,
+    format: Fourcc,
+) -> Result<FbHandle<D>, Error> {
+    let (depth, bpp) = get_depth(format)
+        .and_then(|d| get_bpp(format).map(|b| (d, b)))
+        .ok_or(Error::NoSupportedPlaneFormat)?;
+    let fb = drm
+        .add_framebuffer(buffer, depth as u32, bpp as u32)
+        .map_err(|source| DrmError::Access {
+            errmsg: "Failed to add framebuffer",
+            dev: drm.dev_path(),
+            source,
+        })?;
+    Ok(FbHandle { drm: drm.clone(), fb })
+}
+
+/// Errors thrown by a [`DumbBufferedSurface`]
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// No supported pixel format for the given plane could be determined
+    #[error("No supported plane buffer format found")]
+    NoSupportedPlaneFormat,
+    /// The swapchain is exhausted, you need to call `frame_submitted`
+    #[error("Failed to allocate a new buffer")]
+    NoFreeSlotsError,
+    /// Error accessing the drm device
+    #[error("The underlying drm surface encounted an error: {0}")]
+    DrmError(#[from] DrmError),
+}
+
+impl From<Error> for SwapBuffersError {
+    fn from(err: Error) -> SwapBuffersError {
+        match err {
+            x @ Error::NoSupportedPlaneFormat => SwapBuffersError::ContextLost(Box::new(x)),
+            x @ Error::NoFreeSlotsError => SwapBuffersError::TemporaryFailure(Box::new(x)),
+            Error::DrmError(err) => err.into(),
+        }
+    }
+}
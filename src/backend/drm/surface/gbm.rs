@@ -22,6 +22,7 @@ pub struct GbmBufferedSurface<A: Allocator<BufferObject<()>> + 'static, D: AsRaw
     current_fb: Slot<BufferObject<()>>,
     pending_fb: Option<Slot<BufferObject<()>>>,
     queued_fb: Option<Slot<BufferObject<()>>>,
+    queued_async_flip: bool,
     next_fb: Option<Slot<BufferObject<()>>>,
     swapchain: Swapchain<A, BufferObject<()>>,
     drm: Arc<DrmSurface<D>>,
@@ -77,6 +78,7 @@ where
                         current_fb,
                         pending_fb: None,
                         queued_fb: None,
+                        queued_async_flip: false,
                         next_fb: None,
                         swapchain,
                         drm,
@@ -237,11 +239,16 @@ where
 
     /// Queues the current buffer for rendering.
     ///
+    /// If `async_flip` is set, the buffer is presented without waiting for the next vertical blank,
+    /// if the device supports it (see [`DrmSurface::async_page_flip_supported`]).
+    /// Otherwise, or if the asynchronous flip is rejected, it is presented synchronized as usual.
+    ///
     /// *Note*: This function needs to be followed up with [`GbmBufferedSurface::frame_submitted`]
     /// when a vblank event is received, that denotes successful scanout of the buffer.
     /// Otherwise the underlying swapchain will eventually run out of buffers.
-    pub fn queue_buffer(&mut self) -> Result<(), Error<A::Error>> {
+    pub fn queue_buffer(&mut self, async_flip: bool) -> Result<(), Error<A::Error>> {
         self.queued_fb = self.next_fb.take();
+        self.queued_async_flip = async_flip;
         if self.pending_fb.is_none() && self.queued_fb.is_some() {
             self.submit()?;
         }
@@ -272,7 +279,15 @@ where
         let flip = if self.drm.commit_pending() {
             self.drm.commit([(fb, self.drm.plane())].iter(), true)
         } else {
-            self.drm.page_flip([(fb, self.drm.plane())].iter(), true)
+            let planes = [(fb, self.drm.plane())];
+            let async_flip = self.queued_async_flip && self.drm.async_page_flip_supported();
+            match self.drm.page_flip(planes.iter(), true, async_flip) {
+                // drivers may reject async flips, e.g. if anything besides the framebuffer changed
+                Err(err) if async_flip && is_async_flip_rejection(&err) => {
+                    self.drm.page_flip(planes.iter(), true, false)
+                }
+                flip => flip,
+            }
         };
         if flip.is_ok() {
             self.swapchain.submitted(&slot);
@@ -351,6 +366,31 @@ where
         self.drm.pending_mode()
     }
 
+    /// Returns true, if the given [`connector`](drm::control::connector) supports variable refresh rate
+    /// in combination with the underlying [`crtc`](drm::control::crtc)
+    pub fn vrr_supported(&self, connector: connector::Handle) -> Result<bool, Error<A::Error>> {
+        self.drm.vrr_supported(connector).map_err(Error::DrmError)
+    }
+
+    /// Returns true, if variable refresh rate is currently enabled
+    pub fn vrr_enabled(&self) -> bool {
+        self.drm.vrr_enabled()
+    }
+
+    /// Returns true, if variable refresh rate will be enabled
+    /// for the next frame queued via [`queue_buffer`](GbmBufferedSurface::queue_buffer)
+    pub fn pending_vrr(&self) -> bool {
+        self.drm.pending_vrr()
+    }
+
+    /// Tries to enable or disable variable refresh rate
+    /// for the next frame queued via [`queue_buffer`](GbmBufferedSurface::queue_buffer).
+    ///
+    /// Fails if any of the pending [`connector`](drm::control::connector)s does not support it.
+    pub fn use_vrr(&self, vrr: bool) -> Result<(), Error<A::Error>> {
+        self.drm.use_vrr(vrr).map_err(Error::DrmError)
+    }
+
     /// Tries to set a new [`Mode`](drm::control::Mode)
     /// to be used after the next commit.
     ///
@@ -436,6 +476,19 @@ where
     Ok(FbHandle { drm: drm.clone(), fb })
 }
 
+// Whether the error denotes an async flip the driver does not support, in contrast to
+// errors of the flip itself (e.g. a flip still pending or a paused session).
+fn is_async_flip_rejection(err: &DrmError) -> bool {
+    matches!(
+        err,
+        DrmError::AsyncPageFlipNotSupported(_)
+            | DrmError::Access {
+                source: drm::SystemError::InvalidArgument,
+                ..
+            }
+    )
+}
+
 /// Errors thrown by a [`GbmBufferedSurface`]
 #[derive(Debug, thiserror::Error)]
 pub enum Error<E: std::error::Error + Send + Sync + 'static> {
@@ -1,4 +1,5 @@
 use drm::control::{connector, crtc, encoder, framebuffer, Device as ControlDevice, Mode, PageFlipFlags};
+use drm::DriverCapability;
 
 use std::collections::HashSet;
 use std::os::unix::io::AsRawFd;
@@ -13,7 +14,21 @@ use crate::backend::drm::{
     error::Error,
 };
 
-use slog::{debug, info, o, trace};
+use slog::{debug, info, o, trace, warn};
+
+// Reads the gamma ramp of the crtc, `None` if the crtc does not support any
+pub(super) fn read_gamma<A: AsRawFd + ControlDevice>(
+    fd: &A,
+    crtc: crtc::Handle,
+) -> Result<Option<[Vec<u16>; 3]>, drm::SystemError> {
+    let size = fd.get_crtc(crtc)?.gamma_length() as usize;
+    if size == 0 {
+        return Ok(None);
+    }
+    let (mut red, mut green, mut blue) = (vec![0; size], vec![0; size], vec![0; size]);
+    fd.get_gamma(crtc, &mut red, &mut green, &mut blue)?;
+    Ok(Some([red, green, blue]))
+}
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct State {
@@ -77,6 +92,8 @@ pub struct LegacyDrmSurface<A: AsRawFd + 'static> {
     crtc: crtc::Handle,
     state: RwLock<State>,
     pending: RwLock<State>,
+    // red, green and blue channel of the gamma ramp before the surface was created
+    original_gamma: Option<[Vec<u16>; 3]>,
     pub(crate) logger: ::slog::Logger,
 }
 
@@ -101,12 +118,21 @@ impl<A: AsRawFd + 'static> LegacyDrmSurface<A> {
             connectors: connectors.iter().copied().collect(),
         };
 
+        let original_gamma = match read_gamma(&*fd, crtc) {
+            Ok(gamma) => gamma,
+            Err(err) => {
+                warn!(logger, "Failed to read gamma ramp of {:?}: {}", crtc, err);
+                None
+            }
+        };
+
         let surface = LegacyDrmSurface {
             fd,
             active,
             crtc,
             state: RwLock::new(state),
             pending: RwLock::new(pending),
+            original_gamma,
             logger,
         };
 
@@ -301,28 +327,41 @@ impl<A: AsRawFd + 'static> LegacyDrmSurface<A> {
         Ok(())
     }
 
-    pub fn page_flip(&self, framebuffer: framebuffer::Handle, event: bool) -> Result<(), Error> {
+    pub fn async_page_flip_supported(&self) -> bool {
+        matches!(
+            self.fd.get_driver_capability(DriverCapability::ASyncPageFlip),
+            Ok(1)
+        )
+    }
+
+    pub fn page_flip(
+        &self,
+        framebuffer: framebuffer::Handle,
+        event: bool,
+        async_flip: bool,
+    ) -> Result<(), Error> {
         trace!(self.logger, "Queueing Page flip");
 
         if !self.active.load(Ordering::SeqCst) {
             return Err(Error::DeviceInactive);
         }
 
-        ControlDevice::page_flip(
-            &*self.fd,
-            self.crtc,
-            framebuffer,
-            if event {
-                PageFlipFlags::EVENT
-            } else {
-                PageFlipFlags::empty()
-            },
-            None,
-        )
-        .map_err(|source| Error::Access {
-            errmsg: "Failed to page flip",
-            dev: self.fd.dev_path(),
-            source,
+        let mut flags = PageFlipFlags::empty();
+        if event {
+            flags |= PageFlipFlags::EVENT;
+        }
+        if async_flip {
+            if !self.async_page_flip_supported() {
+                return Err(Error::AsyncPageFlipNotSupported(self.crtc));
+            }
+            flags |= PageFlipFlags::ASYNC;
+        }
+        ControlDevice::page_flip(&*self.fd, self.crtc, framebuffer, flags, None).map_err(|source| {
+            Error::Access {
+                errmsg: "Failed to page flip",
+                dev: self.fd.dev_path(),
+                source,
+            }
         })
     }
 
@@ -402,6 +441,81 @@ impl<A: AsRawFd + 'static> LegacyDrmSurface<A> {
         }
     }
 
+    pub fn vrr_supported(&self, _conn: connector::Handle) -> Result<bool, Error> {
+        // variable refresh rate can only be controlled via the atomic api
+        Ok(false)
+    }
+
+    pub fn use_vrr(&self, vrr: bool) -> Result<(), Error> {
+        if vrr {
+            Err(Error::VrrNotSupported(self.crtc))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn gamma_size(&self) -> Result<u32, Error> {
+        self.fd
+            .get_crtc(self.crtc)
+            .map(|info| info.gamma_length())
+            .map_err(|source| Error::Access {
+                errmsg: "Error loading crtc info",
+                dev: self.fd.dev_path(),
+                source,
+            })
+    }
+
+    pub fn set_gamma(&self, red: &[u16], green: &[u16], blue: &[u16]) -> Result<(), Error> {
+        if !self.active.load(Ordering::SeqCst) {
+            return Err(Error::DeviceInactive);
+        }
+
+        let size = self.gamma_size()?;
+        if let Some(got) = [red.len(), green.len(), blue.len()]
+            .into_iter()
+            .find(|len| *len != size as usize)
+        {
+            return Err(Error::GammaSizeMismatch {
+                crtc: self.crtc,
+                expected: size,
+                got,
+            });
+        }
+
+        trace!(self.logger, "Setting gamma ramp on {:?}", self.crtc);
+        self.fd
+            .set_gamma(self.crtc, red, green, blue)
+            .map_err(|source| Error::Access {
+                errmsg: "Failed to set gamma ramp",
+                dev: self.fd.dev_path(),
+                source,
+            })
+    }
+
+    pub fn reset_gamma(&self) -> Result<(), Error> {
+        if let Some([red, green, blue]) = &self.original_gamma {
+            return self.set_gamma(red, green, blue);
+        }
+
+        // we failed to read the original ramp, so a linear one is the best we can do
+        let size = self.gamma_size()?;
+        if size < 2 {
+            return Ok(());
+        }
+        let ramp = (0..size)
+            .map(|i| (i as u64 * u16::MAX as u64 / (size - 1) as u64) as u16)
+            .collect::<Vec<u16>>();
+        self.set_gamma(&ramp, &ramp, &ramp)
+    }
+
+    // Assumes the crtc and all connectors to be disabled, e.g. after the device was reset.
+    // The next commit will set the complete pending state.
+    pub(crate) fn clear_state(&self) {
+        let mut current = self.state.write().unwrap();
+        current.mode = unsafe { std::mem::zeroed() };
+        current.connectors.clear();
+    }
+
     pub(crate) fn reset_state<B: AsRawFd + ControlDevice + 'static>(
         &self,
         fd: Option<&B>,
@@ -8,14 +8,17 @@ use std::sync::Arc;
 use drm::control::{connector, crtc, framebuffer, plane, property, Device as ControlDevice, Mode};
 use drm::{Device as BasicDevice, DriverCapability};
 
+use io_lifetimes::OwnedFd;
 use nix::libc::dev_t;
+use nix::poll::{poll, PollFd, PollFlags};
 
 pub(super) mod atomic;
+pub(super) mod dumb;
 #[cfg(feature = "backend_gbm")]
 pub(super) mod gbm;
 pub(super) mod legacy;
-use super::{device::DevPath, error::Error, plane_type, planes, PlaneType, Planes};
-use crate::backend::allocator::{Format, Fourcc, Modifier};
+use super::{device::DevPath, error::Error, plane_type, planes, writeback::WritebackJob, PlaneType, Planes};
+use crate::backend::allocator::{dmabuf::Dmabuf, Format, Fourcc, Modifier};
 use atomic::AtomicDrmSurface;
 use legacy::LegacyDrmSurface;
 
@@ -193,6 +196,7 @@ impl<A: AsRawFd + 'static> DrmSurface<A> {
     /// - [`add_connector`](DrmSurface::add_connector)
     /// - [`remove_connector`](DrmSurface::remove_connector)
     /// - [`use_mode`](DrmSurface::use_mode)
+    /// - [`queue_writeback`](DrmSurface::queue_writeback), if the writeback connector is not yet attached
     pub fn commit_pending(&self) -> bool {
         match &*self.internal {
             DrmSurfaceInternal::Atomic(surf) => surf.commit_pending(),
@@ -230,6 +234,16 @@ impl<A: AsRawFd + 'static> DrmSurface<A> {
         }
     }
 
+    /// Returns whether the device supports asynchronous (tearing) page flips
+    ///
+    /// See [`DrmSurface::page_flip`].
+    pub fn async_page_flip_supported(&self) -> bool {
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.async_page_flip_supported(),
+            DrmSurfaceInternal::Legacy(surf) => surf.async_page_flip_supported(),
+        }
+    }
+
     /// Page-flip the underlying [`crtc`](drm::control::crtc)
     /// to a new given [`framebuffer`].
     ///
@@ -237,19 +251,25 @@ impl<A: AsRawFd + 'static> DrmSurface<A> {
     ///
     /// This operation is not blocking and will produce a `vblank` event once swapping is done.
     /// Make sure to have the device registered in your event loop to not miss the event.
+    ///
+    /// If `async_flip` is set, the flip is not synchronized to the vertical blank of the display,
+    /// which reduces latency at the cost of tearing. This requires support by the driver
+    /// (see [`DrmSurface::async_page_flip_supported`]) and, if the driver does not support
+    /// asynchronous atomic commits, is limited to the primary plane.
     pub fn page_flip<'a>(
         &self,
         mut framebuffers: impl Iterator<Item = &'a (framebuffer::Handle, plane::Handle)>,
         event: bool,
+        async_flip: bool,
     ) -> Result<(), Error> {
         match &*self.internal {
-            DrmSurfaceInternal::Atomic(surf) => surf.page_flip(framebuffers, event),
+            DrmSurfaceInternal::Atomic(surf) => surf.page_flip(framebuffers, event, async_flip),
             DrmSurfaceInternal::Legacy(surf) => {
                 if let Some((fb, plane)) = framebuffers.next() {
                     if plane_type(self, *plane)? != PlaneType::Primary {
                         return Err(Error::NonPrimaryPlane(*plane));
                     }
-                    surf.page_flip(*fb, event)
+                    surf.page_flip(*fb, event, async_flip)
                 } else {
                     Ok(())
                 }
@@ -257,6 +277,71 @@ impl<A: AsRawFd + 'static> DrmSurface<A> {
         }
     }
 
+    /// Returns the pixel formats supported by a writeback connector
+    ///
+    /// Buffers passed to [`queue_writeback`](DrmSurface::queue_writeback) need to use one of these formats
+    /// and the size of the [`pending_mode`](DrmSurface::pending_mode).
+    pub fn writeback_formats(&self, connector: connector::Handle) -> Result<Vec<Fourcc>, Error> {
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.writeback_formats(connector),
+            DrmSurfaceInternal::Legacy(_) => Err(Error::WritebackNotSupported),
+        }
+    }
+
+    /// Queues a capture of the output of the underlying [`crtc`](drm::control::crtc) into the given buffer
+    ///
+    /// The capture happens on the next [`commit`](DrmSurface::commit) or [`page_flip`](DrmSurface::page_flip)
+    /// through the given writeback connector (see [`DrmDevice::writeback_connectors`](super::DrmDevice::writeback_connectors)).
+    /// Attaching a writeback connector for the first time requires a commit, page flips leave the job
+    /// pending until then. Asynchronous page flips never include writeback jobs.
+    /// Queueing another job before the current one was submitted replaces it.
+    ///
+    /// The returned [`WritebackJob`] can be used to wait for the capture to complete.
+    ///
+    /// This is only supported on atomic devices.
+    pub fn queue_writeback(
+        &self,
+        connector: connector::Handle,
+        buffer: &Dmabuf,
+    ) -> Result<WritebackJob<A>, Error> {
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.queue_writeback(connector, buffer),
+            DrmSurfaceInternal::Legacy(_) => Err(Error::WritebackNotSupported),
+        }
+    }
+
+    /// Sets a fence the next [`commit`](DrmSurface::commit) or [`page_flip`](DrmSurface::page_flip)
+    /// waits for, before scanning out the new framebuffers
+    ///
+    /// The fence has to be a `sync_file`, e.g. exported from the
+    /// [`SyncPoint`](crate::backend::renderer::sync::SyncPoint) of a rendered frame.
+    /// On atomic devices it is passed as the `IN_FENCE_FD` of the primary plane, so the kernel
+    /// waits for rendering to complete without blocking. Legacy devices, and atomic devices
+    /// without support for `IN_FENCE_FD`, block until the fence is signaled instead.
+    /// Use [`DrmSurface::in_fence_supported`] to check upfront, if that is the case, e.g. to wait
+    /// for the [`SyncPoint`](crate::backend::renderer::sync::SyncPoint) asynchronously instead.
+    ///
+    /// Blocking waits are bounded. If the fence does not signal within one second
+    /// [`Error::FenceTimeout`] is returned and the fence is discarded. The frame must not be
+    /// committed in that case, but may be retried later.
+    ///
+    /// Setting another fence before the next commit or page flip replaces the previous one.
+    pub fn set_in_fence(&self, fence: OwnedFd) -> Result<(), Error> {
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.set_in_fence(fence),
+            DrmSurfaceInternal::Legacy(_) => wait_for_fence(fence.as_raw_fd(), self.crtc),
+        }
+    }
+
+    /// Returns true, if fences set via [`DrmSurface::set_in_fence`] are handed to the kernel
+    /// instead of being waited upon
+    pub fn in_fence_supported(&self) -> bool {
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.in_fence_supported(),
+            DrmSurfaceInternal::Legacy(_) => false,
+        }
+    }
+
     /// Returns a set of supported pixel formats for attached buffers
     pub fn supported_formats(&self, plane: plane::Handle) -> Result<HashSet<Format>, Error> {
         // get plane formats
@@ -441,6 +526,86 @@ impl<A: AsRawFd + 'static> DrmSurface<A> {
         }
     }
 
+    /// Returns true, if the given [`connector`](drm::control::connector) supports
+    /// variable refresh rate (also known as adaptive sync, FreeSync or G-Sync)
+    /// in combination with the underlying [`crtc`](drm::control::crtc)
+    ///
+    /// This is only ever the case for atomic devices.
+    pub fn vrr_supported(&self, connector: connector::Handle) -> Result<bool, Error> {
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.vrr_supported(connector),
+            DrmSurfaceInternal::Legacy(surf) => surf.vrr_supported(connector),
+        }
+    }
+
+    /// Returns true, if variable refresh rate is currently enabled on the underlying [`crtc`](drm::control::crtc)
+    pub fn vrr_enabled(&self) -> bool {
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.vrr_enabled(),
+            DrmSurfaceInternal::Legacy(_) => false,
+        }
+    }
+
+    /// Returns true, if variable refresh rate will be enabled after the next commit or page flip
+    pub fn pending_vrr(&self) -> bool {
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.pending_vrr(),
+            DrmSurfaceInternal::Legacy(_) => false,
+        }
+    }
+
+    /// Tries to enable or disable variable refresh rate after the next commit.
+    ///
+    /// As this does not require a modeset, the change is also applied by the next
+    /// [`page_flip`](DrmSurface::page_flip), that is not asynchronous, and does not
+    /// cause [`commit_pending`](DrmSurface::commit_pending) to return true.
+    ///
+    /// Fails if enabling is requested, but any of the pending [`connector`](drm::control::connector)s
+    /// does not [support](DrmSurface::vrr_supported) it.
+    pub fn use_vrr(&self, vrr: bool) -> Result<(), Error> {
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.use_vrr(vrr),
+            DrmSurfaceInternal::Legacy(surf) => surf.use_vrr(vrr),
+        }
+    }
+
+    /// Returns the number of entries per channel of the gamma ramp of the underlying [`crtc`](drm::control::crtc)
+    ///
+    /// A size of `0` indicates the crtc does not support gamma ramps.
+    pub fn gamma_size(&self) -> Result<u32, Error> {
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.gamma_size(),
+            DrmSurfaceInternal::Legacy(surf) => surf.gamma_size(),
+        }
+    }
+
+    /// Sets the gamma ramp of the underlying [`crtc`](drm::control::crtc)
+    ///
+    /// Every channel needs to have exactly [`gamma_size`](DrmSurface::gamma_size) entries.
+    ///
+    /// Atomic devices set the `GAMMA_LUT` property, which is applied together with the next
+    /// [`commit`](DrmSurface::commit) or [`page_flip`](DrmSurface::page_flip). Async page flips
+    /// do not apply it and leave it pending for the next one.
+    /// Legacy devices and drivers without color management fall back to the legacy gamma ioctl,
+    /// which applies the new ramp immediately.
+    pub fn set_gamma(&self, red: &[u16], green: &[u16], blue: &[u16]) -> Result<(), Error> {
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.set_gamma(red, green, blue),
+            DrmSurfaceInternal::Legacy(surf) => surf.set_gamma(red, green, blue),
+        }
+    }
+
+    /// Restores the gamma ramp the underlying [`crtc`](drm::control::crtc) had, when the surface was created
+    ///
+    /// Falls back to a linear ramp, if the original ramp could not be read.
+    /// Like [`set_gamma`](DrmSurface::set_gamma), the ramp may only be applied with the next commit or page flip.
+    pub fn reset_gamma(&self) -> Result<(), Error> {
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.reset_gamma(),
+            DrmSurfaceInternal::Legacy(surf) => surf.reset_gamma(),
+        }
+    }
+
     /// Re-evaluates the current state of the crtc.
     ///
     /// Usually you do not need to call this, but if the state of
@@ -453,3 +618,19 @@ impl<A: AsRawFd + 'static> DrmSurface<A> {
         }
     }
 }
+
+// Upper bound for blocking on in-fences, a frame taking longer than that is considered lost
+const FENCE_TIMEOUT_MS: i32 = 1000;
+
+// Blocks until the given sync_file is signaled, or the timeout expired
+pub(super) fn wait_for_fence(fence: RawFd, crtc: crtc::Handle) -> Result<(), Error> {
+    let mut fds = [PollFd::new(fence, PollFlags::POLLIN)];
+    loop {
+        match poll(&mut fds, FENCE_TIMEOUT_MS) {
+            Ok(0) => return Err(Error::FenceTimeout(crtc)),
+            Err(nix::errno::Errno::EINTR) => continue,
+            // an invalid fence cannot be waited upon, so there is nothing to wait for
+            _ => return Ok(()),
+        }
+    }
+}
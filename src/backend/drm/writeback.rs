@@ -0,0 +1,260 @@
+//! Capturing the output of a crtc through writeback connectors
+//!
+//! Some display controllers (e.g. `vkms` or many embedded ones) expose writeback connectors.
+//! Instead of driving a display, these write the composed content of a crtc into a framebuffer,
+//! which allows capturing the output (e.g. for screencasting) without reading back the rendered buffers.
+//!
+//! Writeback connectors of a device can be listed by [`DrmDevice::writeback_connectors`](super::DrmDevice::writeback_connectors).
+//! A buffer to capture into is queued via [`DrmSurface::queue_writeback`](super::DrmSurface::queue_writeback)
+//! and written on the next [`commit`](super::DrmSurface::commit) or [`page_flip`](super::DrmSurface::page_flip)
+//! of the surface. The returned [`WritebackJob`] signals, once the buffer contains the captured frame.
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use smithay::backend::drm::{DrmDevice, DrmSurface};
+//! # use smithay::backend::allocator::dmabuf::Dmabuf;
+//! # fn test(device: &DrmDevice<std::fs::File>, surface: &DrmSurface<std::fs::File>, buffer: Dmabuf) {
+//! let connector = device.writeback_connectors().unwrap()[0];
+//! let job = surface.queue_writeback(connector, &buffer).unwrap();
+//! // .. commit or page flip the surface ..
+//! if job.wait(Some(Duration::from_millis(100))) {
+//!     let captured = job.into_buffer().unwrap();
+//! }
+//! # }
+//! ```
+
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+
+use drm::buffer::{DrmFourcc, Handle as BufferHandle, PlanarBuffer};
+use drm::control::{connector, framebuffer, Device as ControlDevice};
+use nix::poll::{poll, PollFd, PollFlags};
+
+use super::{
+    device::{DevPath, DrmDeviceInternal},
+    error::Error,
+};
+use crate::backend::allocator::{
+    dmabuf::{Dmabuf, MAX_PLANES},
+    Buffer, Modifier,
+};
+
+/// Pending or submitted capture of a crtc into a buffer
+///
+/// Dropping the job before it finished is allowed, but the captured content is lost.
+#[derive(Debug)]
+pub struct WritebackJob<A: AsRawFd + 'static> {
+    pub(super) state: Arc<WritebackState<A>>,
+    buffer: Dmabuf,
+}
+
+impl<A: AsRawFd + 'static> WritebackJob<A> {
+    /// Writeback connector used for this capture
+    pub fn connector(&self) -> connector::Handle {
+        self.state.connector
+    }
+
+    /// Buffer the content is captured into
+    pub fn buffer(&self) -> &Dmabuf {
+        &self.buffer
+    }
+
+    /// Framebuffer created for the buffer
+    pub fn framebuffer(&self) -> framebuffer::Handle {
+        self.state.fb
+    }
+
+    /// Returns whether the job was submitted to the device by a commit or page flip
+    pub fn is_submitted(&self) -> bool {
+        self.state.submitted.load(Ordering::SeqCst)
+    }
+
+    /// Returns the out-fence of the job, once submitted
+    ///
+    /// The fence is a `sync_file`, which signals once the capture is complete.
+    /// It stays owned by the job, so it may be used to synchronize with other devices,
+    /// but must not be closed.
+    pub fn fence(&self) -> Option<RawFd> {
+        *self.state.fence.lock().unwrap()
+    }
+
+    /// Returns whether the captured content is available in the [`buffer`](WritebackJob::buffer)
+    pub fn is_finished(&self) -> bool {
+        self.wait(Some(Duration::ZERO))
+    }
+
+    /// Waits for the capture to complete
+    ///
+    /// Returns `false` if the job was not yet submitted or the timeout expired.
+    /// A timeout of `None` waits indefinitely.
+    pub fn wait(&self, timeout: Option<Duration>) -> bool {
+        if !self.is_submitted() {
+            return false;
+        }
+        let fence = match self.fence() {
+            Some(fence) => fence,
+            // the kernel did not hand out a fence, the writeback is done as part of the commit
+            None => return true,
+        };
+
+        let timeout = timeout
+            .map(|timeout| timeout.as_millis().min(i32::MAX as u128) as i32)
+            .unwrap_or(-1);
+        let mut fds = [PollFd::new(fence, PollFlags::POLLIN)];
+        loop {
+            match poll(&mut fds, timeout) {
+                Ok(ready) => return ready > 0,
+                Err(nix::errno::Errno::EINTR) => continue,
+                Err(_) => return false,
+            }
+        }
+    }
+
+    /// Returns the captured buffer, if the job has finished
+    pub fn into_buffer(self) -> Result<Dmabuf, WritebackJob<A>> {
+        if self.is_finished() {
+            Ok(self.buffer)
+        } else {
+            Err(self)
+        }
+    }
+}
+
+/// State of a writeback job shared with the surface submitting it
+#[derive(Debug)]
+pub(super) struct WritebackState<A: AsRawFd + 'static> {
+    pub(super) connector: connector::Handle,
+    pub(super) fb: framebuffer::Handle,
+    fence: Mutex<Option<RawFd>>,
+    submitted: AtomicBool,
+    device: Arc<DrmDeviceInternal<A>>,
+}
+
+impl<A: AsRawFd + 'static> WritebackState<A> {
+    /// Marks the job as submitted with the out-fence returned by the commit
+    pub(super) fn submitted(&self, fence: RawFd) {
+        if fence >= 0 {
+            *self.fence.lock().unwrap() = Some(fence);
+        }
+        self.submitted.store(true, Ordering::SeqCst);
+    }
+}
+
+impl<A: AsRawFd + 'static> Drop for WritebackState<A> {
+    fn drop(&mut self) {
+        if let Some(fence) = self.fence.get_mut().unwrap().take() {
+            let _ = nix::unistd::close(fence);
+        }
+        let _ = self.device.destroy_framebuffer(self.fb);
+    }
+}
+
+pub(super) fn create_job<A: AsRawFd + 'static>(
+    device: &Arc<DrmDeviceInternal<A>>,
+    connector: connector::Handle,
+    buffer: &Dmabuf,
+) -> Result<WritebackJob<A>, Error> {
+    let fb = import_dmabuf(&**device, buffer)?;
+    Ok(WritebackJob {
+        state: Arc::new(WritebackState {
+            connector,
+            fb,
+            fence: Mutex::new(None),
+            submitted: AtomicBool::new(false),
+            device: device.clone(),
+        }),
+        buffer: buffer.clone(),
+    })
+}
+
+struct DmabufPlanes<'a> {
+    dmabuf: &'a Dmabuf,
+    handles: [Option<BufferHandle>; MAX_PLANES],
+}
+
+impl<'a> PlanarBuffer for DmabufPlanes<'a> {
+    fn size(&self) -> (u32, u32) {
+        let size = self.dmabuf.size();
+        (size.w as u32, size.h as u32)
+    }
+
+    fn format(&self) -> DrmFourcc {
+        Buffer::format(self.dmabuf).code
+    }
+
+    fn pitches(&self) -> [u32; 4] {
+        let mut pitches = [0; 4];
+        for (pitch, stride) in pitches.iter_mut().zip(self.dmabuf.strides()) {
+            *pitch = stride;
+        }
+        pitches
+    }
+
+    fn handles(&self) -> [Option<BufferHandle>; 4] {
+        self.handles
+    }
+
+    fn offsets(&self) -> [u32; 4] {
+        let mut offsets = [0; 4];
+        for (offset, dmabuf_offset) in offsets.iter_mut().zip(self.dmabuf.offsets()) {
+            *offset = dmabuf_offset;
+        }
+        offsets
+    }
+}
+
+// Imports the planes of a dmabuf and creates a framebuffer from them.
+fn import_dmabuf<D: ControlDevice + AsRawFd>(
+    device: &D,
+    dmabuf: &Dmabuf,
+) -> Result<framebuffer::Handle, Error> {
+    let mut handles = [None; MAX_PLANES];
+    for (handle, fd) in handles.iter_mut().zip(dmabuf.handles()) {
+        *handle = Some(
+            device
+                .prime_fd_to_buffer(fd.as_raw_fd())
+                .map_err(|source| Error::Access {
+                    errmsg: "Failed to import dmabuf",
+                    dev: device.dev_path(),
+                    source,
+                })?,
+        );
+    }
+
+    let modifier = match Buffer::format(dmabuf).modifier {
+        Modifier::Invalid => None,
+        x => Some(x),
+    };
+    let mut modifiers = [None; MAX_PLANES];
+    for plane_modifier in modifiers.iter_mut().take(dmabuf.num_planes()) {
+        *plane_modifier = modifier;
+    }
+    let flags = if modifier.is_some() {
+        drm_ffi::DRM_MODE_FB_MODIFIERS
+    } else {
+        0
+    };
+
+    let result = device
+        .add_planar_framebuffer(&DmabufPlanes { dmabuf, handles }, &modifiers, flags)
+        .map_err(|source| Error::Access {
+            errmsg: "Failed to add framebuffer",
+            dev: device.dev_path(),
+            source,
+        });
+
+    // the framebuffer holds its own references to the imported buffers
+    let mut closed = Vec::with_capacity(MAX_PLANES);
+    for handle in handles.iter().flatten() {
+        if !closed.contains(handle) {
+            let _ = drm_ffi::gem::close(device.as_raw_fd(), u32::from(*handle));
+            closed.push(*handle);
+        }
+    }
+
+    result
+}
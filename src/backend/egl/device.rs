@@ -9,6 +9,18 @@ use super::{
 use crate::backend::drm::{DrmNode, NodeType};
 
 /// safe EGLDevice wrapper
+///
+/// An `EGLDevice` can be used to create an [`EGLDisplay`] without any gbm device or window,
+/// e.g. for headless rendering on a specific render node or on a software device like llvmpipe:
+///
+/// ```no_run
+/// # use smithay::backend::egl::{EGLDevice, EGLDisplay};
+/// let device = EGLDevice::enumerate()
+///     .unwrap()
+///     .find(|device| device.is_software())
+///     .expect("No software device available");
+/// let display = unsafe { EGLDisplay::new(&device, None) }.unwrap();
+/// ```
 #[derive(Debug)]
 pub struct EGLDevice {
     pub(super) inner: EGLDeviceEXT,
@@ -92,6 +104,26 @@ impl EGLDevice {
             .into_iter())
     }
 
+    /// Returns the [`EGLDevice`] belonging to the given drm node, if any
+    ///
+    /// Devices are matched by their render node, see [`EGLDevice::try_get_render_node`].
+    /// This function will return an error if the extensions required by [`EGLDevice::enumerate`] are not available.
+    #[cfg(feature = "backend_drm")]
+    pub fn device_for_node(node: &DrmNode) -> Result<Option<EGLDevice>, Error> {
+        let node = node
+            .node_with_type(NodeType::Render)
+            .and_then(Result::ok)
+            .unwrap_or(*node);
+        Ok(EGLDevice::enumerate()?.find(|device| {
+            device
+                .try_get_render_node()
+                .ok()
+                .flatten()
+                .map(|device_node| device_node == node)
+                .unwrap_or(false)
+        }))
+    }
+
     /// Returns the [`EGLDevices`](EGLDevice) related to the given `EGLDisplay`.
     ///
     /// This function will return an error if the following extensions are not available:
@@ -143,6 +175,17 @@ impl EGLDevice {
         self.device_extensions.clone()
     }
 
+    /// Returns if the device is a software renderer, e.g. llvmpipe
+    ///
+    /// Software devices are detected through
+    /// [`EGL_MESA_device_software`](https://www.khronos.org/registry/EGL/extensions/MESA/EGL_MESA_device_software.txt)
+    /// and do not have a drm node.
+    pub fn is_software(&self) -> bool {
+        self.device_extensions
+            .iter()
+            .any(|ext| ext == "EGL_MESA_device_software")
+    }
+
     /// Returns the path to the drm node of this EGLDevice.
     ///
     /// This function will return an error if the following extensions are not available:
@@ -220,8 +263,14 @@ impl EGLDevice {
     /// get a render_node from `EGL_EXT_device_drm` (see also [`EGLDevice::drm_device_path`]).
     /// If both fail to produce a render node, whichever device returned by
     /// `EGL_EXT_device_drm` is returned.
+    ///
+    /// [Software devices](EGLDevice::is_software) never have a drm node.
     #[cfg(feature = "backend_drm")]
     pub fn try_get_render_node(&self) -> Result<Option<DrmNode>, Error> {
+        if self.is_software() {
+            return Ok(None);
+        }
+
         // first lets try to get a render_node directly
         match self
             .render_device_path()
@@ -8,7 +8,7 @@ use std::sync::Arc;
 use std::sync::{Mutex, Weak};
 use std::{
     collections::HashSet,
-    os::unix::prelude::{AsRawFd, FromRawFd},
+    os::unix::prelude::{AsRawFd, FromRawFd, RawFd},
 };
 
 use io_lifetimes::OwnedFd;
@@ -607,6 +607,56 @@ impl EGLDisplay {
 
     /// Imports a [`Dmabuf`] as an [`EGLImage`]
     pub fn create_image_from_dmabuf(&self, dmabuf: &Dmabuf) -> Result<EGLImage, Error> {
+        let planes = dmabuf
+            .handles()
+            .zip(dmabuf.offsets())
+            .zip(dmabuf.strides())
+            .map(|((fd, offset), stride)| (fd.as_raw_fd(), offset, stride))
+            .collect::<Vec<_>>();
+
+        self.create_dmabuf_image(
+            dmabuf.size(),
+            dmabuf.format().code,
+            dmabuf.has_modifier().then(|| dmabuf.format().modifier),
+            &planes,
+        )
+    }
+
+    /// Imports a single plane of a [`Dmabuf`] as an [`EGLImage`]
+    ///
+    /// The plane is interpreted as a single-plane buffer of the given `format` and `size`.
+    /// This allows to sample the planes of multi-planar formats (e.g. `NV12`) separately,
+    /// by importing them as e.g. `R8` and `GR88` images.
+    pub fn create_image_from_dmabuf_plane(
+        &self,
+        dmabuf: &Dmabuf,
+        plane: usize,
+        format: Fourcc,
+        size: Size<i32, BufferCoords>,
+    ) -> Result<EGLImage, Error> {
+        let plane = dmabuf
+            .handles()
+            .zip(dmabuf.offsets())
+            .zip(dmabuf.strides())
+            .nth(plane)
+            .map(|((fd, offset), stride)| (fd.as_raw_fd(), offset, stride))
+            .ok_or(Error::EGLImageCreationFailed)?;
+
+        self.create_dmabuf_image(
+            size,
+            format,
+            dmabuf.has_modifier().then(|| dmabuf.format().modifier),
+            &[plane],
+        )
+    }
+
+    fn create_dmabuf_image(
+        &self,
+        size: Size<i32, BufferCoords>,
+        format: Fourcc,
+        modifier: Option<Modifier>,
+        planes: &[(RawFd, u32, u32)],
+    ) -> Result<EGLImage, Error> {
         if !self.extensions.iter().any(|s| s == "EGL_KHR_image_base")
             && !self
                 .extensions
@@ -619,7 +669,7 @@ impl EGLDisplay {
             ]));
         }
 
-        if dmabuf.has_modifier()
+        if modifier.is_some()
             && !self
                 .extensions
                 .iter()
@@ -634,11 +684,11 @@ impl EGLDisplay {
 
         out.extend(&[
             ffi::egl::WIDTH as i32,
-            dmabuf.width() as i32,
+            size.w,
             ffi::egl::HEIGHT as i32,
-            dmabuf.height() as i32,
+            size.h,
             ffi::egl::LINUX_DRM_FOURCC_EXT as i32,
-            dmabuf.format().code as u32 as i32,
+            format as u32 as i32,
         ]);
 
         let names = [
@@ -672,26 +722,21 @@ impl EGLDisplay {
             ],
         ];
 
-        for (i, ((fd, offset), stride)) in dmabuf
-            .handles()
-            .zip(dmabuf.offsets())
-            .zip(dmabuf.strides())
-            .enumerate()
-        {
+        for (i, &(fd, offset, stride)) in planes.iter().enumerate() {
             out.extend(&[
                 names[i][0] as i32,
-                fd.as_raw_fd(),
+                fd,
                 names[i][1] as i32,
                 offset as i32,
                 names[i][2] as i32,
                 stride as i32,
             ]);
-            if dmabuf.has_modifier() {
+            if let Some(modifier) = modifier {
                 out.extend(&[
                     names[i][3] as i32,
-                    (Into::<u64>::into(dmabuf.format().modifier) & 0xFFFFFFFF) as i32,
+                    (Into::<u64>::into(modifier) & 0xFFFFFFFF) as i32,
                     names[i][4] as i32,
-                    (Into::<u64>::into(dmabuf.format().modifier) >> 32) as i32,
+                    (Into::<u64>::into(modifier) >> 32) as i32,
                 ])
             }
         }
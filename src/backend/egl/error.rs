@@ -60,6 +60,15 @@ pub enum Error {
     /// The device does not have the given property
     #[error("The device does not have the given property")]
     EmptyDeviceProperty,
+    /// Failed to create an `EGLFence`
+    #[error("Failed to create an `EGLFence`")]
+    FenceCreationFailed(#[source] EGLError),
+    /// Failed to export an `EGLFence`
+    #[error("Failed to export an `EGLFence`")]
+    FenceExportFailed(#[source] EGLError),
+    /// Failed to wait for an `EGLFence`
+    #[error("Failed to wait for an `EGLFence`")]
+    FenceWaitFailed(#[source] EGLError),
 }
 
 /// Raw EGL error
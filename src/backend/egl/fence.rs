@@ -0,0 +1,162 @@
+//! EGL native fences
+//!
+//! Fences created through `EGL_ANDROID_native_fence_sync` are backed by a `sync_file`,
+//! which can be exported to synchronize with other apis (e.g. drm or other gpus)
+//! and imported to let the gpu wait for work of other devices.
+
+use std::{
+    os::unix::io::{FromRawFd, IntoRawFd},
+    sync::Arc,
+    time::Duration,
+};
+
+use io_lifetimes::OwnedFd;
+
+use super::{
+    display::{EGLDisplay, EGLDisplayHandle},
+    ffi, wrap_egl_call, EGLError, Error,
+};
+
+/// A fence inserted into the command stream of an EGL context
+#[derive(Debug)]
+pub struct EGLFence {
+    display: Arc<EGLDisplayHandle>,
+    sync: ffi::egl::types::EGLSyncKHR,
+    wait_supported: bool,
+}
+
+// The sync object is only accessed through EGL, which is thread-safe
+unsafe impl Send for EGLFence {}
+unsafe impl Sync for EGLFence {}
+
+impl EGLFence {
+    /// Returns whether the display supports native fences
+    ///
+    /// This requires the following extensions:
+    /// - [`EGL_KHR_fence_sync`](https://www.khronos.org/registry/EGL/extensions/KHR/EGL_KHR_fence_sync.txt)
+    /// - [`EGL_ANDROID_native_fence_sync`](https://www.khronos.org/registry/EGL/extensions/ANDROID/EGL_ANDROID_native_fence_sync.txt)
+    pub fn is_supported(display: &EGLDisplay) -> bool {
+        let extensions = display.extensions();
+        extensions.iter().any(|ext| ext == "EGL_KHR_fence_sync")
+            && extensions
+                .iter()
+                .any(|ext| ext == "EGL_ANDROID_native_fence_sync")
+    }
+
+    /// Creates a new native fence, which signals once all previously submitted commands
+    /// of the current context are complete
+    ///
+    /// An EGL context of the display needs to be current. The commands need to be flushed,
+    /// before the fence can be [exported](EGLFence::export).
+    pub fn create(display: &EGLDisplay) -> Result<EGLFence, Error> {
+        if !Self::is_supported(display) {
+            return Err(Error::EglExtensionNotSupported(&[
+                "EGL_KHR_fence_sync",
+                "EGL_ANDROID_native_fence_sync",
+            ]));
+        }
+
+        let attributes = [
+            ffi::egl::SYNC_NATIVE_FENCE_FD_ANDROID as ffi::EGLint,
+            ffi::egl::NO_NATIVE_FENCE_FD_ANDROID,
+            ffi::egl::NONE as ffi::EGLint,
+        ];
+        Self::create_sync(display, &attributes)
+    }
+
+    /// Imports a `sync_file` as a native fence
+    ///
+    /// The fence can be used to let the gpu [wait](EGLFence::wait) for work of other devices.
+    pub fn import(display: &EGLDisplay, fence: OwnedFd) -> Result<EGLFence, Error> {
+        if !Self::is_supported(display) {
+            return Err(Error::EglExtensionNotSupported(&[
+                "EGL_KHR_fence_sync",
+                "EGL_ANDROID_native_fence_sync",
+            ]));
+        }
+
+        let fd = fence.into_raw_fd();
+        let attributes = [
+            ffi::egl::SYNC_NATIVE_FENCE_FD_ANDROID as ffi::EGLint,
+            fd,
+            ffi::egl::NONE as ffi::EGLint,
+        ];
+        let result = Self::create_sync(display, &attributes);
+        if result.is_err() {
+            // EGL only takes ownership of the fd on success
+            // SAFETY: the fd was owned by us before and was not consumed
+            std::mem::drop(unsafe { OwnedFd::from_raw_fd(fd) });
+        }
+        result
+    }
+
+    fn create_sync(display: &EGLDisplay, attributes: &[ffi::EGLint]) -> Result<EGLFence, Error> {
+        let handle = display.get_display_handle();
+        let sync = wrap_egl_call(|| unsafe {
+            ffi::egl::CreateSyncKHR(**handle, ffi::egl::SYNC_NATIVE_FENCE_ANDROID, attributes.as_ptr())
+        })
+        .map_err(Error::FenceCreationFailed)?;
+        if sync == ffi::egl::NO_SYNC {
+            return Err(Error::FenceCreationFailed(EGLError::BadAlloc));
+        }
+
+        Ok(EGLFence {
+            display: handle,
+            sync,
+            wait_supported: display.extensions().iter().any(|ext| ext == "EGL_KHR_wait_sync"),
+        })
+    }
+
+    /// Exports the fence as a new `sync_file`
+    pub fn export(&self) -> Result<OwnedFd, Error> {
+        let fd = wrap_egl_call(|| unsafe { ffi::egl::DupNativeFenceFDANDROID(**self.display, self.sync) })
+            .map_err(Error::FenceExportFailed)?;
+        if fd == ffi::egl::NO_NATIVE_FENCE_FD_ANDROID {
+            return Err(Error::FenceExportFailed(EGLError::BadParameter));
+        }
+        // SAFETY: EGL returned a new file descriptor owned by the caller
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Lets the gpu wait for the fence, before executing any further commands of the current context
+    ///
+    /// This does not block and requires
+    /// [`EGL_KHR_wait_sync`](https://www.khronos.org/registry/EGL/extensions/KHR/EGL_KHR_wait_sync.txt).
+    pub fn wait(&self) -> Result<(), Error> {
+        if !self.wait_supported {
+            return Err(Error::EglExtensionNotSupported(&["EGL_KHR_wait_sync"]));
+        }
+        let result = wrap_egl_call(|| unsafe { ffi::egl::WaitSyncKHR(**self.display, self.sync, 0) })
+            .map_err(Error::FenceWaitFailed)?;
+        if result != ffi::egl::TRUE as ffi::EGLint {
+            return Err(Error::FenceWaitFailed(EGLError::BadParameter));
+        }
+        Ok(())
+    }
+
+    /// Blocks until the fence is signaled or the timeout expired
+    ///
+    /// Returns whether the fence was signaled. A timeout of `None` waits indefinitely.
+    pub fn client_wait(&self, timeout: Option<Duration>) -> bool {
+        let timeout = timeout
+            .map(|timeout| timeout.as_nanos().min(u64::MAX as u128) as u64)
+            .unwrap_or(ffi::egl::FOREVER);
+        let result = wrap_egl_call(|| unsafe {
+            ffi::egl::ClientWaitSyncKHR(
+                **self.display,
+                self.sync,
+                ffi::egl::SYNC_FLUSH_COMMANDS_BIT as ffi::EGLint,
+                timeout,
+            )
+        });
+        matches!(result, Ok(result) if result == ffi::egl::CONDITION_SATISFIED as ffi::EGLint)
+    }
+}
+
+impl Drop for EGLFence {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::egl::DestroySyncKHR(**self.display, self.sync);
+        }
+    }
+}
@@ -46,10 +46,12 @@ pub mod ffi;
 use self::{display::EGLDisplayHandle, ffi::egl::types::EGLImage};
 
 pub mod display;
+pub mod fence;
 pub mod native;
 pub mod surface;
 pub use self::device::EGLDevice;
 pub use self::display::EGLDisplay;
+pub use self::fence::EGLFence;
 pub use self::surface::EGLSurface;
 
 use std::ffi::CString;
@@ -3,7 +3,7 @@
 use std::fmt;
 use std::sync::{
     atomic::{AtomicPtr, Ordering},
-    Arc,
+    Arc, Mutex,
 };
 
 use crate::backend::egl::{
@@ -19,7 +19,7 @@ use slog::{debug, o};
 /// EGL surface of a given EGL context for rendering
 pub struct EGLSurface {
     pub(crate) display: Arc<EGLDisplayHandle>,
-    native: Box<dyn EGLNativeSurface + Send + 'static>,
+    native: Mutex<Box<dyn EGLNativeSurface + Send + 'static>>,
     pub(crate) surface: AtomicPtr<nix::libc::c_void>,
     config_id: ffi::egl::types::EGLConfig,
     pixel_format: PixelFormat,
@@ -42,6 +42,9 @@ impl fmt::Debug for EGLSurface {
 // safe because EGLConfig can be moved between threads
 // and the other types are thread-safe
 unsafe impl Send for EGLSurface {}
+// safe because the native surface is only accessed through its mutex
+// and the surface pointer is only ever replaced atomically, while holding it
+unsafe impl Sync for EGLSurface {}
 
 impl EGLSurface {
     /// Create a new `EGLSurface`.
@@ -72,7 +75,7 @@ impl EGLSurface {
 
         Ok(EGLSurface {
             display: display.get_display_handle(),
-            native: Box::new(native),
+            native: Mutex::new(Box::new(native)),
             surface: AtomicPtr::new(surface as *mut _),
             config_id: config,
             pixel_format,
@@ -144,10 +147,11 @@ impl EGLSurface {
         &self,
         damage: Option<&mut [Rectangle<i32, Physical>]>,
     ) -> ::std::result::Result<(), SwapBuffersError> {
+        let native = self.native.lock().unwrap();
         let surface = self.surface.load(Ordering::SeqCst);
 
         let result = if !surface.is_null() {
-            self.native.swap_buffers(&self.display, surface, damage)
+            native.swap_buffers(&self.display, surface, damage)
         } else {
             Err(SwapBuffersError::EGLSwapBuffers(EGLError::BadSurface))
         };
@@ -158,12 +162,12 @@ impl EGLSurface {
             Err(SwapBuffersError::EGLSwapBuffers(EGLError::BadSurface))
         );
 
-        if self.native.needs_recreation() || surface.is_null() || is_bad_surface {
+        if native.needs_recreation() || surface.is_null() || is_bad_surface {
             let previous = self
                 .surface
                 .compare_exchange(
                     surface,
-                    self.native
+                    native
                         .create(&self.display, self.config_id)
                         .map_err(SwapBuffersError::EGLCreateSurface)? as *mut _,
                     Ordering::SeqCst,
@@ -214,7 +218,7 @@ impl EGLSurface {
     ///
     /// Returns true if the resize was successful.
     pub fn resize(&self, width: i32, height: i32, dx: i32, dy: i32) -> bool {
-        self.native.resize(width, height, dx, dy)
+        self.native.lock().unwrap().resize(width, height, dx, dy)
     }
 
     /// Get a raw handle to the underlying surface
@@ -59,6 +59,9 @@
 //! #     fn transformation(&self) -> Transform {
 //! #         unimplemented!()
 //! #     }
+//! #     fn finish(&mut self) -> Result<smithay::backend::renderer::sync::SyncPoint, Self::Error> {
+//! #         unimplemented!()
+//! #     }
 //! # }
 //! #
 //! # struct FakeRenderer;
@@ -575,3 +578,117 @@ impl DamageTrackedRenderer {
         Ok(Some(new_damage))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        backend::renderer::{
+            element::texture::{TextureBuffer, TextureRenderElement},
+            test::{FrameCall, TestRenderer, TestTexture},
+        },
+        utils::{Point, Rectangle, Transform},
+    };
+
+    use super::DamageTrackedRenderer;
+
+    fn texture_buffer(renderer: &mut TestRenderer) -> TextureBuffer<TestTexture> {
+        TextureBuffer::from_memory(
+            renderer,
+            &[0u8; 10 * 10 * 4],
+            (10, 10),
+            false,
+            1,
+            Transform::Normal,
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn full_damage_without_age() {
+        let mut renderer = TestRenderer::new();
+        let buffer = texture_buffer(&mut renderer);
+        let mut damage_tracked_renderer = DamageTrackedRenderer::new((100, 100), 1.0, Transform::Normal);
+
+        let element =
+            TextureRenderElement::from_texture_buffer(Point::from((10.0, 10.0)), &buffer, None, None);
+        let damage = damage_tracked_renderer
+            .render_output(&mut renderer, 0, &[&element], [0.0, 0.0, 0.0, 1.0], None)
+            .unwrap();
+        assert_eq!(
+            damage,
+            Some(vec![Rectangle::from_loc_and_size((0, 0), (100, 100))])
+        );
+
+        let frame = renderer.last_frame().unwrap();
+        assert_eq!(
+            frame.cleared(),
+            vec![Rectangle::from_loc_and_size((0, 0), (100, 100))]
+        );
+        match &frame.calls[1] {
+            FrameCall::RenderTexture { dst, damage, .. } => {
+                assert_eq!(*dst, Rectangle::from_loc_and_size((10, 10), (10, 10)));
+                assert_eq!(*damage, vec![Rectangle::from_loc_and_size((0, 0), (10, 10))]);
+            }
+            call => panic!("unexpected call {:?}", call),
+        }
+    }
+
+    #[test]
+    fn removed_element_damages_old_geometry() {
+        let mut renderer = TestRenderer::new();
+        let buffer = texture_buffer(&mut renderer);
+        let mut damage_tracked_renderer = DamageTrackedRenderer::new((100, 100), 1.0, Transform::Normal);
+
+        let element =
+            TextureRenderElement::from_texture_buffer(Point::from((10.0, 10.0)), &buffer, None, None);
+        damage_tracked_renderer
+            .render_output(&mut renderer, 0, &[&element], [0.0, 0.0, 0.0, 1.0], None)
+            .unwrap();
+        let damage = damage_tracked_renderer
+            .render_output::<&TextureRenderElement<TestTexture>, _>(
+                &mut renderer,
+                1,
+                &[],
+                [0.0, 0.0, 0.0, 1.0],
+                None,
+            )
+            .unwrap();
+
+        assert_eq!(
+            damage,
+            Some(vec![Rectangle::from_loc_and_size((10, 10), (10, 10))])
+        );
+        assert!(renderer.last_frame().unwrap().rendered_textures().is_empty());
+    }
+
+    #[test]
+    fn moved_element_damages_old_and_new_geometry() {
+        let mut renderer = TestRenderer::new();
+        let buffer = texture_buffer(&mut renderer);
+        let mut damage_tracked_renderer = DamageTrackedRenderer::new((100, 100), 1.0, Transform::Normal);
+
+        let element =
+            TextureRenderElement::from_texture_buffer(Point::from((10.0, 10.0)), &buffer, None, None);
+        damage_tracked_renderer
+            .render_output(&mut renderer, 0, &[&element], [0.0, 0.0, 0.0, 1.0], None)
+            .unwrap();
+
+        let element =
+            TextureRenderElement::from_texture_buffer(Point::from((50.0, 50.0)), &buffer, None, None);
+        let mut damage = damage_tracked_renderer
+            .render_output(&mut renderer, 1, &[&element], [0.0, 0.0, 0.0, 1.0], None)
+            .unwrap()
+            .unwrap();
+        damage.sort_by_key(|rect| rect.loc.x);
+
+        assert_eq!(
+            damage,
+            vec![
+                Rectangle::from_loc_and_size((10, 10), (10, 10)),
+                Rectangle::from_loc_and_size((50, 50), (10, 10)),
+            ]
+        );
+        assert_eq!(renderer.last_frame().unwrap().rendered_textures(), vec![0]);
+    }
+}
@@ -0,0 +1,423 @@
+//! Element caching the output of other elements in a texture
+//!
+//! # Why use this implementation
+//!
+//! Drawing a lot of elements every frame, e.g. all windows of a workspace in an overview,
+//! can get expensive, even if most of them did not change. The [`CachedRenderBuffer`] renders
+//! a list of child [`RenderElement`]s into an offscreen texture using [`Offscreen`] and only
+//! re-renders this texture, if any of the children reports a new [`CommitCounter`], has been moved,
+//! added or removed. The cached texture can then be presented with a [`CachedRenderElement`],
+//! which reports the damage of the re-rendered regions.
+//!
+//! # Why **not** to use this implementation
+//!
+//! The cached texture is rendered for a fixed size and scale. If the children change every frame,
+//! caching only adds an additional copy. The children are rendered without any knowledge of the
+//! final output, so the cached texture can not be used for direct scan-out of the children.
+//!
+//! # How to use it
+//!
+//! The children have to be positioned relative to the origin of the cached region.
+//! Call [`CachedRenderBuffer::update`] before binding the target of your output, as rendering to the
+//! offscreen texture replaces the currently bound target of the renderer.
+//!
+//! ```no_run
+//! # use smithay::backend::renderer::{element::RenderElement, Offscreen, Renderer, Texture};
+//! # fn children<E>() -> Vec<E> { unimplemented!() }
+//! # fn example<R, E>(renderer: &mut R)
+//! # where
+//! #     R: Renderer + Offscreen<<R as Renderer>::TextureId>,
+//! #     <R as Renderer>::TextureId: Texture + Clone,
+//! #     E: RenderElement<R>,
+//! # {
+//! use smithay::{
+//!     backend::renderer::element::cache::CachedRenderBuffer,
+//!     utils::{Point, Scale},
+//! };
+//!
+//! let mut cache = CachedRenderBuffer::new();
+//!
+//! // in your render loop
+//! let elements: Vec<E> = children();
+//! cache
+//!     .update(renderer, (400, 300), Scale::from(1.0), &elements, None)
+//!     .expect("failed to render cached elements");
+//!
+//! // present the cached texture at the desired location
+//! if let Some(element) = cache.render_element(Point::from((100, 100))) {
+//!     // render `element` instead of `elements` on your output
+//! }
+//! # }
+//! ```
+
+use crate::{
+    backend::renderer::{
+        damage::{DamageTrackedRenderer, DamageTrackedRendererError},
+        utils::{DamageTracker, DamageTrackerSnapshot},
+        Frame, Offscreen, Renderer, Texture,
+    },
+    utils::{Buffer, Physical, Point, Rectangle, Scale, Size, Transform},
+};
+
+use super::{CommitCounter, Id, RenderElement, UnderlyingStorage};
+
+use slog::warn;
+
+#[derive(Debug, Clone, PartialEq)]
+struct ChildState {
+    id: Id,
+    commit: CommitCounter,
+    geometry: Rectangle<i32, Physical>,
+}
+
+/// Texture caching the rendered output of a list of [`RenderElement`]s
+#[derive(Debug)]
+pub struct CachedRenderBuffer<T> {
+    id: Id,
+    renderer_id: Option<usize>,
+    texture: Option<T>,
+    size: Size<i32, Physical>,
+    scale: Scale<f64>,
+    damage_tracked_renderer: Option<DamageTrackedRenderer>,
+    children: Vec<ChildState>,
+    opaque_regions: Vec<Rectangle<i32, Physical>>,
+    damage_tracker: DamageTracker<i32, Physical>,
+}
+
+impl<T> Default for CachedRenderBuffer<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> CachedRenderBuffer<T> {
+    /// Create a new empty [`CachedRenderBuffer`]
+    ///
+    /// The texture is allocated on the first call to [`update`](CachedRenderBuffer::update).
+    pub fn new() -> Self {
+        CachedRenderBuffer {
+            id: Id::new(),
+            renderer_id: None,
+            texture: None,
+            size: Size::default(),
+            scale: Scale::from(1.0),
+            damage_tracked_renderer: None,
+            children: Vec::new(),
+            opaque_regions: Vec::new(),
+            damage_tracker: DamageTracker::default(),
+        }
+    }
+
+    /// Returns the cached texture, if already rendered
+    pub fn texture(&self) -> Option<&T> {
+        self.texture.as_ref()
+    }
+
+    /// Force a full re-render on the next call to [`update`](CachedRenderBuffer::update)
+    ///
+    /// This is useful, if the children changed in a way not reflected by their [`CommitCounter`].
+    pub fn invalidate(&mut self) {
+        self.children.clear();
+        self.damage_tracked_renderer = None;
+    }
+}
+
+impl<T: Texture + Clone> CachedRenderBuffer<T> {
+    /// Re-render the cached texture, if necessary
+    ///
+    /// The `elements` are rendered into a texture of the given physical `size` using `scale`.
+    /// Rendering is skipped, if the size and scale did not change and all elements have the same
+    /// [`CommitCounter`] and geometry in the same order as during the last call.
+    ///
+    /// This replaces the currently bound target of the renderer.
+    ///
+    /// Returns `true` if the texture has been re-rendered.
+    pub fn update<R, E>(
+        &mut self,
+        renderer: &mut R,
+        size: impl Into<Size<i32, Physical>>,
+        scale: impl Into<Scale<f64>>,
+        elements: &[E],
+        log: impl Into<Option<slog::Logger>>,
+    ) -> Result<bool, DamageTrackedRendererError<R>>
+    where
+        R: Renderer<TextureId = T> + Offscreen<T>,
+        E: RenderElement<R>,
+    {
+        let size = size.into();
+        let scale = scale.into();
+
+        if self.renderer_id != Some(renderer.id()) || self.size != size || self.scale != scale {
+            self.texture = None;
+        }
+
+        if size.w <= 0 || size.h <= 0 {
+            self.texture = None;
+            self.size = size;
+            self.children.clear();
+            return Ok(false);
+        }
+
+        let children = elements
+            .iter()
+            .map(|element| ChildState {
+                id: element.id().clone(),
+                commit: element.current_commit(),
+                geometry: element.geometry(scale),
+            })
+            .collect::<Vec<_>>();
+        if self.texture.is_some() && self.damage_tracked_renderer.is_some() && self.children == children {
+            return Ok(false);
+        }
+
+        let (texture, age) = match self.texture.take() {
+            Some(texture) => (texture, 1),
+            None => {
+                let texture = renderer
+                    .create_buffer(Size::from((size.w, size.h)))
+                    .map_err(DamageTrackedRendererError::Rendering)?;
+                self.renderer_id = Some(renderer.id());
+                self.size = size;
+                self.scale = scale;
+                self.damage_tracked_renderer = None;
+                self.damage_tracker.reset();
+                (texture, 0)
+            }
+        };
+        // a fresh damage tracked renderer does not know the current content, so redraw in full
+        let (damage_tracked_renderer, age) = match self.damage_tracked_renderer.as_mut() {
+            Some(damage_tracked_renderer) => (damage_tracked_renderer, age),
+            None => (
+                self.damage_tracked_renderer.insert(DamageTrackedRenderer::new(
+                    size,
+                    scale,
+                    Transform::Normal,
+                )),
+                0,
+            ),
+        };
+
+        renderer
+            .bind(texture.clone())
+            .map_err(DamageTrackedRendererError::Rendering)?;
+        let result =
+            damage_tracked_renderer.render_output(renderer, age, elements, [0.0, 0.0, 0.0, 0.0], log);
+        let unbind = renderer.unbind().map_err(DamageTrackedRendererError::Rendering);
+        let damage = match result.and_then(|damage| unbind.map(|_| damage)) {
+            Ok(damage) => damage,
+            Err(err) => {
+                // the content of the texture is unknown, start from scratch next time
+                self.damage_tracked_renderer = None;
+                self.texture = Some(texture);
+                return Err(err);
+            }
+        };
+
+        let texture_geometry = Rectangle::from_loc_and_size((0, 0), size);
+        if age == 0 {
+            self.damage_tracker.add(&[texture_geometry]);
+        } else if let Some(damage) = damage {
+            self.damage_tracker.add(&damage);
+        }
+        self.opaque_regions = elements
+            .iter()
+            .flat_map(|element| {
+                let location = element.geometry(scale).loc;
+                element.opaque_regions(scale).into_iter().map(move |mut region| {
+                    region.loc += location;
+                    region
+                })
+            })
+            .filter_map(|region| region.intersection(texture_geometry))
+            .collect();
+        self.children = children;
+        self.texture = Some(texture);
+
+        Ok(true)
+    }
+
+    /// Create a [`CachedRenderElement`] presenting the cached texture at the given physical location
+    ///
+    /// Returns `None` if the texture has not been rendered yet.
+    pub fn render_element(
+        &self,
+        location: impl Into<Point<i32, Physical>>,
+    ) -> Option<CachedRenderElement<T>> {
+        let texture = self.texture.clone()?;
+        Some(CachedRenderElement {
+            id: self.id.clone(),
+            renderer_id: self.renderer_id?,
+            location: location.into(),
+            texture,
+            size: self.size,
+            opaque_regions: self.opaque_regions.clone(),
+            snapshot: self.damage_tracker.snapshot(),
+        })
+    }
+}
+
+/// A render element for a [`CachedRenderBuffer`]
+///
+/// The element is always drawn with the size and scale used during the last
+/// [`update`](CachedRenderBuffer::update), independent of the scale it is rendered with.
+#[derive(Debug)]
+pub struct CachedRenderElement<T> {
+    id: Id,
+    renderer_id: usize,
+    location: Point<i32, Physical>,
+    texture: T,
+    size: Size<i32, Physical>,
+    opaque_regions: Vec<Rectangle<i32, Physical>>,
+    snapshot: DamageTrackerSnapshot<i32, Physical>,
+}
+
+impl<T> CachedRenderElement<T> {
+    /// Returns the cached texture
+    pub fn texture(&self) -> &T {
+        &self.texture
+    }
+}
+
+impl<R, T> RenderElement<R> for CachedRenderElement<T>
+where
+    R: Renderer<TextureId = T>,
+    T: Texture,
+{
+    fn id(&self) -> &Id {
+        &self.id
+    }
+
+    fn current_commit(&self) -> CommitCounter {
+        self.snapshot.current_commit()
+    }
+
+    fn src(&self) -> Rectangle<f64, Buffer> {
+        Rectangle::from_loc_and_size((0.0, 0.0), (self.size.w as f64, self.size.h as f64))
+    }
+
+    fn geometry(&self, _scale: Scale<f64>) -> Rectangle<i32, Physical> {
+        Rectangle::from_loc_and_size(self.location, self.size)
+    }
+
+    fn damage_since(
+        &self,
+        _scale: Scale<f64>,
+        commit: Option<CommitCounter>,
+    ) -> Vec<Rectangle<i32, Physical>> {
+        self.snapshot
+            .damage_since(commit)
+            .unwrap_or_else(|| vec![Rectangle::from_loc_and_size((0, 0), self.size)])
+    }
+
+    fn opaque_regions(&self, _scale: Scale<f64>) -> Vec<Rectangle<i32, Physical>> {
+        self.opaque_regions.clone()
+    }
+
+    fn underlying_storage(&self, _renderer: &R) -> Option<UnderlyingStorage<'_, R>> {
+        Some(UnderlyingStorage::External(&self.texture))
+    }
+
+    fn draw(
+        &self,
+        renderer: &mut R,
+        frame: &mut <R as Renderer>::Frame,
+        location: Point<i32, Physical>,
+        _scale: Scale<f64>,
+        damage: &[Rectangle<i32, Physical>],
+        log: &slog::Logger,
+    ) -> Result<(), <R as Renderer>::Error> {
+        if renderer.id() != self.renderer_id {
+            warn!(log, "trying to render cached texture from different renderer");
+            return Ok(());
+        }
+
+        let dst = Rectangle::from_loc_and_size(location, self.size);
+        let src = RenderElement::<R>::src(self);
+        frame.render_texture_from_to(&self.texture, src, dst, damage, Transform::Normal, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::renderer::{
+        element::texture::{TextureRenderBuffer, TextureRenderElement},
+        test::TestRenderer,
+    };
+
+    fn child(renderer: &mut TestRenderer) -> TextureRenderBuffer<<TestRenderer as Renderer>::TextureId> {
+        TextureRenderBuffer::from_memory(
+            renderer,
+            &[0u8; 10 * 10 * 4],
+            (10, 10),
+            false,
+            1,
+            Transform::Normal,
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn rerenders_only_on_commit() {
+        let mut renderer = TestRenderer::new();
+        let mut buffer = child(&mut renderer);
+        let mut cache = CachedRenderBuffer::new();
+
+        let elements = [TextureRenderElement::from_texture_render_buffer(
+            (5.0, 5.0),
+            &buffer,
+            None,
+            None,
+        )];
+        assert!(cache
+            .update(&mut renderer, (20, 20), 1.0, &elements, None)
+            .unwrap());
+        let target = cache.texture().unwrap().id();
+        assert_eq!(renderer.last_frame().unwrap().target, Some(target));
+        assert!(renderer.target().is_none());
+        let element = cache.render_element((100, 100)).unwrap();
+        let commit = RenderElement::<TestRenderer>::current_commit(&element);
+
+        // nothing changed, no re-render and no damage
+        let frames = renderer.frames().len();
+        assert!(!cache
+            .update(&mut renderer, (20, 20), 1.0, &elements, None)
+            .unwrap());
+        assert_eq!(renderer.frames().len(), frames);
+        let element = cache.render_element((100, 100)).unwrap();
+        assert!(RenderElement::<TestRenderer>::damage_since(&element, 1.0.into(), Some(commit)).is_empty());
+
+        // a new commit of a child re-renders the damaged region only
+        buffer
+            .update_from_memory(
+                &mut renderer,
+                &[0u8; 10 * 10 * 4],
+                Rectangle::from_loc_and_size((0, 0), (2, 2)),
+                None,
+            )
+            .unwrap();
+        let elements = [TextureRenderElement::from_texture_render_buffer(
+            (5.0, 5.0),
+            &buffer,
+            None,
+            None,
+        )];
+        assert!(cache
+            .update(&mut renderer, (20, 20), 1.0, &elements, None)
+            .unwrap());
+        assert_eq!(renderer.frames().len(), frames + 1);
+        assert_eq!(cache.texture().unwrap().id(), target);
+        let element = cache.render_element((100, 100)).unwrap();
+        assert_eq!(
+            RenderElement::<TestRenderer>::damage_since(&element, 1.0.into(), Some(commit)),
+            vec![Rectangle::from_loc_and_size((5, 5), (2, 2))]
+        );
+
+        // resizing allocates a new texture
+        assert!(cache
+            .update(&mut renderer, (30, 30), 1.0, &elements, None)
+            .unwrap());
+        assert_ne!(cache.texture().unwrap().id(), target);
+    }
+}
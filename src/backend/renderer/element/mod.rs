@@ -8,6 +8,7 @@
 //! of the element.
 //!
 //! Out of the box smithay provides the following elements
+//! - [`cache`](crate::backend::renderer::element::cache) - Element caching other elements in a texture
 //! - [`memory`](crate::backend::renderer::element::memory) - Memory based render element
 //! - [`texture`](crate::backend::renderer::element::texture) - Texture based render element
 //! - [`surface`](crate::backend::renderer::element::surface) - Wayland surface render element
@@ -27,6 +28,7 @@ use crate::utils::{Buffer as BufferCoords, Physical, Point, Rectangle, Scale, Tr
 
 use super::{utils::CommitCounter, Renderer};
 
+pub mod cache;
 pub mod memory;
 #[cfg(feature = "wayland_frontend")]
 pub mod surface;
@@ -903,6 +905,9 @@ macro_rules! render_elements_internal {
 /// #     fn transformation(&self) -> Transform {
 /// #         unimplemented!()
 /// #     }
+/// #     fn finish(&mut self) -> Result<smithay::backend::renderer::sync::SyncPoint, Self::Error> {
+/// #         unimplemented!()
+/// #     }
 /// # }
 /// #
 /// # struct MyRenderer;
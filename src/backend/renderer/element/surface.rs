@@ -66,6 +66,9 @@
 //! #     fn transformation(&self) -> Transform {
 //! #         unimplemented!()
 //! #     }
+//! #     fn finish(&mut self) -> Result<smithay::backend::renderer::sync::SyncPoint, Self::Error> {
+//! #         unimplemented!()
+//! #     }
 //! # }
 //! #
 //! # struct FakeRenderer;
@@ -81,6 +81,9 @@
 //! #     fn transformation(&self) -> Transform {
 //! #         unimplemented!()
 //! #     }
+//! #     fn finish(&mut self) -> Result<smithay::backend::renderer::sync::SyncPoint, Self::Error> {
+//! #         unimplemented!()
+//! #     }
 //! # }
 //! #
 //! # struct FakeRenderer;
@@ -212,6 +215,9 @@
 //! #     fn transformation(&self) -> Transform {
 //! #         unimplemented!()
 //! #     }
+//! #     fn finish(&mut self) -> Result<smithay::backend::renderer::sync::SyncPoint, Self::Error> {
+//! #         unimplemented!()
+//! #     }
 //! # }
 //! #
 //! # struct FakeRenderer;
@@ -0,0 +1,209 @@
+//! Output color transformations applied by the [`Gles2Renderer`](super::Gles2Renderer)
+
+/// A color transformation applied to everything rendered by a [`Gles2Renderer`](super::Gles2Renderer)
+///
+/// The transformation operates on non-premultiplied, non-linear rgb values in the range `0.0..=1.0`
+/// and is applied in two steps:
+///
+/// 1. The color is multiplied with a 3x3 [`matrix`](ColorTransform::matrix) and clamped.
+/// 2. Each channel is optionally mapped through a [`ColorLut`].
+///
+/// Alpha values are left untouched.
+///
+/// This can be used to implement e.g. blue-light reduction ([`ColorTransform::temperature`]),
+/// grayscale ([`ColorTransform::grayscale`]) or color-blindness correction filters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorTransform {
+    /// Row-major 3x3 matrix applied to the rgb components
+    pub matrix: [[f32; 3]; 3],
+    /// Optional per-channel lookup table applied after the matrix
+    pub lut: Option<ColorLut>,
+}
+
+/// Per-channel one-dimensional lookup table
+///
+/// Every channel is sampled at `value * (len - 1)`, linearly interpolating between adjacent entries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorLut {
+    red: Vec<f32>,
+    green: Vec<f32>,
+    blue: Vec<f32>,
+}
+
+impl ColorLut {
+    /// Creates a new lookup table from the entries of each channel.
+    ///
+    /// Entries are expected to be in the range `0.0..=1.0`.
+    ///
+    /// Returns `None` if the channels have different lengths or less than two entries.
+    pub fn new(red: Vec<f32>, green: Vec<f32>, blue: Vec<f32>) -> Option<ColorLut> {
+        if red.len() < 2 || red.len() != green.len() || red.len() != blue.len() {
+            return None;
+        }
+        Some(ColorLut { red, green, blue })
+    }
+
+    /// Creates a lookup table from a gamma ramp as used by DRM, e.g. `wlr_gamma_control`.
+    ///
+    /// Returns `None` if the channels have different lengths or less than two entries.
+    pub fn from_gamma_ramp(red: &[u16], green: &[u16], blue: &[u16]) -> Option<ColorLut> {
+        let normalize = |ramp: &[u16]| ramp.iter().map(|&v| v as f32 / u16::MAX as f32).collect();
+        ColorLut::new(normalize(red), normalize(green), normalize(blue))
+    }
+
+    /// Number of entries per channel
+    pub fn len(&self) -> usize {
+        self.red.len()
+    }
+
+    /// Returns `true` if the table has no entries, which is never the case for a constructed `ColorLut`
+    pub fn is_empty(&self) -> bool {
+        self.red.is_empty()
+    }
+
+    /// Entries of the red, green and blue channel
+    pub fn channels(&self) -> [&[f32]; 3] {
+        [&self.red, &self.green, &self.blue]
+    }
+
+    /// Maps `value` through the given channel (0 = red, 1 = green, 2 = blue)
+    fn sample(&self, channel: usize, value: f32) -> f32 {
+        let entries = self.channels()[channel];
+        let pos = value.clamp(0.0, 1.0) * (entries.len() - 1) as f32;
+        let idx = (pos.floor() as usize).min(entries.len() - 2);
+        let fract = pos - idx as f32;
+        entries[idx] * (1.0 - fract) + entries[idx + 1] * fract
+    }
+
+    /// Texel data of the table as a `len x 1` RGBA8 texture
+    pub(super) fn texture_data(&self) -> Vec<u8> {
+        let to_u8 = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+        (0..self.len())
+            .flat_map(|i| [to_u8(self.red[i]), to_u8(self.green[i]), to_u8(self.blue[i]), 255])
+            .collect()
+    }
+}
+
+impl Default for ColorTransform {
+    fn default() -> Self {
+        ColorTransform::identity()
+    }
+}
+
+impl ColorTransform {
+    /// Transformation leaving all colors unchanged
+    pub fn identity() -> ColorTransform {
+        ColorTransform::from_matrix([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]])
+    }
+
+    /// Creates a transformation only consisting of the given row-major matrix
+    pub fn from_matrix(matrix: [[f32; 3]; 3]) -> ColorTransform {
+        ColorTransform { matrix, lut: None }
+    }
+
+    /// Transformation converting all colors to grayscale using the BT.709 luma coefficients
+    pub fn grayscale() -> ColorTransform {
+        let luma = [0.2126, 0.7152, 0.0722];
+        ColorTransform::from_matrix([luma, luma, luma])
+    }
+
+    /// Transformation approximating the white point of the given color temperature in kelvin
+    ///
+    /// `6500` leaves colors mostly unchanged, lower values reduce blue light.
+    /// The temperature is clamped to `1000..=40000`.
+    pub fn temperature(kelvin: u32) -> ColorTransform {
+        // Approximation of the planckian locus by Tanner Helland
+        let temp = kelvin.clamp(1000, 40000) as f32 / 100.0;
+        let red = if temp <= 66.0 {
+            1.0
+        } else {
+            329.69873 * (temp - 60.0).powf(-0.13320476) / 255.0
+        };
+        let green = if temp <= 66.0 {
+            (99.4708 * temp.ln() - 161.11957) / 255.0
+        } else {
+            288.12216 * (temp - 60.0).powf(-0.075514846) / 255.0
+        };
+        let blue = if temp >= 66.0 {
+            1.0
+        } else if temp <= 19.0 {
+            0.0
+        } else {
+            (138.51773 * (temp - 10.0).ln() - 305.0448) / 255.0
+        };
+
+        let (red, green, blue) = (red.clamp(0.0, 1.0), green.clamp(0.0, 1.0), blue.clamp(0.0, 1.0));
+        ColorTransform::from_matrix([[red, 0.0, 0.0], [0.0, green, 0.0], [0.0, 0.0, blue]])
+    }
+
+    /// Adds a lookup table applied after the matrix
+    pub fn with_lut(mut self, lut: ColorLut) -> ColorTransform {
+        self.lut = Some(lut);
+        self
+    }
+
+    /// Returns `true` if this transformation does not change any color
+    pub fn is_identity(&self) -> bool {
+        self.lut.is_none() && *self == ColorTransform::identity()
+    }
+
+    /// Applies the transformation to a premultiplied rgba color
+    pub fn apply(&self, color: [f32; 4]) -> [f32; 4] {
+        let alpha = color[3];
+        if alpha <= 0.0 {
+            return color;
+        }
+
+        let rgb = [color[0] / alpha, color[1] / alpha, color[2] / alpha];
+        let mut out = [0.0; 3];
+        for (channel, row) in self.matrix.iter().enumerate() {
+            let value = (row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2]).clamp(0.0, 1.0);
+            out[channel] = match self.lut {
+                Some(ref lut) => lut.sample(channel, value),
+                None => value,
+            };
+        }
+
+        [out[0] * alpha, out[1] * alpha, out[2] * alpha, alpha]
+    }
+
+    /// Column-major matrix as expected by `glUniformMatrix3fv`
+    pub(super) fn gl_matrix(&self) -> [f32; 9] {
+        let m = &self.matrix;
+        [
+            m[0][0], m[1][0], m[2][0], m[0][1], m[1][1], m[2][1], m[0][2], m[1][2], m[2][2],
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_matrix_premultiplied() {
+        let transform = ColorTransform::grayscale();
+        let color = transform.apply([0.5, 0.0, 0.0, 0.5]);
+        assert!((color[0] - 0.2126 * 0.5).abs() < 1e-6);
+        assert_eq!(color[0], color[1]);
+        assert_eq!(color[1], color[2]);
+        assert_eq!(color[3], 0.5);
+
+        assert!(ColorTransform::identity().is_identity());
+        assert_eq!(
+            ColorTransform::identity().apply([0.2, 0.4, 0.6, 1.0]),
+            [0.2, 0.4, 0.6, 1.0]
+        );
+    }
+
+    #[test]
+    fn apply_lut_interpolates() {
+        assert!(ColorLut::new(vec![0.0], vec![0.0], vec![0.0]).is_none());
+        assert!(ColorLut::new(vec![0.0, 1.0], vec![0.0, 0.5, 1.0], vec![0.0, 1.0]).is_none());
+
+        let lut = ColorLut::new(vec![1.0, 0.0, 0.0], vec![0.0, 0.5, 1.0], vec![0.0, 0.0, 1.0]).unwrap();
+        let transform = ColorTransform::identity().with_lut(lut);
+        assert!(!transform.is_identity());
+        assert_eq!(transform.apply([0.25, 0.25, 0.75, 1.0]), [0.5, 0.25, 0.5, 1.0]);
+    }
+}
@@ -10,22 +10,31 @@ use std::{
     fmt, mem,
     os::raw::c_char,
     ptr,
-    rc::Rc,
     sync::{
         atomic::{AtomicPtr, Ordering},
         mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
     },
 };
 
 #[cfg(feature = "wayland_frontend")]
-use std::{cell::RefCell, collections::HashMap};
+use crate::utils::user_data::UserDataMap;
+#[cfg(feature = "wayland_frontend")]
+use std::collections::HashMap;
 
+mod color;
+mod pool;
 mod shaders;
 mod version;
+mod yuv;
+
+pub use self::color::{ColorLut, ColorTransform};
+pub use self::pool::Gles2TexturePool;
+pub use self::yuv::{YuvColorSpace, YuvRange};
 
 use super::{
-    Bind, ExportDma, ExportMem, Frame, ImportDma, ImportMem, Offscreen, Renderer, Texture, TextureFilter,
-    TextureMapping, Unbind,
+    sync::SyncPoint, Bind, ExportDma, ExportMem, Frame, ImportDma, ImportMem, Offscreen, Renderer, Texture,
+    TextureFilter, TextureMapping, Unbind,
 };
 use crate::backend::allocator::{
     dmabuf::{Dmabuf, WeakDmabuf},
@@ -33,7 +42,7 @@ use crate::backend::allocator::{
 };
 use crate::backend::egl::{
     ffi::egl::{self as ffi_egl, types::EGLImage},
-    EGLContext, EGLSurface, MakeCurrentError,
+    EGLContext, EGLDisplay, EGLFence, EGLSurface, MakeCurrentError,
 };
 use crate::backend::SwapBuffersError;
 use crate::utils::{Buffer as BufferCoord, Physical, Rectangle, Size, Transform};
@@ -42,6 +51,8 @@ use crate::utils::{Buffer as BufferCoord, Physical, Rectangle, Size, Transform};
 use super::ImportEgl;
 #[cfg(feature = "wayland_frontend")]
 use super::{ImportDmaWl, ImportMemWl};
+#[cfg(feature = "wayland_frontend")]
+use crate::backend::allocator::Fourcc;
 #[cfg(all(feature = "wayland_frontend", feature = "use_system_lib"))]
 use crate::backend::egl::{display::EGLBufferReader, Format as EGLFormat};
 #[cfg(feature = "wayland_frontend")]
@@ -63,6 +74,11 @@ struct Gles2TexProgram {
     uniform_tex_matrix: ffi::types::GLint,
     uniform_matrix: ffi::types::GLint,
     uniform_alpha: ffi::types::GLint,
+    uniform_color_transform: ffi::types::GLint,
+    uniform_color_matrix: ffi::types::GLint,
+    uniform_color_lut_size: ffi::types::GLint,
+    uniform_yuv_matrix: ffi::types::GLint,
+    uniform_yuv_offset: ffi::types::GLint,
     attrib_vert: ffi::types::GLint,
     attrib_vert_position: ffi::types::GLint,
 }
@@ -78,7 +94,7 @@ struct Gles2SolidProgram {
 
 /// A handle to a GLES2 texture
 #[derive(Debug, Clone)]
-pub struct Gles2Texture(Rc<Gles2TextureInternal>);
+pub struct Gles2Texture(Arc<Gles2TextureInternal>);
 
 impl Gles2Texture {
     /// Create a Gles2Texture from a raw gl texture id.
@@ -97,13 +113,14 @@ impl Gles2Texture {
         tex: ffi::types::GLuint,
         size: Size<i32, BufferCoord>,
     ) -> Gles2Texture {
-        Gles2Texture(Rc::new(Gles2TextureInternal {
+        Gles2Texture(Arc::new(Gles2TextureInternal {
             texture: tex,
-            texture_kind: 0,
+            texture_kind: shaders::TEXTURE_KIND_ABGR,
             is_external: false,
             y_inverted: false,
             size,
             egl_images: None,
+            yuv: None,
             destruction_callback_sender: renderer.destruction_callback_sender.clone(),
         }))
     }
@@ -111,9 +128,26 @@ impl Gles2Texture {
     /// OpenGL texture id of this texture
     ///
     /// This id will become invalid, when the Gles2Texture is dropped and does not transfer ownership.
+    ///
+    /// For multi-planar YUV textures this is the texture of the luma plane.
     pub fn tex_id(&self) -> ffi::types::GLuint {
         self.0.texture
     }
+
+    /// Color space and range used to convert this texture to rgb, if it is a multi-planar YUV texture
+    pub fn yuv_encoding(&self) -> Option<(YuvColorSpace, YuvRange)> {
+        self.0.yuv.as_ref().map(|yuv| *yuv.encoding.lock().unwrap())
+    }
+
+    /// Sets the color space and range used to convert this texture to rgb.
+    ///
+    /// Defaults to [`YuvColorSpace::guess`] and [`YuvRange::Limited`], if not set.
+    /// Has no effect on textures, that are not multi-planar YUV textures.
+    pub fn set_yuv_encoding(&self, color_space: YuvColorSpace, range: YuvRange) {
+        if let Some(yuv) = self.0.yuv.as_ref() {
+            *yuv.encoding.lock().unwrap() = (color_space, range);
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -124,14 +158,53 @@ struct Gles2TextureInternal {
     y_inverted: bool,
     size: Size<i32, BufferCoord>,
     egl_images: Option<Vec<EGLImage>>,
+    yuv: Option<Gles2YuvPlanes>,
     destruction_callback_sender: Sender<CleanupResource>,
 }
 
+// Textures are shared between threads, e.g. by renderers of the same share group on multiple threads.
+// Per field:
+// - `texture`, `texture_kind`, `is_external`, `y_inverted`, `size` and the texture names of `yuv`
+//   are plain values and never modified after creation. The gl names may be used by any context
+//   of the share group, which is current on the using thread.
+// - `egl_images` is never modified after creation either, it is only taken on drop, which requires
+//   exclusive access. Concurrent access only reads the handles, which are objects of the display
+//   and may be used by any thread and context of the display (EGL_KHR_image_base).
+// - the encoding of `yuv` is guarded by a mutex.
+// - `destruction_callback_sender` is not `Sync`, but only used on drop, which requires exclusive access.
+unsafe impl Send for Gles2TextureInternal {}
+unsafe impl Sync for Gles2TextureInternal {}
+
+/// Chroma planes of a multi-planar YUV texture, the luma plane is stored as the main texture
+#[derive(Debug)]
+struct Gles2YuvPlanes {
+    textures: Vec<ffi::types::GLuint>,
+    swap_chroma: bool,
+    encoding: Mutex<(YuvColorSpace, YuvRange)>,
+}
+
+impl Gles2YuvPlanes {
+    fn new(textures: Vec<ffi::types::GLuint>, swap_chroma: bool, height: i32) -> Gles2YuvPlanes {
+        Gles2YuvPlanes {
+            textures,
+            swap_chroma,
+            encoding: Mutex::new((YuvColorSpace::guess(height), YuvRange::Limited)),
+        }
+    }
+}
+
 impl Drop for Gles2TextureInternal {
     fn drop(&mut self) {
         let _ = self
             .destruction_callback_sender
             .send(CleanupResource::Texture(self.texture));
+        if let Some(yuv) = self.yuv.take() {
+            for texture in yuv.textures {
+                let _ = self
+                    .destruction_callback_sender
+                    .send(CleanupResource::Texture(texture));
+            }
+        }
         if let Some(images) = self.egl_images.take() {
             for image in images {
                 let _ = self
@@ -142,6 +215,39 @@ impl Drop for Gles2TextureInternal {
     }
 }
 
+// Shm textures cached per surface and renderer id.
+//
+// why not store a `Gles2Texture`? because the user might do so.
+// this is guaranteed a non-public internal type, so we are good.
+//
+// The cache is accessible from any thread, so renderers sharing their id
+// (see `Gles2TexturePool`) on multiple threads also share the cached textures.
+#[cfg(feature = "wayland_frontend")]
+type ShmCache = Arc<Mutex<HashMap<usize, Arc<Gles2TextureInternal>>>>;
+
+#[cfg(feature = "wayland_frontend")]
+fn cached_shm_texture(data_map: &UserDataMap, id: usize) -> Option<Arc<Gles2TextureInternal>> {
+    data_map.insert_if_missing_threadsafe(ShmCache::default);
+    data_map
+        .get::<ShmCache>()
+        .unwrap()
+        .lock()
+        .unwrap()
+        .get(&id)
+        .cloned()
+}
+
+#[cfg(feature = "wayland_frontend")]
+fn cache_shm_texture(data_map: &UserDataMap, id: usize, texture: Arc<Gles2TextureInternal>) {
+    data_map.insert_if_missing_threadsafe(ShmCache::default);
+    data_map
+        .get::<ShmCache>()
+        .unwrap()
+        .lock()
+        .unwrap()
+        .insert(id, texture);
+}
+
 enum CleanupResource {
     Texture(ffi::types::GLuint),
     FramebufferObject(ffi::types::GLuint),
@@ -150,6 +256,9 @@ enum CleanupResource {
     Mapping(ffi::types::GLuint, *const nix::libc::c_void),
 }
 
+// The resources are only ever freed by the renderer, that created them.
+unsafe impl Send for CleanupResource {}
+
 impl Texture for Gles2Texture {
     fn width(&self) -> u32 {
         self.0.size.w as u32
@@ -210,7 +319,7 @@ struct Gles2Buffer {
 /// Usually more performant than using a texture as a framebuffer.
 /// Can be read out, but not used like a texture otherwise.
 #[derive(Debug, Clone)]
-pub struct Gles2Renderbuffer(Rc<Gles2RenderbufferInternal>);
+pub struct Gles2Renderbuffer(Arc<Gles2RenderbufferInternal>);
 
 #[derive(Debug)]
 struct Gles2RenderbufferInternal {
@@ -226,13 +335,16 @@ impl Drop for Gles2RenderbufferInternal {
     }
 }
 
+// The sender is only accessed on drop, which requires exclusive access.
+unsafe impl Sync for Gles2RenderbufferInternal {}
+
 #[derive(Debug)]
 enum Gles2Target {
     Image {
         buf: Gles2Buffer,
         dmabuf: Dmabuf,
     },
-    Surface(Rc<EGLSurface>),
+    Surface(Arc<EGLSurface>),
     Texture {
         texture: Gles2Texture,
         fbo: ffi::types::GLuint,
@@ -266,6 +378,9 @@ impl Drop for Gles2Target {
 }
 
 /// A renderer utilizing OpenGL ES 2
+///
+/// The renderer may be moved to another thread, but its context needs to be released first,
+/// see the [`Send`](#impl-Send-for-Gles2Renderer) implementation.
 pub struct Gles2Renderer {
     buffers: Vec<Gles2Buffer>,
     target: Option<Gles2Target>,
@@ -273,9 +388,13 @@ pub struct Gles2Renderer {
     tex_programs: [Gles2TexProgram; shaders::FRAGMENT_COUNT],
     solid_program: Gles2SolidProgram,
     dmabuf_cache: std::collections::HashMap<WeakDmabuf, Gles2Texture>,
+    dmabuf_texture_formats: HashSet<Format>,
+    #[cfg(feature = "wayland_frontend")]
+    shm_formats: Vec<wl_shm::Format>,
     egl: EGLContext,
+    // the reader and the thread the wl_display was bound on
     #[cfg(all(feature = "wayland_frontend", feature = "use_system_lib"))]
-    egl_reader: Option<EGLBufferReader>,
+    egl_reader: Option<(EGLBufferReader, std::thread::ThreadId)>,
     gl_version: version::GlVersion,
     vbos: [ffi::types::GLuint; 2],
     gl: ffi::Gles2,
@@ -285,12 +404,29 @@ pub struct Gles2Renderer {
     destruction_callback_sender: Sender<CleanupResource>,
     min_filter: TextureFilter,
     max_filter: TextureFilter,
+    color_transform: Option<Arc<ColorTransform>>,
+    color_lut: ffi::types::GLuint,
+    color_lut_dirty: bool,
     supports_instancing: bool,
+    supports_fences: bool,
     logger_ptr: Option<*mut ::slog::Logger>,
     pub(crate) logger: ::slog::Logger,
-    _not_send: *mut (),
 }
 
+/// The renderer may be moved to another thread, if its context is not current on the old thread anymore.
+///
+/// The renderer makes its context current on whichever thread it is used and does not release it
+/// afterwards. A context, that is still current on another thread, cannot be made current
+/// (`EGL_BAD_ACCESS`), which makes all operations of the renderer fail and leaks its gl resources on drop.
+/// So before moving the renderer, call [`EGLContext::unbind`] on the old thread
+/// (e.g. via [`Gles2Renderer::egl_context`]).
+///
+/// A wl_display bound via `ImportEgl::bind_wl_display` must only be used on the thread it was bound on,
+/// so unbind it before moving the renderer. Using the renderer to import egl buffers on another thread panics.
+// SAFETY: The egl images of the buffers and the leaked logger are only accessed by the renderer itself.
+// The requirements on the context are documented above, the thread of the wl_display is asserted.
+unsafe impl Send for Gles2Renderer {}
+
 struct RendererId(usize);
 impl Drop for RendererId {
     fn drop(&mut self) {
@@ -309,7 +445,12 @@ pub struct Gles2Frame {
     size: Size<i32, Physical>,
     min_filter: TextureFilter,
     max_filter: TextureFilter,
+    color_transform: Option<Arc<ColorTransform>>,
+    color_lut: ffi::types::GLuint,
     supports_instancing: bool,
+    // only set, if the display supports native fences
+    fence_display: Option<EGLDisplay>,
+    finished: bool,
 }
 
 impl fmt::Debug for Gles2Frame {
@@ -321,6 +462,7 @@ impl fmt::Debug for Gles2Frame {
             .field("size", &self.size)
             .field("min_filter", &self.min_filter)
             .field("max_filter", &self.max_filter)
+            .field("color_transform", &self.color_transform)
             .finish_non_exhaustive()
     }
 }
@@ -368,6 +510,9 @@ pub enum Gles2Error {
         "The OpenGL ES version of the underlying GL implementation is too low, at least required: {0:?}"
     )]
     GLVersionNotSupported(version::GlVersion),
+    /// A new egl context could not be created
+    #[error("Failed to create egl context")]
+    ContextCreationError(#[source] crate::backend::egl::Error),
     /// The underlying egl context could not be activated
     #[error("Failed to active egl context")]
     ContextActivationError(#[from] crate::backend::egl::MakeCurrentError),
@@ -407,6 +552,7 @@ impl From<Gles2Error> for SwapBuffersError {
             | x @ Gles2Error::GLExtensionNotSupported(_)
             | x @ Gles2Error::EGLExtensionNotSupported(_)
             | x @ Gles2Error::GLVersionNotSupported(_)
+            | x @ Gles2Error::ContextCreationError(_)
             | x @ Gles2Error::UnconstraintRenderingOperation => SwapBuffersError::ContextLost(Box::new(x)),
             Gles2Error::ContextActivationError(err) => err.into(),
             x @ Gles2Error::FramebufferBindingError
@@ -427,6 +573,7 @@ impl From<Gles2Error> for SwapBuffersError {
             | x @ Gles2Error::GLExtensionNotSupported(_)
             | x @ Gles2Error::EGLExtensionNotSupported(_)
             | x @ Gles2Error::GLVersionNotSupported(_)
+            | x @ Gles2Error::ContextCreationError(_)
             | x @ Gles2Error::UnconstraintRenderingOperation => SwapBuffersError::ContextLost(Box::new(x)),
             Gles2Error::ContextActivationError(err) => err.into(),
             x @ Gles2Error::FramebufferBindingError
@@ -511,6 +658,9 @@ unsafe fn link_program(
     Ok(program)
 }
 
+/// Texture unit the color lookup table is bound to, the units before are used by YUV planes
+const COLOR_LUT_UNIT: u32 = 3;
+
 unsafe fn texture_program(gl: &ffi::Gles2, frag: &'static str) -> Result<Gles2TexProgram, Gles2Error> {
     let program = link_program(gl, shaders::VERTEX_SHADER, frag)?;
 
@@ -520,6 +670,26 @@ unsafe fn texture_program(gl: &ffi::Gles2, frag: &'static str) -> Result<Gles2Te
     let matrix = CStr::from_bytes_with_nul(b"matrix\0").expect("NULL terminated");
     let tex_matrix = CStr::from_bytes_with_nul(b"tex_matrix\0").expect("NULL terminated");
     let alpha = CStr::from_bytes_with_nul(b"alpha\0").expect("NULL terminated");
+    let color_transform = CStr::from_bytes_with_nul(b"color_transform\0").expect("NULL terminated");
+    let color_matrix = CStr::from_bytes_with_nul(b"color_matrix\0").expect("NULL terminated");
+    let color_lut = CStr::from_bytes_with_nul(b"color_lut\0").expect("NULL terminated");
+    let color_lut_size = CStr::from_bytes_with_nul(b"color_lut_size\0").expect("NULL terminated");
+    let yuv_matrix = CStr::from_bytes_with_nul(b"yuv_matrix\0").expect("NULL terminated");
+    let yuv_offset = CStr::from_bytes_with_nul(b"yuv_offset\0").expect("NULL terminated");
+
+    let uniform_color_lut = gl.GetUniformLocation(program, color_lut.as_ptr() as *const ffi::types::GLchar);
+    // Chroma planes of YUV textures use the second and third texture unit, the lookup table the fourth.
+    // Samplers not used by a program have no location and are ignored.
+    gl.UseProgram(program);
+    for (name, unit) in [(&b"tex_uv\0"[..], 1), (b"tex_u\0", 1), (b"tex_v\0", 2)] {
+        let name = CStr::from_bytes_with_nul(name).expect("NULL terminated");
+        gl.Uniform1i(
+            gl.GetUniformLocation(program, name.as_ptr() as *const ffi::types::GLchar),
+            unit,
+        );
+    }
+    gl.Uniform1i(uniform_color_lut, COLOR_LUT_UNIT as i32);
+    gl.UseProgram(0);
 
     Ok(Gles2TexProgram {
         program,
@@ -527,6 +697,14 @@ unsafe fn texture_program(gl: &ffi::Gles2, frag: &'static str) -> Result<Gles2Te
         uniform_matrix: gl.GetUniformLocation(program, matrix.as_ptr() as *const ffi::types::GLchar),
         uniform_tex_matrix: gl.GetUniformLocation(program, tex_matrix.as_ptr() as *const ffi::types::GLchar),
         uniform_alpha: gl.GetUniformLocation(program, alpha.as_ptr() as *const ffi::types::GLchar),
+        uniform_color_transform: gl
+            .GetUniformLocation(program, color_transform.as_ptr() as *const ffi::types::GLchar),
+        uniform_color_matrix: gl
+            .GetUniformLocation(program, color_matrix.as_ptr() as *const ffi::types::GLchar),
+        uniform_color_lut_size: gl
+            .GetUniformLocation(program, color_lut_size.as_ptr() as *const ffi::types::GLchar),
+        uniform_yuv_matrix: gl.GetUniformLocation(program, yuv_matrix.as_ptr() as *const ffi::types::GLchar),
+        uniform_yuv_offset: gl.GetUniformLocation(program, yuv_offset.as_ptr() as *const ffi::types::GLchar),
         attrib_vert: gl.GetAttribLocation(program, vert.as_ptr() as *const ffi::types::GLchar),
         attrib_vert_position: gl
             .GetAttribLocation(program, vert_position.as_ptr() as *const ffi::types::GLchar),
@@ -638,13 +816,63 @@ impl Gles2Renderer {
             (gl, gl_version, exts, logger, supports_instancing)
         };
 
+        // ordered by the `shaders::TEXTURE_KIND_*` indices
         let tex_programs = [
             texture_program(&gl, shaders::FRAGMENT_SHADER_ABGR)?,
             texture_program(&gl, shaders::FRAGMENT_SHADER_XBGR)?,
             texture_program(&gl, shaders::FRAGMENT_SHADER_EXTERNAL)?,
+            texture_program(&gl, shaders::FRAGMENT_SHADER_YUV_2PLANE)?,
+            texture_program(&gl, shaders::FRAGMENT_SHADER_YUV_3PLANE)?,
         ];
         let solid_program = solid_program(&gl)?;
 
+        // YUV formats can be imported plane by plane, if the planes are importable as single channel formats
+        let dmabuf_texture_formats = {
+            let render_formats = context.dmabuf_render_formats();
+            let mut formats = context.dmabuf_texture_formats().clone();
+            for code in yuv::YUV_FORMATS {
+                let layout = yuv::yuv_layout(code).expect("YUV_FORMATS have a layout");
+                formats.extend(
+                    render_formats
+                        .iter()
+                        .filter(|format| format.code == layout.planes[0].0)
+                        .filter(|format| {
+                            layout.planes.iter().all(|&(plane, _)| {
+                                render_formats.contains(&Format {
+                                    code: plane,
+                                    modifier: format.modifier,
+                                })
+                            })
+                        })
+                        .map(|format| Format {
+                            code,
+                            modifier: format.modifier,
+                        }),
+                );
+            }
+            formats
+        };
+
+        #[cfg(feature = "wayland_frontend")]
+        let shm_formats = {
+            let mut formats = vec![
+                wl_shm::Format::Abgr8888,
+                wl_shm::Format::Xbgr8888,
+                wl_shm::Format::Argb8888,
+                wl_shm::Format::Xrgb8888,
+            ];
+            // single and dual channel textures are required for YUV planes
+            if gl_version >= version::GLES_3_0 || exts.iter().any(|ext| ext == "GL_EXT_texture_rg") {
+                formats.extend([
+                    wl_shm::Format::Nv12,
+                    wl_shm::Format::Nv21,
+                    wl_shm::Format::Yuv420,
+                    wl_shm::Format::Yvu420,
+                ]);
+            }
+            formats
+        };
+
         // Initialize vertices based on drawing methodology.
         let vertices: &[ffi::types::GLfloat] = if supports_instancing {
             &INSTANCED_VERTS
@@ -665,7 +893,8 @@ impl Gles2Renderer {
 
         context
             .user_data()
-            .insert_if_missing(|| RendererId(next_renderer_id()));
+            .insert_if_missing_threadsafe(|| RendererId(next_renderer_id()));
+        let supports_fences = EGLFence::is_supported(context.display());
         let (tx, rx) = channel();
         let renderer = Gles2Renderer {
             gl,
@@ -679,15 +908,21 @@ impl Gles2Renderer {
             target: None,
             buffers: Vec::new(),
             dmabuf_cache: std::collections::HashMap::new(),
+            dmabuf_texture_formats,
+            #[cfg(feature = "wayland_frontend")]
+            shm_formats,
             destruction_callback: rx,
             destruction_callback_sender: tx,
             vbos,
             min_filter: TextureFilter::Linear,
             max_filter: TextureFilter::Linear,
+            color_transform: None,
+            color_lut: 0,
+            color_lut_dirty: false,
             supports_instancing,
+            supports_fences,
             logger_ptr,
             logger: log,
-            _not_send: std::ptr::null_mut(),
         };
         renderer.egl.unbind()?;
         Ok(renderer)
@@ -773,10 +1008,6 @@ impl ImportMemWl for Gles2Renderer {
     ) -> Result<Gles2Texture, Gles2Error> {
         use crate::wayland::shm::with_buffer_contents;
 
-        // why not store a `Gles2Texture`? because the user might do so.
-        // this is guaranteed a non-public internal type, so we are good.
-        type CacheMap = HashMap<usize, Rc<Gles2TextureInternal>>;
-
         with_buffer_contents(buffer, |slice, data| {
             self.make_current()?;
 
@@ -785,18 +1016,27 @@ impl ImportMemWl for Gles2Renderer {
             let height = data.height as i32;
             let stride = data.stride as i32;
 
-            // number of bytes per pixel
-            // TODO: compute from data.format
-            let pixelsize = 4i32;
-
-            // ensure consistency, the SHM handler of smithay should ensure this
-            assert!((offset + (height - 1) * stride + width * pixelsize) as usize <= slice.len());
-
-            let (gl_format, shader_idx) = match data.format {
-                wl_shm::Format::Abgr8888 => (ffi::RGBA, 0),
-                wl_shm::Format::Xbgr8888 => (ffi::RGBA, 1),
-                wl_shm::Format::Argb8888 => (ffi::BGRA_EXT, 0),
-                wl_shm::Format::Xrgb8888 => (ffi::BGRA_EXT, 1),
+            let (gl_format, shader_idx, yuv_layout) = match data.format {
+                wl_shm::Format::Abgr8888 => (ffi::RGBA, shaders::TEXTURE_KIND_ABGR, None),
+                wl_shm::Format::Xbgr8888 => (ffi::RGBA, shaders::TEXTURE_KIND_XBGR, None),
+                wl_shm::Format::Argb8888 => (ffi::BGRA_EXT, shaders::TEXTURE_KIND_ABGR, None),
+                wl_shm::Format::Xrgb8888 => (ffi::BGRA_EXT, shaders::TEXTURE_KIND_XBGR, None),
+                format if self.shm_formats.contains(&format) => {
+                    let layout = match format {
+                        wl_shm::Format::Nv12 => yuv::yuv_layout(Fourcc::Nv12),
+                        wl_shm::Format::Nv21 => yuv::yuv_layout(Fourcc::Nv21),
+                        wl_shm::Format::Yuv420 => yuv::yuv_layout(Fourcc::Yuv420),
+                        wl_shm::Format::Yvu420 => yuv::yuv_layout(Fourcc::Yvu420),
+                        _ => None,
+                    }
+                    .ok_or(Gles2Error::UnsupportedPixelFormat(format))?;
+                    let shader_idx = if layout.planes.len() == 2 {
+                        shaders::TEXTURE_KIND_YUV_2PLANE
+                    } else {
+                        shaders::TEXTURE_KIND_YUV_3PLANE
+                    };
+                    (0, shader_idx, Some(layout))
+                }
                 format => return Err(Gles2Error::UnsupportedPixelFormat(format)),
             };
 
@@ -805,46 +1045,51 @@ impl ImportMemWl for Gles2Renderer {
             let id = self.id();
             let texture = Gles2Texture(
                 surface
-                    .and_then(|surface| {
-                        surface
-                            .data_map
-                            .insert_if_missing(|| Rc::new(RefCell::new(CacheMap::new())));
-                        surface
-                            .data_map
-                            .get::<Rc<RefCell<CacheMap>>>()
-                            .unwrap()
-                            .borrow()
-                            .get(&id)
-                            .cloned()
+                    .and_then(|surface| cached_shm_texture(&surface.data_map, id))
+                    .filter(|texture| {
+                        texture.size == (width, height).into() && texture.texture_kind == shader_idx
                     })
-                    .filter(|texture| texture.size == (width, height).into())
                     .unwrap_or_else(|| {
                         let mut tex = 0;
                         unsafe { self.gl.GenTextures(1, &mut tex) };
+                        let yuv = yuv_layout.map(|layout| {
+                            let mut planes = vec![0; layout.planes.len() - 1];
+                            unsafe { self.gl.GenTextures(planes.len() as i32, planes.as_mut_ptr()) };
+                            Gles2YuvPlanes::new(planes, layout.swap_chroma, height)
+                        });
                         // new texture, upload in full
                         upload_full = true;
-                        let new = Rc::new(Gles2TextureInternal {
+                        let new = Arc::new(Gles2TextureInternal {
                             texture: tex,
                             texture_kind: shader_idx,
                             is_external: false,
                             y_inverted: false,
                             size: (width, height).into(),
                             egl_images: None,
+                            yuv,
                             destruction_callback_sender: self.destruction_callback_sender.clone(),
                         });
                         if let Some(surface) = surface {
-                            let copy = new.clone();
-                            surface
-                                .data_map
-                                .get::<Rc<RefCell<CacheMap>>>()
-                                .unwrap()
-                                .borrow_mut()
-                                .insert(id, copy);
+                            cache_shm_texture(&surface.data_map, id, new.clone());
                         }
                         new
                     }),
             );
 
+            if let Some(layout) = yuv_layout {
+                // chroma planes are subsampled, so damage is not tracked and the planes are uploaded in full
+                trace!(self.logger, "Uploading yuv shm texture for {:?}", buffer);
+                self.upload_shm_yuv(&texture, slice, &data, layout)?;
+                return Ok(texture);
+            }
+
+            // number of bytes per pixel
+            // TODO: compute from data.format
+            let pixelsize = 4i32;
+
+            // ensure consistency, the SHM handler of smithay should ensure this
+            assert!((offset + (height - 1) * stride + width * pixelsize) as usize <= slice.len());
+
             unsafe {
                 self.gl.BindTexture(ffi::TEXTURE_2D, texture.0.texture);
                 self.gl
@@ -897,12 +1142,79 @@ impl ImportMemWl for Gles2Renderer {
     }
 
     fn shm_formats(&self) -> &[wl_shm::Format] {
-        &[
-            wl_shm::Format::Abgr8888,
-            wl_shm::Format::Xbgr8888,
-            wl_shm::Format::Argb8888,
-            wl_shm::Format::Xrgb8888,
-        ]
+        &self.shm_formats
+    }
+}
+
+#[cfg(feature = "wayland_frontend")]
+impl Gles2Renderer {
+    /// Uploads all planes of a multi-planar YUV shm buffer.
+    ///
+    /// Chroma planes are expected to directly follow the previous plane, using the stride
+    /// of the luma plane scaled by their subsampling and bytes per pixel.
+    fn upload_shm_yuv(
+        &self,
+        texture: &Gles2Texture,
+        slice: &[u8],
+        data: &crate::wayland::shm::BufferData,
+        layout: yuv::YuvLayout,
+    ) -> Result<(), Gles2Error> {
+        let planes = texture
+            .0
+            .yuv
+            .as_ref()
+            .map(|yuv| yuv.textures.as_slice())
+            .unwrap_or(&[]);
+        let mut offset = data.offset as usize;
+        for (&(code, subsampling), &tex) in layout
+            .planes
+            .iter()
+            .zip(std::iter::once(&texture.0.texture).chain(planes))
+        {
+            let (pixelsize, internal_format, gl_format) = match (code, self.gl_version >= version::GLES_3_0) {
+                (Fourcc::R8, true) => (1, ffi::R8, ffi::RED),
+                (Fourcc::R8, false) => (1, ffi::RED, ffi::RED),
+                (_, true) => (2, ffi::RG8, ffi::RG),
+                (_, false) => (2, ffi::RG, ffi::RG),
+            };
+            let width = (data.width + subsampling - 1) / subsampling;
+            let height = (data.height + subsampling - 1) / subsampling;
+            let stride = (data.stride * pixelsize / subsampling) as usize;
+            if height == 0
+                || offset + stride * (height as usize - 1) + (width * pixelsize) as usize > slice.len()
+            {
+                return Err(Gles2Error::UnexpectedSize);
+            }
+
+            unsafe {
+                self.gl.BindTexture(ffi::TEXTURE_2D, tex);
+                self.gl
+                    .TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_WRAP_S, ffi::CLAMP_TO_EDGE as i32);
+                self.gl
+                    .TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_WRAP_T, ffi::CLAMP_TO_EDGE as i32);
+                self.gl.PixelStorei(ffi::UNPACK_ALIGNMENT, 1);
+                self.gl
+                    .PixelStorei(ffi::UNPACK_ROW_LENGTH, stride as i32 / pixelsize);
+                self.gl.TexImage2D(
+                    ffi::TEXTURE_2D,
+                    0,
+                    internal_format as i32,
+                    width,
+                    height,
+                    0,
+                    gl_format,
+                    ffi::UNSIGNED_BYTE as u32,
+                    slice.as_ptr().add(offset) as *const _,
+                );
+                self.gl.PixelStorei(ffi::UNPACK_ROW_LENGTH, 0);
+                self.gl.PixelStorei(ffi::UNPACK_ALIGNMENT, 4);
+                self.gl.BindTexture(ffi::TEXTURE_2D, 0);
+            }
+
+            offset += stride * height as usize;
+        }
+
+        Ok(())
     }
 }
 
@@ -919,7 +1231,7 @@ impl ImportMem for Gles2Renderer {
             return Err(Gles2Error::UnexpectedSize);
         }
 
-        let texture = Gles2Texture(Rc::new({
+        let texture = Gles2Texture(Arc::new({
             let mut tex = 0;
             unsafe {
                 self.gl.GenTextures(1, &mut tex);
@@ -944,11 +1256,12 @@ impl ImportMem for Gles2Renderer {
             // new texture, upload in full
             Gles2TextureInternal {
                 texture: tex,
-                texture_kind: 0,
+                texture_kind: shaders::TEXTURE_KIND_ABGR,
                 is_external: false,
                 y_inverted: flipped,
                 size,
                 egl_images: None,
+                yuv: None,
                 destruction_callback_sender: self.destruction_callback_sender.clone(),
             }
         }));
@@ -1008,16 +1321,22 @@ impl ImportEgl for Gles2Renderer {
         &mut self,
         display: &wayland_server::DisplayHandle,
     ) -> Result<(), crate::backend::egl::Error> {
-        self.egl_reader = Some(self.egl.display().bind_wl_display(display)?);
+        self.assert_egl_reader_thread();
+        self.egl_reader = Some((
+            self.egl.display().bind_wl_display(display)?,
+            std::thread::current().id(),
+        ));
         Ok(())
     }
 
     fn unbind_wl_display(&mut self) {
+        self.assert_egl_reader_thread();
         self.egl_reader = None;
     }
 
     fn egl_reader(&self) -> Option<&EGLBufferReader> {
-        self.egl_reader.as_ref()
+        self.assert_egl_reader_thread();
+        self.egl_reader.as_ref().map(|(reader, _)| reader)
     }
 
     fn import_egl_buffer(
@@ -1044,26 +1363,26 @@ impl ImportEgl for Gles2Renderer {
         self.make_current()?;
 
         let egl = self
-            .egl_reader
-            .as_ref()
+            .egl_reader()
             .unwrap()
             .egl_buffer_contents(buffer)
             .map_err(Gles2Error::EGLBufferAccessError)?;
 
         let tex = self.import_egl_image(egl.image(0).unwrap(), egl.format == EGLFormat::External, None)?;
 
-        let texture = Gles2Texture(Rc::new(Gles2TextureInternal {
+        let texture = Gles2Texture(Arc::new(Gles2TextureInternal {
             texture: tex,
             texture_kind: match egl.format {
-                EGLFormat::RGB => 1,
-                EGLFormat::RGBA => 0,
-                EGLFormat::External => 2,
+                EGLFormat::RGB => shaders::TEXTURE_KIND_XBGR,
+                EGLFormat::RGBA => shaders::TEXTURE_KIND_ABGR,
+                EGLFormat::External => shaders::TEXTURE_KIND_EXTERNAL,
                 _ => unreachable!("EGLBuffer currenly does not expose multi-planar buffers to us"),
             },
             is_external: egl.format == EGLFormat::External,
             y_inverted: egl.y_inverted,
             size: egl.size,
             egl_images: Some(egl.into_images()),
+            yuv: None,
             destruction_callback_sender: self.destruction_callback_sender.clone(),
         }));
 
@@ -1084,6 +1403,11 @@ impl ImportDma for Gles2Renderer {
 
         self.make_current()?;
         self.existing_dmabuf_texture(buffer)?.map(Ok).unwrap_or_else(|| {
+            if let Some(texture) = self.import_dmabuf_yuv(buffer)? {
+                self.dmabuf_cache.insert(buffer.weak(), texture.clone());
+                return Ok(texture);
+            }
+
             let is_external = !self.egl.dmabuf_render_formats().contains(&buffer.format());
             let image = self
                 .egl
@@ -1092,13 +1416,18 @@ impl ImportDma for Gles2Renderer {
                 .map_err(Gles2Error::BindBufferEGLError)?;
 
             let tex = self.import_egl_image(image, is_external, None)?;
-            let texture = Gles2Texture(Rc::new(Gles2TextureInternal {
+            let texture = Gles2Texture(Arc::new(Gles2TextureInternal {
                 texture: tex,
-                texture_kind: if is_external { 2 } else { 0 },
+                texture_kind: if is_external {
+                    shaders::TEXTURE_KIND_EXTERNAL
+                } else {
+                    shaders::TEXTURE_KIND_ABGR
+                },
                 is_external,
                 y_inverted: buffer.y_inverted(),
                 size: buffer.size(),
                 egl_images: Some(vec![image]),
+                yuv: None,
                 destruction_callback_sender: self.destruction_callback_sender.clone(),
             }));
             self.dmabuf_cache.insert(buffer.weak(), texture.clone());
@@ -1107,7 +1436,7 @@ impl ImportDma for Gles2Renderer {
     }
 
     fn dmabuf_formats<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Format> + 'a> {
-        Box::new(self.egl.dmabuf_texture_formats().iter())
+        Box::new(self.dmabuf_texture_formats.iter())
     }
 }
 
@@ -1131,11 +1460,16 @@ impl Gles2Renderer {
             );
             if !texture.0.is_external {
                 if let Some(egl_images) = texture.0.egl_images.as_ref() {
-                    if egl_images[0] == ffi_egl::NO_IMAGE_KHR {
+                    if egl_images.iter().any(|image| *image == ffi_egl::NO_IMAGE_KHR) {
                         return Ok(None);
                     }
-                    let tex = Some(texture.0.texture);
-                    self.import_egl_image(egl_images[0], false, tex)?;
+                    let planes = texture.0.yuv.iter().flat_map(|yuv| yuv.textures.iter());
+                    for (image, tex) in egl_images
+                        .iter()
+                        .zip(std::iter::once(&texture.0.texture).chain(planes))
+                    {
+                        self.import_egl_image(*image, false, Some(*tex))?;
+                    }
                 }
             }
             Ok(Some(texture))
@@ -1144,6 +1478,73 @@ impl Gles2Renderer {
         }
     }
 
+    /// Imports a multi-planar YUV dmabuf plane by plane, if supported.
+    ///
+    /// Returns `None` for buffers, that need to be imported as a whole.
+    fn import_dmabuf_yuv(&self, buffer: &Dmabuf) -> Result<Option<Gles2Texture>, Gles2Error> {
+        use crate::backend::allocator::Buffer;
+
+        let format = buffer.format();
+        let layout = match yuv::yuv_layout(format.code) {
+            Some(layout) if layout.planes.len() == buffer.num_planes() => layout,
+            _ => return Ok(None),
+        };
+        let render_formats = self.egl.dmabuf_render_formats();
+        if !layout.planes.iter().all(|&(code, _)| {
+            render_formats.contains(&Format {
+                code,
+                modifier: format.modifier,
+            })
+        }) {
+            return Ok(None);
+        }
+
+        let size = buffer.size();
+        let mut images = Vec::with_capacity(layout.planes.len());
+        for (i, &(code, subsampling)) in layout.planes.iter().enumerate() {
+            let plane_size = (
+                (size.w + subsampling - 1) / subsampling,
+                (size.h + subsampling - 1) / subsampling,
+            );
+            match self
+                .egl
+                .display()
+                .create_image_from_dmabuf_plane(buffer, i, code, plane_size.into())
+            {
+                Ok(image) => images.push(image),
+                Err(err) => {
+                    for image in images {
+                        let _ = self
+                            .destruction_callback_sender
+                            .send(CleanupResource::EGLImage(image));
+                    }
+                    return Err(Gles2Error::BindBufferEGLError(err));
+                }
+            }
+        }
+
+        let mut textures = images
+            .iter()
+            .map(|image| self.import_egl_image(*image, false, None))
+            .collect::<Result<Vec<_>, _>>()?;
+        let texture = textures.remove(0);
+
+        Ok(Some(Gles2Texture(Arc::new(Gles2TextureInternal {
+            texture,
+            texture_kind: if textures.len() == 1 {
+                shaders::TEXTURE_KIND_YUV_2PLANE
+            } else {
+                shaders::TEXTURE_KIND_YUV_3PLANE
+            },
+            is_external: false,
+            y_inverted: buffer.y_inverted(),
+            size,
+            egl_images: Some(images),
+            yuv: Some(Gles2YuvPlanes::new(textures, layout.swap_chroma, size.h)),
+            destruction_callback_sender: self.destruction_callback_sender.clone(),
+        }))))
+    }
+
     fn import_egl_image(
         &self,
         image: EGLImage,
@@ -1430,8 +1831,8 @@ impl ExportDma for Gles2Renderer {
     }
 }
 
-impl Bind<Rc<EGLSurface>> for Gles2Renderer {
-    fn bind(&mut self, surface: Rc<EGLSurface>) -> Result<(), Gles2Error> {
+impl Bind<Arc<EGLSurface>> for Gles2Renderer {
+    fn bind(&mut self, surface: Arc<EGLSurface>) -> Result<(), Gles2Error> {
         self.unbind()?;
         self.target = Some(Gles2Target::Surface(surface));
         self.make_current()?;
@@ -1619,7 +2020,7 @@ impl Offscreen<Gles2Renderbuffer> for Gles2Renderer {
                 .RenderbufferStorage(ffi::RENDERBUFFER, ffi::RGBA8, size.w, size.h);
             self.gl.BindRenderbuffer(ffi::RENDERBUFFER, 0);
 
-            Ok(Gles2Renderbuffer(Rc::new(Gles2RenderbufferInternal {
+            Ok(Gles2Renderbuffer(Arc::new(Gles2RenderbufferInternal {
                 rbo,
                 destruction_callback_sender: self.destruction_callback_sender.clone(),
             })))
@@ -1649,6 +2050,9 @@ impl Drop for Gles2Renderer {
                 }
                 self.gl.DeleteProgram(self.solid_program.program);
                 self.gl.DeleteBuffers(self.vbos.len() as i32, self.vbos.as_ptr());
+                if self.color_lut != 0 {
+                    self.gl.DeleteTextures(1, &self.color_lut);
+                }
 
                 if self.extensions.iter().any(|ext| ext == "GL_KHR_debug") {
                     self.gl.Disable(ffi::DEBUG_OUTPUT);
@@ -1659,7 +2063,16 @@ impl Drop for Gles2Renderer {
                 }
 
                 #[cfg(all(feature = "wayland_frontend", feature = "use_system_lib"))]
-                let _ = self.egl_reader.take();
+                if let Some((reader, thread)) = self.egl_reader.take() {
+                    if thread != std::thread::current().id() {
+                        // unbinding the wl_display would race with its thread
+                        warn!(
+                            self.logger,
+                            "Renderer with a bound wl_display dropped on another thread, leaking the binding"
+                        );
+                        std::mem::forget(reader);
+                    }
+                }
                 let _ = self.egl.unbind();
             }
         }
@@ -1678,6 +2091,18 @@ impl Gles2Renderer {
         &self.egl
     }
 
+    // The reader holds the raw wl_display, which must only be used on the thread it was bound on
+    #[cfg(all(feature = "wayland_frontend", feature = "use_system_lib"))]
+    fn assert_egl_reader_thread(&self) {
+        if let Some((_, thread)) = self.egl_reader.as_ref() {
+            assert_eq!(
+                *thread,
+                std::thread::current().id(),
+                "Gles2Renderer with a bound wl_display used on another thread"
+            );
+        }
+    }
+
     /// Run custom code in the GL context owned by this renderer.
     ///
     /// The OpenGL state of the renderer is considered an implementation detail
@@ -1694,6 +2119,105 @@ impl Gles2Renderer {
         let gl = self.gl.clone();
         Ok(func(self, &gl))
     }
+
+    /// Set the color transformation applied to all following [`render`](Renderer::render) calls.
+    ///
+    /// The transformation is folded into the shaders used for rendering textures and applied to
+    /// the colors passed to [`Frame::clear`]. As such it affects every pixel drawn afterwards, but no
+    /// additional render pass is necessary.
+    ///
+    /// The transformation is applied to every rendered element *before* blending. The matrix is linear
+    /// and thus commutes with blending, as long as no channel is clamped. The lookup table does not,
+    /// so translucent content is only approximated, if the transformation contains one.
+    ///
+    /// Changing the transformation does not damage anything. Pixels, which are not redrawn, keep the old
+    /// colors. When using a [`DamageTrackedRenderer`](super::damage::DamageTrackedRenderer), the whole output
+    /// needs to be redrawn after a change, e.g. by passing an age of `0` to
+    /// [`render_output`](super::damage::DamageTrackedRenderer::render_output) for as many frames as the
+    /// output has buffers.
+    ///
+    /// To use different transformations for multiple outputs, set the transformation before each
+    /// `render` call. Passing `None` or an [identity](ColorTransform::is_identity) transformation
+    /// disables any color transformation.
+    pub fn set_color_transform(&mut self, transform: Option<ColorTransform>) {
+        let transform = transform.filter(|transform| !transform.is_identity());
+        let lut_changed = self.color_transform.as_ref().and_then(|t| t.lut.as_ref())
+            != transform.as_ref().and_then(|t| t.lut.as_ref());
+        self.color_lut_dirty |= lut_changed;
+        self.color_transform = transform.map(Arc::new);
+    }
+
+    /// Returns the currently set color transformation
+    pub fn color_transform(&self) -> Option<&ColorTransform> {
+        self.color_transform.as_deref()
+    }
+
+    /// Flushes all previously submitted commands and returns a [`SyncPoint`], that is reached once they are complete.
+    ///
+    /// Textures imported or updated by this renderer may only be sampled by another renderer of the same
+    /// share group (see [`Gles2TexturePool`]) after this sync point was reached, e.g. by passing it to
+    /// [`Frame::wait`] of the other renderer.
+    ///
+    /// If the display does not support native fences, this blocks until the commands are complete.
+    pub fn flush(&mut self) -> Result<SyncPoint, Gles2Error> {
+        self.make_current()?;
+        let display = if self.supports_fences {
+            Some(self.egl.display())
+        } else {
+            None
+        };
+        Ok(unsafe { insert_sync_point(&self.gl, display) })
+    }
+
+    fn upload_color_lut(&mut self) {
+        if !self.color_lut_dirty {
+            return;
+        }
+        self.color_lut_dirty = false;
+
+        let lut = match self
+            .color_transform
+            .as_ref()
+            .and_then(|transform| transform.lut.as_ref())
+        {
+            Some(lut) => lut,
+            None => {
+                if self.color_lut != 0 {
+                    unsafe { self.gl.DeleteTextures(1, &self.color_lut) };
+                    self.color_lut = 0;
+                }
+                return;
+            }
+        };
+
+        let data = lut.texture_data();
+        unsafe {
+            if self.color_lut == 0 {
+                self.gl.GenTextures(1, &mut self.color_lut);
+            }
+            self.gl.BindTexture(ffi::TEXTURE_2D, self.color_lut);
+            self.gl
+                .TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_WRAP_S, ffi::CLAMP_TO_EDGE as i32);
+            self.gl
+                .TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_WRAP_T, ffi::CLAMP_TO_EDGE as i32);
+            self.gl
+                .TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_MIN_FILTER, ffi::LINEAR as i32);
+            self.gl
+                .TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_MAG_FILTER, ffi::LINEAR as i32);
+            self.gl.TexImage2D(
+                ffi::TEXTURE_2D,
+                0,
+                ffi::RGBA as i32,
+                lut.len() as i32,
+                1,
+                0,
+                ffi::RGBA,
+                ffi::UNSIGNED_BYTE as u32,
+                data.as_ptr() as *const _,
+            );
+            self.gl.BindTexture(ffi::TEXTURE_2D, 0);
+        }
+    }
 }
 
 impl Renderer for Gles2Renderer {
@@ -1724,6 +2248,7 @@ impl Renderer for Gles2Renderer {
         F: FnOnce(&mut Self, &mut Self::Frame) -> R,
     {
         self.make_current()?;
+        self.upload_color_lut();
 
         unsafe {
             self.gl.Viewport(0, 0, output_size.w, output_size.h);
@@ -1771,20 +2296,27 @@ impl Renderer for Gles2Renderer {
             size: output_size,
             min_filter: self.min_filter,
             max_filter: self.max_filter,
+            color_transform: self.color_transform.clone(),
+            color_lut: self.color_lut,
             supports_instancing: self.supports_instancing,
+            fence_display: if self.supports_fences {
+                Some(self.egl.display().clone())
+            } else {
+                None
+            },
+            finished: false,
         };
 
         let result = rendering(self, &mut frame);
 
         unsafe {
             self.gl.Flush();
-            // We need to wait for the previously submitted GL commands to complete
-            // or otherwise the buffer could be submitted to the drm surface while
-            // still writing to the buffer which results in flickering on the screen.
-            // The proper solution would be to create a fence just before calling
-            // glFlush that the backend can use to wait for the commands to be finished.
-            // In case of a drm atomic backend the fence could be supplied by using the
-            // IN_FENCE_FD property.
+            // If the frame was not finished explicitly, we need to wait for the previously
+            // submitted GL commands to complete or otherwise the buffer could be submitted
+            // to the drm surface while still writing to the buffer which results in flickering
+            // on the screen.
+            // Callers of `Frame::finish` receive a `SyncPoint` instead, which in case of a drm
+            // atomic backend can be supplied by using the IN_FENCE_FD property.
             // See https://01.org/linuxgraphics/gfx-docs/drm/gpu/drm-kms.html#explicit-fencing-properties for
             // the topic on submitting a IN_FENCE_FD and the mesa kmskube example
             // https://gitlab.freedesktop.org/mesa/kmscube/-/blob/9f63f359fab1b5d8e862508e4e51c9dfe339ccb0/drm-atomic.c
@@ -1792,7 +2324,9 @@ impl Renderer for Gles2Renderer {
             // https://gitlab.freedesktop.org/mesa/kmscube/-/blob/9f63f359fab1b5d8e862508e4e51c9dfe339ccb0/drm-atomic.c#L147
             // and here
             // https://gitlab.freedesktop.org/mesa/kmscube/-/blob/9f63f359fab1b5d8e862508e4e51c9dfe339ccb0/drm-atomic.c#L235
-            self.gl.Finish();
+            if !frame.finished {
+                self.gl.Finish();
+            }
             self.gl.Disable(ffi::BLEND);
         }
 
@@ -1859,6 +2393,11 @@ impl Frame for Gles2Frame {
         let mut mat = Matrix3::<f32>::identity();
         mat = self.current_projection * mat;
 
+        let color = match self.color_transform {
+            Some(ref transform) => transform.apply(color),
+            None => color,
+        };
+
         let damage = at
             .iter()
             .flat_map(|rect| {
@@ -2055,6 +2594,48 @@ impl Frame for Gles2Frame {
     fn transformation(&self) -> Transform {
         self.transform
     }
+
+    fn wait(&mut self, sync: &SyncPoint) -> Result<(), Self::Error> {
+        if sync.is_reached() {
+            return Ok(());
+        }
+
+        // let the gpu wait for the fence, if possible
+        if let (Some(display), Some(fd)) = (self.fence_display.as_ref(), sync.export()) {
+            if let Ok(fence) = EGLFence::import(display, fd) {
+                if fence.wait().is_ok() {
+                    return Ok(());
+                }
+            }
+        }
+
+        sync.wait();
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<SyncPoint, Self::Error> {
+        let sync = unsafe { insert_sync_point(&self.gl, self.fence_display.as_ref()) };
+        self.finished = true;
+        Ok(sync)
+    }
+}
+
+/// Inserts a native fence into the command stream of the current context,
+/// or blocks until all commands are complete, if that is not possible.
+unsafe fn insert_sync_point(gl: &ffi::Gles2, fence_display: Option<&EGLDisplay>) -> SyncPoint {
+    if let Some(display) = fence_display {
+        if let Ok(fence) = EGLFence::create(display) {
+            // the fence can only be exported after the commands were flushed
+            gl.Flush();
+            if let Ok(fd) = fence.export() {
+                return SyncPoint::from_sync_file(fd);
+            }
+        }
+    }
+
+    // no native fences, block until the gpu is done
+    gl.Finish();
+    SyncPoint::signaled()
 }
 
 impl Gles2Frame {
@@ -2116,6 +2697,9 @@ impl Gles2Frame {
 
             self.gl
                 .Uniform1i(self.tex_programs[tex.0.texture_kind].uniform_tex, 0);
+            if let Some(yuv) = tex.0.yuv.as_ref() {
+                self.set_yuv_uniforms(&self.tex_programs[tex.0.texture_kind], yuv);
+            }
             self.gl.UniformMatrix3fv(
                 self.tex_programs[tex.0.texture_kind].uniform_matrix,
                 1,
@@ -2130,6 +2714,7 @@ impl Gles2Frame {
             );
             self.gl
                 .Uniform1f(self.tex_programs[tex.0.texture_kind].uniform_alpha, alpha);
+            self.set_color_transform_uniforms(&self.tex_programs[tex.0.texture_kind]);
 
             self.gl
                 .EnableVertexAttribArray(self.tex_programs[tex.0.texture_kind].attrib_vert as u32);
@@ -2211,6 +2796,18 @@ impl Gles2Frame {
 
             self.gl.BindBuffer(ffi::ARRAY_BUFFER, 0);
             self.gl.BindTexture(target, 0);
+            if let Some(yuv) = tex.0.yuv.as_ref() {
+                for i in 0..yuv.textures.len() as u32 {
+                    self.gl.ActiveTexture(ffi::TEXTURE1 + i);
+                    self.gl.BindTexture(ffi::TEXTURE_2D, 0);
+                }
+                self.gl.ActiveTexture(ffi::TEXTURE0);
+            }
+            if self.color_lut != 0 {
+                self.gl.ActiveTexture(ffi::TEXTURE0 + COLOR_LUT_UNIT);
+                self.gl.BindTexture(ffi::TEXTURE_2D, 0);
+                self.gl.ActiveTexture(ffi::TEXTURE0);
+            }
             self.gl
                 .DisableVertexAttribArray(self.tex_programs[tex.0.texture_kind].attrib_vert as u32);
             self.gl
@@ -2224,4 +2821,68 @@ impl Gles2Frame {
     pub fn projection(&self) -> &[f32; 9] {
         self.current_projection.as_ref()
     }
+
+    /// Color transformation applied to this frame, see [`Gles2Renderer::set_color_transform`]
+    pub fn color_transform(&self) -> Option<&ColorTransform> {
+        self.color_transform.as_deref()
+    }
+
+    unsafe fn set_yuv_uniforms(&self, program: &Gles2TexProgram, yuv: &Gles2YuvPlanes) {
+        for (i, texture) in yuv.textures.iter().enumerate() {
+            self.gl.ActiveTexture(ffi::TEXTURE1 + i as u32);
+            self.gl.BindTexture(ffi::TEXTURE_2D, *texture);
+            self.gl.TexParameteri(
+                ffi::TEXTURE_2D,
+                ffi::TEXTURE_MIN_FILTER,
+                match self.min_filter {
+                    TextureFilter::Nearest => ffi::NEAREST as i32,
+                    TextureFilter::Linear => ffi::LINEAR as i32,
+                },
+            );
+            self.gl.TexParameteri(
+                ffi::TEXTURE_2D,
+                ffi::TEXTURE_MAG_FILTER,
+                match self.max_filter {
+                    TextureFilter::Nearest => ffi::NEAREST as i32,
+                    TextureFilter::Linear => ffi::LINEAR as i32,
+                },
+            );
+        }
+        self.gl.ActiveTexture(ffi::TEXTURE0);
+
+        let (color_space, range) = *yuv.encoding.lock().unwrap();
+        let (matrix, offset) = yuv::yuv_to_rgb(color_space, range, yuv.swap_chroma);
+        self.gl
+            .UniformMatrix3fv(program.uniform_yuv_matrix, 1, ffi::FALSE, matrix.as_ptr());
+        self.gl
+            .Uniform3f(program.uniform_yuv_offset, offset[0], offset[1], offset[2]);
+    }
+
+    unsafe fn set_color_transform_uniforms(&self, program: &Gles2TexProgram) {
+        let transform = match self.color_transform {
+            Some(ref transform) => transform,
+            None => {
+                self.gl.Uniform1f(program.uniform_color_transform, 0.0);
+                return;
+            }
+        };
+
+        self.gl.UniformMatrix3fv(
+            program.uniform_color_matrix,
+            1,
+            ffi::FALSE,
+            transform.gl_matrix().as_ptr(),
+        );
+        match transform.lut {
+            Some(ref lut) if self.color_lut != 0 => {
+                self.gl.ActiveTexture(ffi::TEXTURE0 + COLOR_LUT_UNIT);
+                self.gl.BindTexture(ffi::TEXTURE_2D, self.color_lut);
+                self.gl.ActiveTexture(ffi::TEXTURE0);
+                self.gl
+                    .Uniform1f(program.uniform_color_lut_size, lut.len() as f32);
+                self.gl.Uniform1f(program.uniform_color_transform, 2.0);
+            }
+            _ => self.gl.Uniform1f(program.uniform_color_transform, 1.0),
+        }
+    }
 }
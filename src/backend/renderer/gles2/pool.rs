@@ -0,0 +1,130 @@
+//! Sharing textures between [`Gles2Renderer`]s on multiple threads
+
+use std::sync::{Arc, Mutex};
+
+use super::{Gles2Error, Gles2Renderer};
+use crate::backend::egl::{EGLContext, EGLDisplay};
+
+use slog::o;
+
+/// A pool of [`Gles2Renderer`]s, which share their textures
+///
+/// Every renderer created by the pool uses its own [`EGLContext`] sharing resources with
+/// a root context of the pool (see [`EGLContext::new_shared`]). As [`Gles2Renderer`] and
+/// [`Gles2Texture`](super::Gles2Texture) are `Send`, the renderers can be moved to dedicated threads
+/// and textures imported by one of them can be sampled by any other.
+///
+/// All renderers of the pool report the same [`Renderer::id`](crate::backend::renderer::Renderer::id),
+/// so textures cached for surfaces are shared between them as well.
+///
+/// Texture uploads are not synchronized between the threads. Before using a texture, that was imported
+/// or updated on another thread, call [`Gles2Renderer::flush`] on the importing renderer
+/// and pass the resulting [`SyncPoint`](crate::backend::renderer::sync::SyncPoint) to
+/// [`Frame::wait`](crate::backend::renderer::Frame::wait) of the sampling renderer.
+///
+/// Textures are freed by the renderer, that created them. Gl objects of textures outliving their renderer
+/// are only released, once every context of the pool is destroyed.
+///
+/// The pool itself is cheap to clone and can be shared between threads.
+#[derive(Debug, Clone)]
+pub struct Gles2TexturePool {
+    context: Arc<Mutex<EGLContext>>,
+    logger: ::slog::Logger,
+}
+
+impl Gles2TexturePool {
+    /// Creates a new pool for the given [`EGLDisplay`]
+    pub fn new<L>(display: &EGLDisplay, logger: L) -> Result<Gles2TexturePool, Gles2Error>
+    where
+        L: Into<Option<::slog::Logger>>,
+    {
+        let log = crate::slog_or_fallback(logger).new(o!("smithay_module" => "renderer_gles2"));
+        let context = EGLContext::new(display, log.clone()).map_err(Gles2Error::ContextCreationError)?;
+        Ok(Gles2TexturePool::from_context(context, log))
+    }
+
+    /// Creates a new pool using the given context as its root context
+    ///
+    /// The context is only used to share resources with the contexts of the created renderers
+    /// and should not be made current by the caller anymore.
+    pub fn from_context<L>(context: EGLContext, logger: L) -> Gles2TexturePool
+    where
+        L: Into<Option<::slog::Logger>>,
+    {
+        Gles2TexturePool {
+            context: Arc::new(Mutex::new(context)),
+            logger: crate::slog_or_fallback(logger).new(o!("smithay_module" => "renderer_gles2")),
+        }
+    }
+
+    /// Returns the [`EGLDisplay`] of the pool
+    pub fn display(&self) -> EGLDisplay {
+        self.context.lock().unwrap().display().clone()
+    }
+
+    /// Creates a new renderer sharing its textures with all other renderers of this pool
+    ///
+    /// The renderer is initialized on the calling thread, but may be moved to another one afterwards.
+    /// Make sure to [`unbind`](crate::backend::renderer::Unbind::unbind) it before doing so.
+    pub fn create_renderer(&self) -> Result<Gles2Renderer, Gles2Error> {
+        let context = {
+            let root = self.context.lock().unwrap();
+            EGLContext::new_shared(root.display(), &root, self.logger.clone())
+                .map_err(Gles2Error::ContextCreationError)?
+        };
+        // SAFETY: the context was just created and is not current on any thread
+        unsafe { Gles2Renderer::new(context, self.logger.clone()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Gles2Renderbuffer, Gles2Texture};
+    use super::{Gles2Renderer, Gles2TexturePool};
+
+    fn is_send<T: Send>() {}
+    fn is_send_sync<T: Send + Sync>() {}
+
+    /// Test that renderers can be moved to and textures shared between render threads.
+    #[test]
+    fn send_sync() {
+        is_send::<Gles2Renderer>();
+        is_send_sync::<Gles2Texture>();
+        is_send_sync::<Gles2Renderbuffer>();
+        is_send_sync::<Gles2TexturePool>();
+    }
+
+    /// Test that shm textures cached for a surface by one renderer are found by another renderer
+    /// of the same pool (sharing its id) on another thread.
+    #[cfg(feature = "wayland_frontend")]
+    #[test]
+    fn shm_cache_is_shared_between_threads() {
+        use super::super::{cache_shm_texture, cached_shm_texture, shaders, Gles2TextureInternal};
+        use crate::utils::user_data::UserDataMap;
+        use std::sync::{mpsc::channel, Arc};
+
+        let (tx, _rx) = channel();
+        let texture = Arc::new(Gles2TextureInternal {
+            texture: 1,
+            texture_kind: shaders::TEXTURE_KIND_ABGR,
+            is_external: false,
+            y_inverted: false,
+            size: (4, 4).into(),
+            egl_images: None,
+            yuv: None,
+            destruction_callback_sender: tx,
+        });
+
+        let data_map = Arc::new(UserDataMap::new());
+        let imported = {
+            let (data_map, texture) = (data_map.clone(), texture.clone());
+            std::thread::spawn(move || cache_shm_texture(&data_map, 1, texture))
+        };
+        imported.join().unwrap();
+
+        let cached = cached_shm_texture(&data_map, 1).expect("texture not shared");
+        assert!(Arc::ptr_eq(&cached, &texture));
+        // renderers outside of the pool do not share the texture
+        assert!(cached_shm_texture(&data_map, 2).is_none());
+    }
+}
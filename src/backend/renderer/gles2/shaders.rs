@@ -27,35 +27,85 @@ void main() {
 }
 "#;
 
-pub const FRAGMENT_COUNT: usize = 3;
+/// Applies the output color transformation to a premultiplied color.
+///
+/// `color_transform` is `0.0` if disabled, `1.0` for only applying `color_matrix`
+/// and `2.0` for additionally mapping the result through `color_lut`.
+macro_rules! color_transform {
+    () => {
+        r#"
+uniform float color_transform;
+uniform mat3 color_matrix;
+uniform sampler2D color_lut;
+uniform float color_lut_size;
+
+vec4 transform_color(vec4 color) {
+    if (color_transform < 0.5 || color.a <= 0.0) {
+        return color;
+    }
+    vec3 rgb = clamp(color_matrix * (color.rgb / color.a), 0.0, 1.0);
+    if (color_transform > 1.5) {
+        vec3 coords = rgb * ((color_lut_size - 1.0) / color_lut_size) + 0.5 / color_lut_size;
+        rgb = vec3(
+            texture2D(color_lut, vec2(coords.r, 0.5)).r,
+            texture2D(color_lut, vec2(coords.g, 0.5)).g,
+            texture2D(color_lut, vec2(coords.b, 0.5)).b
+        );
+    }
+    return vec4(rgb * color.a, color.a);
+}
+"#
+    };
+}
+
+pub const FRAGMENT_COUNT: usize = 5;
+
+// Indices of the texture programs compiled from the fragment shaders below,
+// used as the `texture_kind` of a texture.
+pub const TEXTURE_KIND_ABGR: usize = 0;
+// only used by wayland buffers
+#[cfg_attr(not(feature = "wayland_frontend"), allow(dead_code))]
+pub const TEXTURE_KIND_XBGR: usize = 1;
+pub const TEXTURE_KIND_EXTERNAL: usize = 2;
+pub const TEXTURE_KIND_YUV_2PLANE: usize = 3;
+pub const TEXTURE_KIND_YUV_3PLANE: usize = 4;
 
-pub const FRAGMENT_SHADER_ABGR: &str = r#"
+pub const FRAGMENT_SHADER_ABGR: &str = concat!(
+    r#"
 #version 100
 
 precision mediump float;
 uniform sampler2D tex;
 uniform float alpha;
 varying vec2 v_tex_coords;
-
+"#,
+    color_transform!(),
+    r#"
 void main() {
-    gl_FragColor = texture2D(tex, v_tex_coords) * alpha;
+    gl_FragColor = transform_color(texture2D(tex, v_tex_coords)) * alpha;
 }
-"#;
+"#
+);
 
-pub const FRAGMENT_SHADER_XBGR: &str = r#"
+pub const FRAGMENT_SHADER_XBGR: &str = concat!(
+    r#"
 #version 100
 
 precision mediump float;
 uniform sampler2D tex;
 uniform float alpha;
 varying vec2 v_tex_coords;
-
+"#,
+    color_transform!(),
+    r#"
 void main() {
-    gl_FragColor = vec4(texture2D(tex, v_tex_coords).rgb, 1.0) * alpha;
+    gl_FragColor = transform_color(vec4(texture2D(tex, v_tex_coords).rgb, 1.0)) * alpha;
 }
-"#;
+"#
+);
 
-pub const FRAGMENT_SHADER_EXTERNAL: &str = r#"
+pub const FRAGMENT_SHADER_EXTERNAL: &str = concat!(
+    r#"
 #version 100
 #extension GL_OES_EGL_image_external : require
 
@@ -63,11 +113,65 @@ precision mediump float;
 uniform samplerExternalOES tex;
 uniform float alpha;
 varying vec2 v_tex_coords;
+"#,
+    color_transform!(),
+    r#"
+void main() {
+    gl_FragColor = transform_color(texture2D(tex, v_tex_coords)) * alpha;
+}
+"#
+);
 
+/// Two planes, luma in `tex`, interleaved chroma in `tex_uv` (e.g. NV12)
+pub const FRAGMENT_SHADER_YUV_2PLANE: &str = concat!(
+    r#"
+#version 100
+
+precision mediump float;
+uniform sampler2D tex;
+uniform sampler2D tex_uv;
+uniform mat3 yuv_matrix;
+uniform vec3 yuv_offset;
+uniform float alpha;
+varying vec2 v_tex_coords;
+"#,
+    color_transform!(),
+    r#"
 void main() {
-    gl_FragColor = texture2D(tex, v_tex_coords) * alpha;
+    vec3 yuv = vec3(texture2D(tex, v_tex_coords).r, texture2D(tex_uv, v_tex_coords).rg);
+    vec3 rgb = clamp(yuv_matrix * yuv + yuv_offset, 0.0, 1.0);
+    gl_FragColor = transform_color(vec4(rgb, 1.0)) * alpha;
 }
-"#;
+"#
+);
+
+/// Three planes, luma in `tex`, chroma in `tex_u` and `tex_v` (e.g. YUV420)
+pub const FRAGMENT_SHADER_YUV_3PLANE: &str = concat!(
+    r#"
+#version 100
+
+precision mediump float;
+uniform sampler2D tex;
+uniform sampler2D tex_u;
+uniform sampler2D tex_v;
+uniform mat3 yuv_matrix;
+uniform vec3 yuv_offset;
+uniform float alpha;
+varying vec2 v_tex_coords;
+"#,
+    color_transform!(),
+    r#"
+void main() {
+    vec3 yuv = vec3(
+        texture2D(tex, v_tex_coords).r,
+        texture2D(tex_u, v_tex_coords).r,
+        texture2D(tex_v, v_tex_coords).r
+    );
+    vec3 rgb = clamp(yuv_matrix * yuv + yuv_offset, 0.0, 1.0);
+    gl_FragColor = transform_color(vec4(rgb, 1.0)) * alpha;
+}
+"#
+);
 
 pub const VERTEX_SHADER_SOLID: &str = r#"
 #version 100
@@ -0,0 +1,172 @@
+//! Conversion of multi-planar YUV buffers sampled by the [`Gles2Renderer`](super::Gles2Renderer)
+
+use crate::backend::allocator::Fourcc;
+
+/// Color space used to encode the rgb values of a YUV buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum YuvColorSpace {
+    /// ITU-R BT.601, commonly used for SD video
+    Bt601,
+    /// ITU-R BT.709, commonly used for HD video
+    Bt709,
+}
+
+/// Value range of the components of a YUV buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum YuvRange {
+    /// Luma values range from 16 to 235, chroma values from 16 to 240 (for 8 bit components)
+    Limited,
+    /// All components use the full range from 0 to 255 (for 8 bit components)
+    Full,
+}
+
+impl YuvColorSpace {
+    /// Guesses the color space of a buffer with the given height, if not provided by the client.
+    ///
+    /// Follows the common convention to use BT.709 for HD content and BT.601 otherwise.
+    pub fn guess(height: i32) -> YuvColorSpace {
+        if height >= 720 {
+            YuvColorSpace::Bt709
+        } else {
+            YuvColorSpace::Bt601
+        }
+    }
+
+    /// Luma coefficients of the red and blue channel
+    fn coefficients(&self) -> (f32, f32) {
+        match self {
+            YuvColorSpace::Bt601 => (0.299, 0.114),
+            YuvColorSpace::Bt709 => (0.2126, 0.0722),
+        }
+    }
+}
+
+/// Memory layout of a supported YUV format
+#[derive(Debug, Clone, Copy)]
+pub(super) struct YuvLayout {
+    /// Single-plane format and subsampling factor of every plane
+    pub planes: &'static [(Fourcc, i32)],
+    /// Whether the chroma components are stored as Cr, Cb instead of Cb, Cr
+    pub swap_chroma: bool,
+}
+
+/// Multi-planar YUV formats the renderer is able to convert
+pub(super) const YUV_FORMATS: [Fourcc; 4] = [Fourcc::Nv12, Fourcc::Nv21, Fourcc::Yuv420, Fourcc::Yvu420];
+
+const TWO_PLANES: &[(Fourcc, i32)] = &[(Fourcc::R8, 1), (Fourcc::Gr88, 2)];
+const THREE_PLANES: &[(Fourcc, i32)] = &[(Fourcc::R8, 1), (Fourcc::R8, 2), (Fourcc::R8, 2)];
+
+/// Returns the layout of `format`, if it is a supported YUV format
+pub(super) fn yuv_layout(format: Fourcc) -> Option<YuvLayout> {
+    let (planes, swap_chroma) = match format {
+        Fourcc::Nv12 => (TWO_PLANES, false),
+        Fourcc::Nv21 => (TWO_PLANES, true),
+        Fourcc::Yuv420 => (THREE_PLANES, false),
+        Fourcc::Yvu420 => (THREE_PLANES, true),
+        _ => return None,
+    };
+    Some(YuvLayout { planes, swap_chroma })
+}
+
+/// Computes the matrix and offset converting sampled (normalized) YUV values to rgb
+///
+/// The returned matrix is column-major, as expected by `glUniformMatrix3fv`, and is applied as
+/// `rgb = matrix * vec3(y, c1, c2) + offset`.
+pub(super) fn yuv_to_rgb(
+    color_space: YuvColorSpace,
+    range: YuvRange,
+    swap_chroma: bool,
+) -> ([f32; 9], [f32; 3]) {
+    let (kr, kb) = color_space.coefficients();
+    let kg = 1.0 - kr - kb;
+
+    let (y_scale, c_scale, y_offset) = match range {
+        YuvRange::Limited => (255.0 / 219.0, 255.0 / 224.0, 16.0 / 255.0),
+        YuvRange::Full => (1.0, 1.0, 0.0),
+    };
+    let c_offset = 128.0 / 255.0;
+
+    // rows: r, g, b; columns: y, cb, cr
+    let mut m = [
+        [y_scale, 0.0, c_scale * 2.0 * (1.0 - kr)],
+        [
+            y_scale,
+            -c_scale * 2.0 * (1.0 - kb) * kb / kg,
+            -c_scale * 2.0 * (1.0 - kr) * kr / kg,
+        ],
+        [y_scale, c_scale * 2.0 * (1.0 - kb), 0.0],
+    ];
+    // the offset is the same for both chroma channels, so swapping the columns is sufficient
+    let offset = [0, 1, 2].map(|row| -(m[row][0] * y_offset + (m[row][1] + m[row][2]) * c_offset));
+    if swap_chroma {
+        for row in m.iter_mut() {
+            row.swap(1, 2);
+        }
+    }
+
+    (
+        [
+            m[0][0], m[1][0], m[2][0], m[0][1], m[1][1], m[2][1], m[0][2], m[1][2], m[2][2],
+        ],
+        offset,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(yuv: [f32; 3], color_space: YuvColorSpace, range: YuvRange, swap_chroma: bool) -> [f32; 3] {
+        let (m, offset) = yuv_to_rgb(color_space, range, swap_chroma);
+        [0, 1, 2].map(|row| m[row] * yuv[0] + m[3 + row] * yuv[1] + m[6 + row] * yuv[2] + offset[row])
+    }
+
+    fn assert_rgb(rgb: [f32; 3], expected: [f32; 3]) {
+        for (value, expected) in rgb.iter().zip(expected.iter()) {
+            assert!((value - expected).abs() < 0.005, "{:?} != {:?}", rgb, expected);
+        }
+    }
+
+    #[test]
+    fn gray_levels() {
+        for color_space in [YuvColorSpace::Bt601, YuvColorSpace::Bt709] {
+            let neutral = 128.0 / 255.0;
+            let limited = |y: f32| {
+                convert(
+                    [y / 255.0, neutral, neutral],
+                    color_space,
+                    YuvRange::Limited,
+                    false,
+                )
+            };
+            assert_rgb(limited(16.0), [0.0, 0.0, 0.0]);
+            assert_rgb(limited(235.0), [1.0, 1.0, 1.0]);
+
+            let full = |y: f32| convert([y / 255.0, neutral, neutral], color_space, YuvRange::Full, true);
+            assert_rgb(full(0.0), [0.0, 0.0, 0.0]);
+            assert_rgb(full(255.0), [1.0, 1.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn primaries() {
+        // BT.709 limited range red: Y = 63, Cb = 102, Cr = 240
+        let red = [63.0 / 255.0, 102.0 / 255.0, 240.0 / 255.0];
+        assert_rgb(
+            convert(red, YuvColorSpace::Bt709, YuvRange::Limited, false),
+            [1.0, 0.0, 0.0],
+        );
+        let swapped = [red[0], red[2], red[1]];
+        assert_rgb(
+            convert(swapped, YuvColorSpace::Bt709, YuvRange::Limited, true),
+            [1.0, 0.0, 0.0],
+        );
+
+        // BT.601 full range blue: Y = 29, Cb = 255, Cr = 107
+        let blue = [29.0 / 255.0, 255.0 / 255.0, 107.0 / 255.0];
+        assert_rgb(
+            convert(blue, YuvColorSpace::Bt601, YuvRange::Full, false),
+            [0.0, 0.0, 1.0],
+        );
+    }
+}
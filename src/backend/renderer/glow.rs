@@ -32,7 +32,6 @@ use std::{
     collections::HashSet,
     fmt,
     ops::{Deref, DerefMut},
-    rc::Rc,
     sync::Arc,
 };
 
@@ -141,6 +140,18 @@ impl GlowRenderer {
         let glow = self.glow.clone();
         Ok(func(self, &glow))
     }
+
+    /// Set the color transformation applied to all following [`render`](Renderer::render) calls.
+    ///
+    /// See [`Gles2Renderer::set_color_transform`] for details.
+    pub fn set_color_transform(&mut self, transform: Option<ColorTransform>) {
+        self.gl.set_color_transform(transform)
+    }
+
+    /// Returns the currently set color transformation
+    pub fn color_transform(&self) -> Option<&ColorTransform> {
+        self.gl.color_transform()
+    }
 }
 
 // TODO: When GAT, use TryFrom and be generic over the Error,
@@ -326,12 +337,12 @@ impl ExportDma for GlowRenderer {
     }
 }
 
-impl Bind<Rc<EGLSurface>> for GlowRenderer {
-    fn bind(&mut self, surface: Rc<EGLSurface>) -> Result<(), Gles2Error> {
+impl Bind<Arc<EGLSurface>> for GlowRenderer {
+    fn bind(&mut self, surface: Arc<EGLSurface>) -> Result<(), Gles2Error> {
         self.gl.bind(surface)
     }
     fn supported_formats(&self) -> Option<HashSet<Format>> {
-        Bind::<Rc<EGLSurface>>::supported_formats(&*self.gl)
+        Bind::<Arc<EGLSurface>>::supported_formats(&*self.gl)
     }
 }
 
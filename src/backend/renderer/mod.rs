@@ -6,6 +6,7 @@
 //! Supported rendering apis:
 //!
 //! - Raw OpenGL ES 2
+//! - Vulkan
 
 use std::collections::HashSet;
 use std::error::Error;
@@ -24,6 +25,9 @@ pub mod gles2;
 #[cfg(feature = "renderer_glow")]
 pub mod glow;
 
+#[cfg(feature = "renderer_vulkan")]
+pub mod vulkan;
+
 use crate::backend::allocator::{dmabuf::Dmabuf, Format};
 #[cfg(all(
     feature = "wayland_frontend",
@@ -43,6 +47,12 @@ pub mod element;
 
 pub mod damage;
 
+pub mod sync;
+use self::sync::SyncPoint;
+
+#[cfg(any(test, feature = "renderer_test"))]
+pub mod test;
+
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 /// Texture filtering methods
 pub enum TextureFilter {
@@ -189,6 +199,27 @@ pub trait Frame {
 
     /// Output transformation that is applied to this frame
     fn transformation(&self) -> Transform;
+
+    /// Waits for the given sync point before executing any further rendering operations of this frame
+    ///
+    /// This is used to synchronize with buffers written by other devices, e.g. a [`SyncPoint`] exported by another renderer.
+    /// The default implementation blocks until the sync point is reached,
+    /// renderers may instead wait on the gpu without blocking.
+    fn wait(&mut self, sync: &SyncPoint) -> Result<(), Self::Error> {
+        sync.wait();
+        Ok(())
+    }
+
+    /// Finishes the frame and returns a [`SyncPoint`], which is reached once all rendering operations are complete
+    ///
+    /// This should be called after the last rendering operation of the frame,
+    /// operations issued afterwards are not guaranteed to be covered by the returned sync point.
+    /// The sync point is only meaningful after [`Renderer::render`] returned.
+    ///
+    /// Renderers supporting it, return a sync point backed by a fence and do not block on the gpu
+    /// at the end of [`Renderer::render`] anymore, if this was called.
+    /// Otherwise the returned sync point is already reached once [`Renderer::render`] returns.
+    fn finish(&mut self) -> Result<SyncPoint, Self::Error>;
 }
 
 /// Abstraction of commonly used rendering operations for compositors.
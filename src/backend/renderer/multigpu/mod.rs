@@ -6,6 +6,7 @@
 //!
 //! smithay provides the following graphics apis:
 //! - [`egl::EglGlesBackend`]
+//! - [`vulkan::VulkanBackend`]
 //!
 //! A [`MultiRenderer`] gets created using two [`DrmNode`]s to identify gpus.
 //! One gpu will be referred to as the render-gpu, the other as the target-gpu.
@@ -27,11 +28,23 @@
 //!
 //! Any `Export*`-implementations will reside on the render-gpu, if applicable.
 //!
+//! Buffers are shared between gpus via dmabufs, if possible, and otherwise copied through
+//! system memory using [`ExportMem`] and [`ImportMem`], only copying damaged regions.
+//! This behaviour can be configured using [`GpuManager::set_copy_strategy`].
+//!
 //! *Note*: This module will not keep you from selecting sub-optimal configurations.
 //! Any heuristics for which render-gpu to use for a given set of client buffers
 //! and desired target-gpu are up to be implemented by the compositor. The module only
 //! reduces the amount of necessary setup operations.
 //!
+//! ## Hot-unplugging
+//!
+//! Devices may be removed at runtime with [`GpuManager::remove_device`] or by re-enumerating
+//! them via [`GpuManager::refresh`], e.g. in response to udev events.
+//! The resulting [`GpuEvent`]s notify the compositor about removed devices, after which textures
+//! residing on those devices are invalid. Outputs should be rendered using a remaining device
+//! and the client buffers of affected surfaces can be re-imported using [`GpuManager::migrate_surface`].
+//!
 
 use super::*;
 use std::{
@@ -58,10 +71,26 @@ use crate::{
 use wayland_server::protocol::{wl_buffer, wl_surface::WlSurface};
 #[cfg(all(feature = "backend_egl", feature = "renderer_gl"))]
 pub mod egl;
+#[cfg(feature = "renderer_vulkan")]
+pub mod vulkan;
 
 lazy_static::lazy_static! {
     /// Tuple denotes `(source_node, target_node, buffer_format)`.
     static ref CAN_IMPORT: Mutex<HashMap<(DrmNode, DrmNode, Format), bool>> = Mutex::new(HashMap::new());
+    /// Counts how often a node was removed, to invalidate textures of removed devices.
+    static ref NODE_GENERATIONS: Mutex<HashMap<DrmNode, usize>> = Mutex::new(HashMap::new());
+}
+
+fn node_generation(node: &DrmNode) -> usize {
+    NODE_GENERATIONS.lock().unwrap().get(node).copied().unwrap_or(0)
+}
+
+fn invalidate_node(node: &DrmNode) {
+    *NODE_GENERATIONS.lock().unwrap().entry(*node).or_default() += 1;
+    CAN_IMPORT
+        .lock()
+        .unwrap()
+        .retain(|(source, target, _), _| source != node && target != node);
 }
 
 /// Tracks available gpus from a given [`GraphicsApi`]
@@ -70,9 +99,52 @@ pub struct GpuManager<A: GraphicsApi> {
     api: A,
     devices: Vec<A::Device>,
     dma_source: HashMap<WeakDmabuf, DrmNode>,
+    copy_strategy: CopyStrategy,
     log: ::slog::Logger,
 }
 
+/// Strategy for transferring buffers between devices
+///
+/// Used for copying rendering results to the target-gpu of a [`MultiRenderer`]
+/// and for importing client buffers residing on a different gpu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CopyStrategy {
+    /// Only share buffers between devices via dmabufs, failing if a buffer cannot be imported
+    AlwaysDmabuf,
+    /// Share buffers via dmabufs if possible and fall back to copying
+    /// through system memory using [`ExportMem`] and [`ImportMem`]
+    PreferDmabuf,
+    /// Always copy rendering results through system memory
+    ///
+    /// Client buffers are only imported directly on the device they were first imported on.
+    AlwaysCpu,
+}
+
+impl Default for CopyStrategy {
+    fn default() -> Self {
+        CopyStrategy::PreferDmabuf
+    }
+}
+
+/// Events generated by a [`GpuManager`], when the set of available devices changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GpuEvent {
+    /// A new device was added
+    Added(DrmNode),
+    /// A device was removed
+    ///
+    /// All textures residing on the device were invalidated.
+    /// Outputs rendered by the device should create their [`MultiRenderer`]s with
+    /// a different render node from now on and surfaces should be re-imported
+    /// via [`GpuManager::migrate_surface`].
+    Removed {
+        /// The node of the removed device
+        node: DrmNode,
+        /// A remaining device, that may be used instead, if any
+        replacement: Option<DrmNode>,
+    },
+}
+
 /// Errors generated by [`GpuManager`] and [`MultiRenderer`].
 #[derive(thiserror::Error)]
 pub enum Error<R: GraphicsApi, T: GraphicsApi>
@@ -170,10 +242,80 @@ impl<A: GraphicsApi> GpuManager<A> {
             api,
             devices,
             dma_source: HashMap::new(),
+            copy_strategy: CopyStrategy::default(),
             log,
         })
     }
 
+    /// Returns the [`CopyStrategy`] used by [`MultiRenderer`]s created by this manager
+    pub fn copy_strategy(&self) -> CopyStrategy {
+        self.copy_strategy
+    }
+
+    /// Sets the [`CopyStrategy`] used by [`MultiRenderer`]s created by this manager
+    ///
+    /// Defaults to [`CopyStrategy::PreferDmabuf`].
+    pub fn set_copy_strategy(&mut self, strategy: CopyStrategy) {
+        self.copy_strategy = strategy;
+    }
+
+    /// Returns the nodes of all currently known devices
+    pub fn nodes(&self) -> impl Iterator<Item = &DrmNode> {
+        self.devices.iter().map(|device| device.node())
+    }
+
+    /// Re-enumerates the devices of the underlying [`GraphicsApi`]
+    ///
+    /// Returns events for every device, that was added or removed since the last enumeration.
+    /// Removed devices are handled like in [`GpuManager::remove_device`].
+    pub fn refresh(&mut self) -> Result<Vec<GpuEvent>, Error<A, A>> {
+        let old_nodes = self.nodes().copied().collect::<Vec<_>>();
+        self.api
+            .enumerate(&mut self.devices, &self.log)
+            .map_err(Error::RenderApiError)?;
+
+        let mut events = Vec::new();
+        for node in old_nodes.iter() {
+            if !self.devices.iter().any(|device| device.node() == node) {
+                events.push(self.device_removed(node));
+            }
+        }
+        for device in self.devices.iter() {
+            if !old_nodes.contains(device.node()) {
+                slog::info!(self.log, "Device {} was added", device.node());
+                events.push(GpuEvent::Added(*device.node()));
+            }
+        }
+        Ok(events)
+    }
+
+    /// Removes a device, e.g. after it was unplugged
+    ///
+    /// Any textures residing on the device are invalidated, client buffers
+    /// need to be re-imported (see [`GpuManager::migrate_surface`]) on one of the remaining devices.
+    /// Returns `None`, if no device with the given node is known.
+    ///
+    /// *Note*: A later call to [`GpuManager::refresh`] or [`GpuManager::renderer`] will add the device again,
+    /// if the [`GraphicsApi`] still enumerates it.
+    pub fn remove_device(&mut self, node: &DrmNode) -> Option<GpuEvent> {
+        let len = self.devices.len();
+        self.devices.retain(|device| device.node() != node);
+        if self.devices.len() == len {
+            return None;
+        }
+        Some(self.device_removed(node))
+    }
+
+    fn device_removed(&mut self, node: &DrmNode) -> GpuEvent {
+        slog::info!(self.log, "Device {} was removed", node);
+        invalidate_node(node);
+        self.dma_source.retain(|_, source| source != node);
+        GpuEvent::Removed {
+            node: *node,
+            replacement: self.devices.first().map(|device| *device.node()),
+        }
+    }
+
     /// Create a [`MultiRenderer`].
     ///
     /// - `render_device` should referr to the gpu node rendering operations will take place upon.
@@ -217,6 +359,7 @@ impl<A: GraphicsApi> GpuManager<A> {
 
             Ok(MultiRenderer {
                 dma_source: Some(&mut self.dma_source),
+                copy_strategy: self.copy_strategy,
                 render: RenderDevice::Device(render.remove(0)),
                 target: Some(target.remove(0)),
                 other_renderers: others,
@@ -226,6 +369,7 @@ impl<A: GraphicsApi> GpuManager<A> {
         } else {
             Ok(MultiRenderer {
                 dma_source: Some(&mut self.dma_source),
+                copy_strategy: self.copy_strategy,
                 render: RenderDevice::Device(render.remove(0)),
                 target: None,
                 other_renderers: others,
@@ -304,6 +448,7 @@ impl<A: GraphicsApi> GpuManager<A> {
 
             Ok(MultiRenderer {
                 dma_source: Some(&mut render_api.dma_source),
+                copy_strategy: render_api.copy_strategy,
                 render: RenderDevice::Device(render.remove(0)),
                 target: Some(target),
                 other_renderers: others,
@@ -313,6 +458,7 @@ impl<A: GraphicsApi> GpuManager<A> {
         } else {
             Ok(MultiRenderer {
                 dma_source: Some(&mut render_api.dma_source),
+                copy_strategy: render_api.copy_strategy,
                 render: RenderDevice::Device(render.remove(0)),
                 target: None,
                 other_renderers: others,
@@ -322,6 +468,54 @@ impl<A: GraphicsApi> GpuManager<A> {
         }
     }
 
+    /// Re-imports the client buffers of a surface and its subsurfaces after a device was removed.
+    ///
+    /// - `target` referrs to the gpu node, that the surface is going to be rendered with from now on.
+    /// - `surface` is the wayland surface, whos buffer and subsurfaces buffers shall be migrated
+    ///
+    /// Textures of removed devices are dropped and all existing textures of the surfaces are
+    /// invalidated, causing them to be fully re-imported on their next use.
+    /// The buffers are then imported to `target` like with [`GpuManager::early_import`].
+    ///
+    /// Note: This will do nothing, if you are not using
+    /// [`crate::backend::renderer::utils::on_commit_buffer_handler`]
+    /// to let smithay handle buffer management.
+    #[cfg(feature = "wayland_frontend")]
+    pub fn migrate_surface(&mut self, target: DrmNode, surface: &WlSurface) -> Result<(), Error<A, A>>
+    where
+        A: 'static,
+        <A::Device as ApiDevice>::Renderer: ImportMemWl + ImportDmaWl + ExportMem,
+        <<A::Device as ApiDevice>::Renderer as ExportMem>::TextureMapping: 'static,
+    {
+        use crate::{
+            backend::renderer::utils::RendererSurfaceState,
+            wayland::compositor::{with_surface_tree_upward, TraversalAction},
+        };
+
+        if !self.devices.iter().any(|device| device.node() == &target) {
+            return Err(Error::NoDevice(target));
+        }
+
+        with_surface_tree_upward(
+            surface,
+            (),
+            |_, _, _| TraversalAction::DoChildren(()),
+            |_surface, states, _| {
+                if let Some(texture) = states.data_map.get::<Rc<RefCell<MultiTextureInternal>>>() {
+                    texture.borrow_mut().prune_removed();
+                }
+                if let Some(data) = states.data_map.get::<RefCell<RendererSurfaceState>>() {
+                    let mut data = data.borrow_mut();
+                    data.textures.clear();
+                    data.renderer_seen.clear();
+                }
+            },
+            |_, _, _| true,
+        );
+
+        self.early_import(None, target, surface)
+    }
+
     /// Function for optimizing buffer imports across multiple gpus.
     ///
     /// If you are using [`MultiRenderer`]s do rendering of your client buffers,
@@ -434,7 +628,8 @@ impl<A: GraphicsApi> GpuManager<A> {
                 let format = dmabuf.format();
 
                 let dma_source = self.dma_source.entry(dmabuf.weak());
-                if matches!(dma_source, Entry::Vacant(_))
+                if self.copy_strategy == CopyStrategy::AlwaysDmabuf
+                    || matches!(dma_source, Entry::Vacant(_))
                     || matches!(dma_source, Entry::Occupied(ref x) if x.get() == &target)
                 {
                     match target_device
@@ -450,6 +645,9 @@ impl<A: GraphicsApi> GpuManager<A> {
                             surface.data_map.insert_if_missing(|| texture.0);
                             return Ok(());
                         }
+                        Err(err) if self.copy_strategy == CopyStrategy::AlwaysDmabuf => {
+                            return Err(Error::Target(err));
+                        }
                         Err(err) => {
                             slog::trace!(
                                 self.log,
@@ -595,6 +793,7 @@ pub trait ApiDevice {
 #[derive(Debug)]
 pub struct MultiRenderer<'a, 'b, R: GraphicsApi, T: GraphicsApi, Target> {
     dma_source: Option<&'a mut HashMap<WeakDmabuf, DrmNode>>,
+    copy_strategy: CopyStrategy,
     render: RenderDevice<'a, R>,
     target: Option<&'b mut T::Device>,
     other_renderers: Vec<&'a mut R::Device>,
@@ -602,6 +801,18 @@ pub struct MultiRenderer<'a, 'b, R: GraphicsApi, T: GraphicsApi, Target> {
     log: ::slog::Logger,
 }
 
+impl<'a, 'b, R: GraphicsApi, T: GraphicsApi, Target> MultiRenderer<'a, 'b, R, T, Target> {
+    /// Returns the [`CopyStrategy`] used by this renderer
+    pub fn copy_strategy(&self) -> CopyStrategy {
+        self.copy_strategy
+    }
+
+    /// Overrides the [`CopyStrategy`] inherited from the [`GpuManager`] for this renderer
+    pub fn set_copy_strategy(&mut self, strategy: CopyStrategy) {
+        self.copy_strategy = strategy;
+    }
+}
+
 impl<'a, 'b, R: GraphicsApi, T: GraphicsApi, Target> AsRef<<R::Device as ApiDevice>::Renderer>
     for MultiRenderer<'a, 'b, R, T, Target>
 {
@@ -665,6 +876,8 @@ pub struct MultiFrame<R: GraphicsApi, T: GraphicsApi> {
     // FIXME: With GAT this would not need to be a raw-pointer
     frame: *mut <<R::Device as ApiDevice>::Renderer as Renderer>::Frame,
     damage: Vec<Rectangle<i32, Physical>>,
+    // whether the result is copied to the target device after rendering
+    copy_to_target: bool,
     // We need this for the associated Error type of the Frame implementation
     _target: std::marker::PhantomData<T>,
     log: ::slog::Logger,
@@ -840,12 +1053,15 @@ where
         let other_renderers_ref = &mut other_renderers;
 
         let log = self.log.clone();
+        let copy_strategy = self.copy_strategy;
+        let copy_to_target = target.is_some();
         let res = self
             .render
             .renderer_mut()
             .render(size, dst_transform, move |render, frame| {
                 let mut new_renderer = MultiRenderer {
                     dma_source: dma_source_ref.take(),
+                    copy_strategy,
                     render: RenderDevice::Renderer(render, node),
                     target: target_ref.take(),
                     other_renderers: other_renderers_ref.drain(..).collect(),
@@ -856,6 +1072,7 @@ where
                     node,
                     frame, // we cheat here and use a raw-ptr, because otherwise your associated type would gain an uncostraint lifetime parameter
                     damage: Vec::new(),
+                    copy_to_target,
                     _target: std::marker::PhantomData::<T>,
                     log,
                 };
@@ -887,17 +1104,31 @@ where
             .collect::<Vec<_>>();
 
         if let Some(target) = self.target.as_mut() {
-            {
+            if copy_strategy != CopyStrategy::AlwaysCpu {
                 let mut can_import = CAN_IMPORT.lock().unwrap();
-                let dmabuf = self
-                    .render
-                    .renderer_mut()
-                    .export_framebuffer(buffer_size)
-                    .map_err(Error::Render)?;
-                let might_import = *can_import
-                    .get(&(*self.render.node(), *target.node(), dmabuf.format()))
-                    .unwrap_or(&true);
-                if might_import {
+                let dmabuf = match self.render.renderer_mut().export_framebuffer(buffer_size) {
+                    Ok(dmabuf) => Some(dmabuf),
+                    Err(err) if copy_strategy == CopyStrategy::AlwaysDmabuf => {
+                        return Err(Error::Render(err));
+                    }
+                    Err(err) => {
+                        slog::warn!(
+                            self.log,
+                            "Error exporting framebuffer of {}: {}",
+                            self.render.node(),
+                            err
+                        );
+                        slog::info!(self.log, "Falling back to cpu-copy.");
+                        None
+                    }
+                };
+                let might_import = dmabuf.as_ref().map_or(false, |dmabuf| {
+                    copy_strategy == CopyStrategy::AlwaysDmabuf
+                        || *can_import
+                            .get(&(*self.render.node(), *target.node(), dmabuf.format()))
+                            .unwrap_or(&true)
+                });
+                if let Some(dmabuf) = dmabuf.filter(|_| might_import) {
                     // try gpu copy
                     match target.renderer_mut().import_dmabuf(&dmabuf, Some(&damage)) {
                         Ok(texture) => {
@@ -924,6 +1155,9 @@ where
                             can_import.insert((*self.render.node(), *target.node(), dmabuf.format()), true);
                             return Ok(res);
                         }
+                        Err(err) if copy_strategy == CopyStrategy::AlwaysDmabuf => {
+                            return Err(Error::Target(err));
+                        }
                         Err(err) => {
                             let (source, target, format) =
                                 (*self.render.node(), *target.node(), dmabuf.format());
@@ -1030,6 +1264,27 @@ type DamageAnyTextureMappings = Vec<(Rectangle<i32, BufferCoords>, Box<dyn Any +
 struct GpuSingleTexture {
     mapping: Option<(DrmNode, DamageAnyTextureMappings)>,
     texture: Option<Box<dyn Any + 'static>>,
+    // generations of the render node and the source node of the mapping at creation
+    generation: usize,
+    source_generation: usize,
+}
+
+impl MultiTextureInternal {
+    // Drops textures and mappings of removed devices
+    fn prune_removed(&mut self) {
+        for textures in self.textures.values_mut() {
+            textures.retain(|node, texture| {
+                if texture.generation != node_generation(node) {
+                    return false;
+                }
+                if matches!(texture.mapping, Some((ref source, _)) if texture.source_generation != node_generation(source))
+                {
+                    texture.mapping = None;
+                }
+                texture.mapping.is_some() || texture.texture.is_some()
+            });
+        }
+    }
 }
 
 impl MultiTexture {
@@ -1057,6 +1312,7 @@ impl MultiTexture {
                 internal.textures.clear();
                 internal.size = size;
             }
+            internal.prune_removed();
         }
         MultiTexture(internal)
     }
@@ -1075,6 +1331,7 @@ impl MultiTexture {
     where
         <<A::Device as ApiDevice>::Renderer as Renderer>::TextureId: 'static,
     {
+        self.0.borrow_mut().prune_removed();
         let tex = self.0.borrow();
         // TODO: use Ref::filter_map when stabilized
         if tex
@@ -1122,6 +1379,8 @@ impl MultiTexture {
             GpuSingleTexture {
                 mapping: None,
                 texture: Some(Box::new(texture) as Box<_>),
+                generation: node_generation(&render),
+                source_generation: 0,
             },
         );
     }
@@ -1146,6 +1405,7 @@ impl MultiTexture {
         <<T::Device as ApiDevice>::Renderer as ExportMem>::TextureMapping: 'static,
     {
         let mut tex = self.0.borrow_mut();
+        tex.prune_removed();
         let textures = tex.textures.entry(TypeId::of::<R>()).or_default();
         let (old_texture, old_mapping) = textures
             .remove(&render)
@@ -1177,6 +1437,8 @@ impl MultiTexture {
             GpuSingleTexture {
                 mapping: Some((source, mappings)),
                 texture: old_texture,
+                generation: node_generation(&render),
+                source_generation: node_generation(&source),
             },
         );
     }
@@ -1253,6 +1515,20 @@ where
     fn transformation(&self) -> Transform {
         unsafe { &mut *self.frame }.transformation()
     }
+
+    fn wait(&mut self, sync: &SyncPoint) -> Result<(), Self::Error> {
+        unsafe { &mut *self.frame }.wait(sync).map_err(Error::Render)
+    }
+
+    fn finish(&mut self) -> Result<SyncPoint, Self::Error> {
+        if self.copy_to_target {
+            // The copy to the target device relies on the rendering being complete
+            // and is waited upon, before `render` returns.
+            Ok(SyncPoint::signaled())
+        } else {
+            unsafe { &mut *self.frame }.finish().map_err(Error::Render)
+        }
+    }
 }
 
 #[cfg(feature = "wayland_frontend")]
@@ -1499,7 +1775,8 @@ where
         damage: Option<&[Rectangle<i32, BufferCoords>]>,
     ) -> Result<<Self as Renderer>::TextureId, <Self as Renderer>::Error> {
         let dma_source = self.dma_source.as_mut().unwrap().entry(dmabuf.weak());
-        if matches!(dma_source, Entry::Vacant(_))
+        if self.copy_strategy == CopyStrategy::AlwaysDmabuf
+            || matches!(dma_source, Entry::Vacant(_))
             || matches!(dma_source, Entry::Occupied(ref x) if x.get() == self.render.node())
         {
             match self.render.renderer_mut().import_dmabuf(dmabuf, damage) {
@@ -1510,6 +1787,9 @@ where
                     texture.insert_texture::<R>(*self.render.node(), imported);
                     return Ok(texture);
                 }
+                Err(err) if self.copy_strategy == CopyStrategy::AlwaysDmabuf => {
+                    return Err(Error::Render(err));
+                }
                 Err(err) => {
                     slog::trace!(
                         self.log,
@@ -1916,3 +2196,83 @@ where
             .map_err(Error::Render)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{invalidate_node, node_generation, GpuSingleTexture, MultiTextureInternal, CAN_IMPORT};
+    use crate::backend::{
+        allocator::{Format, Fourcc, Modifier},
+        drm::DrmNode,
+    };
+    use std::{any::TypeId, collections::HashMap};
+
+    // the generations are global, so every test uses its own nodes
+
+    fn single(node: &DrmNode, mapping: Option<DrmNode>, texture: bool) -> GpuSingleTexture {
+        GpuSingleTexture {
+            mapping: mapping.map(|source| (source, Vec::new())),
+            texture: if texture { Some(Box::new(0u32)) } else { None },
+            generation: node_generation(node),
+            source_generation: mapping.map(|source| node_generation(&source)).unwrap_or(0),
+        }
+    }
+
+    #[test]
+    fn invalidate_node_bumps_generation() {
+        let removed = DrmNode::dummy(0);
+        let other = DrmNode::dummy(1);
+        let format = Format {
+            code: Fourcc::Argb8888,
+            modifier: Modifier::Linear,
+        };
+        {
+            let mut can_import = CAN_IMPORT.lock().unwrap();
+            can_import.insert((removed, other, format), true);
+            can_import.insert((other, removed, format), true);
+            can_import.insert((other, other, format), true);
+        }
+
+        let generation = node_generation(&removed);
+        invalidate_node(&removed);
+        assert_eq!(node_generation(&removed), generation + 1);
+        assert_eq!(node_generation(&other), 0);
+
+        let can_import = CAN_IMPORT.lock().unwrap();
+        assert!(!can_import.contains_key(&(removed, other, format)));
+        assert!(!can_import.contains_key(&(other, removed, format)));
+        assert!(can_import.contains_key(&(other, other, format)));
+    }
+
+    #[test]
+    fn prune_removed_drops_textures_of_removed_nodes() {
+        let kept = DrmNode::dummy(10);
+        let removed = DrmNode::dummy(11);
+        let mapped = DrmNode::dummy(12);
+        let mapped_only = DrmNode::dummy(13);
+
+        let mut textures = HashMap::new();
+        textures.insert(kept, single(&kept, None, true));
+        textures.insert(removed, single(&removed, None, true));
+        textures.insert(mapped, single(&mapped, Some(removed), true));
+        textures.insert(mapped_only, single(&mapped_only, Some(removed), false));
+        let mut internal = MultiTextureInternal {
+            textures: HashMap::from([(TypeId::of::<()>(), textures)]),
+            size: (1, 1).into(),
+        };
+
+        internal.prune_removed();
+        assert_eq!(internal.textures[&TypeId::of::<()>()].len(), 4);
+
+        invalidate_node(&removed);
+        internal.prune_removed();
+        let textures = &internal.textures[&TypeId::of::<()>()];
+        assert!(textures[&kept].texture.is_some());
+        // textures on the removed device are gone
+        assert!(!textures.contains_key(&removed));
+        // mappings from the removed device are dropped, the texture stays valid
+        assert!(textures[&mapped].texture.is_some());
+        assert!(textures[&mapped].mapping.is_none());
+        // entries without anything left are removed
+        assert!(!textures.contains_key(&mapped_only));
+    }
+}
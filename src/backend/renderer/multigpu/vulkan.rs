@@ -0,0 +1,120 @@
+//! Implementation of the multi-gpu [`GraphicsApi`] using
+//! Vulkan for device enumeration and rendering.
+
+use ash::vk;
+
+use crate::backend::{
+    drm::DrmNode,
+    renderer::{
+        multigpu::{ApiDevice, Error as MultiError, GraphicsApi},
+        vulkan::{VulkanError, VulkanRenderer},
+        Renderer,
+    },
+    vulkan::{Instance, PhysicalDevice},
+    SwapBuffersError,
+};
+
+/// Errors raised by the [`VulkanBackend`]
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Vulkan api error during device enumeration
+    #[error(transparent)]
+    Vk(#[from] vk::Result),
+    /// Vulkan renderer error
+    #[error(transparent)]
+    Renderer(#[from] VulkanError),
+}
+
+impl From<Error> for SwapBuffersError {
+    fn from(err: Error) -> SwapBuffersError {
+        match err {
+            x @ Error::Vk(_) => SwapBuffersError::ContextLost(Box::new(x)),
+            Error::Renderer(x) => x.into(),
+        }
+    }
+}
+
+/// A [`GraphicsApi`] utilizing Vulkan for device enumeration and rendering.
+///
+/// Only physical devices, which expose a drm render node, are enumerated.
+#[derive(Debug)]
+pub struct VulkanBackend {
+    instance: Instance,
+}
+
+impl VulkanBackend {
+    /// Creates a new [`VulkanBackend`] enumerating the physical devices of the given [`Instance`].
+    pub fn new(instance: Instance) -> VulkanBackend {
+        VulkanBackend { instance }
+    }
+}
+
+impl GraphicsApi for VulkanBackend {
+    type Device = VulkanDevice;
+    type Error = Error;
+
+    fn enumerate(&self, list: &mut Vec<Self::Device>, log: &slog::Logger) -> Result<(), Self::Error> {
+        let devices = PhysicalDevice::enumerate(&self.instance)?
+            .flat_map(|device| {
+                let node = device.render_node().ok()??;
+                Some((device, node))
+            })
+            .collect::<Vec<_>>();
+        // remove old stuff
+        list.retain(|renderer| devices.iter().any(|(_, node)| &renderer.node == node));
+        // add new stuff
+        let new_renderers = devices
+            .into_iter()
+            .filter(|(_, node)| !list.iter().any(|renderer| &renderer.node == node))
+            .map(|(device, node)| {
+                slog::info!(log, "Trying to initialize {} from {}", device.name(), node);
+                let renderer = VulkanRenderer::new(&device, log.clone())?;
+
+                Ok(VulkanDevice { node, renderer })
+            })
+            .flat_map(|x: Result<VulkanDevice, Error>| match x {
+                Ok(x) => Some(x),
+                Err(x) => {
+                    slog::warn!(log, "Skipping PhysicalDevice: {}", x);
+                    None
+                }
+            })
+            .collect::<Vec<VulkanDevice>>();
+        list.extend(new_renderers);
+        // but don't replace already initialized renderers
+
+        Ok(())
+    }
+}
+
+// TODO: Replace with specialization impl in multigpu/mod once possible
+impl<T: GraphicsApi> std::convert::From<VulkanError> for MultiError<VulkanBackend, T>
+where
+    T::Error: 'static,
+    <<T::Device as ApiDevice>::Renderer as Renderer>::Error: 'static,
+{
+    fn from(err: VulkanError) -> MultiError<VulkanBackend, T> {
+        MultiError::Render(err)
+    }
+}
+
+/// [`ApiDevice`] of the [`VulkanBackend`]
+#[derive(Debug)]
+pub struct VulkanDevice {
+    node: DrmNode,
+    renderer: VulkanRenderer,
+}
+
+impl ApiDevice for VulkanDevice {
+    type Renderer = VulkanRenderer;
+
+    fn renderer(&self) -> &Self::Renderer {
+        &self.renderer
+    }
+    fn renderer_mut(&mut self) -> &mut Self::Renderer {
+        &mut self.renderer
+    }
+    fn node(&self) -> &DrmNode {
+        &self.node
+    }
+}
@@ -0,0 +1,196 @@
+//! Synchronization of rendering operations with other apis
+//!
+//! A [`SyncPoint`] is returned by [`Frame::finish`](super::Frame::finish) and signals,
+//! once all rendering operations of the frame are complete.
+//! If supported by the renderer, it is backed by a `sync_file`, which may be handed
+//! to other devices, e.g. as the `IN_FENCE_FD` of a drm atomic commit
+//! via [`DrmSurface::set_in_fence`](crate::backend::drm::DrmSurface::set_in_fence),
+//! instead of blocking until the gpu is done.
+//!
+//! A `SyncPoint` can be waited upon directly, or asynchronously by inserting its [`SyncPointSource`]
+//! into an event loop.
+
+use std::{
+    io,
+    os::unix::io::{AsRawFd, FromRawFd},
+    sync::Arc,
+    time::Duration,
+};
+
+use calloop::{
+    generic::Generic, EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory,
+};
+use io_lifetimes::{AsFd, BorrowedFd, OwnedFd};
+use nix::poll::{poll, PollFd, PollFlags};
+
+/// A point in the command stream of a renderer, which is reached once all previously submitted operations are complete
+///
+/// Cloning a `SyncPoint` is cheap, all clones refer to the same fence.
+#[derive(Debug, Clone, Default)]
+pub struct SyncPoint {
+    fence: Option<Arc<OwnedFd>>,
+}
+
+impl SyncPoint {
+    /// Creates an already reached sync point
+    ///
+    /// Used by renderers, which wait for their operations to complete or do not support fences.
+    pub fn signaled() -> SyncPoint {
+        SyncPoint { fence: None }
+    }
+
+    /// Creates a sync point from a `sync_file` file descriptor
+    ///
+    /// The sync point is reached once the fence of the `sync_file` signals.
+    pub fn from_sync_file(fd: OwnedFd) -> SyncPoint {
+        SyncPoint {
+            fence: Some(Arc::new(fd)),
+        }
+    }
+
+    /// Returns the `sync_file` backing this sync point, if any
+    ///
+    /// A sync point without a fence is always reached.
+    pub fn fence(&self) -> Option<BorrowedFd<'_>> {
+        self.fence.as_ref().map(|fence| fence.as_fd())
+    }
+
+    /// Exports the fence of this sync point as a new `sync_file` file descriptor
+    ///
+    /// Returns `None` for sync points without a fence, which are always reached.
+    pub fn export(&self) -> Option<OwnedFd> {
+        self.fence
+            .as_ref()
+            .and_then(|fence| nix::unistd::dup(fence.as_raw_fd()).ok())
+            // SAFETY: dup returned a new file descriptor, which is owned by nobody else
+            .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Returns whether the sync point was reached
+    pub fn is_reached(&self) -> bool {
+        self.wait_timeout(Some(Duration::ZERO))
+    }
+
+    /// Blocks until the sync point is reached
+    pub fn wait(&self) {
+        self.wait_timeout(None);
+    }
+
+    /// Blocks until the sync point is reached or the timeout expired
+    ///
+    /// Returns whether the sync point was reached. A timeout of `None` waits indefinitely.
+    pub fn wait_timeout(&self, timeout: Option<Duration>) -> bool {
+        let fence = match self.fence.as_ref() {
+            Some(fence) => fence,
+            None => return true,
+        };
+
+        let timeout = timeout
+            .map(|timeout| timeout.as_millis().min(i32::MAX as u128) as i32)
+            .unwrap_or(-1);
+        let mut fds = [PollFd::new(fence.as_raw_fd(), PollFlags::POLLIN)];
+        loop {
+            match poll(&mut fds, timeout) {
+                Ok(ready) => return ready > 0,
+                Err(nix::errno::Errno::EINTR) => continue,
+                // an invalid fence can never be waited upon
+                Err(_) => return true,
+            }
+        }
+    }
+
+    /// Creates an event source, which fires once the sync point is reached
+    ///
+    /// The source is removed from the event loop after firing.
+    pub fn source(&self) -> io::Result<SyncPointSource> {
+        let fd = match self.export() {
+            Some(fd) => fd,
+            None => {
+                // an eventfd with a non-zero counter is readable right away
+                let fd = nix::sys::eventfd::eventfd(1, nix::sys::eventfd::EfdFlags::EFD_CLOEXEC)?;
+                // SAFETY: eventfd returned a new file descriptor, which is owned by nobody else
+                unsafe { OwnedFd::from_raw_fd(fd) }
+            }
+        };
+        Ok(SyncPointSource {
+            source: Generic::new(fd, Interest::READ, Mode::OneShot),
+        })
+    }
+}
+
+/// Event source for a [`SyncPoint`], see [`SyncPoint::source`]
+#[derive(Debug)]
+pub struct SyncPointSource {
+    source: Generic<OwnedFd>,
+}
+
+impl EventSource for SyncPointSource {
+    type Event = ();
+    type Metadata = ();
+    type Ret = ();
+    type Error = io::Error;
+
+    fn process_events<F>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: F,
+    ) -> io::Result<PostAction>
+    where
+        F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        self.source.process_events(readiness, token, |_, _| {
+            callback((), &mut ());
+            Ok(PostAction::Remove)
+        })
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> calloop::Result<()> {
+        self.source.register(poll, token_factory)
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> calloop::Result<()> {
+        self.source.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> calloop::Result<()> {
+        self.source.unregister(poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        os::unix::io::{AsRawFd, FromRawFd},
+        time::Duration,
+    };
+
+    use io_lifetimes::OwnedFd;
+
+    use super::SyncPoint;
+
+    #[test]
+    fn signaled() {
+        let sync = SyncPoint::signaled();
+        assert!(sync.is_reached());
+        assert!(sync.fence().is_none());
+        assert!(sync.export().is_none());
+    }
+
+    #[test]
+    fn fence() {
+        // a pipe becomes readable just like a signaled sync_file
+        let (read, write) = nix::unistd::pipe().unwrap();
+        let write = unsafe { OwnedFd::from_raw_fd(write) };
+        let sync = SyncPoint::from_sync_file(unsafe { OwnedFd::from_raw_fd(read) });
+        let clone = sync.clone();
+
+        assert!(!sync.is_reached());
+        assert!(!clone.wait_timeout(Some(Duration::from_millis(1))));
+        assert!(sync.export().is_some());
+
+        nix::unistd::write(write.as_raw_fd(), &[1]).unwrap();
+        assert!(sync.is_reached());
+        assert!(clone.wait_timeout(None));
+    }
+}
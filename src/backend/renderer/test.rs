@@ -0,0 +1,512 @@
+//! Recording renderer for deterministic rendering tests
+//!
+//! The [`TestRenderer`] does not produce any pixels. Instead it records every
+//! operation issued through its [`Frame`] implementation, so the behavior of
+//! higher level helpers like [`DamageTrackedRenderer`](super::damage::DamageTrackedRenderer)
+//! or the [`Space`](crate::desktop::space::Space) rendering can be asserted on in unit tests.
+//!
+//! Every call to [`Renderer::render`] results in a [`RecordedFrame`] containing
+//! the output size, transformation, the bound target (if any) and all the
+//! [`FrameCall`]s issued during that frame.
+//!
+//! ```
+//! use smithay::{
+//!     backend::renderer::{
+//!         damage::DamageTrackedRenderer,
+//!         element::texture::{TextureBuffer, TextureRenderElement},
+//!         test::{FrameCall, TestRenderer},
+//!     },
+//!     utils::{Point, Rectangle, Transform},
+//! };
+//!
+//! let mut renderer = TestRenderer::new();
+//! let buffer = TextureBuffer::from_memory(
+//!     &mut renderer,
+//!     &[0u8; 10 * 10 * 4],
+//!     (10, 10),
+//!     false,
+//!     1,
+//!     Transform::Normal,
+//!     None,
+//! )
+//! .unwrap();
+//!
+//! let mut damage_tracked_renderer = DamageTrackedRenderer::new((800, 600), 1.0, Transform::Normal);
+//! let element = TextureRenderElement::from_texture_buffer(Point::from((100.0, 100.0)), &buffer, None, None);
+//! damage_tracked_renderer
+//!     .render_output(&mut renderer, 0, &[&element], [0.0, 0.0, 0.0, 1.0], None)
+//!     .unwrap();
+//!
+//! let frame = renderer.last_frame().unwrap();
+//! assert!(matches!(
+//!     &frame.calls[1],
+//!     FrameCall::RenderTexture { dst, .. } if *dst == Rectangle::from_loc_and_size((100, 100), (10, 10))
+//! ));
+//! ```
+
+use std::{cell::Cell, collections::HashSet, rc::Rc};
+
+use crate::{
+    backend::allocator::{Format, Fourcc, Modifier},
+    utils::{Buffer as BufferCoord, Physical, Rectangle, Size, Transform},
+};
+
+use super::{
+    sync::SyncPoint, Bind, ExportMem, Frame, ImportMem, Offscreen, Renderer, Texture, TextureFilter,
+    TextureMapping, Unbind,
+};
+
+crate::utils::ids::id_gen!(next_renderer_id, RENDERER_ID, RENDERER_IDS);
+
+#[derive(Debug)]
+struct RendererId(usize);
+impl Drop for RendererId {
+    fn drop(&mut self) {
+        RENDERER_IDS.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// Errors returned by the [`TestRenderer`]
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum TestRendererError {
+    /// The provided buffer's size did not match the requested one.
+    #[error("Error reading buffer, size is too small for the given dimensions")]
+    UnexpectedSize,
+    /// The updated region is out of bounds of the texture
+    #[error("The region {0:?} is out of bounds of the texture")]
+    OutOfBounds(Rectangle<i32, BufferCoord>),
+    /// The texture was created by a different renderer
+    #[error("The texture was created by a different renderer")]
+    WrongRenderer,
+    /// A failure was injected via [`TestRenderer::fail_next_render`]
+    #[error("Injected rendering failure")]
+    Injected,
+}
+
+/// Texture of the [`TestRenderer`]
+///
+/// Textures are cheap to clone, all clones refer to the same texture.
+#[derive(Debug, Clone)]
+pub struct TestTexture {
+    id: usize,
+    renderer_id: usize,
+    size: Size<i32, BufferCoord>,
+    flipped: bool,
+    updates: Rc<Cell<usize>>,
+}
+
+impl TestTexture {
+    /// Unique id of this texture for the renderer, that created it.
+    ///
+    /// Ids are assigned incrementally starting at 0.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Returns if this texture was imported as flipped
+    pub fn flipped(&self) -> bool {
+        self.flipped
+    }
+
+    /// Number of times [`ImportMem::update_memory`] was called for this texture
+    pub fn updates(&self) -> usize {
+        self.updates.get()
+    }
+}
+
+impl PartialEq for TestTexture {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && self.renderer_id == other.renderer_id
+    }
+}
+
+impl Eq for TestTexture {}
+
+impl Texture for TestTexture {
+    fn width(&self) -> u32 {
+        self.size.w as u32
+    }
+    fn height(&self) -> u32 {
+        self.size.h as u32
+    }
+    fn size(&self) -> Size<i32, BufferCoord> {
+        self.size
+    }
+}
+
+/// Texture mapping of the [`TestRenderer`]
+///
+/// The contents are always zeroed.
+#[derive(Debug)]
+pub struct TestTextureMapping {
+    size: Size<i32, BufferCoord>,
+    data: Vec<u8>,
+}
+
+impl Texture for TestTextureMapping {
+    fn width(&self) -> u32 {
+        self.size.w as u32
+    }
+    fn height(&self) -> u32 {
+        self.size.h as u32
+    }
+    fn size(&self) -> Size<i32, BufferCoord> {
+        self.size
+    }
+}
+
+impl TextureMapping for TestTextureMapping {
+    fn flipped(&self) -> bool {
+        false
+    }
+}
+
+/// A single operation recorded during a [`RecordedFrame`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameCall {
+    /// A call to [`Frame::clear`]
+    Clear {
+        /// The clear color
+        color: [f32; 4],
+        /// The cleared regions
+        at: Vec<Rectangle<i32, Physical>>,
+    },
+    /// A call to [`Frame::render_texture_from_to`] (or [`Frame::render_texture_at`])
+    RenderTexture {
+        /// Id of the rendered texture, see [`TestTexture::id`]
+        texture: usize,
+        /// Source rectangle in the texture
+        src: Rectangle<f64, BufferCoord>,
+        /// Destination rectangle on the target
+        dst: Rectangle<i32, Physical>,
+        /// Damage relative to `dst`
+        damage: Vec<Rectangle<i32, Physical>>,
+        /// Transformation of the texture
+        transform: Transform,
+        /// Alpha value
+        alpha: f32,
+    },
+}
+
+/// A frame recorded by the [`TestRenderer`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedFrame {
+    /// Size of the output as passed to [`Renderer::render`]
+    pub output_size: Size<i32, Physical>,
+    /// Transformation of the output as passed to [`Renderer::render`]
+    pub transform: Transform,
+    /// Id of the bound offscreen target, `None` for the default target
+    pub target: Option<usize>,
+    /// All operations issued during this frame in order
+    pub calls: Vec<FrameCall>,
+}
+
+impl RecordedFrame {
+    /// Returns the regions cleared during this frame
+    pub fn cleared(&self) -> Vec<Rectangle<i32, Physical>> {
+        self.calls
+            .iter()
+            .filter_map(|call| match call {
+                FrameCall::Clear { at, .. } => Some(at.iter().copied()),
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+
+    /// Returns the ids of all textures rendered during this frame in order
+    pub fn rendered_textures(&self) -> Vec<usize> {
+        self.calls
+            .iter()
+            .filter_map(|call| match call {
+                FrameCall::RenderTexture { texture, .. } => Some(*texture),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Frame of the [`TestRenderer`]
+#[derive(Debug)]
+pub struct TestFrame {
+    renderer_id: usize,
+    transform: Transform,
+    calls: Vec<FrameCall>,
+}
+
+impl Frame for TestFrame {
+    type Error = TestRendererError;
+    type TextureId = TestTexture;
+
+    fn clear(&mut self, color: [f32; 4], at: &[Rectangle<i32, Physical>]) -> Result<(), Self::Error> {
+        self.calls.push(FrameCall::Clear {
+            color,
+            at: at.to_vec(),
+        });
+        Ok(())
+    }
+
+    fn render_texture_from_to(
+        &mut self,
+        texture: &Self::TextureId,
+        src: Rectangle<f64, BufferCoord>,
+        dst: Rectangle<i32, Physical>,
+        damage: &[Rectangle<i32, Physical>],
+        src_transform: Transform,
+        alpha: f32,
+    ) -> Result<(), Self::Error> {
+        if texture.renderer_id != self.renderer_id {
+            return Err(TestRendererError::WrongRenderer);
+        }
+        self.calls.push(FrameCall::RenderTexture {
+            texture: texture.id,
+            src,
+            dst,
+            damage: damage.to_vec(),
+            transform: src_transform,
+            alpha,
+        });
+        Ok(())
+    }
+
+    fn transformation(&self) -> Transform {
+        self.transform
+    }
+
+    fn finish(&mut self) -> Result<SyncPoint, Self::Error> {
+        Ok(SyncPoint::signaled())
+    }
+}
+
+/// A renderer recording all rendering operations
+///
+/// See the [module-level documentation](self) for more information.
+#[derive(Debug)]
+pub struct TestRenderer {
+    id: RendererId,
+    next_texture_id: usize,
+    target: Option<TestTexture>,
+    frames: Vec<RecordedFrame>,
+    min_filter: TextureFilter,
+    max_filter: TextureFilter,
+    fail_next_render: bool,
+}
+
+impl Default for TestRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TestRenderer {
+    /// Create a new [`TestRenderer`]
+    pub fn new() -> TestRenderer {
+        TestRenderer {
+            id: RendererId(next_renderer_id()),
+            next_texture_id: 0,
+            target: None,
+            frames: Vec::new(),
+            min_filter: TextureFilter::Linear,
+            max_filter: TextureFilter::Linear,
+            fail_next_render: false,
+        }
+    }
+
+    /// All frames recorded so far
+    pub fn frames(&self) -> &[RecordedFrame] {
+        &self.frames
+    }
+
+    /// The most recently recorded frame, if any
+    pub fn last_frame(&self) -> Option<&RecordedFrame> {
+        self.frames.last()
+    }
+
+    /// Take all frames recorded so far, clearing the internal record
+    pub fn take_frames(&mut self) -> Vec<RecordedFrame> {
+        std::mem::take(&mut self.frames)
+    }
+
+    /// Currently bound offscreen target, if any
+    pub fn target(&self) -> Option<&TestTexture> {
+        self.target.as_ref()
+    }
+
+    /// Filter set via [`Renderer::downscale_filter`]
+    pub fn current_downscale_filter(&self) -> TextureFilter {
+        self.min_filter
+    }
+
+    /// Filter set via [`Renderer::upscale_filter`]
+    pub fn current_upscale_filter(&self) -> TextureFilter {
+        self.max_filter
+    }
+
+    /// Let the next call to [`Renderer::render`] fail with [`TestRendererError::Injected`]
+    /// after running the rendering closure.
+    ///
+    /// The frame is still recorded, which allows to test error handling of partially rendered frames.
+    pub fn fail_next_render(&mut self) {
+        self.fail_next_render = true;
+    }
+
+    fn create_texture(&mut self, size: Size<i32, BufferCoord>, flipped: bool) -> TestTexture {
+        let id = self.next_texture_id;
+        self.next_texture_id += 1;
+        TestTexture {
+            id,
+            renderer_id: self.id.0,
+            size,
+            flipped,
+            updates: Rc::new(Cell::new(0)),
+        }
+    }
+}
+
+impl Renderer for TestRenderer {
+    type Error = TestRendererError;
+    type TextureId = TestTexture;
+    type Frame = TestFrame;
+
+    fn id(&self) -> usize {
+        self.id.0
+    }
+
+    fn downscale_filter(&mut self, filter: TextureFilter) -> Result<(), Self::Error> {
+        self.min_filter = filter;
+        Ok(())
+    }
+    fn upscale_filter(&mut self, filter: TextureFilter) -> Result<(), Self::Error> {
+        self.max_filter = filter;
+        Ok(())
+    }
+
+    fn render<F, R>(
+        &mut self,
+        output_size: Size<i32, Physical>,
+        dst_transform: Transform,
+        rendering: F,
+    ) -> Result<R, Self::Error>
+    where
+        F: FnOnce(&mut Self, &mut Self::Frame) -> R,
+    {
+        let mut frame = TestFrame {
+            renderer_id: self.id.0,
+            transform: dst_transform,
+            calls: Vec::new(),
+        };
+        let target = self.target.as_ref().map(|t| t.id);
+        let result = rendering(self, &mut frame);
+        self.frames.push(RecordedFrame {
+            output_size,
+            transform: dst_transform,
+            target,
+            calls: frame.calls,
+        });
+
+        if std::mem::take(&mut self.fail_next_render) {
+            return Err(TestRendererError::Injected);
+        }
+        Ok(result)
+    }
+}
+
+impl ImportMem for TestRenderer {
+    fn import_memory(
+        &mut self,
+        data: &[u8],
+        size: Size<i32, BufferCoord>,
+        flipped: bool,
+    ) -> Result<Self::TextureId, Self::Error> {
+        if data.len() < (size.w * size.h * 4) as usize {
+            return Err(TestRendererError::UnexpectedSize);
+        }
+        Ok(self.create_texture(size, flipped))
+    }
+
+    fn update_memory(
+        &mut self,
+        texture: &Self::TextureId,
+        data: &[u8],
+        region: Rectangle<i32, BufferCoord>,
+    ) -> Result<(), Self::Error> {
+        if texture.renderer_id != self.id.0 {
+            return Err(TestRendererError::WrongRenderer);
+        }
+        if !Rectangle::from_loc_and_size((0, 0), texture.size).contains_rect(region) {
+            return Err(TestRendererError::OutOfBounds(region));
+        }
+        if data.len() < (texture.size.w * texture.size.h * 4) as usize {
+            return Err(TestRendererError::UnexpectedSize);
+        }
+        texture.updates.set(texture.updates.get() + 1);
+        Ok(())
+    }
+}
+
+impl Bind<TestTexture> for TestRenderer {
+    fn bind(&mut self, target: TestTexture) -> Result<(), Self::Error> {
+        if target.renderer_id != self.id.0 {
+            return Err(TestRendererError::WrongRenderer);
+        }
+        self.target = Some(target);
+        Ok(())
+    }
+
+    fn supported_formats(&self) -> Option<HashSet<Format>> {
+        Some(
+            [Fourcc::Abgr8888, Fourcc::Xbgr8888]
+                .into_iter()
+                .map(|code| Format {
+                    code,
+                    modifier: Modifier::Linear,
+                })
+                .collect(),
+        )
+    }
+}
+
+impl Unbind for TestRenderer {
+    fn unbind(&mut self) -> Result<(), Self::Error> {
+        self.target = None;
+        Ok(())
+    }
+}
+
+impl Offscreen<TestTexture> for TestRenderer {
+    fn create_buffer(&mut self, size: Size<i32, BufferCoord>) -> Result<TestTexture, Self::Error> {
+        Ok(self.create_texture(size, false))
+    }
+}
+
+impl ExportMem for TestRenderer {
+    type TextureMapping = TestTextureMapping;
+
+    fn copy_framebuffer(
+        &mut self,
+        region: Rectangle<i32, BufferCoord>,
+    ) -> Result<Self::TextureMapping, Self::Error> {
+        Ok(TestTextureMapping {
+            size: region.size,
+            data: vec![0; (region.size.w * region.size.h * 4) as usize],
+        })
+    }
+
+    fn copy_texture(
+        &mut self,
+        texture: &Self::TextureId,
+        region: Rectangle<i32, BufferCoord>,
+    ) -> Result<Self::TextureMapping, Self::Error> {
+        if !Rectangle::from_loc_and_size((0, 0), texture.size).contains_rect(region) {
+            return Err(TestRendererError::OutOfBounds(region));
+        }
+        self.copy_framebuffer(region)
+    }
+
+    fn map_texture<'a>(
+        &mut self,
+        texture_mapping: &'a Self::TextureMapping,
+    ) -> Result<&'a [u8], Self::Error> {
+        Ok(&texture_mapping.data)
+    }
+}
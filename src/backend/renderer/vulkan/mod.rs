@@ -0,0 +1,2708 @@
+//! Implementation of the rendering traits using Vulkan
+//!
+//! The [`VulkanRenderer`] is created from a [`PhysicalDevice`] and creates its own logical device
+//! with a single graphics queue.
+//!
+//! The renderer has no default framebuffer. It can render into [`Dmabuf`]s (imported using
+//! `VK_EXT_image_drm_format_modifier`) and into offscreen [`VulkanTexture`]s.
+//! Rendering is submitted on [`Frame::finish`] or at the end of [`Renderer::render`] and waited upon
+//! before the call returns, so a bound dmabuf may be handed to other apis (e.g. for scan-out) right away.
+//! Accordingly [`Frame::finish`] always returns an already reached [`SyncPoint`].
+//!
+//! The renderer requires Vulkan 1.1 and the following device extensions (and their dependencies):
+//! - `VK_EXT_image_drm_format_modifier`
+//! - `VK_EXT_external_memory_dmabuf`
+//! - `VK_KHR_external_memory_fd`
+//!
+//! Additionally the renderer enables `VK_EXT_queue_family_foreign` if available.
+//!
+//! To get the required extensions a device must support, use [`VulkanRenderer::required_extensions`].
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use smithay::backend::{
+//! #     renderer::{vulkan::VulkanRenderer, Bind, ExportMem, Frame, Offscreen, Renderer},
+//! #     vulkan::{version::Version, Instance, PhysicalDevice},
+//! # };
+//! # use smithay::utils::{Rectangle, Transform};
+//! let instance = Instance::new(Version::VERSION_1_3, None, None).unwrap();
+//! let phd = PhysicalDevice::enumerate(&instance).unwrap().next().unwrap();
+//! let mut renderer = VulkanRenderer::new(&phd, None).unwrap();
+//!
+//! let texture = Offscreen::<smithay::backend::renderer::vulkan::VulkanTexture>::create_buffer(
+//!     &mut renderer,
+//!     (64, 64).into(),
+//! )
+//! .unwrap();
+//! renderer.bind(texture).unwrap();
+//! renderer
+//!     .render((64, 64).into(), Transform::Normal, |_, frame| {
+//!         frame.clear([1.0, 0.0, 0.0, 1.0], &[Rectangle::from_loc_and_size((0, 0), (64, 64))])
+//!     })
+//!     .unwrap()
+//!     .unwrap();
+//!
+//! let mapping = renderer
+//!     .copy_framebuffer(Rectangle::from_loc_and_size((0, 0), (64, 64)))
+//!     .unwrap();
+//! let pixels = renderer.map_texture(&mapping).unwrap();
+//! assert_eq!(&pixels[0..4], &[255, 0, 0, 255]);
+//! ```
+
+#![deny(unsafe_op_in_unsafe_fn)]
+
+use std::{
+    cell::Cell,
+    collections::{HashMap, HashSet},
+    ffi::CStr,
+    fmt,
+    io::Cursor,
+    mem,
+    os::unix::io::{AsRawFd, FromRawFd},
+    rc::Rc,
+    sync::mpsc::{channel, Receiver, Sender},
+};
+
+use ash::{
+    extensions::{ext, khr},
+    vk,
+};
+use cgmath::{prelude::*, Matrix3, Vector2};
+use io_lifetimes::OwnedFd;
+use slog::{debug, info, o, trace};
+
+use super::{
+    sync::SyncPoint, Bind, ExportDma, ExportMem, Frame, ImportDma, ImportMem, Offscreen, Renderer, Texture,
+    TextureFilter, TextureMapping, Unbind,
+};
+#[cfg(feature = "wayland_frontend")]
+use super::{ImportDmaWl, ImportMemWl};
+use crate::backend::{
+    allocator::{
+        dmabuf::{Dmabuf, DmabufFlags, WeakDmabuf, MAX_PLANES},
+        Buffer, Format, Fourcc, Modifier,
+    },
+    vulkan::{version::Version, PhysicalDevice},
+    SwapBuffersError,
+};
+use crate::utils::{Buffer as BufferCoord, Physical, Rectangle, Size, Transform};
+#[cfg(feature = "wayland_frontend")]
+use wayland_server::protocol::{wl_buffer, wl_shm};
+
+crate::utils::ids::id_gen!(next_renderer_id, RENDERER_ID, RENDERER_IDS);
+
+/*
+ * SPIR-V compiled from the GLSL sources in the `shaders` directory, e.g. using
+ * `glslangValidator -V quad.vert -o quad.vert.spv`.
+ */
+const QUAD_VERT: &[u8] = include_bytes!("shaders/quad.vert.spv");
+const TEXTURE_FRAG: &[u8] = include_bytes!("shaders/texture.frag.spv");
+const SOLID_FRAG: &[u8] = include_bytes!("shaders/solid.frag.spv");
+
+/// Maximum amount of texture descriptor sets allocated from a single descriptor pool.
+const DESCRIPTOR_POOL_SIZE: u32 = 256;
+
+/// Usage of images created and owned by the renderer.
+const OWNED_IMAGE_USAGE: vk::ImageUsageFlags = vk::ImageUsageFlags::from_raw(
+    vk::ImageUsageFlags::SAMPLED.as_raw()
+        | vk::ImageUsageFlags::COLOR_ATTACHMENT.as_raw()
+        | vk::ImageUsageFlags::TRANSFER_SRC.as_raw()
+        | vk::ImageUsageFlags::TRANSFER_DST.as_raw(),
+);
+
+const COLOR_SUBRESOURCE_RANGE: vk::ImageSubresourceRange = vk::ImageSubresourceRange {
+    aspect_mask: vk::ImageAspectFlags::COLOR,
+    base_mip_level: 0,
+    level_count: 1,
+    base_array_layer: 0,
+    layer_count: 1,
+};
+
+/// Formats supported by the renderer and whether their alpha channel has to be ignored.
+///
+/// Unlike [`allocator::vulkan::format`](crate::backend::allocator::vulkan::format) this maps to `UNORM`
+/// formats, as blending happens without any color space conversion, just like with the other renderers.
+const FORMATS: &[(Fourcc, vk::Format, bool)] = &[
+    (Fourcc::Argb8888, vk::Format::B8G8R8A8_UNORM, false),
+    (Fourcc::Xrgb8888, vk::Format::B8G8R8A8_UNORM, true),
+    (Fourcc::Abgr8888, vk::Format::R8G8B8A8_UNORM, false),
+    (Fourcc::Xbgr8888, vk::Format::R8G8B8A8_UNORM, true),
+    (Fourcc::Argb2101010, vk::Format::A2R10G10B10_UNORM_PACK32, false),
+    (Fourcc::Xrgb2101010, vk::Format::A2R10G10B10_UNORM_PACK32, true),
+    (Fourcc::Abgr2101010, vk::Format::A2B10G10R10_UNORM_PACK32, false),
+    (Fourcc::Xbgr2101010, vk::Format::A2B10G10R10_UNORM_PACK32, true),
+];
+
+fn get_format(fourcc: Fourcc) -> Option<(vk::Format, bool)> {
+    FORMATS
+        .iter()
+        .find(|(code, _, _)| *code == fourcc)
+        .map(|(_, format, opaque)| (*format, *opaque))
+}
+
+/// Error returned during rendering using Vulkan
+#[derive(Debug, thiserror::Error)]
+pub enum VulkanError {
+    /// The physical device does not support Vulkan 1.1
+    #[error("The physical device does not support Vulkan 1.1")]
+    UnsupportedVersion,
+    /// A required device extension is not supported by the physical device
+    #[error("The required device extension {0:?} is not supported")]
+    MissingExtension(&'static CStr),
+    /// The physical device has no queue family supporting graphics operations
+    #[error("No queue family with graphics support found")]
+    NoGraphicsQueue,
+    /// No memory type matching the requirements of a resource was found
+    #[error("No suitable memory type found")]
+    NoSuitableMemoryType,
+    /// A rendering or readback operation was issued without a bound target
+    #[error("No framebuffer is bound")]
+    NoTarget,
+    /// The given format and modifier combination is not supported
+    #[error("Unsupported format: {0:?}")]
+    UnsupportedFormat(Format),
+    /// The planes of the given dmabuf do not share the same memory object
+    #[error("Dmabufs with disjoint planes are not supported")]
+    DisjointDmabuf,
+    /// The given buffer has an unsupported pixel format
+    #[error("Unsupported pixel format: {0:?}")]
+    #[cfg(feature = "wayland_frontend")]
+    UnsupportedPixelFormat(wl_shm::Format),
+    /// The given buffer was not accessible
+    #[error("Error accessing the buffer ({0:?})")]
+    #[cfg(feature = "wayland_frontend")]
+    BufferAccessError(crate::wayland::shm::BufferAccessError),
+    /// The operation is not supported by the given texture or framebuffer
+    ///
+    /// E.g. updating the contents of an imported dmabuf or exporting a texture that was not
+    /// created from a dmabuf.
+    #[error("The operation is not supported by the texture or framebuffer")]
+    UnsupportedOperation,
+    /// The provided buffer's size did not match the requested one.
+    #[error("Error reading buffer, size is too small for the given dimensions")]
+    UnexpectedSize,
+    /// Some error from the Vulkan driver
+    #[error(transparent)]
+    Vk(#[from] vk::Result),
+}
+
+impl From<VulkanError> for SwapBuffersError {
+    fn from(err: VulkanError) -> SwapBuffersError {
+        match err {
+            x @ VulkanError::UnsupportedVersion
+            | x @ VulkanError::MissingExtension(_)
+            | x @ VulkanError::NoGraphicsQueue
+            | x @ VulkanError::Vk(vk::Result::ERROR_DEVICE_LOST) => {
+                SwapBuffersError::ContextLost(Box::new(x))
+            }
+            x => SwapBuffersError::TemporaryFailure(Box::new(x)),
+        }
+    }
+}
+
+/// Vulkan objects backing a texture or render target
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ImageResources {
+    image: vk::Image,
+    view: vk::ImageView,
+    memory: vk::DeviceMemory,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+}
+
+impl ImageResources {
+    const fn null() -> ImageResources {
+        ImageResources {
+            image: vk::Image::null(),
+            view: vk::ImageView::null(),
+            memory: vk::DeviceMemory::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_set: vk::DescriptorSet::null(),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The resources must have been created from `device` and must not be in use anymore.
+    unsafe fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            if self.descriptor_set != vk::DescriptorSet::null() {
+                let _ = device.free_descriptor_sets(self.descriptor_pool, &[self.descriptor_set]);
+            }
+            device.destroy_image_view(self.view, None);
+            device.destroy_image(self.image, None);
+            device.free_memory(self.memory, None);
+        }
+    }
+}
+
+/// State of an image backed by a dmabuf.
+///
+/// In between uses these images are owned by the foreign queue family (the rest of the system).
+#[derive(Debug)]
+struct DmabufImage {
+    buffer: WeakDmabuf,
+    acquired: Cell<bool>,
+}
+
+/// A handle to a Vulkan texture
+#[derive(Debug, Clone)]
+pub struct VulkanTexture(Rc<VulkanTextureInternal>);
+
+#[derive(Debug)]
+struct VulkanTextureInternal {
+    resources: ImageResources,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+    opaque: bool,
+    y_inverted: bool,
+    size: Size<i32, BufferCoord>,
+    dmabuf: Option<DmabufImage>,
+    destruction_callback_sender: Sender<ImageResources>,
+}
+
+impl VulkanTexture {
+    /// Vulkan image of this texture
+    ///
+    /// The handle will become invalid, when the `VulkanTexture` is dropped.
+    pub fn image(&self) -> vk::Image {
+        self.0.resources.image
+    }
+
+    /// Vulkan format of this texture
+    pub fn format(&self) -> vk::Format {
+        self.0.format
+    }
+}
+
+impl PartialEq for VulkanTexture {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Drop for VulkanTextureInternal {
+    fn drop(&mut self) {
+        let _ = self.destruction_callback_sender.send(self.resources);
+    }
+}
+
+impl Texture for VulkanTexture {
+    fn width(&self) -> u32 {
+        self.0.size.w as u32
+    }
+    fn height(&self) -> u32 {
+        self.0.size.h as u32
+    }
+    fn size(&self) -> Size<i32, BufferCoord> {
+        self.0.size
+    }
+}
+
+impl VulkanTextureInternal {
+    /// Barrier transitioning the image from its resting state into `layout`.
+    fn acquire(
+        &self,
+        queue_family: u32,
+        foreign_queue_family: u32,
+        layout: vk::ImageLayout,
+    ) -> vk::ImageMemoryBarrier {
+        match self.dmabuf {
+            Some(ref dmabuf) => {
+                // The contents of a freshly imported image are defined by the foreign queue.
+                let old_layout = if dmabuf.acquired.replace(true) {
+                    vk::ImageLayout::GENERAL
+                } else {
+                    vk::ImageLayout::UNDEFINED
+                };
+                image_barrier(
+                    self.resources.image,
+                    old_layout,
+                    layout,
+                    foreign_queue_family,
+                    queue_family,
+                )
+            }
+            None => image_barrier(
+                self.resources.image,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                layout,
+                vk::QUEUE_FAMILY_IGNORED,
+                vk::QUEUE_FAMILY_IGNORED,
+            ),
+        }
+    }
+
+    /// Barrier transitioning the image from `layout` back into its resting state.
+    fn release(
+        &self,
+        queue_family: u32,
+        foreign_queue_family: u32,
+        layout: vk::ImageLayout,
+    ) -> vk::ImageMemoryBarrier {
+        match self.dmabuf {
+            Some(_) => image_barrier(
+                self.resources.image,
+                layout,
+                vk::ImageLayout::GENERAL,
+                queue_family,
+                foreign_queue_family,
+            ),
+            None => image_barrier(
+                self.resources.image,
+                layout,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::QUEUE_FAMILY_IGNORED,
+                vk::QUEUE_FAMILY_IGNORED,
+            ),
+        }
+    }
+}
+
+fn image_barrier(
+    image: vk::Image,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src_queue_family: u32,
+    dst_queue_family: u32,
+) -> vk::ImageMemoryBarrier {
+    vk::ImageMemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::MEMORY_WRITE)
+        .dst_access_mask(vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE)
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_queue_family_index(src_queue_family)
+        .dst_queue_family_index(dst_queue_family)
+        .image(image)
+        .subresource_range(COLOR_SUBRESOURCE_RANGE)
+        .build()
+}
+
+/// Texture mapping of a Vulkan texture
+///
+/// The contents are downloaded and converted to RGBA8 when the mapping is created.
+#[derive(Debug)]
+pub struct VulkanMapping {
+    data: Vec<u8>,
+    size: Size<i32, BufferCoord>,
+}
+
+impl Texture for VulkanMapping {
+    fn width(&self) -> u32 {
+        self.size.w as u32
+    }
+    fn height(&self) -> u32 {
+        self.size.h as u32
+    }
+    fn size(&self) -> Size<i32, BufferCoord> {
+        self.size
+    }
+}
+
+impl TextureMapping for VulkanMapping {
+    fn flipped(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug)]
+struct VulkanTarget {
+    texture: VulkanTexture,
+    framebuffer: Rc<Framebuffer>,
+    dmabuf: Option<Dmabuf>,
+}
+
+/// Framebuffer of a bound target
+///
+/// It is shared with the frames rendering to it, so it stays alive even if the target is
+/// unbound during [`Renderer::render`], until the recorded commands were submitted.
+struct Framebuffer {
+    handle: vk::Framebuffer,
+    device: ash::Device,
+}
+
+impl fmt::Debug for Framebuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Framebuffer").field(&self.handle).finish()
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        // SAFETY: All rendering to the framebuffer has been waited upon
+        unsafe { self.device.destroy_framebuffer(self.handle, None) };
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RenderSetup {
+    render_pass: vk::RenderPass,
+    texture_pipeline: vk::Pipeline,
+    solid_pipeline: vk::Pipeline,
+}
+
+#[derive(Debug)]
+struct FormatEntry {
+    format: Format,
+    vk_format: vk::Format,
+    opaque: bool,
+    plane_count: u32,
+    /// Usage to import dmabufs of this format with for sampling, if supported
+    texture_usage: Option<vk::ImageUsageFlags>,
+    /// Usage to import dmabufs of this format with for rendering, if supported
+    render_usage: Option<vk::ImageUsageFlags>,
+}
+
+struct ExtensionFns {
+    ext_image_format_modifier: ext::ImageDrmFormatModifier,
+    khr_external_memory_fd: khr::ExternalMemoryFd,
+}
+
+/// Host visible buffer used for uploads and downloads
+struct HostBuffer {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct PushConstants {
+    matrix: [[f32; 4]; 3],
+    tex_matrix: [[f32; 4]; 3],
+    position: [f32; 4],
+    color: [f32; 4],
+}
+
+impl PushConstants {
+    fn new(matrix: Matrix3<f32>, tex_matrix: Matrix3<f32>, position: [f32; 4], color: [f32; 4]) -> Self {
+        fn columns(matrix: Matrix3<f32>) -> [[f32; 4]; 3] {
+            [
+                [matrix.x.x, matrix.x.y, matrix.x.z, 0.0],
+                [matrix.y.x, matrix.y.y, matrix.y.z, 0.0],
+                [matrix.z.x, matrix.z.y, matrix.z.z, 0.0],
+            ]
+        }
+
+        PushConstants {
+            matrix: columns(matrix),
+            tex_matrix: columns(tex_matrix),
+            position,
+            color,
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        // SAFETY: PushConstants is `repr(C)` and only consists of f32s, so there is no padding.
+        unsafe { std::slice::from_raw_parts(self as *const _ as *const u8, mem::size_of::<Self>()) }
+    }
+}
+
+struct RendererId(usize);
+impl Drop for RendererId {
+    fn drop(&mut self) {
+        RENDERER_IDS.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// A renderer utilizing Vulkan
+pub struct VulkanRenderer {
+    target: Option<VulkanTarget>,
+    buffers: HashMap<WeakDmabuf, VulkanTexture>,
+    dmabuf_cache: HashMap<WeakDmabuf, VulkanTexture>,
+    formats: Vec<FormatEntry>,
+    dmabuf_formats: Vec<Format>,
+    render_formats: HashSet<Format>,
+    render_setups: HashMap<vk::Format, RenderSetup>,
+    descriptor_pools: Vec<vk::DescriptorPool>,
+    sampler_pool: vk::DescriptorPool,
+    sampler_sets: [vk::DescriptorSet; 4],
+    samplers: [vk::Sampler; 4],
+    texture_set_layout: vk::DescriptorSetLayout,
+    sampler_set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    shaders: [vk::ShaderModule; 3],
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+    queue: vk::Queue,
+    queue_family_index: u32,
+    foreign_queue_family: u32,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    extension_fns: ExtensionFns,
+    destruction_callback: Receiver<ImageResources>,
+    destruction_callback_sender: Sender<ImageResources>,
+    min_filter: TextureFilter,
+    max_filter: TextureFilter,
+    renderer_id: RendererId,
+    phd: PhysicalDevice,
+    device: ash::Device,
+    logger: ::slog::Logger,
+}
+
+impl fmt::Debug for VulkanRenderer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VulkanRenderer")
+            .field("target", &self.target)
+            .field("buffers", &self.buffers)
+            .field("dmabuf_formats", &self.dmabuf_formats)
+            .field("render_formats", &self.render_formats)
+            .field("phd", &self.phd)
+            .field("min_filter", &self.min_filter)
+            .field("max_filter", &self.max_filter)
+            .field("logger", &self.logger)
+            .finish_non_exhaustive()
+    }
+}
+
+impl VulkanRenderer {
+    /// Returns the list of device extensions required by the Vulkan renderer.
+    ///
+    /// This function may return a different list for each [`PhysicalDevice`], meaning each device should be
+    /// filtered using it's own call to this function.
+    pub fn required_extensions(phd: &PhysicalDevice) -> Vec<&'static CStr> {
+        let mut extensions = vec![
+            vk::ExtImageDrmFormatModifierFn::name(),
+            vk::ExtExternalMemoryDmaBufFn::name(),
+            vk::KhrExternalMemoryFdFn::name(),
+        ];
+
+        if phd.api_version() < Version::VERSION_1_2 {
+            // VK_EXT_image_drm_format_modifier requires VK_KHR_image_format_list.
+            // VK_KHR_image_format_list is part of the core API in Vulkan 1.2
+            extensions.push(vk::KhrImageFormatListFn::name());
+        }
+
+        extensions
+    }
+
+    /// Creates a new Vulkan renderer from a given [`PhysicalDevice`].
+    ///
+    /// # Implementation details
+    ///
+    /// - Texture handles created by the resulting renderer are only valid for this renderer.
+    /// - This renderer has no default framebuffer, use `Bind::bind` before rendering.
+    /// - Binding a new target, while another one is already bound, will replace the current target.
+    /// - Shm buffers can be released after a successful import, without the texture handle becoming invalid.
+    /// - Dmabufs have to be kept alive as long as the texture imported from them is used.
+    /// - Texture filtering starts with Linear-downscaling and Linear-upscaling
+    pub fn new<L>(phd: &PhysicalDevice, logger: L) -> Result<VulkanRenderer, VulkanError>
+    where
+        L: Into<Option<::slog::Logger>>,
+    {
+        let log = crate::slog_or_fallback(logger).new(o!("smithay_module" => "renderer_vulkan"));
+
+        info!(log, "Initializing Vulkan Renderer on {}", phd.name());
+
+        if phd.api_version() < Version::VERSION_1_1 || phd.instance().api_version() < Version::VERSION_1_1 {
+            return Err(VulkanError::UnsupportedVersion);
+        }
+
+        let mut extensions = Self::required_extensions(phd);
+        if let Some(missing) = extensions.iter().find(|ext| !phd.has_device_extension(ext)) {
+            return Err(VulkanError::MissingExtension(missing));
+        }
+        // Allows to hand images back to the rest of the system instead of another Vulkan instance.
+        let foreign_queue_family = if phd.has_device_extension(vk::ExtQueueFamilyForeignFn::name()) {
+            extensions.push(vk::ExtQueueFamilyForeignFn::name());
+            vk::QUEUE_FAMILY_FOREIGN_EXT
+        } else {
+            vk::QUEUE_FAMILY_EXTERNAL
+        };
+        debug!(log, "Enabled device extensions: {:?}", extensions);
+        let extension_pointers = extensions.iter().copied().map(CStr::as_ptr).collect::<Vec<_>>();
+
+        let instance = phd.instance().handle();
+        let queue_family_index = unsafe { instance.get_physical_device_queue_family_properties(phd.handle()) }
+            .iter()
+            .position(|properties| properties.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+            .ok_or(VulkanError::NoGraphicsQueue)? as u32;
+
+        let queue_create_info = [vk::DeviceQueueCreateInfo::builder()
+            .queue_family_index(queue_family_index)
+            .queue_priorities(&[1.0])
+            .build()];
+        let create_info = vk::DeviceCreateInfo::builder()
+            .enabled_extension_names(&extension_pointers)
+            .queue_create_infos(&queue_create_info);
+        let device = unsafe { instance.create_device(phd.handle(), &create_info, None) }?;
+
+        let extension_fns = ExtensionFns {
+            ext_image_format_modifier: ext::ImageDrmFormatModifier::new(instance, &device),
+            khr_external_memory_fd: khr::ExternalMemoryFd::new(instance, &device),
+        };
+        let queue = unsafe { device.get_device_queue(queue_family_index, 0) };
+        let memory_properties = unsafe { instance.get_physical_device_memory_properties(phd.handle()) };
+        let (tx, rx) = channel();
+
+        // Every handle starts out null, which allows `Drop` to clean up a partially initialized renderer.
+        let mut renderer = VulkanRenderer {
+            target: None,
+            buffers: HashMap::new(),
+            dmabuf_cache: HashMap::new(),
+            formats: Vec::new(),
+            dmabuf_formats: Vec::new(),
+            render_formats: HashSet::new(),
+            render_setups: HashMap::new(),
+            descriptor_pools: Vec::new(),
+            sampler_pool: vk::DescriptorPool::null(),
+            sampler_sets: [vk::DescriptorSet::null(); 4],
+            samplers: [vk::Sampler::null(); 4],
+            texture_set_layout: vk::DescriptorSetLayout::null(),
+            sampler_set_layout: vk::DescriptorSetLayout::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            shaders: [vk::ShaderModule::null(); 3],
+            command_pool: vk::CommandPool::null(),
+            command_buffer: vk::CommandBuffer::null(),
+            fence: vk::Fence::null(),
+            queue,
+            queue_family_index,
+            foreign_queue_family,
+            memory_properties,
+            extension_fns,
+            destruction_callback: rx,
+            destruction_callback_sender: tx,
+            min_filter: TextureFilter::Linear,
+            max_filter: TextureFilter::Linear,
+            renderer_id: RendererId(next_renderer_id()),
+            phd: phd.clone(),
+            device,
+            logger: log,
+        };
+
+        unsafe { renderer.init() }?;
+        renderer.init_formats();
+
+        Ok(renderer)
+    }
+
+    /// Returns the [`PhysicalDevice`] this renderer was created with.
+    pub fn physical_device(&self) -> &PhysicalDevice {
+        &self.phd
+    }
+
+    /// Returns the logical device used by this renderer.
+    pub fn device(&self) -> &ash::Device {
+        &self.device
+    }
+
+    unsafe fn init(&mut self) -> Result<(), vk::Result> {
+        let device = &self.device;
+
+        let pool_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .queue_family_index(self.queue_family_index);
+        self.command_pool = unsafe { device.create_command_pool(&pool_info, None) }?;
+        let buffer_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(self.command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+        self.command_buffer = unsafe { device.allocate_command_buffers(&buffer_info) }?[0];
+        self.fence = unsafe { device.create_fence(&vk::FenceCreateInfo::default(), None) }?;
+
+        for (module, code) in self.shaders.iter_mut().zip([QUAD_VERT, TEXTURE_FRAG, SOLID_FRAG]) {
+            let code = ash::util::read_spv(&mut Cursor::new(code)).expect("Invalid embedded SPIR-V");
+            let info = vk::ShaderModuleCreateInfo::builder().code(&code);
+            *module = unsafe { device.create_shader_module(&info, None) }?;
+        }
+
+        // Textures and samplers are bound separately, so the filters can change without touching the
+        // descriptor sets of any texture.
+        let texture_binding = [vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build()];
+        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&texture_binding);
+        self.texture_set_layout = unsafe { device.create_descriptor_set_layout(&info, None) }?;
+        let sampler_binding = [vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build()];
+        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&sampler_binding);
+        self.sampler_set_layout = unsafe { device.create_descriptor_set_layout(&info, None) }?;
+
+        let set_layouts = [self.texture_set_layout, self.sampler_set_layout];
+        let push_constant_ranges = [vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            offset: 0,
+            size: mem::size_of::<PushConstants>() as u32,
+        }];
+        let info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
+        self.pipeline_layout = unsafe { device.create_pipeline_layout(&info, None) }?;
+
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::SAMPLER,
+            descriptor_count: self.samplers.len() as u32,
+        }];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(self.samplers.len() as u32)
+            .pool_sizes(&pool_sizes);
+        self.sampler_pool = unsafe { device.create_descriptor_pool(&info, None) }?;
+        let layouts = [self.sampler_set_layout; 4];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.sampler_pool)
+            .set_layouts(&layouts);
+        let sets = unsafe { device.allocate_descriptor_sets(&info) }?;
+        self.sampler_sets.copy_from_slice(&sets);
+
+        for min_filter in [TextureFilter::Linear, TextureFilter::Nearest] {
+            for max_filter in [TextureFilter::Linear, TextureFilter::Nearest] {
+                let idx = sampler_index(min_filter, max_filter);
+                let info = vk::SamplerCreateInfo::builder()
+                    .min_filter(vk_filter(min_filter))
+                    .mag_filter(vk_filter(max_filter))
+                    .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .max_lod(0.25);
+                self.samplers[idx] = unsafe { device.create_sampler(&info, None) }?;
+
+                let image_info = [vk::DescriptorImageInfo::builder()
+                    .sampler(self.samplers[idx])
+                    .build()];
+                let write = vk::WriteDescriptorSet::builder()
+                    .dst_set(self.sampler_sets[idx])
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::SAMPLER)
+                    .image_info(&image_info)
+                    .build();
+                unsafe { device.update_descriptor_sets(&[write], &[]) };
+            }
+        }
+
+        Ok(())
+    }
+
+    fn init_formats(&mut self) {
+        for &(fourcc, vk_format, opaque) in FORMATS {
+            let modifiers = match self.phd.get_format_modifier_properties(vk_format) {
+                Ok(modifiers) => modifiers,
+                Err(_) => continue,
+            };
+
+            for properties in modifiers {
+                let format = Format {
+                    code: fourcc,
+                    modifier: Modifier::from(properties.drm_format_modifier),
+                };
+                let features = properties.drm_format_modifier_tiling_features;
+
+                let texture_usage = features
+                    .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE)
+                    .then(|| self.modifier_usage(vk_format, format.modifier, vk::ImageUsageFlags::SAMPLED))
+                    .flatten();
+                let render_usage = features
+                    .contains(
+                        vk::FormatFeatureFlags::COLOR_ATTACHMENT
+                            | vk::FormatFeatureFlags::COLOR_ATTACHMENT_BLEND,
+                    )
+                    .then(|| {
+                        self.modifier_usage(vk_format, format.modifier, vk::ImageUsageFlags::COLOR_ATTACHMENT)
+                    })
+                    .flatten();
+
+                if texture_usage.is_some() {
+                    self.dmabuf_formats.push(format);
+                }
+                if render_usage.is_some() {
+                    self.render_formats.insert(format);
+                }
+                self.formats.push(FormatEntry {
+                    format,
+                    vk_format,
+                    opaque,
+                    plane_count: properties.drm_format_modifier_plane_count,
+                    texture_usage,
+                    render_usage,
+                });
+            }
+        }
+
+        debug!(
+            self.logger,
+            "Supported dmabuf texture formats: {:?}", self.dmabuf_formats
+        );
+        debug!(
+            self.logger,
+            "Supported dmabuf render formats: {:?}", self.render_formats
+        );
+    }
+
+    /// Returns the usage dmabufs with the given format may be imported with.
+    ///
+    /// `TRANSFER_SRC` is added to the `usage`, if supported, to allow reading the buffer back.
+    fn modifier_usage(
+        &self,
+        vk_format: vk::Format,
+        modifier: Modifier,
+        usage: vk::ImageUsageFlags,
+    ) -> Option<vk::ImageUsageFlags> {
+        let supported = |usage: vk::ImageUsageFlags| {
+            let mut external_info = vk::PhysicalDeviceExternalImageFormatInfo::builder()
+                .handle_type(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT);
+            let mut modifier_info = vk::PhysicalDeviceImageDrmFormatModifierInfoEXT::builder()
+                .drm_format_modifier(modifier.into())
+                .sharing_mode(vk::SharingMode::EXCLUSIVE);
+            let format_info = vk::PhysicalDeviceImageFormatInfo2::builder()
+                .format(vk_format)
+                .ty(vk::ImageType::TYPE_2D)
+                .tiling(vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT)
+                .usage(usage)
+                // VUID-VkPhysicalDeviceImageFormatInfo2-tiling-02249
+                .push_next(&mut modifier_info)
+                .push_next(&mut external_info);
+            let mut external_properties = vk::ExternalImageFormatProperties::default();
+            let mut properties = vk::ImageFormatProperties2::builder().push_next(&mut external_properties);
+
+            let result = unsafe {
+                self.phd
+                    .instance()
+                    .handle()
+                    .get_physical_device_image_format_properties2(
+                        self.phd.handle(),
+                        &format_info,
+                        &mut properties,
+                    )
+            };
+
+            result.is_ok()
+                && external_properties
+                    .external_memory_properties
+                    .external_memory_features
+                    .contains(vk::ExternalMemoryFeatureFlags::IMPORTABLE)
+        };
+
+        if supported(usage | vk::ImageUsageFlags::TRANSFER_SRC) {
+            Some(usage | vk::ImageUsageFlags::TRANSFER_SRC)
+        } else if supported(usage) {
+            Some(usage)
+        } else {
+            None
+        }
+    }
+
+    fn cleanup(&mut self) {
+        self.dmabuf_cache.retain(|entry, _| !entry.is_gone());
+        self.buffers.retain(|entry, _| !entry.is_gone());
+        for resources in self.destruction_callback.try_iter() {
+            // SAFETY: All submitted work is waited upon, so the resources cannot be in use anymore.
+            unsafe { resources.destroy(&self.device) };
+        }
+    }
+
+    fn find_memory_type(&self, type_bits: u32, flags: vk::MemoryPropertyFlags) -> Option<u32> {
+        (0..self.memory_properties.memory_type_count).find(|&idx| {
+            type_bits & (1 << idx) != 0
+                && self.memory_properties.memory_types[idx as usize]
+                    .property_flags
+                    .contains(flags)
+        })
+    }
+
+    /// Records commands using `record` and waits for their execution.
+    fn submit<F>(&self, record: F) -> Result<(), VulkanError>
+    where
+        F: FnOnce(&ash::Device, vk::CommandBuffer),
+    {
+        submit_commands(&self.device, self.queue, self.command_buffer, self.fence, record)
+    }
+
+    fn allocate_descriptor_set(&mut self) -> Result<(vk::DescriptorPool, vk::DescriptorSet), VulkanError> {
+        let layouts = [self.texture_set_layout];
+
+        // Sets of destroyed textures are freed back into their pool, so older pools are reused
+        // before allocating a new one. The pool last allocated from is kept at the end.
+        for idx in (0..self.descriptor_pools.len()).rev() {
+            let pool = self.descriptor_pools[idx];
+            let info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts);
+            match unsafe { self.device.allocate_descriptor_sets(&info) } {
+                Ok(sets) => {
+                    let last = self.descriptor_pools.len() - 1;
+                    self.descriptor_pools.swap(idx, last);
+                    return Ok((pool, sets[0]));
+                }
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY) | Err(vk::Result::ERROR_FRAGMENTED_POOL) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        // All pools are exhausted, start a new one
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::SAMPLED_IMAGE,
+            descriptor_count: DESCRIPTOR_POOL_SIZE,
+        }];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+            .max_sets(DESCRIPTOR_POOL_SIZE)
+            .pool_sizes(&pool_sizes);
+        let pool = unsafe { self.device.create_descriptor_pool(&info, None) }?;
+        self.descriptor_pools.push(pool);
+
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pool)
+            .set_layouts(&layouts);
+        let sets = unsafe { self.device.allocate_descriptor_sets(&info) }?;
+        Ok((pool, sets[0]))
+    }
+
+    /// Creates the view and, if the image may be sampled, the descriptor set for an image with bound memory.
+    fn finish_image(
+        &mut self,
+        resources: &mut ImageResources,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+    ) -> Result<(), VulkanError> {
+        let info = vk::ImageViewCreateInfo::builder()
+            .image(resources.image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .subresource_range(COLOR_SUBRESOURCE_RANGE);
+        resources.view = unsafe { self.device.create_image_view(&info, None) }?;
+
+        if usage.contains(vk::ImageUsageFlags::SAMPLED) {
+            let (pool, set) = self.allocate_descriptor_set()?;
+            resources.descriptor_pool = pool;
+            resources.descriptor_set = set;
+
+            let image_info = [vk::DescriptorImageInfo::builder()
+                .image_view(resources.view)
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .build()];
+            let write = vk::WriteDescriptorSet::builder()
+                .dst_set(set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .image_info(&image_info)
+                .build();
+            unsafe { self.device.update_descriptor_sets(&[write], &[]) };
+        }
+
+        Ok(())
+    }
+
+    /// Creates a texture with memory owned by the renderer.
+    ///
+    /// The image is left in an undefined layout, the caller is responsible for transitioning it into
+    /// `SHADER_READ_ONLY_OPTIMAL`.
+    fn create_texture(
+        &mut self,
+        size: Size<i32, BufferCoord>,
+        format: vk::Format,
+        opaque: bool,
+        y_inverted: bool,
+    ) -> Result<VulkanTexture, VulkanError> {
+        let limits = self.phd.limits();
+        if size.w <= 0
+            || size.h <= 0
+            || size.w as u32 > limits.max_framebuffer_width
+            || size.h as u32 > limits.max_framebuffer_height
+        {
+            return Err(VulkanError::UnexpectedSize);
+        }
+
+        let info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: size.w as u32,
+                height: size.h as u32,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(OWNED_IMAGE_USAGE)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+
+        let device = self.device.clone();
+        let mut guard = scopeguard::guard(ImageResources::null(), |resources| unsafe {
+            resources.destroy(&device)
+        });
+        guard.image = unsafe { self.device.create_image(&info, None) }?;
+
+        let requirements = unsafe { self.device.get_image_memory_requirements(guard.image) };
+        let memory_type = self
+            .find_memory_type(
+                requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+            .or_else(|| {
+                self.find_memory_type(requirements.memory_type_bits, vk::MemoryPropertyFlags::empty())
+            })
+            .ok_or(VulkanError::NoSuitableMemoryType)?;
+        let info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type);
+        guard.memory = unsafe { self.device.allocate_memory(&info, None) }?;
+        unsafe { self.device.bind_image_memory(guard.image, guard.memory, 0) }?;
+
+        self.finish_image(&mut guard, format, OWNED_IMAGE_USAGE)?;
+
+        Ok(VulkanTexture(Rc::new(VulkanTextureInternal {
+            resources: scopeguard::ScopeGuard::into_inner(guard),
+            format,
+            usage: OWNED_IMAGE_USAGE,
+            opaque,
+            y_inverted,
+            size,
+            dmabuf: None,
+            destruction_callback_sender: self.destruction_callback_sender.clone(),
+        })))
+    }
+
+    /// Imports a dmabuf as a Vulkan image.
+    fn import_dmabuf_image(&mut self, buffer: &Dmabuf, render: bool) -> Result<VulkanTexture, VulkanError> {
+        let format = buffer.format();
+        let entry = self
+            .formats
+            .iter()
+            .find(|entry| entry.format == format)
+            .ok_or(VulkanError::UnsupportedFormat(format))?;
+        let usage = if render {
+            entry.render_usage
+        } else {
+            entry.texture_usage
+        }
+        .ok_or(VulkanError::UnsupportedFormat(format))?;
+        let (vk_format, opaque) = (entry.vk_format, entry.opaque);
+
+        if entry.plane_count as usize != buffer.num_planes() {
+            return Err(VulkanError::UnsupportedFormat(format));
+        }
+        // All planes have to reside in the same memory object
+        let stat = nix::sys::stat::fstat(buffer.handles().next().unwrap().as_raw_fd())
+            .map_err(|_| VulkanError::DisjointDmabuf)?;
+        for handle in buffer.handles().skip(1) {
+            match nix::sys::stat::fstat(handle.as_raw_fd()) {
+                Ok(plane) if plane.st_dev == stat.st_dev && plane.st_ino == stat.st_ino => {}
+                _ => return Err(VulkanError::DisjointDmabuf),
+            }
+        }
+
+        let size = buffer.size();
+        let limits = self.phd.limits();
+        if size.w <= 0
+            || size.h <= 0
+            || (render
+                && (size.w as u32 > limits.max_framebuffer_width
+                    || size.h as u32 > limits.max_framebuffer_height))
+        {
+            return Err(VulkanError::UnexpectedSize);
+        }
+
+        trace!(self.logger, "Importing dmabuf: {:?}", buffer);
+
+        let plane_layouts = buffer
+            .offsets()
+            .zip(buffer.strides())
+            .map(|(offset, stride)| vk::SubresourceLayout {
+                offset: offset as u64,
+                size: 0,
+                row_pitch: stride as u64,
+                array_pitch: 0,
+                depth_pitch: 0,
+            })
+            .collect::<Vec<_>>();
+        let mut modifier_info = vk::ImageDrmFormatModifierExplicitCreateInfoEXT::builder()
+            .drm_format_modifier(format.modifier.into())
+            .plane_layouts(&plane_layouts);
+        let mut external_info = vk::ExternalMemoryImageCreateInfo::builder()
+            .handle_types(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT);
+        let info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(vk_format)
+            .extent(vk::Extent3D {
+                width: size.w as u32,
+                height: size.h as u32,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            // VUID-VkImageCreateInfo-pNext-02262
+            .tiling(vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            // VUID-VkImageCreateInfo-pNext-01443
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .push_next(&mut modifier_info)
+            .push_next(&mut external_info);
+
+        let device = self.device.clone();
+        let mut guard = scopeguard::guard(ImageResources::null(), |resources| unsafe {
+            resources.destroy(&device)
+        });
+        guard.image = unsafe { self.device.create_image(&info, None) }?;
+
+        // Vulkan takes ownership of the file descriptor on a successful import
+        let fd = nix::unistd::dup(buffer.handles().next().unwrap().as_raw_fd())
+            .map_err(|_| VulkanError::Vk(vk::Result::ERROR_TOO_MANY_OBJECTS))?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let fd_properties = unsafe {
+            self.extension_fns
+                .khr_external_memory_fd
+                .get_memory_fd_properties(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT, fd.as_raw_fd())
+        }?;
+        let requirements = unsafe { self.device.get_image_memory_requirements(guard.image) };
+        let memory_type = self
+            .find_memory_type(
+                requirements.memory_type_bits & fd_properties.memory_type_bits,
+                vk::MemoryPropertyFlags::empty(),
+            )
+            .ok_or(VulkanError::NoSuitableMemoryType)?;
+
+        let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::builder().image(guard.image);
+        let mut import_info = vk::ImportMemoryFdInfoKHR::builder()
+            .handle_type(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT)
+            .fd(fd.as_raw_fd());
+        let info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type)
+            .push_next(&mut dedicated_info)
+            .push_next(&mut import_info);
+        guard.memory = unsafe { self.device.allocate_memory(&info, None) }?;
+        // The import succeeded, the fd is now owned by the driver.
+        mem::forget(fd);
+        unsafe { self.device.bind_image_memory(guard.image, guard.memory, 0) }?;
+
+        self.finish_image(&mut guard, vk_format, usage)?;
+
+        Ok(VulkanTexture(Rc::new(VulkanTextureInternal {
+            resources: scopeguard::ScopeGuard::into_inner(guard),
+            format: vk_format,
+            usage,
+            opaque,
+            y_inverted: buffer.y_inverted(),
+            size,
+            dmabuf: Some(DmabufImage {
+                buffer: buffer.weak(),
+                acquired: Cell::new(false),
+            }),
+            destruction_callback_sender: self.destruction_callback_sender.clone(),
+        })))
+    }
+
+    /// Creates a new image, that is exported as a dmabuf and can be rendered into.
+    fn create_dmabuf(&mut self, size: Size<i32, BufferCoord>) -> Result<Dmabuf, VulkanError> {
+        let limits = self.phd.limits();
+        if size.w <= 0
+            || size.h <= 0
+            || size.w as u32 > limits.max_framebuffer_width
+            || size.h as u32 > limits.max_framebuffer_height
+        {
+            return Err(VulkanError::UnexpectedSize);
+        }
+
+        let fourcc = [Fourcc::Argb8888, Fourcc::Abgr8888]
+            .into_iter()
+            .find(|code| self.render_formats.iter().any(|format| format.code == *code))
+            .ok_or(VulkanError::UnsupportedFormat(Format {
+                code: Fourcc::Argb8888,
+                modifier: Modifier::Invalid,
+            }))?;
+        let entries = self
+            .formats
+            .iter()
+            .filter(|entry| entry.format.code == fourcc && entry.format.modifier != Modifier::Invalid)
+            .filter_map(|entry| entry.render_usage.map(|usage| (entry, usage)))
+            .collect::<Vec<_>>();
+        let modifiers = entries
+            .iter()
+            .map(|(entry, _)| entry.format.modifier.into())
+            .collect::<Vec<u64>>();
+        // Only use flags every modifier supports
+        let usage = entries.iter().fold(
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            |usage, (_, u)| usage & *u,
+        );
+        let (vk_format, opaque) = get_format(fourcc).unwrap();
+
+        let mut modifier_info =
+            vk::ImageDrmFormatModifierListCreateInfoEXT::builder().drm_format_modifiers(&modifiers);
+        let mut external_info = vk::ExternalMemoryImageCreateInfo::builder()
+            .handle_types(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT);
+        let info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(vk_format)
+            .extent(vk::Extent3D {
+                width: size.w as u32,
+                height: size.h as u32,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .push_next(&mut modifier_info)
+            .push_next(&mut external_info);
+
+        let device = self.device.clone();
+        let mut guard = scopeguard::guard(ImageResources::null(), |resources| unsafe {
+            resources.destroy(&device)
+        });
+        guard.image = unsafe { self.device.create_image(&info, None) }?;
+
+        let mut modifier_properties = vk::ImageDrmFormatModifierPropertiesEXT::default();
+        unsafe {
+            self.extension_fns
+                .ext_image_format_modifier
+                .get_image_drm_format_modifier_properties(guard.image, &mut modifier_properties)
+        }?;
+        let modifier = Modifier::from(modifier_properties.drm_format_modifier);
+        let plane_count = entries
+            .iter()
+            .find(|(entry, _)| entry.format.modifier == modifier)
+            .map(|(entry, _)| entry.plane_count)
+            .unwrap_or(1);
+        assert!(
+            plane_count as usize <= MAX_PLANES,
+            "Vulkan implementation reported too many planes"
+        );
+
+        let requirements = unsafe { self.device.get_image_memory_requirements(guard.image) };
+        let memory_type = self
+            .find_memory_type(
+                requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+            .or_else(|| {
+                self.find_memory_type(requirements.memory_type_bits, vk::MemoryPropertyFlags::empty())
+            })
+            .ok_or(VulkanError::NoSuitableMemoryType)?;
+        let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::builder().image(guard.image);
+        let mut export_info = vk::ExportMemoryAllocateInfo::builder()
+            .handle_types(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT);
+        let info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type)
+            .push_next(&mut dedicated_info)
+            .push_next(&mut export_info);
+        guard.memory = unsafe { self.device.allocate_memory(&info, None) }?;
+        unsafe { self.device.bind_image_memory(guard.image, guard.memory, 0) }?;
+
+        let mut builder = Dmabuf::builder(size, fourcc, DmabufFlags::empty());
+        for idx in 0..plane_count {
+            let aspect_mask = match idx {
+                0 => vk::ImageAspectFlags::MEMORY_PLANE_0_EXT,
+                1 => vk::ImageAspectFlags::MEMORY_PLANE_1_EXT,
+                2 => vk::ImageAspectFlags::MEMORY_PLANE_2_EXT,
+                3 => vk::ImageAspectFlags::MEMORY_PLANE_3_EXT,
+                _ => unreachable!(),
+            };
+            let subresource = vk::ImageSubresource::builder().aspect_mask(aspect_mask).build();
+            let layout = unsafe { self.device.get_image_subresource_layout(guard.image, subresource) };
+
+            // Every call creates a new file descriptor owned by the caller.
+            let info = vk::MemoryGetFdInfoKHR::builder()
+                .memory(guard.memory)
+                .handle_type(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT);
+            let fd = unsafe { self.extension_fns.khr_external_memory_fd.get_memory_fd(&info) }?;
+            builder.add_plane(
+                unsafe { OwnedFd::from_raw_fd(fd) },
+                idx,
+                layout.offset as u32,
+                layout.row_pitch as u32,
+                modifier,
+            );
+        }
+        let dmabuf = builder.build().unwrap();
+
+        self.finish_image(&mut guard, vk_format, usage)?;
+
+        let texture = VulkanTexture(Rc::new(VulkanTextureInternal {
+            resources: scopeguard::ScopeGuard::into_inner(guard),
+            format: vk_format,
+            usage,
+            opaque,
+            y_inverted: false,
+            size,
+            dmabuf: Some(DmabufImage {
+                buffer: dmabuf.weak(),
+                acquired: Cell::new(false),
+            }),
+            destruction_callback_sender: self.destruction_callback_sender.clone(),
+        }));
+        self.buffers.insert(dmabuf.weak(), texture);
+
+        Ok(dmabuf)
+    }
+
+    fn create_host_buffer(&self, size: u64, usage: vk::BufferUsageFlags) -> Result<HostBuffer, VulkanError> {
+        let info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let buffer = unsafe { self.device.create_buffer(&info, None) }?;
+        let buffer = scopeguard::guard(buffer, |buffer| unsafe {
+            self.device.destroy_buffer(buffer, None)
+        });
+
+        let requirements = unsafe { self.device.get_buffer_memory_requirements(*buffer) };
+        let memory_type = self
+            .find_memory_type(
+                requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )
+            .ok_or(VulkanError::NoSuitableMemoryType)?;
+        let info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type);
+        let memory = unsafe { self.device.allocate_memory(&info, None) }?;
+        if let Err(err) = unsafe { self.device.bind_buffer_memory(*buffer, memory, 0) } {
+            unsafe { self.device.free_memory(memory, None) };
+            return Err(err.into());
+        }
+
+        Ok(HostBuffer {
+            buffer: scopeguard::ScopeGuard::into_inner(buffer),
+            memory,
+        })
+    }
+
+    fn destroy_host_buffer(&self, buffer: HostBuffer) {
+        unsafe {
+            self.device.destroy_buffer(buffer.buffer, None);
+            self.device.free_memory(buffer.memory, None);
+        }
+    }
+
+    /// Uploads `regions` of `data` into a texture owned by the renderer.
+    ///
+    /// `data` starts at `offset` and has rows of `stride` bytes.
+    /// If `initial` is set, the previous content of the texture is discarded.
+    fn upload(
+        &self,
+        texture: &VulkanTexture,
+        data: &[u8],
+        offset: usize,
+        stride: usize,
+        regions: &[Rectangle<i32, BufferCoord>],
+        initial: bool,
+    ) -> Result<(), VulkanError> {
+        if texture.0.dmabuf.is_some() || !texture.0.usage.contains(vk::ImageUsageFlags::TRANSFER_DST) {
+            return Err(VulkanError::UnsupportedOperation);
+        }
+
+        let bounds = Rectangle::from_loc_and_size((0, 0), texture.size());
+        let regions = regions
+            .iter()
+            .filter_map(|region| region.intersection(bounds))
+            .filter(|region| !region.is_empty())
+            .collect::<Vec<_>>();
+        for region in &regions {
+            let end = offset
+                + (region.loc.y + region.size.h - 1) as usize * stride
+                + (region.loc.x + region.size.w) as usize * 4;
+            if end > data.len() {
+                return Err(VulkanError::UnexpectedSize);
+            }
+        }
+        if regions.is_empty() && !initial {
+            return Ok(());
+        }
+
+        let total = regions
+            .iter()
+            .map(|region| (region.size.w * region.size.h * 4) as u64)
+            .sum::<u64>();
+        let staging = self.create_host_buffer(total.max(4), vk::BufferUsageFlags::TRANSFER_SRC)?;
+        let staging = scopeguard::guard(staging, |staging| self.destroy_host_buffer(staging));
+
+        let mut copies = Vec::with_capacity(regions.len());
+        unsafe {
+            let ptr =
+                self.device
+                    .map_memory(staging.memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())?
+                    as *mut u8;
+            let mut buffer_offset = 0usize;
+            for region in &regions {
+                let row_len = region.size.w as usize * 4;
+                for row in 0..region.size.h as usize {
+                    let src = offset + (region.loc.y as usize + row) * stride + region.loc.x as usize * 4;
+                    std::ptr::copy_nonoverlapping(
+                        data[src..src + row_len].as_ptr(),
+                        ptr.add(buffer_offset + row * row_len),
+                        row_len,
+                    );
+                }
+                copies.push(
+                    vk::BufferImageCopy::builder()
+                        .buffer_offset(buffer_offset as u64)
+                        .image_subresource(vk::ImageSubresourceLayers {
+                            aspect_mask: vk::ImageAspectFlags::COLOR,
+                            mip_level: 0,
+                            base_array_layer: 0,
+                            layer_count: 1,
+                        })
+                        .image_offset(vk::Offset3D {
+                            x: region.loc.x,
+                            y: region.loc.y,
+                            z: 0,
+                        })
+                        .image_extent(vk::Extent3D {
+                            width: region.size.w as u32,
+                            height: region.size.h as u32,
+                            depth: 1,
+                        })
+                        .build(),
+                );
+                buffer_offset += row_len * region.size.h as usize;
+            }
+            self.device.unmap_memory(staging.memory);
+        }
+
+        let image = texture.image();
+        let old_layout = if initial {
+            vk::ImageLayout::UNDEFINED
+        } else {
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        };
+        self.submit(|device, command_buffer| unsafe {
+            let barrier = image_barrier(
+                image,
+                old_layout,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::QUEUE_FAMILY_IGNORED,
+                vk::QUEUE_FAMILY_IGNORED,
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier],
+            );
+            if !copies.is_empty() {
+                device.cmd_copy_buffer_to_image(
+                    command_buffer,
+                    staging.buffer,
+                    image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &copies,
+                );
+            }
+            let barrier = image_barrier(
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::QUEUE_FAMILY_IGNORED,
+                vk::QUEUE_FAMILY_IGNORED,
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier],
+            );
+        })
+    }
+
+    /// Downloads a region of a texture and converts it to RGBA8.
+    fn download(
+        &self,
+        texture: &VulkanTexture,
+        region: Rectangle<i32, BufferCoord>,
+    ) -> Result<VulkanMapping, VulkanError> {
+        if !texture.0.usage.contains(vk::ImageUsageFlags::TRANSFER_SRC) {
+            return Err(VulkanError::UnsupportedOperation);
+        }
+        if region.is_empty() || !Rectangle::from_loc_and_size((0, 0), texture.size()).contains_rect(region) {
+            return Err(VulkanError::UnexpectedSize);
+        }
+
+        let len = (region.size.w * region.size.h * 4) as usize;
+        let buffer = self.create_host_buffer(len as u64, vk::BufferUsageFlags::TRANSFER_DST)?;
+        let buffer = scopeguard::guard(buffer, |buffer| self.destroy_host_buffer(buffer));
+
+        let copy = vk::BufferImageCopy::builder()
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image_offset(vk::Offset3D {
+                x: region.loc.x,
+                y: region.loc.y,
+                z: 0,
+            })
+            .image_extent(vk::Extent3D {
+                width: region.size.w as u32,
+                height: region.size.h as u32,
+                depth: 1,
+            })
+            .build();
+        let acquire = texture.0.acquire(
+            self.queue_family_index,
+            self.foreign_queue_family,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        );
+        let release = texture.0.release(
+            self.queue_family_index,
+            self.foreign_queue_family,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        );
+        self.submit(|device, command_buffer| unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[acquire],
+            );
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                texture.image(),
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer.buffer,
+                &[copy],
+            );
+            let host_barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::HOST_READ)
+                .build();
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::PipelineStageFlags::ALL_COMMANDS | vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[host_barrier],
+                &[],
+                &[release],
+            );
+        })?;
+
+        let mut data = vec![0u8; len];
+        unsafe {
+            let ptr = self
+                .device
+                .map_memory(buffer.memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())?
+                as *const u8;
+            std::ptr::copy_nonoverlapping(ptr, data.as_mut_ptr(), len);
+            self.device.unmap_memory(buffer.memory);
+        }
+
+        for pixel in data.chunks_exact_mut(4) {
+            match texture.0.format {
+                vk::Format::B8G8R8A8_UNORM => pixel.swap(0, 2),
+                vk::Format::A2R10G10B10_UNORM_PACK32 | vk::Format::A2B10G10R10_UNORM_PACK32 => {
+                    let value = u32::from_ne_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+                    let (r, b) = if texture.0.format == vk::Format::A2B10G10R10_UNORM_PACK32 {
+                        (value & 0x3ff, (value >> 20) & 0x3ff)
+                    } else {
+                        ((value >> 20) & 0x3ff, value & 0x3ff)
+                    };
+                    let g = (value >> 10) & 0x3ff;
+                    pixel[0] = (r >> 2) as u8;
+                    pixel[1] = (g >> 2) as u8;
+                    pixel[2] = (b >> 2) as u8;
+                    pixel[3] = ((value >> 30) * 85) as u8;
+                }
+                _ => {}
+            }
+            if texture.0.opaque {
+                pixel[3] = 255;
+            }
+        }
+
+        Ok(VulkanMapping {
+            data,
+            size: region.size,
+        })
+    }
+
+    fn render_setup(&mut self, format: vk::Format) -> Result<RenderSetup, VulkanError> {
+        if let Some(setup) = self.render_setups.get(&format) {
+            return Ok(*setup);
+        }
+
+        let device = &self.device;
+        let attachments = [vk::AttachmentDescription::builder()
+            .format(format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .build()];
+        let color_attachments = [vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        }];
+        let subpasses = [vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_attachments)
+            .build()];
+        let info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(&subpasses);
+        let render_pass = unsafe { device.create_render_pass(&info, None) }?;
+        let render_pass = scopeguard::guard(render_pass, |render_pass| unsafe {
+            device.destroy_render_pass(render_pass, None)
+        });
+
+        let entry_point = CStr::from_bytes_with_nul(b"main\0").expect("NULL terminated");
+        let vertex_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::VERTEX)
+            .module(self.shaders[0])
+            .name(entry_point)
+            .build();
+        let texture_stages = [
+            vertex_stage,
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .module(self.shaders[1])
+                .name(entry_point)
+                .build(),
+        ];
+        let solid_stages = [
+            vertex_stage,
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .module(self.shaders[2])
+                .name(entry_point)
+                .build(),
+        ];
+
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::default();
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(vk::PrimitiveTopology::TRIANGLE_STRIP);
+        let viewport = vk::PipelineViewportStateCreateInfo::builder()
+            .viewport_count(1)
+            .scissor_count(1);
+        let rasterization = vk::PipelineRasterizationStateCreateInfo::builder()
+            .polygon_mode(vk::PolygonMode::FILL)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+            .line_width(1.0);
+        let multisample = vk::PipelineMultisampleStateCreateInfo::builder()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic = vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
+
+        // Textures use premultiplied alpha, clearing overwrites the target
+        let blend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
+            .blend_enable(true)
+            .src_color_blend_factor(vk::BlendFactor::ONE)
+            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .alpha_blend_op(vk::BlendOp::ADD)
+            .color_write_mask(vk::ColorComponentFlags::RGBA)
+            .build()];
+        let texture_blend = vk::PipelineColorBlendStateCreateInfo::builder().attachments(&blend_attachments);
+        let solid_attachments = [vk::PipelineColorBlendAttachmentState::builder()
+            .blend_enable(false)
+            .color_write_mask(vk::ColorComponentFlags::RGBA)
+            .build()];
+        let solid_blend = vk::PipelineColorBlendStateCreateInfo::builder().attachments(&solid_attachments);
+
+        let infos = [
+            vk::GraphicsPipelineCreateInfo::builder()
+                .stages(&texture_stages)
+                .vertex_input_state(&vertex_input)
+                .input_assembly_state(&input_assembly)
+                .viewport_state(&viewport)
+                .rasterization_state(&rasterization)
+                .multisample_state(&multisample)
+                .color_blend_state(&texture_blend)
+                .dynamic_state(&dynamic)
+                .layout(self.pipeline_layout)
+                .render_pass(*render_pass)
+                .subpass(0)
+                .build(),
+            vk::GraphicsPipelineCreateInfo::builder()
+                .stages(&solid_stages)
+                .vertex_input_state(&vertex_input)
+                .input_assembly_state(&input_assembly)
+                .viewport_state(&viewport)
+                .rasterization_state(&rasterization)
+                .multisample_state(&multisample)
+                .color_blend_state(&solid_blend)
+                .dynamic_state(&dynamic)
+                .layout(self.pipeline_layout)
+                .render_pass(*render_pass)
+                .subpass(0)
+                .build(),
+        ];
+        let pipelines = unsafe { device.create_graphics_pipelines(vk::PipelineCache::null(), &infos, None) }
+            .map_err(|(pipelines, err)| {
+                for pipeline in pipelines {
+                    unsafe { device.destroy_pipeline(pipeline, None) };
+                }
+                err
+            })?;
+
+        let setup = RenderSetup {
+            render_pass: scopeguard::ScopeGuard::into_inner(render_pass),
+            texture_pipeline: pipelines[0],
+            solid_pipeline: pipelines[1],
+        };
+        self.render_setups.insert(format, setup);
+        Ok(setup)
+    }
+
+    fn bind_texture(&mut self, texture: VulkanTexture, dmabuf: Option<Dmabuf>) -> Result<(), VulkanError> {
+        if !texture.0.usage.contains(vk::ImageUsageFlags::COLOR_ATTACHMENT) {
+            return Err(VulkanError::UnsupportedOperation);
+        }
+
+        let setup = self.render_setup(texture.0.format)?;
+        let attachments = [texture.0.resources.view];
+        let info = vk::FramebufferCreateInfo::builder()
+            .render_pass(setup.render_pass)
+            .attachments(&attachments)
+            .width(texture.width())
+            .height(texture.height())
+            .layers(1);
+        let framebuffer = Rc::new(Framebuffer {
+            handle: unsafe { self.device.create_framebuffer(&info, None) }?,
+            device: self.device.clone(),
+        });
+
+        self.target = Some(VulkanTarget {
+            texture,
+            framebuffer,
+            dmabuf,
+        });
+        Ok(())
+    }
+}
+
+/// Records commands using `record`, submits them to `queue` and waits for their execution.
+fn submit_commands<F>(
+    device: &ash::Device,
+    queue: vk::Queue,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+    record: F,
+) -> Result<(), VulkanError>
+where
+    F: FnOnce(&ash::Device, vk::CommandBuffer),
+{
+    unsafe {
+        device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+        let begin_info =
+            vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        device.begin_command_buffer(command_buffer, &begin_info)?;
+        record(device, command_buffer);
+        device.end_command_buffer(command_buffer)?;
+
+        let command_buffers = [command_buffer];
+        let submit_info = [vk::SubmitInfo::builder()
+            .command_buffers(&command_buffers)
+            .build()];
+        device.queue_submit(queue, &submit_info, fence)?;
+        let result = device.wait_for_fences(&[fence], true, u64::MAX);
+        device.reset_fences(&[fence])?;
+        result?;
+    }
+
+    Ok(())
+}
+
+fn sampler_index(min_filter: TextureFilter, max_filter: TextureFilter) -> usize {
+    (min_filter == TextureFilter::Nearest) as usize * 2 + (max_filter == TextureFilter::Nearest) as usize
+}
+
+fn vk_filter(filter: TextureFilter) -> vk::Filter {
+    match filter {
+        TextureFilter::Linear => vk::Filter::LINEAR,
+        TextureFilter::Nearest => vk::Filter::NEAREST,
+    }
+}
+
+impl Drop for VulkanRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.device.device_wait_idle();
+
+            self.target = None;
+            self.buffers.clear();
+            self.dmabuf_cache.clear();
+            for resources in self.destruction_callback.try_iter() {
+                resources.destroy(&self.device);
+            }
+
+            for setup in self.render_setups.values() {
+                self.device.destroy_pipeline(setup.texture_pipeline, None);
+                self.device.destroy_pipeline(setup.solid_pipeline, None);
+                self.device.destroy_render_pass(setup.render_pass, None);
+            }
+            for pool in self.descriptor_pools.drain(..) {
+                self.device.destroy_descriptor_pool(pool, None);
+            }
+            self.device.destroy_descriptor_pool(self.sampler_pool, None);
+            for sampler in self.samplers {
+                self.device.destroy_sampler(sampler, None);
+            }
+            self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+            self.device
+                .destroy_descriptor_set_layout(self.texture_set_layout, None);
+            self.device
+                .destroy_descriptor_set_layout(self.sampler_set_layout, None);
+            for shader in self.shaders {
+                self.device.destroy_shader_module(shader, None);
+            }
+            self.device.destroy_fence(self.fence, None);
+            self.device.destroy_command_pool(self.command_pool, None);
+            self.device.destroy_device(None);
+        }
+    }
+}
+
+#[cfg(feature = "wayland_frontend")]
+impl ImportMemWl for VulkanRenderer {
+    fn import_shm_buffer(
+        &mut self,
+        buffer: &wl_buffer::WlBuffer,
+        surface: Option<&crate::wayland::compositor::SurfaceData>,
+        damage: &[Rectangle<i32, BufferCoord>],
+    ) -> Result<VulkanTexture, VulkanError> {
+        use crate::wayland::shm::with_buffer_contents;
+
+        // why not store a `VulkanTexture`? because the user might do so.
+        // this is guaranteed a non-public internal type, so we are good.
+        type CacheMap = HashMap<usize, Rc<VulkanTextureInternal>>;
+        use std::cell::RefCell;
+
+        self.cleanup();
+        with_buffer_contents(buffer, |slice, data| {
+            let (format, opaque) = match data.format {
+                wl_shm::Format::Abgr8888 => (vk::Format::R8G8B8A8_UNORM, false),
+                wl_shm::Format::Xbgr8888 => (vk::Format::R8G8B8A8_UNORM, true),
+                wl_shm::Format::Argb8888 => (vk::Format::B8G8R8A8_UNORM, false),
+                wl_shm::Format::Xrgb8888 => (vk::Format::B8G8R8A8_UNORM, true),
+                format => return Err(VulkanError::UnsupportedPixelFormat(format)),
+            };
+            let size = Size::<i32, BufferCoord>::from((data.width, data.height));
+
+            let id = self.id();
+            let cached = surface
+                .and_then(|surface| {
+                    surface
+                        .data_map
+                        .insert_if_missing(|| Rc::new(RefCell::new(CacheMap::new())));
+                    surface
+                        .data_map
+                        .get::<Rc<RefCell<CacheMap>>>()
+                        .unwrap()
+                        .borrow()
+                        .get(&id)
+                        .cloned()
+                })
+                .filter(|texture| {
+                    texture.size == size && texture.format == format && texture.opaque == opaque
+                });
+
+            let (texture, initial) = match cached {
+                Some(texture) => (VulkanTexture(texture), false),
+                None => {
+                    let texture = self.create_texture(size, format, opaque, false)?;
+                    if let Some(surface) = surface {
+                        surface
+                            .data_map
+                            .get::<Rc<RefCell<CacheMap>>>()
+                            .unwrap()
+                            .borrow_mut()
+                            .insert(id, texture.0.clone());
+                    }
+                    (texture, true)
+                }
+            };
+
+            let full = [Rectangle::from_loc_and_size((0, 0), size)];
+            let regions = if initial || damage.is_empty() {
+                trace!(self.logger, "Uploading shm texture for {:?}", buffer);
+                &full[..]
+            } else {
+                trace!(self.logger, "Uploading partial shm texture for {:?}", buffer);
+                damage
+            };
+            self.upload(
+                &texture,
+                slice,
+                data.offset as usize,
+                data.stride as usize,
+                regions,
+                initial,
+            )?;
+
+            Ok(texture)
+        })
+        .map_err(VulkanError::BufferAccessError)?
+    }
+
+    fn shm_formats(&self) -> &[wl_shm::Format] {
+        &[
+            wl_shm::Format::Abgr8888,
+            wl_shm::Format::Xbgr8888,
+            wl_shm::Format::Argb8888,
+            wl_shm::Format::Xrgb8888,
+        ]
+    }
+}
+
+impl ImportMem for VulkanRenderer {
+    fn import_memory(
+        &mut self,
+        data: &[u8],
+        size: Size<i32, BufferCoord>,
+        flipped: bool,
+    ) -> Result<VulkanTexture, VulkanError> {
+        self.cleanup();
+
+        if data.len() < (size.w * size.h * 4) as usize {
+            return Err(VulkanError::UnexpectedSize);
+        }
+
+        let texture = self.create_texture(size, vk::Format::R8G8B8A8_UNORM, false, flipped)?;
+        self.upload(
+            &texture,
+            data,
+            0,
+            size.w as usize * 4,
+            &[Rectangle::from_loc_and_size((0, 0), size)],
+            true,
+        )?;
+
+        Ok(texture)
+    }
+
+    fn update_memory(
+        &mut self,
+        texture: &VulkanTexture,
+        data: &[u8],
+        region: Rectangle<i32, BufferCoord>,
+    ) -> Result<(), VulkanError> {
+        self.cleanup();
+
+        let size = texture.size();
+        if data.len() < (size.w * size.h * 4) as usize {
+            return Err(VulkanError::UnexpectedSize);
+        }
+
+        self.upload(texture, data, 0, size.w as usize * 4, &[region], false)
+    }
+}
+
+impl ImportDma for VulkanRenderer {
+    fn dmabuf_formats<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Format> + 'a> {
+        Box::new(self.dmabuf_formats.iter())
+    }
+
+    fn import_dmabuf(
+        &mut self,
+        buffer: &Dmabuf,
+        _damage: Option<&[Rectangle<i32, BufferCoord>]>,
+    ) -> Result<VulkanTexture, VulkanError> {
+        self.cleanup();
+
+        if let Some(texture) = self.dmabuf_cache.get(&buffer.weak()) {
+            return Ok(texture.clone());
+        }
+
+        let texture = self.import_dmabuf_image(buffer, false)?;
+        self.dmabuf_cache.insert(buffer.weak(), texture.clone());
+        Ok(texture)
+    }
+}
+
+#[cfg(feature = "wayland_frontend")]
+impl ImportDmaWl for VulkanRenderer {}
+
+impl ExportMem for VulkanRenderer {
+    type TextureMapping = VulkanMapping;
+
+    fn copy_framebuffer(
+        &mut self,
+        region: Rectangle<i32, BufferCoord>,
+    ) -> Result<VulkanMapping, VulkanError> {
+        self.cleanup();
+
+        let target = self.target.as_ref().ok_or(VulkanError::NoTarget)?;
+        self.download(&target.texture, region)
+    }
+
+    fn copy_texture(
+        &mut self,
+        texture: &VulkanTexture,
+        region: Rectangle<i32, BufferCoord>,
+    ) -> Result<VulkanMapping, VulkanError> {
+        self.cleanup();
+        self.download(texture, region)
+    }
+
+    fn map_texture<'a>(&mut self, texture_mapping: &'a VulkanMapping) -> Result<&'a [u8], VulkanError> {
+        Ok(&texture_mapping.data)
+    }
+}
+
+impl ExportDma for VulkanRenderer {
+    fn export_framebuffer(&mut self, size: Size<i32, BufferCoord>) -> Result<Dmabuf, VulkanError> {
+        let target = self.target.as_ref().ok_or(VulkanError::NoTarget)?;
+        let dmabuf = target.dmabuf.clone().ok_or(VulkanError::UnsupportedOperation)?;
+        if dmabuf.size().w < size.w || dmabuf.size().h < size.h {
+            return Err(VulkanError::UnexpectedSize);
+        }
+        Ok(dmabuf)
+    }
+
+    fn export_texture(&mut self, texture: &VulkanTexture) -> Result<Dmabuf, VulkanError> {
+        texture
+            .0
+            .dmabuf
+            .as_ref()
+            .and_then(|dmabuf| dmabuf.buffer.upgrade())
+            .ok_or(VulkanError::UnsupportedOperation)
+    }
+}
+
+impl Bind<Dmabuf> for VulkanRenderer {
+    fn bind(&mut self, dmabuf: Dmabuf) -> Result<(), VulkanError> {
+        self.unbind()?;
+
+        let texture = match self.buffers.get(&dmabuf.weak()) {
+            Some(texture) => texture.clone(),
+            None => {
+                trace!(self.logger, "Importing dmabuf as render target: {:?}", dmabuf);
+                let texture = self.import_dmabuf_image(&dmabuf, true)?;
+                self.buffers.insert(dmabuf.weak(), texture.clone());
+                texture
+            }
+        };
+
+        self.bind_texture(texture, Some(dmabuf))
+    }
+
+    fn supported_formats(&self) -> Option<HashSet<Format>> {
+        Some(self.render_formats.clone())
+    }
+}
+
+impl Bind<VulkanTexture> for VulkanRenderer {
+    fn bind(&mut self, texture: VulkanTexture) -> Result<(), VulkanError> {
+        self.unbind()?;
+        self.bind_texture(texture, None)
+    }
+}
+
+impl Offscreen<VulkanTexture> for VulkanRenderer {
+    fn create_buffer(&mut self, size: Size<i32, BufferCoord>) -> Result<VulkanTexture, VulkanError> {
+        self.cleanup();
+
+        let texture = self.create_texture(size, vk::Format::R8G8B8A8_UNORM, false, false)?;
+        let image = texture.image();
+        self.submit(|device, command_buffer| unsafe {
+            let barrier = image_barrier(
+                image,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::QUEUE_FAMILY_IGNORED,
+                vk::QUEUE_FAMILY_IGNORED,
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier],
+            );
+        })?;
+
+        Ok(texture)
+    }
+}
+
+impl Offscreen<Dmabuf> for VulkanRenderer {
+    fn create_buffer(&mut self, size: Size<i32, BufferCoord>) -> Result<Dmabuf, VulkanError> {
+        self.cleanup();
+        self.create_dmabuf(size)
+    }
+}
+
+impl Unbind for VulkanRenderer {
+    fn unbind(&mut self) -> Result<(), VulkanError> {
+        // a frame still rendering to the target keeps the framebuffer alive
+        self.target = None;
+        self.cleanup();
+        Ok(())
+    }
+}
+
+impl Renderer for VulkanRenderer {
+    type Error = VulkanError;
+    type TextureId = VulkanTexture;
+    type Frame = VulkanFrame;
+
+    fn id(&self) -> usize {
+        self.renderer_id.0
+    }
+
+    fn downscale_filter(&mut self, filter: TextureFilter) -> Result<(), Self::Error> {
+        self.min_filter = filter;
+        Ok(())
+    }
+    fn upscale_filter(&mut self, filter: TextureFilter) -> Result<(), Self::Error> {
+        self.max_filter = filter;
+        Ok(())
+    }
+
+    fn render<F, R>(
+        &mut self,
+        mut output_size: Size<i32, Physical>,
+        transform: Transform,
+        rendering: F,
+    ) -> Result<R, Self::Error>
+    where
+        F: FnOnce(&mut Self, &mut Self::Frame) -> R,
+    {
+        self.cleanup();
+        let target = self.target.as_ref().ok_or(VulkanError::NoTarget)?;
+        let target = FrameTarget {
+            device: self.device.clone(),
+            queue: self.queue,
+            command_buffer: self.command_buffer,
+            fence: self.fence,
+            queue_family: self.queue_family_index,
+            foreign_queue_family: self.foreign_queue_family,
+            setup: self.render_setups[&target.texture.0.format],
+            texture: target.texture.clone(),
+            framebuffer: target.framebuffer.clone(),
+            pipeline_layout: self.pipeline_layout,
+            sampler_sets: self.sampler_sets,
+            viewport_size: output_size,
+        };
+
+        // Handle the width/height swap when the output is rotated by 90°/270°.
+        if let Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270 = transform {
+            mem::swap(&mut output_size.w, &mut output_size.h);
+        }
+
+        // Same projection as the gles2 renderer, so that textures and targets have identical contents.
+        // While Vulkan's y-axis points downwards, the origin of a framebuffer is at its top-left
+        // instead of bottom-left, so the flip cancels out.
+        let mut renderer = Matrix3::<f32>::identity();
+        let x = 2.0 / (output_size.w as f32);
+        let y = 2.0 / (output_size.h as f32);
+
+        // Rotation & Reflection
+        renderer[0][0] = x;
+        renderer[1][1] = -y;
+
+        //Translation
+        renderer[2][0] = -(1.0f32.copysign(renderer[0][0] + renderer[1][0]));
+        renderer[2][1] = -(1.0f32.copysign(renderer[0][1] + renderer[1][1]));
+
+        let flip180 = Matrix3::new(1.0, 0.0, 0.0, 0.0, -1.0, 0.0, 0.0, 0.0, 1.0);
+
+        let mut frame = VulkanFrame {
+            current_projection: flip180 * transform.matrix() * renderer,
+            transform,
+            size: output_size,
+            min_filter: self.min_filter,
+            max_filter: self.max_filter,
+            commands: Vec::new(),
+            target,
+        };
+
+        let result = rendering(self, &mut frame);
+        frame.submit()?;
+
+        Ok(result)
+    }
+}
+
+#[derive(Debug)]
+enum FrameCommand {
+    Clear {
+        constants: PushConstants,
+        rects: Vec<[f32; 4]>,
+    },
+    Texture {
+        texture: VulkanTexture,
+        constants: PushConstants,
+        rects: Vec<[f32; 4]>,
+        sampler: usize,
+    },
+}
+
+/// Handle to the currently rendered frame during [`VulkanRenderer::render`](Renderer::render)
+///
+/// Operations are recorded and submitted on [`Frame::finish`] or at the end of [`Renderer::render`].
+#[derive(Debug)]
+pub struct VulkanFrame {
+    current_projection: Matrix3<f32>,
+    transform: Transform,
+    size: Size<i32, Physical>,
+    min_filter: TextureFilter,
+    max_filter: TextureFilter,
+    commands: Vec<FrameCommand>,
+    target: FrameTarget,
+}
+
+/// Everything needed to submit the recorded commands of a [`VulkanFrame`]
+struct FrameTarget {
+    device: ash::Device,
+    queue: vk::Queue,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+    queue_family: u32,
+    foreign_queue_family: u32,
+    setup: RenderSetup,
+    texture: VulkanTexture,
+    framebuffer: Rc<Framebuffer>,
+    pipeline_layout: vk::PipelineLayout,
+    sampler_sets: [vk::DescriptorSet; 4],
+    viewport_size: Size<i32, Physical>,
+}
+
+impl fmt::Debug for FrameTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FrameTarget")
+            .field("texture", &self.texture)
+            .field("viewport_size", &self.viewport_size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Frame for VulkanFrame {
+    type Error = VulkanError;
+    type TextureId = VulkanTexture;
+
+    fn clear(&mut self, color: [f32; 4], at: &[Rectangle<i32, Physical>]) -> Result<(), Self::Error> {
+        if at.is_empty() {
+            return Ok(());
+        }
+
+        let rects = at
+            .iter()
+            .map(|rect| {
+                [
+                    rect.loc.x as f32,
+                    rect.loc.y as f32,
+                    rect.size.w as f32,
+                    rect.size.h as f32,
+                ]
+            })
+            .collect();
+
+        self.commands.push(FrameCommand::Clear {
+            constants: PushConstants::new(self.current_projection, Matrix3::identity(), [0.0; 4], color),
+            rects,
+        });
+
+        Ok(())
+    }
+
+    fn render_texture_from_to(
+        &mut self,
+        texture: &Self::TextureId,
+        src: Rectangle<f64, BufferCoord>,
+        dest: Rectangle<i32, Physical>,
+        damage: &[Rectangle<i32, Physical>],
+        transform: Transform,
+        alpha: f32,
+    ) -> Result<(), Self::Error> {
+        if texture.0.resources.descriptor_set == vk::DescriptorSet::null() {
+            return Err(VulkanError::UnsupportedOperation);
+        }
+
+        let mut mat = Matrix3::<f32>::identity();
+
+        // dest position and scale
+        mat = mat * Matrix3::from_translation(Vector2::new(dest.loc.x as f32, dest.loc.y as f32));
+
+        // src scale, position, tranform and y_inverted
+        let tex_size = texture.size().to_f64();
+        let src_size = src.size;
+
+        let transform_mat = if transform.flipped() {
+            transform.matrix()
+        } else {
+            transform.invert().matrix()
+        };
+
+        let mut tex_mat = Matrix3::<f32>::identity();
+        // first scale to meet the src size
+        tex_mat = tex_mat
+            * Matrix3::from_nonuniform_scale(
+                (src_size.w / tex_size.w) as f32,
+                (src_size.h / tex_size.h) as f32,
+            );
+        // now translate by the src location
+        tex_mat = tex_mat
+            * Matrix3::from_translation(Vector2::new(
+                (src.loc.x / src_size.w) as f32,
+                (src.loc.y / src_size.h) as f32,
+            ));
+        // then apply the transform and if necessary invert the y axis
+        tex_mat = tex_mat * Matrix3::from_translation(Vector2::new(0.5, 0.5));
+        tex_mat = tex_mat * transform_mat;
+        if texture.0.y_inverted {
+            tex_mat = tex_mat * Matrix3::new(1.0, 0.0, 0.0, 0.0, -1.0, 0.0, 0.0, 0.0, 1.0);
+        }
+        tex_mat = tex_mat * Matrix3::from_translation(Vector2::new(-0.5, -0.5));
+        // at last scale back to tex space
+        tex_mat = tex_mat
+            * Matrix3::from_nonuniform_scale(
+                (1.0f64 / dest.size.w as f64) as f32,
+                (1.0f64 / dest.size.h as f64) as f32,
+            );
+
+        let rects = damage
+            .iter()
+            .map(|rect| {
+                let dest_size = dest.size;
+
+                let rect_constrained_loc = rect
+                    .loc
+                    .constrain(Rectangle::from_extemities((0, 0), dest_size.to_point()));
+                let rect_clamped_size = rect
+                    .size
+                    .clamp((0, 0), (dest_size.to_point() - rect_constrained_loc).to_size());
+
+                [
+                    rect_constrained_loc.x as f32,
+                    rect_constrained_loc.y as f32,
+                    rect_clamped_size.w as f32,
+                    rect_clamped_size.h as f32,
+                ]
+            })
+            .collect::<Vec<_>>();
+        if rects.is_empty() {
+            return Ok(());
+        }
+
+        self.commands.push(FrameCommand::Texture {
+            texture: texture.clone(),
+            constants: PushConstants::new(
+                self.current_projection * mat,
+                tex_mat,
+                [0.0; 4],
+                [alpha, if texture.0.opaque { 1.0 } else { 0.0 }, 0.0, 0.0],
+            ),
+            rects,
+            sampler: sampler_index(self.min_filter, self.max_filter),
+        });
+
+        Ok(())
+    }
+
+    fn transformation(&self) -> Transform {
+        self.transform
+    }
+
+    fn finish(&mut self) -> Result<SyncPoint, Self::Error> {
+        // submission waits for the commands to complete, so the sync point is reached already
+        self.submit()?;
+        Ok(SyncPoint::signaled())
+    }
+}
+
+impl VulkanFrame {
+    /// Size of the output, this frame is rendered for, after applying the output transformation.
+    pub fn size(&self) -> Size<i32, Physical> {
+        self.size
+    }
+
+    /// Submits the commands recorded so far and waits for their execution.
+    fn submit(&mut self) -> Result<(), VulkanError> {
+        let commands = mem::take(&mut self.commands);
+        if commands.is_empty() {
+            return Ok(());
+        }
+
+        let target = &self.target;
+        let setup = target.setup;
+        let output_size = target.viewport_size;
+        let (queue_family, foreign_queue_family) = (target.queue_family, target.foreign_queue_family);
+        let extent = vk::Extent2D {
+            width: target.texture.width(),
+            height: target.texture.height(),
+        };
+
+        // Textures backed by dmabufs need to be acquired from the foreign queue before sampling
+        let mut sampled = Vec::<&VulkanTexture>::new();
+        for command in &commands {
+            if let FrameCommand::Texture { texture, .. } = command {
+                if texture.0.dmabuf.is_some() && !sampled.contains(&texture) {
+                    sampled.push(texture);
+                }
+            }
+        }
+        let mut acquire = vec![target.texture.0.acquire(
+            queue_family,
+            foreign_queue_family,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        )];
+        let mut release = vec![target.texture.0.release(
+            queue_family,
+            foreign_queue_family,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        )];
+        for texture in sampled {
+            acquire.push(texture.0.acquire(
+                queue_family,
+                foreign_queue_family,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ));
+            release.push(texture.0.release(
+                queue_family,
+                foreign_queue_family,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ));
+        }
+
+        let pipeline_layout = target.pipeline_layout;
+        let sampler_sets = target.sampler_sets;
+        let record = |device: &ash::Device, command_buffer| unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &acquire,
+            );
+
+            let render_area = vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent,
+            };
+            let begin_info = vk::RenderPassBeginInfo::builder()
+                .render_pass(setup.render_pass)
+                .framebuffer(target.framebuffer.handle)
+                .render_area(render_area);
+            device.cmd_begin_render_pass(command_buffer, &begin_info, vk::SubpassContents::INLINE);
+            device.cmd_set_viewport(
+                command_buffer,
+                0,
+                &[vk::Viewport {
+                    x: 0.0,
+                    y: 0.0,
+                    width: output_size.w as f32,
+                    height: output_size.h as f32,
+                    min_depth: 0.0,
+                    max_depth: 1.0,
+                }],
+            );
+            device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+
+            let mut bound_pipeline = vk::Pipeline::null();
+            for command in &commands {
+                let (pipeline, rects, push_constants) = match command {
+                    FrameCommand::Clear { rects, constants } => (setup.solid_pipeline, rects, constants),
+                    FrameCommand::Texture {
+                        texture,
+                        rects,
+                        constants,
+                        sampler,
+                    } => {
+                        device.cmd_bind_descriptor_sets(
+                            command_buffer,
+                            vk::PipelineBindPoint::GRAPHICS,
+                            pipeline_layout,
+                            0,
+                            &[texture.0.resources.descriptor_set, sampler_sets[*sampler]],
+                            &[],
+                        );
+                        (setup.texture_pipeline, rects, constants)
+                    }
+                };
+                if pipeline != bound_pipeline {
+                    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+                    bound_pipeline = pipeline;
+                }
+
+                let mut push_constants = *push_constants;
+                for rect in rects {
+                    push_constants.position = *rect;
+                    device.cmd_push_constants(
+                        command_buffer,
+                        pipeline_layout,
+                        vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                        0,
+                        push_constants.as_bytes(),
+                    );
+                    device.cmd_draw(command_buffer, 4, 1, 0, 0);
+                }
+            }
+
+            device.cmd_end_render_pass(command_buffer);
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &release,
+            );
+        };
+        submit_commands(
+            &target.device,
+            target.queue,
+            target.command_buffer,
+            target.fence,
+            record,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{VulkanError, VulkanRenderer, VulkanTexture};
+    use crate::backend::{
+        renderer::{Bind, ExportMem, Frame, ImportMem, Offscreen, Renderer, Unbind},
+        vulkan::{version::Version, Instance, PhysicalDevice},
+    };
+    use crate::utils::{Buffer as BufferCoord, Physical, Rectangle, Transform};
+
+    const SIZE: (i32, i32) = (4, 4);
+
+    // Vulkan is not available everywhere (e.g. without an installed ICD), the tests are skipped without it
+    fn test_renderer() -> Option<VulkanRenderer> {
+        let instance = match Instance::new(Version::VERSION_1_2, None, None::<::slog::Logger>) {
+            Ok(instance) => instance,
+            Err(err) => {
+                eprintln!("skipping test, vulkan is not available: {}", err);
+                return None;
+            }
+        };
+        let renderer = PhysicalDevice::enumerate(&instance)
+            .ok()
+            .into_iter()
+            .flatten()
+            .find_map(|phd| VulkanRenderer::new(&phd, None::<::slog::Logger>).ok());
+        if renderer.is_none() {
+            eprintln!("skipping test, no vulkan device supports the renderer");
+        }
+        renderer
+    }
+
+    fn full_damage() -> Rectangle<i32, Physical> {
+        Rectangle::from_loc_and_size((0, 0), SIZE)
+    }
+
+    fn region() -> Rectangle<i32, BufferCoord> {
+        Rectangle::from_loc_and_size((0, 0), SIZE)
+    }
+
+    fn download(renderer: &mut VulkanRenderer, texture: &VulkanTexture) -> Vec<u8> {
+        let mapping = renderer.copy_texture(texture, region()).unwrap();
+        renderer.map_texture(&mapping).unwrap().to_vec()
+    }
+
+    fn solid(color: [u8; 4]) -> Vec<u8> {
+        color.repeat((SIZE.0 * SIZE.1) as usize)
+    }
+
+    #[test]
+    fn import_render_export() {
+        let mut renderer = match test_renderer() {
+            Some(renderer) => renderer,
+            None => return,
+        };
+
+        let data = (0..SIZE.0 * SIZE.1)
+            .flat_map(|i| [i as u8 * 16, 255 - i as u8 * 16, i as u8, 255])
+            .collect::<Vec<u8>>();
+        let texture = renderer.import_memory(&data, SIZE.into(), false).unwrap();
+        assert_eq!(download(&mut renderer, &texture), data);
+
+        let target: VulkanTexture = renderer.create_buffer(SIZE.into()).unwrap();
+        renderer.bind(target).unwrap();
+        renderer
+            .render(SIZE.into(), Transform::Normal, |_, frame| {
+                frame.clear([0.0, 0.0, 0.0, 1.0], &[full_damage()])?;
+                frame.render_texture_at(
+                    &texture,
+                    (0, 0).into(),
+                    1,
+                    1.0,
+                    Transform::Normal,
+                    &[full_damage()],
+                    1.0,
+                )
+            })
+            .unwrap()
+            .unwrap();
+
+        let mapping = renderer.copy_framebuffer(region()).unwrap();
+        assert_eq!(renderer.map_texture(&mapping).unwrap(), &data[..]);
+    }
+
+    #[test]
+    fn offscreen_bind_render_unbind() {
+        let mut renderer = match test_renderer() {
+            Some(renderer) => renderer,
+            None => return,
+        };
+
+        let target: VulkanTexture = renderer.create_buffer(SIZE.into()).unwrap();
+        renderer.bind(target.clone()).unwrap();
+        renderer
+            .render(SIZE.into(), Transform::Normal, |_, frame| {
+                frame.clear([1.0, 0.0, 0.0, 1.0], &[full_damage()])
+            })
+            .unwrap()
+            .unwrap();
+        renderer.unbind().unwrap();
+
+        assert!(matches!(
+            renderer.copy_framebuffer(region()),
+            Err(VulkanError::NoTarget)
+        ));
+        assert_eq!(download(&mut renderer, &target), solid([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn unbind_during_render() {
+        let mut renderer = match test_renderer() {
+            Some(renderer) => renderer,
+            None => return,
+        };
+
+        let target: VulkanTexture = renderer.create_buffer(SIZE.into()).unwrap();
+        renderer.bind(target.clone()).unwrap();
+        // the frame keeps rendering to the previous target
+        renderer
+            .render(SIZE.into(), Transform::Normal, |renderer, frame| {
+                renderer.unbind().unwrap();
+                frame.clear([0.0, 1.0, 0.0, 1.0], &[full_damage()])
+            })
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(download(&mut renderer, &target), solid([0, 255, 0, 255]));
+    }
+}
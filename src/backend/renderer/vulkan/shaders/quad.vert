@@ -0,0 +1,29 @@
+#version 450
+
+// Push constants shared by all pipelines of the vulkan renderer.
+//
+// Matrices are passed column by column, the w component of every column is unused.
+layout(push_constant) uniform PushConstants {
+    vec4 matrix_0;
+    vec4 matrix_1;
+    vec4 matrix_2;
+    vec4 tex_matrix_0;
+    vec4 tex_matrix_1;
+    vec4 tex_matrix_2;
+    // offset (xy) and size (zw) of the drawn rectangle
+    vec4 position;
+    // solid color, or (alpha, opaque, _, _) when rendering a texture
+    vec4 color;
+} data;
+
+layout(location = 0) out vec2 v_tex_coords;
+
+void main() {
+    vec2 vert = vec2(float(gl_VertexIndex & 1), float((gl_VertexIndex >> 1) & 1));
+    mat3 matrix = mat3(data.matrix_0.xyz, data.matrix_1.xyz, data.matrix_2.xyz);
+    mat3 tex_matrix = mat3(data.tex_matrix_0.xyz, data.tex_matrix_1.xyz, data.tex_matrix_2.xyz);
+
+    vec3 position = vec3(vert * data.position.zw + data.position.xy, 1.0);
+    v_tex_coords = (tex_matrix * position).xy;
+    gl_Position = vec4((matrix * position).xy, 0.0, 1.0);
+}
@@ -0,0 +1,18 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+    vec4 matrix_0;
+    vec4 matrix_1;
+    vec4 matrix_2;
+    vec4 tex_matrix_0;
+    vec4 tex_matrix_1;
+    vec4 tex_matrix_2;
+    vec4 position;
+    vec4 color;
+} data;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    out_color = data.color;
+}
@@ -0,0 +1,26 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+    vec4 matrix_0;
+    vec4 matrix_1;
+    vec4 matrix_2;
+    vec4 tex_matrix_0;
+    vec4 tex_matrix_1;
+    vec4 tex_matrix_2;
+    vec4 position;
+    vec4 color;
+} data;
+
+layout(set = 0, binding = 0) uniform texture2D tex;
+layout(set = 1, binding = 0) uniform sampler tex_sampler;
+
+layout(location = 0) in vec2 v_tex_coords;
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    vec4 color = texture(sampler2D(tex, tex_sampler), v_tex_coords);
+    if (data.color.y > 0.5) {
+        color.a = 1.0;
+    }
+    out_color = color * data.color.x;
+}
@@ -34,7 +34,7 @@ use crate::{
     },
     utils::{Logical, Physical, Rectangle, Size},
 };
-use std::{cell::RefCell, rc::Rc, time::Instant};
+use std::{cell::RefCell, rc::Rc, sync::Arc, time::Instant};
 use wayland_egl as wegl;
 use winit::{
     dpi::LogicalSize,
@@ -93,7 +93,7 @@ pub struct WinitGraphicsBackend {
     renderer: Gles2Renderer,
     // The display isn't used past this point but must be kept alive.
     _display: EGLDisplay,
-    egl: Rc<RefCell<Option<Rc<EGLSurface>>>>,
+    egl: Rc<RefCell<Option<Arc<EGLSurface>>>>,
     window: Rc<WinitWindow>,
     size: Rc<RefCell<WindowSize>>,
     damage_tracking: bool,
@@ -253,7 +253,7 @@ where
 
     let window = Rc::new(winit_window);
     let egl = Rc::new(RefCell::new(
-        surface.map_or_else(|| None, |surface| Some(Rc::new(surface))),
+        surface.map_or_else(|| None, |surface| Some(Arc::new(surface))),
     ));
     //let egl = Rc::new(surface);
     let renderer = unsafe { Gles2Renderer::new(context, log.clone())? };
@@ -474,7 +474,7 @@ impl WinitEventLoop {
                     Event::Resumed => {
                         #[cfg(target_os = "android")]
                         if let Some(window) = ndk_glue::native_window().as_ref() {
-                            egl.replace(Some(Rc::new(
+                            egl.replace(Some(Arc::new(
                                 EGLSurface::new(
                                     display,
                                     context.pixel_format().unwrap(),
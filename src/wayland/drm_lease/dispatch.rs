@@ -0,0 +1,286 @@
+use std::sync::Mutex;
+
+use drm::control::connector;
+use wayland_protocols::wp::drm_lease::v1::server::{
+    wp_drm_lease_connector_v1::{self, WpDrmLeaseConnectorV1},
+    wp_drm_lease_device_v1::{self, WpDrmLeaseDeviceV1},
+    wp_drm_lease_request_v1::{self, WpDrmLeaseRequestV1},
+    wp_drm_lease_v1::{self, WpDrmLeaseV1},
+};
+use wayland_server::{
+    backend::{ClientId, ObjectId},
+    Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+};
+
+use crate::backend::drm::DrmNode;
+
+use super::{open_unprivileged, DrmLeaseHandler, DrmLeaseRequest, DrmLeaseState};
+
+/// Global data of the [WpDrmLeaseDeviceV1] global
+#[derive(Debug)]
+pub struct DrmLeaseDeviceGlobalData {
+    pub(super) node: DrmNode,
+}
+
+/// User data of [WpDrmLeaseConnectorV1] object
+#[derive(Debug)]
+pub struct DrmLeaseConnectorUserData {
+    pub(super) node: DrmNode,
+    pub(super) connector: connector::Handle,
+}
+
+/// User data of [WpDrmLeaseRequestV1] object
+#[derive(Debug)]
+pub struct DrmLeaseRequestUserData {
+    node: DrmNode,
+    connectors: Mutex<Vec<connector::Handle>>,
+}
+
+/// User data of [WpDrmLeaseV1] object
+#[derive(Debug)]
+pub struct DrmLeaseUserData {
+    node: DrmNode,
+}
+
+impl<D> GlobalDispatch<WpDrmLeaseDeviceV1, DrmLeaseDeviceGlobalData, D> for DrmLeaseState
+where
+    D: GlobalDispatch<WpDrmLeaseDeviceV1, DrmLeaseDeviceGlobalData>,
+    D: Dispatch<WpDrmLeaseDeviceV1, DrmLeaseDeviceGlobalData>,
+    D: Dispatch<WpDrmLeaseConnectorV1, DrmLeaseConnectorUserData>,
+    D: DrmLeaseHandler,
+    D: 'static,
+{
+    fn bind(
+        state: &mut D,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<WpDrmLeaseDeviceV1>,
+        global_data: &DrmLeaseDeviceGlobalData,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        let device = data_init.init(
+            resource,
+            DrmLeaseDeviceGlobalData {
+                node: global_data.node,
+            },
+        );
+
+        let lease_state = state.drm_lease_state(global_data.node);
+        match open_unprivileged(&global_data.node) {
+            Some(fd) => {
+                device.drm_fd(fd);
+                let _ = nix::unistd::close(fd);
+            }
+            None => {
+                slog::warn!(
+                    lease_state.logger,
+                    "Failed to open drm node {:?} for client",
+                    global_data.node
+                );
+                device.released();
+                return;
+            }
+        }
+
+        for idx in 0..lease_state.connectors.len() {
+            lease_state.advertise_connector::<D>(&device, idx);
+        }
+        device.done();
+        lease_state.devices.push(device);
+    }
+}
+
+impl<D> Dispatch<WpDrmLeaseDeviceV1, DrmLeaseDeviceGlobalData, D> for DrmLeaseState
+where
+    D: Dispatch<WpDrmLeaseDeviceV1, DrmLeaseDeviceGlobalData>,
+    D: Dispatch<WpDrmLeaseRequestV1, DrmLeaseRequestUserData>,
+    D: DrmLeaseHandler,
+    D: 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        resource: &WpDrmLeaseDeviceV1,
+        request: wp_drm_lease_device_v1::Request,
+        data: &DrmLeaseDeviceGlobalData,
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            wp_drm_lease_device_v1::Request::CreateLeaseRequest { id } => {
+                data_init.init(
+                    id,
+                    DrmLeaseRequestUserData {
+                        node: data.node,
+                        connectors: Mutex::new(Vec::new()),
+                    },
+                );
+            }
+            wp_drm_lease_device_v1::Request::Release => {
+                let lease_state = state.drm_lease_state(data.node);
+                lease_state.devices.retain(|device| device != resource);
+                resource.released();
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, data: &DrmLeaseDeviceGlobalData) {
+        let lease_state = state.drm_lease_state(data.node);
+        lease_state.devices.retain(|device| device.id() != resource);
+    }
+}
+
+impl<D> Dispatch<WpDrmLeaseConnectorV1, DrmLeaseConnectorUserData, D> for DrmLeaseState
+where
+    D: Dispatch<WpDrmLeaseConnectorV1, DrmLeaseConnectorUserData>,
+    D: DrmLeaseHandler,
+    D: 'static,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _resource: &WpDrmLeaseConnectorV1,
+        request: wp_drm_lease_connector_v1::Request,
+        _data: &DrmLeaseConnectorUserData,
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            wp_drm_lease_connector_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, data: &DrmLeaseConnectorUserData) {
+        let lease_state = state.drm_lease_state(data.node);
+        if let Some(connector) = lease_state
+            .connectors
+            .iter_mut()
+            .find(|conn| conn.handle == data.connector)
+        {
+            connector.instances.retain(|instance| instance.id() != resource);
+        }
+    }
+}
+
+impl<D> Dispatch<WpDrmLeaseRequestV1, DrmLeaseRequestUserData, D> for DrmLeaseState
+where
+    D: Dispatch<WpDrmLeaseRequestV1, DrmLeaseRequestUserData>,
+    D: Dispatch<WpDrmLeaseV1, DrmLeaseUserData>,
+    D: Dispatch<WpDrmLeaseConnectorV1, DrmLeaseConnectorUserData>,
+    D: DrmLeaseHandler,
+    D: 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        resource: &WpDrmLeaseRequestV1,
+        request: wp_drm_lease_request_v1::Request,
+        data: &DrmLeaseRequestUserData,
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            wp_drm_lease_request_v1::Request::RequestConnector { connector } => {
+                let connector_data = connector.data::<DrmLeaseConnectorUserData>().unwrap();
+                if connector_data.node != data.node {
+                    resource.post_error(
+                        wp_drm_lease_request_v1::Error::WrongDevice,
+                        "Requested connector from a different lease device",
+                    );
+                    return;
+                }
+
+                let mut connectors = data.connectors.lock().unwrap();
+                if connectors.contains(&connector_data.connector) {
+                    resource.post_error(
+                        wp_drm_lease_request_v1::Error::DuplicateConnector,
+                        "Requested connector twice",
+                    );
+                    return;
+                }
+                connectors.push(connector_data.connector);
+            }
+            wp_drm_lease_request_v1::Request::Submit { id } => {
+                let connectors = std::mem::take(&mut *data.connectors.lock().unwrap());
+                if connectors.is_empty() {
+                    resource.post_error(
+                        wp_drm_lease_request_v1::Error::EmptyLease,
+                        "Lease request has no connectors",
+                    );
+                    return;
+                }
+
+                let lease = data_init.init(id, DrmLeaseUserData { node: data.node });
+
+                // connectors might have been leased or withdrawn in the meantime
+                let lease_state = state.drm_lease_state(data.node);
+                if !connectors.iter().all(|conn| lease_state.is_available(*conn)) {
+                    lease.finished();
+                    return;
+                }
+
+                match state.lease_request(data.node, DrmLeaseRequest { connectors }) {
+                    Ok(mut drm_lease) => {
+                        if let Some(fd) = drm_lease.take_fd() {
+                            lease.lease_fd(fd);
+                            let _ = nix::unistd::close(fd);
+                        }
+
+                        state
+                            .drm_lease_state(data.node)
+                            .lease_started(drm_lease.connectors());
+                        state.new_active_lease(data.node, &drm_lease);
+                        state.drm_lease_state(data.node).leases.push((lease, drm_lease));
+                    }
+                    Err(_) => {
+                        slog::debug!(
+                            state.drm_lease_state(data.node).logger,
+                            "Lease request was rejected"
+                        );
+                        lease.finished();
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<D> Dispatch<WpDrmLeaseV1, DrmLeaseUserData, D> for DrmLeaseState
+where
+    D: Dispatch<WpDrmLeaseV1, DrmLeaseUserData>,
+    D: Dispatch<WpDrmLeaseConnectorV1, DrmLeaseConnectorUserData>,
+    D: DrmLeaseHandler,
+    D: 'static,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _resource: &WpDrmLeaseV1,
+        request: wp_drm_lease_v1::Request,
+        _data: &DrmLeaseUserData,
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            wp_drm_lease_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, data: &DrmLeaseUserData) {
+        let lease_state = state.drm_lease_state(data.node);
+        if let Some(pos) = lease_state
+            .leases
+            .iter()
+            .position(|(lease, _)| lease.id() == resource)
+        {
+            let (_, drm_lease) = lease_state.leases.remove(pos);
+            let lease_id = drm_lease.id();
+            lease_state.lease_ended::<D>(drm_lease);
+            state.lease_destroyed(data.node, lease_id);
+        }
+    }
+}
@@ -0,0 +1,452 @@
+//! Utilities for handling the `wp_drm_lease_v1` protocol
+//!
+//! This protocol allows clients (most notably VR runtimes) to lease connectors of a drm device,
+//! which are not used by the compositor itself (usually those flagged as `non-desktop`),
+//! to drive them directly.
+//!
+//! A [`DrmLeaseState`] advertises the connectors of a single drm device. Connectors are offered to
+//! clients via [`DrmLeaseState::add_connector`] and withdrawn via [`DrmLeaseState::withdraw_connector`].
+//! Once a client requests a lease, the compositor is asked to approve it by creating a
+//! [`DrmLease`] through [`DrmDevice::create_lease`](crate::backend::drm::DrmDevice::create_lease),
+//! or to deny it via [`LeaseRejected`].
+//! Leased connectors are withdrawn from all clients, until the lease ends.
+//!
+//! ## How to use it
+//!
+//! ```no_run
+//! use std::collections::HashSet;
+//! use smithay::backend::drm::{scanner::{propose_crtcs, ConnectorScanner}, DrmDevice, DrmLease, DrmNode};
+//! use smithay::delegate_drm_lease;
+//! use smithay::reexports::drm::control::Device as ControlDevice;
+//! use smithay::wayland::drm_lease::{DrmLeaseHandler, DrmLeaseRequest, DrmLeaseState, LeaseRejected};
+//! # use std::fs::File;
+//!
+//! # struct State { drm_lease_state: DrmLeaseState, device: DrmDevice<File>, scanner: ConnectorScanner }
+//! # let mut display = wayland_server::Display::<State>::new().unwrap();
+//! # let node: DrmNode = unimplemented!();
+//! let drm_lease_state = DrmLeaseState::new::<State, _>(&display.handle(), &node, None);
+//!
+//! impl DrmLeaseHandler for State {
+//!     fn drm_lease_state(&mut self, node: DrmNode) -> &mut DrmLeaseState {
+//!         &mut self.drm_lease_state
+//!     }
+//!
+//!     fn lease_request(&mut self, node: DrmNode, request: DrmLeaseRequest) -> Result<DrmLease, LeaseRejected> {
+//!         // crtcs driving outputs of the compositor or leased already are not available
+//!         let used = self
+//!             .scanner
+//!             .crtcs()
+//!             .values()
+//!             .chain(self.drm_lease_state.active_leases().flat_map(|lease| lease.crtcs()))
+//!             .copied()
+//!             .collect::<HashSet<_>>();
+//!         let res_handles = self.device.resource_handles().map_err(|_| LeaseRejected)?;
+//!         let mapping = propose_crtcs(&self.device, &res_handles, &request.connectors, &used)?;
+//!
+//!         // every connector needs a crtc and the primary plane of that crtc
+//!         let mut crtcs = Vec::new();
+//!         let mut planes = Vec::new();
+//!         for connector in request.connectors.iter() {
+//!             let crtc = *mapping.get(connector).ok_or(LeaseRejected)?;
+//!             crtcs.push(crtc);
+//!             planes.push(self.device.planes(&crtc)?.primary);
+//!         }
+//!         Ok(self.device.create_lease(&request.connectors, &crtcs, &planes)?)
+//!     }
+//! }
+//!
+//! delegate_drm_lease!(State);
+//! ```
+//!
+//! Non-desktop connectors can then be offered via [`DrmLeaseState::add_non_desktop_connectors`].
+//! As they are not meant to be used as regular outputs, the compositor should skip them
+//! (see [`is_non_desktop`](crate::backend::drm::is_non_desktop)), when setting up its outputs.
+
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use drm::control::{connector, Device as ControlDevice, RawResourceHandle};
+use wayland_protocols::wp::drm_lease::v1::server::{
+    wp_drm_lease_connector_v1::WpDrmLeaseConnectorV1, wp_drm_lease_device_v1::WpDrmLeaseDeviceV1,
+    wp_drm_lease_request_v1::WpDrmLeaseRequestV1, wp_drm_lease_v1::WpDrmLeaseV1,
+};
+use wayland_server::{backend::GlobalId, Dispatch, DisplayHandle, GlobalDispatch, Resource};
+
+use crate::backend::drm::{edid::Edid, is_non_desktop, DevPath, DrmError, DrmLease, DrmNode};
+
+mod dispatch;
+pub use dispatch::{
+    DrmLeaseConnectorUserData, DrmLeaseDeviceGlobalData, DrmLeaseRequestUserData, DrmLeaseUserData,
+};
+
+/// State of a `wp_drm_lease_device_v1` global advertising the connectors of a single drm device
+#[derive(Debug)]
+pub struct DrmLeaseState {
+    node: DrmNode,
+    dh: DisplayHandle,
+    global: GlobalId,
+    connectors: Vec<LeaseConnector>,
+    devices: Vec<WpDrmLeaseDeviceV1>,
+    leases: Vec<(WpDrmLeaseV1, DrmLease)>,
+    logger: ::slog::Logger,
+}
+
+#[derive(Debug)]
+struct LeaseConnector {
+    handle: connector::Handle,
+    name: String,
+    description: String,
+    leased: bool,
+    instances: Vec<WpDrmLeaseConnectorV1>,
+}
+
+/// A lease requested by a client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DrmLeaseRequest {
+    /// Connectors requested by the client
+    ///
+    /// All of them are currently offered and not leased.
+    pub connectors: Vec<connector::Handle>,
+}
+
+/// The compositor denied a lease request
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("The lease request was rejected")]
+pub struct LeaseRejected;
+
+impl From<DrmError> for LeaseRejected {
+    fn from(_: DrmError) -> Self {
+        LeaseRejected
+    }
+}
+
+impl DrmLeaseState {
+    /// Create a new `wp_drm_lease_device_v1` global for the given drm node
+    pub fn new<D, L>(display: &DisplayHandle, node: &DrmNode, logger: L) -> DrmLeaseState
+    where
+        D: GlobalDispatch<WpDrmLeaseDeviceV1, DrmLeaseDeviceGlobalData>
+            + Dispatch<WpDrmLeaseDeviceV1, DrmLeaseDeviceGlobalData>
+            + Dispatch<WpDrmLeaseConnectorV1, DrmLeaseConnectorUserData>
+            + Dispatch<WpDrmLeaseRequestV1, DrmLeaseRequestUserData>
+            + Dispatch<WpDrmLeaseV1, DrmLeaseUserData>
+            + DrmLeaseHandler
+            + 'static,
+        L: Into<Option<::slog::Logger>>,
+    {
+        let logger = crate::slog_or_fallback(logger).new(slog::o!("smithay_module" => "wp_drm_lease"));
+        let global =
+            display.create_global::<D, WpDrmLeaseDeviceV1, _>(1, DrmLeaseDeviceGlobalData { node: *node });
+        DrmLeaseState {
+            node: *node,
+            dh: display.clone(),
+            global,
+            connectors: Vec::new(),
+            devices: Vec::new(),
+            leases: Vec::new(),
+            logger,
+        }
+    }
+
+    /// Returns the `wp_drm_lease_device_v1` global
+    pub fn global(&self) -> GlobalId {
+        self.global.clone()
+    }
+
+    /// Returns the drm node this global advertises connectors of
+    pub fn node(&self) -> DrmNode {
+        self.node
+    }
+
+    /// Offers a connector to clients
+    ///
+    /// `name` and `description` are presented to the user to choose a connector,
+    /// e.g. the connector name (`DP-2`) and the model of the connected headset.
+    pub fn add_connector<D>(&mut self, connector: connector::Handle, name: String, description: String)
+    where
+        D: Dispatch<WpDrmLeaseConnectorV1, DrmLeaseConnectorUserData> + 'static,
+    {
+        if self.connectors.iter().any(|conn| conn.handle == connector) {
+            return;
+        }
+
+        self.connectors.push(LeaseConnector {
+            handle: connector,
+            name,
+            description,
+            leased: false,
+            instances: Vec::new(),
+        });
+        let idx = self.connectors.len() - 1;
+        for device in self.devices.clone() {
+            self.advertise_connector::<D>(&device, idx);
+            device.done();
+        }
+    }
+
+    /// Offers all connected `non-desktop` connectors of the given device to clients
+    ///
+    /// Connectors are named after their interface (e.g. `DP-2`) and described by the model of
+    /// the connected display. Already offered connectors are skipped, so this may be called again
+    /// on every hotplug event. Disconnected connectors still need to be
+    /// [withdrawn](DrmLeaseState::withdraw_connector).
+    ///
+    /// Returns the newly offered connectors.
+    pub fn add_non_desktop_connectors<D, A>(&mut self, device: &A) -> Result<Vec<connector::Handle>, DrmError>
+    where
+        D: Dispatch<WpDrmLeaseConnectorV1, DrmLeaseConnectorUserData> + 'static,
+        A: ControlDevice,
+    {
+        let res_handles = device.resource_handles().map_err(|source| DrmError::Access {
+            errmsg: "Error loading drm resources",
+            dev: device.dev_path(),
+            source,
+        })?;
+
+        let mut added = Vec::new();
+        for conn in res_handles.connectors() {
+            if self.connectors.iter().any(|offered| offered.handle == *conn) {
+                continue;
+            }
+            let info = device
+                .get_connector(*conn, false)
+                .map_err(|source| DrmError::Access {
+                    errmsg: "Error loading connector info",
+                    dev: device.dev_path(),
+                    source,
+                })?;
+            if info.state() != connector::State::Connected || !is_non_desktop(device, *conn)? {
+                continue;
+            }
+
+            let name = connector_name(info.interface(), info.interface_id());
+            let description = match Edid::from_connector(device, *conn) {
+                Ok(edid) => edid.model_name(),
+                Err(_) => format!("{} (unknown display)", name),
+            };
+            slog::info!(self.logger, "Offering non-desktop connector {} for leasing", name);
+            self.add_connector::<D>(*conn, name, description);
+            added.push(*conn);
+        }
+
+        Ok(added)
+    }
+
+    /// Withdraws a connector, e.g. because it was disconnected
+    ///
+    /// Active leases including this connector are not affected, use [`DrmLeaseState::revoke_lease`]
+    /// to end them.
+    pub fn withdraw_connector(&mut self, connector: connector::Handle) {
+        if let Some(pos) = self.connectors.iter().position(|conn| conn.handle == connector) {
+            let connector = self.connectors.remove(pos);
+            for instance in connector.instances {
+                instance.withdrawn();
+            }
+            for device in self.devices.iter() {
+                device.done();
+            }
+        }
+    }
+
+    /// Returns all active leases
+    pub fn active_leases(&self) -> impl Iterator<Item = &DrmLease> {
+        self.leases.iter().map(|(_, lease)| lease)
+    }
+
+    /// Revokes an active lease, notifying the lessee
+    ///
+    /// The leased connectors are offered to clients again.
+    pub fn revoke_lease<D>(&mut self, lease_id: u32)
+    where
+        D: Dispatch<WpDrmLeaseConnectorV1, DrmLeaseConnectorUserData> + 'static,
+    {
+        if let Some(pos) = self.leases.iter().position(|(_, lease)| lease.id() == lease_id) {
+            let (resource, lease) = self.leases.remove(pos);
+            resource.finished();
+            self.lease_ended::<D>(lease);
+        }
+    }
+
+    fn advertise_connector<D>(&mut self, device: &WpDrmLeaseDeviceV1, idx: usize)
+    where
+        D: Dispatch<WpDrmLeaseConnectorV1, DrmLeaseConnectorUserData> + 'static,
+    {
+        let connector = &mut self.connectors[idx];
+        if connector.leased {
+            return;
+        }
+        let client = match self.dh.get_client(device.id()) {
+            Ok(client) => client,
+            Err(_) => return,
+        };
+        let instance = match client.create_resource::<WpDrmLeaseConnectorV1, _, D>(
+            &self.dh,
+            device.version(),
+            DrmLeaseConnectorUserData {
+                node: self.node,
+                connector: connector.handle,
+            },
+        ) {
+            Ok(instance) => instance,
+            Err(_) => {
+                slog::warn!(self.logger, "Failed to create wp_drm_lease_connector_v1");
+                return;
+            }
+        };
+
+        device.connector(&instance);
+        instance.name(connector.name.clone());
+        instance.description(connector.description.clone());
+        instance.connector_id(RawResourceHandle::from(connector.handle).get());
+        instance.done();
+        connector.instances.push(instance);
+    }
+
+    /// Marks the connectors of an ended lease as available again and revokes the lease
+    fn lease_ended<D>(&mut self, lease: DrmLease)
+    where
+        D: Dispatch<WpDrmLeaseConnectorV1, DrmLeaseConnectorUserData> + 'static,
+    {
+        slog::debug!(self.logger, "Lease {} ended", lease.id());
+        let indices = self
+            .connectors
+            .iter()
+            .enumerate()
+            .filter(|(_, conn)| lease.connectors().contains(&conn.handle))
+            .map(|(idx, _)| idx)
+            .collect::<Vec<_>>();
+        for idx in indices.iter() {
+            self.connectors[*idx].leased = false;
+        }
+        if !indices.is_empty() {
+            for device in self.devices.clone() {
+                for idx in indices.iter() {
+                    self.advertise_connector::<D>(&device, *idx);
+                }
+                device.done();
+            }
+        }
+        drop(lease);
+    }
+
+    /// Marks the connectors of a new lease as unavailable
+    fn lease_started(&mut self, connectors: &[connector::Handle]) {
+        for connector in self
+            .connectors
+            .iter_mut()
+            .filter(|conn| connectors.contains(&conn.handle))
+        {
+            connector.leased = true;
+            for instance in connector.instances.drain(..) {
+                instance.withdrawn();
+            }
+        }
+        for device in self.devices.iter() {
+            device.done();
+        }
+    }
+
+    fn is_available(&self, connector: connector::Handle) -> bool {
+        self.connectors
+            .iter()
+            .any(|conn| conn.handle == connector && !conn.leased)
+    }
+}
+
+/// Short name of a connector as used by the kernel, e.g. `DP-2`
+fn connector_name(interface: connector::Interface, id: u32) -> String {
+    let interface = match interface {
+        connector::Interface::DVII => "DVI-I".into(),
+        connector::Interface::DVID => "DVI-D".into(),
+        connector::Interface::DVIA => "DVI-A".into(),
+        connector::Interface::SVideo => "S-VIDEO".into(),
+        connector::Interface::DisplayPort => "DP".into(),
+        connector::Interface::HDMIA => "HDMI-A".into(),
+        connector::Interface::HDMIB => "HDMI-B".into(),
+        connector::Interface::EmbeddedDisplayPort => "eDP".into(),
+        other => format!("{:?}", other),
+    };
+    format!("{}-{}", interface, id)
+}
+
+/// Opens a new, unprivileged fd of the given node, which is sent to clients for enumerating resources
+fn open_unprivileged(node: &DrmNode) -> Option<RawFd> {
+    use nix::fcntl::OFlag;
+
+    struct Fd(RawFd);
+    impl AsRawFd for Fd {
+        fn as_raw_fd(&self) -> RawFd {
+            self.0
+        }
+    }
+    impl drm::Device for Fd {}
+
+    let path = node.dev_path()?;
+    let fd = nix::fcntl::open(
+        &path,
+        OFlag::O_RDWR | OFlag::O_CLOEXEC,
+        nix::sys::stat::Mode::empty(),
+    )
+    .ok()?;
+    // the kernel grants master to the first opener, if there is no current master (e.g. while the session is paused)
+    let _ = drm::Device::release_master_lock(&Fd(fd));
+    Some(fd)
+}
+
+/// Handler trait for drm leases
+#[allow(unused_variables)]
+pub trait DrmLeaseHandler: Sized {
+    /// Returns the [`DrmLeaseState`] of the given node
+    fn drm_lease_state(&mut self, node: DrmNode) -> &mut DrmLeaseState;
+
+    /// A client requested a lease of the given connectors
+    ///
+    /// Approve the request by leasing the connectors, a crtc for each of them and their primary planes via
+    /// [`DrmDevice::create_lease`](crate::backend::drm::DrmDevice::create_lease).
+    /// The fd of the lease is handed to the client and closed afterwards.
+    ///
+    /// The lease is revoked, once the client destroys it or [`DrmLeaseState::revoke_lease`] is called.
+    fn lease_request(&mut self, node: DrmNode, request: DrmLeaseRequest) -> Result<DrmLease, LeaseRejected>;
+
+    /// A lease was handed to a client
+    fn new_active_lease(&mut self, node: DrmNode, lease: &DrmLease) {}
+
+    /// A lease was revoked or destroyed by the client, its resources may be used again
+    fn lease_destroyed(&mut self, node: DrmNode, lease_id: u32) {}
+}
+
+/// Macro to delegate implementation of the drm lease protocol to [`DrmLeaseState`].
+///
+/// You must also implement [`DrmLeaseHandler`] to use this.
+#[macro_export]
+macro_rules! delegate_drm_lease {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        $crate::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::reexports::wayland_protocols::wp::drm_lease::v1::server::wp_drm_lease_device_v1::WpDrmLeaseDeviceV1: $crate::wayland::drm_lease::DrmLeaseDeviceGlobalData
+        ] => $crate::wayland::drm_lease::DrmLeaseState);
+
+        $crate::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::reexports::wayland_protocols::wp::drm_lease::v1::server::wp_drm_lease_device_v1::WpDrmLeaseDeviceV1: $crate::wayland::drm_lease::DrmLeaseDeviceGlobalData
+        ] => $crate::wayland::drm_lease::DrmLeaseState);
+        $crate::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::reexports::wayland_protocols::wp::drm_lease::v1::server::wp_drm_lease_connector_v1::WpDrmLeaseConnectorV1: $crate::wayland::drm_lease::DrmLeaseConnectorUserData
+        ] => $crate::wayland::drm_lease::DrmLeaseState);
+        $crate::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::reexports::wayland_protocols::wp::drm_lease::v1::server::wp_drm_lease_request_v1::WpDrmLeaseRequestV1: $crate::wayland::drm_lease::DrmLeaseRequestUserData
+        ] => $crate::wayland::drm_lease::DrmLeaseState);
+        $crate::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::reexports::wayland_protocols::wp::drm_lease::v1::server::wp_drm_lease_v1::WpDrmLeaseV1: $crate::wayland::drm_lease::DrmLeaseUserData
+        ] => $crate::wayland::drm_lease::DrmLeaseState);
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::connector_name;
+    use drm::control::connector::Interface;
+
+    #[test]
+    fn connector_names() {
+        assert_eq!(connector_name(Interface::DisplayPort, 2), "DP-2");
+        assert_eq!(connector_name(Interface::HDMIA, 1), "HDMI-A-1");
+        assert_eq!(connector_name(Interface::Virtual, 1), "Virtual-1");
+    }
+}
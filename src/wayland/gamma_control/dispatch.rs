@@ -0,0 +1,180 @@
+use std::{fs::File, io::Read, os::unix::io::AsRawFd, sync::Mutex};
+
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use wayland_protocols_wlr::gamma_control::v1::server::{
+    zwlr_gamma_control_manager_v1::{self, ZwlrGammaControlManagerV1},
+    zwlr_gamma_control_v1::{self, ZwlrGammaControlV1},
+};
+use wayland_server::{
+    backend::{ClientId, ObjectId},
+    Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New,
+};
+
+use crate::output::Output;
+
+use super::{GammaControlHandler, GammaControlManagerState};
+
+/// User data of [ZwlrGammaControlV1] object
+#[derive(Debug)]
+pub struct GammaControlUserData {
+    /// Output controlled by this object, `None` once the control failed
+    pub(super) output: Mutex<Option<Output>>,
+    gamma_size: u32,
+}
+
+impl GammaControlUserData {
+    /// Output controlled by this gamma control, if it is still active
+    pub fn output(&self) -> Option<Output> {
+        self.output.lock().unwrap().clone()
+    }
+}
+
+impl<D> GlobalDispatch<ZwlrGammaControlManagerV1, (), D> for GammaControlManagerState
+where
+    D: GlobalDispatch<ZwlrGammaControlManagerV1, ()>,
+    D: Dispatch<ZwlrGammaControlManagerV1, ()>,
+    D: Dispatch<ZwlrGammaControlV1, GammaControlUserData>,
+    D: GammaControlHandler,
+    D: 'static,
+{
+    fn bind(
+        _state: &mut D,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwlrGammaControlManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl<D> Dispatch<ZwlrGammaControlManagerV1, (), D> for GammaControlManagerState
+where
+    D: Dispatch<ZwlrGammaControlManagerV1, ()>,
+    D: Dispatch<ZwlrGammaControlV1, GammaControlUserData>,
+    D: GammaControlHandler,
+    D: 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        _resource: &ZwlrGammaControlManagerV1,
+        request: zwlr_gamma_control_manager_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwlr_gamma_control_manager_v1::Request::GetGammaControl { id, output } => {
+                let output = Output::from_resource(&output);
+                // only a single client may control an output at a time
+                let gamma_size = output
+                    .as_ref()
+                    .filter(|output| !state.gamma_control_state().is_controlled(output))
+                    .and_then(|output| state.get_gamma_size(output))
+                    .filter(|size| *size > 0);
+
+                let (output, gamma_size) = match (output, gamma_size) {
+                    (Some(output), Some(gamma_size)) => (Some(output), gamma_size),
+                    _ => (None, 0),
+                };
+                let control = data_init.init(
+                    id,
+                    GammaControlUserData {
+                        output: Mutex::new(output.clone()),
+                        gamma_size,
+                    },
+                );
+
+                match output {
+                    Some(output) => {
+                        state
+                            .gamma_control_state()
+                            .controls
+                            .insert(output, control.clone());
+                        control.gamma_size(gamma_size);
+                    }
+                    None => control.failed(),
+                }
+            }
+            zwlr_gamma_control_manager_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<D> Dispatch<ZwlrGammaControlV1, GammaControlUserData, D> for GammaControlManagerState
+where
+    D: Dispatch<ZwlrGammaControlV1, GammaControlUserData>,
+    D: GammaControlHandler,
+    D: 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        resource: &ZwlrGammaControlV1,
+        request: zwlr_gamma_control_v1::Request,
+        data: &GammaControlUserData,
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwlr_gamma_control_v1::Request::SetGamma { fd } => {
+                let output = match data.output() {
+                    Some(output) => output,
+                    // the control already failed, ignore further requests
+                    None => return,
+                };
+
+                let mut ramps = vec![0u16; data.gamma_size as usize * 3];
+                if read_ramps(File::from(fd), &mut ramps).is_err() {
+                    resource.post_error(
+                        zwlr_gamma_control_v1::Error::InvalidGamma,
+                        "Gamma ramps have an invalid size",
+                    );
+                    return;
+                }
+
+                let (red, rest) = ramps.split_at(data.gamma_size as usize);
+                let (green, blue) = rest.split_at(data.gamma_size as usize);
+                if !state.set_gamma(&output, Some([red, green, blue])) {
+                    revoke(state, data);
+                    resource.failed();
+                }
+            }
+            zwlr_gamma_control_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, _id: ObjectId, data: &GammaControlUserData) {
+        revoke(state, data);
+    }
+}
+
+/// Releases the output of a gamma control, restoring its original ramps
+fn revoke<D: GammaControlHandler>(state: &mut D, data: &GammaControlUserData) {
+    let output = data.output.lock().unwrap().take();
+    if let Some(output) = output {
+        state.gamma_control_state().controls.remove(&output);
+        state.set_gamma(&output, None);
+    }
+}
+
+/// Reads exactly `ramps.len()` entries in native endianness from `file`
+fn read_ramps(mut file: File, ramps: &mut [u16]) -> std::io::Result<()> {
+    // the client is expected to have written all data already, never block on a misbehaving one
+    let flags = fcntl(file.as_raw_fd(), FcntlArg::F_GETFL)?;
+    fcntl(
+        file.as_raw_fd(),
+        FcntlArg::F_SETFL(OFlag::from_bits_truncate(flags) | OFlag::O_NONBLOCK),
+    )?;
+
+    let mut data = vec![0u8; ramps.len() * 2];
+    file.read_exact(&mut data)?;
+    for (value, bytes) in ramps.iter_mut().zip(data.chunks_exact(2)) {
+        *value = u16::from_ne_bytes([bytes[0], bytes[1]]);
+    }
+    Ok(())
+}
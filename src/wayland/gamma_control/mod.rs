@@ -0,0 +1,144 @@
+//! Utilities for handling the `wlr-gamma-control` protocol
+//!
+//! This protocol allows privileged clients (like night light applications) to set the
+//! gamma ramps of an output.
+//!
+//! Only a single client can control the gamma ramps of an output at a time. The ramps
+//! submitted by the client are read from the provided fd and validated against the gamma size
+//! of the output, before being handed to the compositor through [`GammaControlHandler::set_gamma`].
+//! Once the client destroys its gamma control or disconnects, the compositor is asked
+//! to restore the original ramps.
+//!
+//! Before destroying an [`Output`], call [`GammaControlManagerState::output_removed`] to
+//! notify the controlling client and release the output.
+//!
+//! ## How to use it
+//!
+//! ```no_run
+//! use smithay::delegate_gamma_control;
+//! use smithay::output::Output;
+//! use smithay::wayland::gamma_control::{GammaControlHandler, GammaControlManagerState};
+//!
+//! # struct State { gamma_control_state: GammaControlManagerState }
+//! # let mut display = wayland_server::Display::<State>::new().unwrap();
+//! let gamma_control_state = GammaControlManagerState::new::<State>(&display.handle());
+//!
+//! impl GammaControlHandler for State {
+//!     fn gamma_control_state(&mut self) -> &mut GammaControlManagerState {
+//!         &mut self.gamma_control_state
+//!     }
+//!
+//!     fn get_gamma_size(&mut self, output: &Output) -> Option<u32> {
+//!         // e.g. forward `DrmSurface::gamma_size` of the output
+//!         # None
+//!     }
+//!
+//!     fn set_gamma(&mut self, output: &Output, ramps: Option<[&[u16]; 3]>) -> bool {
+//!         // e.g. forward to `DrmSurface::set_gamma` or `DrmSurface::reset_gamma`
+//!         # true
+//!     }
+//! }
+//!
+//! delegate_gamma_control!(State);
+//! ```
+
+use std::collections::HashMap;
+
+use wayland_protocols_wlr::gamma_control::v1::server::{
+    zwlr_gamma_control_manager_v1::ZwlrGammaControlManagerV1, zwlr_gamma_control_v1::ZwlrGammaControlV1,
+};
+use wayland_server::{backend::GlobalId, Dispatch, DisplayHandle, GlobalDispatch, Resource};
+
+use crate::output::Output;
+
+mod dispatch;
+pub use dispatch::GammaControlUserData;
+
+/// State of the wlr gamma control manager
+#[derive(Debug)]
+pub struct GammaControlManagerState {
+    global: GlobalId,
+    controls: HashMap<Output, ZwlrGammaControlV1>,
+}
+
+impl GammaControlManagerState {
+    /// Register a new [ZwlrGammaControlManagerV1] global
+    pub fn new<D>(display: &DisplayHandle) -> Self
+    where
+        D: GlobalDispatch<ZwlrGammaControlManagerV1, ()>,
+        D: Dispatch<ZwlrGammaControlManagerV1, ()>,
+        D: Dispatch<ZwlrGammaControlV1, GammaControlUserData>,
+        D: GammaControlHandler,
+        D: 'static,
+    {
+        let global = display.create_global::<D, ZwlrGammaControlManagerV1, _>(1, ());
+        Self {
+            global,
+            controls: HashMap::new(),
+        }
+    }
+
+    /// [ZwlrGammaControlManagerV1] GlobalId getter
+    pub fn global(&self) -> GlobalId {
+        self.global.clone()
+    }
+
+    /// Returns `true` if a client currently controls the gamma ramps of the given output
+    pub fn is_controlled(&self, output: &Output) -> bool {
+        self.controls.contains_key(output)
+    }
+
+    /// Revokes the gamma control of the given output, which is about to be removed
+    ///
+    /// The controlling client is notified, that its gamma control failed.
+    /// As the output is gone, [`GammaControlHandler::set_gamma`] is not called to restore its ramps.
+    pub fn output_removed(&mut self, output: &Output) {
+        if let Some(control) = self.controls.remove(output) {
+            if let Some(data) = control.data::<GammaControlUserData>() {
+                data.output.lock().unwrap().take();
+            }
+            control.failed();
+        }
+    }
+}
+
+/// Handler trait for wlr gamma control
+pub trait GammaControlHandler {
+    /// [GammaControlManagerState] getter
+    fn gamma_control_state(&mut self) -> &mut GammaControlManagerState;
+
+    /// Returns the number of entries per channel of the gamma ramps of the given output
+    ///
+    /// Return `None` if setting the gamma ramps of this output is not supported.
+    fn get_gamma_size(&mut self, output: &Output) -> Option<u32>;
+
+    /// Sets the gamma ramps of the given output
+    ///
+    /// `ramps` contains the red, green and blue channel, each having exactly
+    /// [`get_gamma_size`](GammaControlHandler::get_gamma_size) entries.
+    /// `None` means the original ramps of the output should be restored, which happens once the
+    /// controlling client destroys its gamma control or disconnects.
+    ///
+    /// Return `false` if the ramps could not be applied, which will notify the client
+    /// and revoke its control over the output.
+    fn set_gamma(&mut self, output: &Output, ramps: Option<[&[u16]; 3]>) -> bool;
+}
+
+/// Macro to delegate implementation of the wlr gamma control protocol to [`GammaControlManagerState`].
+///
+/// You must also implement [`GammaControlHandler`] to use this.
+#[macro_export]
+macro_rules! delegate_gamma_control {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        $crate::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::reexports::wayland_protocols_wlr::gamma_control::v1::server::zwlr_gamma_control_manager_v1::ZwlrGammaControlManagerV1: ()
+        ] => $crate::wayland::gamma_control::GammaControlManagerState);
+
+        $crate::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::reexports::wayland_protocols_wlr::gamma_control::v1::server::zwlr_gamma_control_manager_v1::ZwlrGammaControlManagerV1: ()
+        ] => $crate::wayland::gamma_control::GammaControlManagerState);
+        $crate::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::reexports::wayland_protocols_wlr::gamma_control::v1::server::zwlr_gamma_control_v1::ZwlrGammaControlV1: $crate::wayland::gamma_control::GammaControlUserData
+        ] => $crate::wayland::gamma_control::GammaControlManagerState);
+    };
+}
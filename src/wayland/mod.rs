@@ -49,6 +49,9 @@ pub mod buffer;
 pub mod compositor;
 pub mod data_device;
 pub mod dmabuf;
+#[cfg(feature = "backend_drm")]
+pub mod drm_lease;
+pub mod gamma_control;
 pub mod input_method;
 pub mod keyboard_shortcuts_inhibit;
 pub mod output;
@@ -58,6 +61,7 @@ pub mod shell;
 pub mod shm;
 pub mod socket;
 pub mod tablet_manager;
+pub mod tearing_control;
 pub mod text_input;
 pub mod viewporter;
 pub mod xdg_activation;
@@ -0,0 +1,284 @@
+//! Utilities for handling the `wp_tearing_control_v1` protocol
+//!
+//! This protocol allows clients to hint, whether their content should be presented synchronized
+//! to the vertical blank (`vsync`) or as soon as possible (`async`), accepting tearing for lower latency.
+//! This is usually requested by games and only makes sense to honor for fullscreen surfaces.
+//!
+//! ## How to use it
+//!
+//! ### Initialization
+//!
+//! To initialize this implementation, create [`TearingControlState`], store it in your `State` struct and
+//! implement the required traits, as shown in this example:
+//!
+//! ```
+//! use smithay::wayland::tearing_control::TearingControlState;
+//! use smithay::delegate_tearing_control;
+//!
+//! # struct State;
+//! # let mut display = wayland_server::Display::<State>::new().unwrap();
+//!
+//! // Create the tearing control state:
+//! let tearing_control_state = TearingControlState::new::<State, _>(
+//!     &display.handle(), // the display
+//!     None // provide a logger, if you want
+//! );
+//!
+//! // implement Dispatch for the tearing control types
+//! delegate_tearing_control!(State);
+//!
+//! // You're now ready to go!
+//! ```
+//!
+//! ### Use the presentation hint
+//!
+//! The [`presentation hint`](TearingControlSurfaceCachedState) is double-buffered and
+//! can be accessed by using the [`with_states`] function
+//!
+//! ```no_compile
+//! let allow_tearing = with_states(surface, |states| {
+//!     states.cached_state.current::<TearingControlSurfaceCachedState>().is_async()
+//! });
+//! ```
+//!
+//! For drm outputs the hint can be passed on to
+//! [`GbmBufferedSurface::queue_buffer`](crate::backend::drm::GbmBufferedSurface::queue_buffer).
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use wayland_server::{
+    backend::GlobalId, protocol::wl_surface, Dispatch, DisplayHandle, GlobalDispatch, Resource, WEnum,
+};
+
+use crate::utils::IsAlive;
+
+use super::compositor::{with_states, Cacheable};
+
+use self::protocol::{wp_tearing_control_manager_v1, wp_tearing_control_v1};
+pub use wp_tearing_control_v1::PresentationHint;
+
+/// Server-side bindings of the `wp_tearing_control_v1` protocol
+///
+/// The protocol is not part of the `wayland-protocols` version smithay depends on,
+/// so the bindings are generated from the vendored `tearing-control-v1.xml`.
+#[allow(
+    missing_docs,
+    non_upper_case_globals,
+    non_camel_case_types,
+    unused_imports,
+    clippy::all
+)]
+pub mod protocol {
+    use wayland_server;
+    use wayland_server::protocol::*;
+
+    pub mod __interfaces {
+        use wayland_server::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!("src/wayland/tearing_control/tearing-control-v1.xml");
+    }
+    use self::__interfaces::*;
+
+    wayland_scanner::generate_server_code!("src/wayland/tearing_control/tearing-control-v1.xml");
+}
+
+/// State of the wp_tearing_control_manager_v1 Global
+#[derive(Debug)]
+pub struct TearingControlState {
+    global: GlobalId,
+}
+
+impl TearingControlState {
+    /// Create new [`wp_tearing_control_manager_v1`] global.
+    ///
+    /// It returns the tearing control state, which you can drop to remove these global from
+    /// the event loop in the future.
+    pub fn new<D, L>(display: &DisplayHandle, log: L) -> TearingControlState
+    where
+        D: GlobalDispatch<wp_tearing_control_manager_v1::WpTearingControlManagerV1, slog::Logger>
+            + Dispatch<wp_tearing_control_manager_v1::WpTearingControlManagerV1, slog::Logger>
+            + Dispatch<wp_tearing_control_v1::WpTearingControlV1, TearingControlUserData>
+            + 'static,
+        L: Into<Option<slog::Logger>>,
+    {
+        TearingControlState {
+            global: display
+                .create_global::<D, wp_tearing_control_manager_v1::WpTearingControlManagerV1, slog::Logger>(
+                    1,
+                    crate::slog_or_fallback(log).new(slog::o!("smithay_module" => "wp_tearing_control")),
+                ),
+        }
+    }
+
+    /// Returns the tearing control manager global.
+    pub fn global(&self) -> GlobalId {
+        self.global.clone()
+    }
+}
+
+impl<D> GlobalDispatch<wp_tearing_control_manager_v1::WpTearingControlManagerV1, slog::Logger, D>
+    for TearingControlState
+where
+    D: GlobalDispatch<wp_tearing_control_manager_v1::WpTearingControlManagerV1, slog::Logger>,
+    D: Dispatch<wp_tearing_control_manager_v1::WpTearingControlManagerV1, slog::Logger>,
+    D: Dispatch<wp_tearing_control_v1::WpTearingControlV1, TearingControlUserData>,
+{
+    fn bind(
+        _state: &mut D,
+        _handle: &DisplayHandle,
+        _client: &wayland_server::Client,
+        resource: wayland_server::New<wp_tearing_control_manager_v1::WpTearingControlManagerV1>,
+        global_data: &slog::Logger,
+        data_init: &mut wayland_server::DataInit<'_, D>,
+    ) {
+        data_init.init(resource, global_data.clone());
+    }
+}
+
+impl<D> Dispatch<wp_tearing_control_manager_v1::WpTearingControlManagerV1, slog::Logger, D>
+    for TearingControlState
+where
+    D: GlobalDispatch<wp_tearing_control_manager_v1::WpTearingControlManagerV1, slog::Logger>,
+    D: Dispatch<wp_tearing_control_manager_v1::WpTearingControlManagerV1, slog::Logger>,
+    D: Dispatch<wp_tearing_control_v1::WpTearingControlV1, TearingControlUserData>,
+{
+    fn request(
+        _state: &mut D,
+        _client: &wayland_server::Client,
+        resource: &wp_tearing_control_manager_v1::WpTearingControlManagerV1,
+        request: wp_tearing_control_manager_v1::Request,
+        data: &slog::Logger,
+        _dhandle: &DisplayHandle,
+        data_init: &mut wayland_server::DataInit<'_, D>,
+    ) {
+        match request {
+            wp_tearing_control_manager_v1::Request::GetTearingControl { id, surface } => {
+                let already_controlled = with_states(&surface, |states| {
+                    states
+                        .data_map
+                        .insert_if_missing_threadsafe(|| TearingControlMarker(AtomicBool::new(false)));
+                    let marker = states.data_map.get::<TearingControlMarker>().unwrap();
+                    marker.0.swap(true, Ordering::SeqCst)
+                });
+
+                if already_controlled {
+                    resource.post_error(
+                        wp_tearing_control_manager_v1::Error::TearingControlExists,
+                        "the surface already has a tearing control object associated".to_string(),
+                    );
+                    return;
+                }
+
+                slog::trace!(data, "New tearing control for surface {:?}", surface);
+                data_init.init(id, TearingControlUserData { surface });
+            }
+            wp_tearing_control_manager_v1::Request::Destroy => {
+                // Existing tearing controls are not affected
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<D> Dispatch<wp_tearing_control_v1::WpTearingControlV1, TearingControlUserData, D> for TearingControlState
+where
+    D: Dispatch<wp_tearing_control_v1::WpTearingControlV1, TearingControlUserData>,
+{
+    fn request(
+        _state: &mut D,
+        _client: &wayland_server::Client,
+        _resource: &wp_tearing_control_v1::WpTearingControlV1,
+        request: wp_tearing_control_v1::Request,
+        data: &TearingControlUserData,
+        _dhandle: &DisplayHandle,
+        _data_init: &mut wayland_server::DataInit<'_, D>,
+    ) {
+        // the object becomes inert, once the surface is destroyed
+        if !data.surface.alive() {
+            return;
+        }
+
+        match request {
+            wp_tearing_control_v1::Request::SetPresentationHint { hint } => {
+                if let WEnum::Value(hint) = hint {
+                    with_states(&data.surface, |states| {
+                        states
+                            .cached_state
+                            .pending::<TearingControlSurfaceCachedState>()
+                            .hint = hint;
+                    });
+                }
+            }
+            wp_tearing_control_v1::Request::Destroy => {
+                // Destroying the object reverts the hint on the next commit
+                with_states(&data.surface, |states| {
+                    if let Some(marker) = states.data_map.get::<TearingControlMarker>() {
+                        marker.0.store(false, Ordering::SeqCst);
+                    }
+                    *states.cached_state.pending::<TearingControlSurfaceCachedState>() =
+                        TearingControlSurfaceCachedState::default();
+                });
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// User data of a [`wp_tearing_control_v1::WpTearingControlV1`] object
+#[derive(Debug)]
+pub struct TearingControlUserData {
+    surface: wl_surface::WlSurface,
+}
+
+struct TearingControlMarker(AtomicBool);
+
+/// Represents the double-buffered presentation hint
+/// of a [`WlSurface`](wl_surface::WlSurface)
+#[derive(Debug, Clone, Copy)]
+pub struct TearingControlSurfaceCachedState {
+    /// Presentation hint requested by the client
+    pub hint: PresentationHint,
+}
+
+impl TearingControlSurfaceCachedState {
+    /// Returns whether the client prefers asynchronous presentation, accepting tearing
+    pub fn is_async(&self) -> bool {
+        self.hint == PresentationHint::Async
+    }
+}
+
+impl Default for TearingControlSurfaceCachedState {
+    fn default() -> Self {
+        TearingControlSurfaceCachedState {
+            hint: PresentationHint::Vsync,
+        }
+    }
+}
+
+impl Cacheable for TearingControlSurfaceCachedState {
+    fn commit(&mut self, _dh: &DisplayHandle) -> Self {
+        *self
+    }
+
+    fn merge_into(self, into: &mut Self, _dh: &DisplayHandle) {
+        into.hint = self.hint;
+    }
+}
+
+/// Macro to delegate implementation of the tearing control protocol to [`TearingControlState`].
+///
+/// You must also store a [`TearingControlState`] to use this.
+#[macro_export]
+macro_rules! delegate_tearing_control {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        $crate::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::wayland::tearing_control::protocol::wp_tearing_control_manager_v1::WpTearingControlManagerV1: slog::Logger
+        ] => $crate::wayland::tearing_control::TearingControlState);
+
+        $crate::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::wayland::tearing_control::protocol::wp_tearing_control_manager_v1::WpTearingControlManagerV1: slog::Logger
+        ] => $crate::wayland::tearing_control::TearingControlState);
+        $crate::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::wayland::tearing_control::protocol::wp_tearing_control_v1::WpTearingControlV1: $crate::wayland::tearing_control::TearingControlUserData
+        ] => $crate::wayland::tearing_control::TearingControlState);
+    };
+}